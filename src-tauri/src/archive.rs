@@ -0,0 +1,298 @@
+use crate::error::StorageError;
+use crate::paths::RemotePath;
+use crate::storage::{detect_mime_type, Storage, MAX_RECURSIVE_ENTRIES};
+use serde::{Deserialize, Serialize};
+use shell_escape::escape;
+use std::io::{Seek, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// The binary that has to exist on the remote host.
+    pub fn remote_tool(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar",
+        }
+    }
+}
+
+/// An archive type that can be unpacked on the remote host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExtractFormat {
+    Zip,
+    Tar,
+    TarGz,
+    SevenZip,
+}
+
+impl ExtractFormat {
+    /// Detects the format from the file name. Plain `.gz` files are not
+    /// archives and yield `None`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let name = path.to_lowercase();
+        match detect_mime_type(&name)?.as_str() {
+            "application/zip" => Some(ExtractFormat::Zip),
+            "application/x-tar" => Some(ExtractFormat::Tar),
+            "application/gzip" if name.ends_with(".tar.gz") || name.ends_with(".tgz") => {
+                Some(ExtractFormat::TarGz)
+            }
+            "application/x-7z-compressed" => Some(ExtractFormat::SevenZip),
+            _ => None,
+        }
+    }
+
+    /// The binary that has to exist on the remote host.
+    pub fn remote_tool(&self) -> &'static str {
+        match self {
+            ExtractFormat::Zip => "unzip",
+            ExtractFormat::Tar | ExtractFormat::TarGz => "tar",
+            ExtractFormat::SevenZip => "7z",
+        }
+    }
+}
+
+/// Prints `yes` when `tool` is available.
+pub fn tool_check_command(tool: &str) -> String {
+    format!(
+        "command -v {} >/dev/null 2>&1 && echo yes",
+        escape(tool.into())
+    )
+}
+
+/// Builds a command writing an archive of `absolute_dir` to stdout. Entries
+/// are stored under the directory's own name; `exclude` names a directory
+/// left out at any depth.
+pub fn archive_command(absolute_dir: &str, format: ArchiveFormat, exclude: Option<&str>) -> String {
+    let (parent, name) = match absolute_dir.trim_end_matches('/').rsplit_once('/') {
+        Some((parent, name)) if !name.is_empty() => {
+            (if parent.is_empty() { "/" } else { parent }, name)
+        }
+        _ => (absolute_dir, "."),
+    };
+    let parent = escape(parent.into());
+    let quoted = escape(name.into());
+    match format {
+        ArchiveFormat::Zip => {
+            let exclude = exclude
+                .map(|ex| format!(" -x {}", escape(format!("*/{}/*", ex).into())))
+                .unwrap_or_default();
+            format!("cd {} && zip -qr - {}{}", parent, quoted, exclude)
+        }
+        ArchiveFormat::TarGz => {
+            let exclude = exclude
+                .map(|ex| format!(" --exclude={}", escape(ex.into())))
+                .unwrap_or_default();
+            format!("cd {} && tar czf -{} -- {}", parent, exclude, quoted)
+        }
+    }
+}
+
+/// Builds a command unpacking `archive` into `destination`, creating it if
+/// needed. On success the archive's entry paths are printed one per line; on
+/// failure a single `error\t` line carries the tool's output.
+pub fn extract_command(archive: &str, destination: &str, format: ExtractFormat) -> String {
+    let archive = escape(archive.into());
+    let destination = escape(destination.into());
+    let (extract, list) = match format {
+        ExtractFormat::Zip => (
+            format!("unzip -oq {} -d {}", archive, destination),
+            format!("unzip -Z1 {}", archive),
+        ),
+        ExtractFormat::Tar => (
+            format!("tar xf {} -C {}", archive, destination),
+            format!("tar tf {}", archive),
+        ),
+        ExtractFormat::TarGz => (
+            format!("tar xzf {} -C {}", archive, destination),
+            format!("tar tzf {}", archive),
+        ),
+        ExtractFormat::SevenZip => (
+            format!("7z x -y -o{} {}", destination, archive),
+            format!("7z l -ba -slt {} | sed -n 's/^Path = //p'", archive),
+        ),
+    };
+    format!(
+        "out=$(mkdir -p {} && {} 2>&1) || {{ printf 'error\\t%s\\n' \"$out\"; exit 1; }}; {}",
+        destination, extract, list
+    )
+}
+
+/// Reduces the output of [`extract_command`] to the distinct top-level
+/// entries, in archive order.
+pub fn parse_extract_output(output: &str) -> Result<Vec<String>, String> {
+    if let Some(message) = output.strip_prefix("error\t") {
+        return Err(message.trim().to_string());
+    }
+    let mut entries: Vec<String> = Vec::new();
+    for line in output.lines() {
+        if line.starts_with('/') {
+            continue;
+        }
+        let line = line.trim_start_matches("./");
+        let top = line.split('/').next().unwrap_or("");
+        if !top.is_empty() && !entries.iter().any(|e| e == top) {
+            entries.push(top.to_string());
+        }
+    }
+    Ok(entries)
+}
+
+/// Builds a zip of `path` on the client by reading every file through the
+/// backend. Used when the remote host has no `zip`. Returns `false` if
+/// `cancelled` was raised, and fails rather than leave out entries when the
+/// directory holds more than the recursive listing returns.
+pub fn zip_directory<W: Write + Seek>(
+    storage: &dyn Storage,
+    path: &str,
+    writer: W,
+    cancelled: &AtomicBool,
+    on_progress: &mut dyn FnMut(u64),
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let root = RemotePath::new(path);
+    let prefix = root.file_name().unwrap_or("archive").to_string();
+    let mut zip = ZipWriter::new(writer);
+    let options = SimpleFileOptions::default();
+    let mut bytes_done = 0u64;
+
+    let listing = storage.list_directory_recursive(path, 0, cancelled)?;
+    if listing.truncated {
+        return Err(StorageError::TooLarge(format!(
+            "{} has more than {} entries to archive",
+            root, MAX_RECURSIVE_ENTRIES
+        ))
+        .into());
+    }
+    for entry in listing.files {
+        let relative = entry
+            .path
+            .strip_prefix(root.as_str())
+            .unwrap_or(&entry.path)
+            .trim_start_matches('/');
+        let name = format!("{}/{}", prefix, relative);
+        if entry.is_dir {
+            zip.add_directory(name, options)?;
+            continue;
+        }
+
+        zip.start_file(name, options)?;
+        let mut write_error = None;
+        let completed = storage.read_file_streamed(&entry.path, &mut |chunk, _| {
+            if cancelled.load(Ordering::Relaxed) {
+                return false;
+            }
+            if let Err(e) = zip.write_all(chunk) {
+                write_error = Some(e);
+                return false;
+            }
+            bytes_done += chunk.len() as u64;
+            on_progress(bytes_done);
+            true
+        })?;
+        if let Some(e) = write_error {
+            return Err(e.into());
+        }
+        if !completed {
+            return Ok(false);
+        }
+    }
+
+    zip.finish()?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_command_zip() {
+        assert_eq!(
+            archive_command("/home/ubuntu/raw files", ArchiveFormat::Zip, None),
+            "cd /home/ubuntu && zip -qr - 'raw files'"
+        );
+        assert_eq!(
+            archive_command("/tmp/image-repo", ArchiveFormat::Zip, Some(".git")),
+            "cd /tmp && zip -qr - image-repo -x '*/.git/*'"
+        );
+    }
+
+    #[test]
+    fn test_archive_command_tar() {
+        assert_eq!(
+            archive_command("/srv/it's/", ArchiveFormat::TarGz, None),
+            "cd /srv && tar czf - -- 'it'\\''s'"
+        );
+        assert_eq!(
+            archive_command("/photos", ArchiveFormat::TarGz, Some(".git")),
+            "cd / && tar czf - --exclude=.git -- photos"
+        );
+    }
+
+    #[test]
+    fn test_extract_format_from_path() {
+        assert_eq!(
+            ExtractFormat::from_path("a/b.ZIP"),
+            Some(ExtractFormat::Zip)
+        );
+        assert_eq!(ExtractFormat::from_path("b.tar"), Some(ExtractFormat::Tar));
+        assert_eq!(
+            ExtractFormat::from_path("b.tar.gz"),
+            Some(ExtractFormat::TarGz)
+        );
+        assert_eq!(
+            ExtractFormat::from_path("b.tgz"),
+            Some(ExtractFormat::TarGz)
+        );
+        assert_eq!(
+            ExtractFormat::from_path("b.7z"),
+            Some(ExtractFormat::SevenZip)
+        );
+        assert_eq!(ExtractFormat::from_path("notes.txt.gz"), None);
+        assert_eq!(ExtractFormat::from_path("photo.jpg"), None);
+    }
+
+    #[test]
+    fn test_extract_command_quotes_paths() {
+        assert_eq!(
+            extract_command("/srv/my assets.zip", "/srv/out", ExtractFormat::Zip),
+            "out=$(mkdir -p /srv/out && unzip -oq '/srv/my assets.zip' -d /srv/out 2>&1) \
+             || { printf 'error\\t%s\\n' \"$out\"; exit 1; }; unzip -Z1 '/srv/my assets.zip'"
+        );
+    }
+
+    #[test]
+    fn test_parse_extract_output_top_level() {
+        let output = "./photos/\n./photos/a.jpg\nphotos/b.jpg\nREADME\n/srv/x.7z\n";
+        assert_eq!(
+            parse_extract_output(output).unwrap(),
+            vec!["photos".to_string(), "README".to_string()]
+        );
+        assert_eq!(
+            parse_extract_output("error\tunzip: cannot find zipfile\n").unwrap_err(),
+            "unzip: cannot find zipfile"
+        );
+    }
+
+    #[test]
+    fn test_archive_format_serde() {
+        assert_eq!(
+            serde_json::from_str::<ArchiveFormat>("\"tar.gz\"").unwrap(),
+            ArchiveFormat::TarGz
+        );
+        assert_eq!(
+            serde_json::to_string(&ArchiveFormat::Zip).unwrap(),
+            "\"zip\""
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! BlurHash placeholders: a short string the UI decodes into a blurred
+//! preview while the real thumbnail loads. See https://blurha.sh.
+
+use image::{DynamicImage, RgbImage};
+use std::f32::consts::PI;
+
+/// Images are shrunk to at most this size before encoding, which is plenty
+/// for a handful of components and keeps the cost negligible.
+pub const PLACEHOLDER_SIZE: u32 = 32;
+/// Horizontal and vertical components of placeholders.
+pub const PLACEHOLDER_COMPONENTS: (u32, u32) = (4, 3);
+
+const BASE83: &[u8; 83] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+fn push_base83(hash: &mut String, value: u32, digits: u32) {
+    for i in (0..digits).rev() {
+        hash.push(BASE83[(value / 83u32.pow(i) % 83) as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let v = f32::from(value) / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    let srgb = if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u32
+}
+
+fn sign_pow(value: f32, exponent: f32) -> f32 {
+    value.abs().powf(exponent).copysign(value)
+}
+
+/// BlurHash of `img` with `components_x` by `components_y` components,
+/// each between 1 and 9.
+pub fn encode(img: &RgbImage, components_x: u32, components_y: u32) -> String {
+    let components_x = components_x.clamp(1, 9);
+    let components_y = components_y.clamp(1, 9);
+    let (width, height) = img.dimensions();
+    let linear: Vec<[f32; 3]> = img.pixels().map(|p| p.0.map(srgb_to_linear)).collect();
+
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for j in 0..components_y {
+        for i in 0..components_x {
+            let mut factor = [0.0f32; 3];
+            for y in 0..height {
+                let basis_y = (PI * j as f32 * y as f32 / height as f32).cos();
+                for x in 0..width {
+                    let basis = basis_y * (PI * i as f32 * x as f32 / width as f32).cos();
+                    let pixel = linear[(y * width + x) as usize];
+                    for c in 0..3 {
+                        factor[c] += basis * pixel[c];
+                    }
+                }
+            }
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let scale = normalisation / (width * height).max(1) as f32;
+            factors.push(factor.map(|f| f * scale));
+        }
+    }
+
+    let mut hash = String::new();
+    push_base83(&mut hash, (components_x - 1) + (components_y - 1) * 9, 1);
+    let (dc, ac) = factors.split_first().expect("at least one component");
+    let max_value = if ac.is_empty() {
+        push_base83(&mut hash, 0, 1);
+        1.0
+    } else {
+        let actual_max = ac.iter().flatten().fold(0.0f32, |max, f| max.max(f.abs()));
+        let quantised = (actual_max * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+        push_base83(&mut hash, quantised, 1);
+        (quantised + 1) as f32 / 166.0
+    };
+    let dc_value =
+        (linear_to_srgb(dc[0]) << 16) + (linear_to_srgb(dc[1]) << 8) + linear_to_srgb(dc[2]);
+    push_base83(&mut hash, dc_value, 4);
+    for factor in ac {
+        let [r, g, b] = factor.map(|f| {
+            (sign_pow(f / max_value, 0.5) * 9.0 + 9.5)
+                .floor()
+                .clamp(0.0, 18.0) as u32
+        });
+        push_base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+    hash
+}
+
+/// Placeholder for `img`, computed from a [`PLACEHOLDER_SIZE`] copy.
+pub fn placeholder(img: &DynamicImage) -> String {
+    let small = img.thumbnail(PLACEHOLDER_SIZE, PLACEHOLDER_SIZE).to_rgb8();
+    let (x, y) = PLACEHOLDER_COMPONENTS;
+    encode(&small, x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_encode_solid_black() {
+        let black = RgbImage::new(8, 6);
+        assert_eq!(encode(&black, 4, 3), "L00000fQfQfQfQfQfQfQfQfQfQfQ");
+        assert_eq!(encode(&black, 1, 1), "000000");
+    }
+
+    #[test]
+    fn test_placeholder_reflects_content() {
+        let gradient = RgbImage::from_fn(300, 200, |x, _| Rgb([(x % 256) as u8, 80, 200]));
+        let hash = placeholder(&DynamicImage::ImageRgb8(gradient));
+        // Size flag, maximum AC value, DC and 11 AC components.
+        assert_eq!(hash.len(), 1 + 1 + 4 + 11 * 2);
+        assert!(hash.starts_with('L'));
+        assert_ne!(
+            &hash[6..8],
+            "fQ",
+            "a horizontal gradient has an x component"
+        );
+    }
+}
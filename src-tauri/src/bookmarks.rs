@@ -0,0 +1,165 @@
+use crate::storage::Storage;
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// File in the app data directory holding every connection's bookmarks.
+pub const BOOKMARKS_FILE: &str = "bookmarks.json";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Bookmark {
+    pub id: u64,
+    pub path: String,
+    pub label: String,
+    pub created_at: u64,
+    /// The path no longer existed when the bookmarks were last listed.
+    #[serde(default)]
+    pub stale: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct BookmarkStore {
+    next_id: u64,
+    /// Bookmarks per [`connection_key`].
+    connections: BTreeMap<String, Vec<Bookmark>>,
+}
+
+/// Keeps bookmarks of different backends and remotes apart.
+pub fn connection_key(storage: &dyn Storage) -> String {
+    format!("{}:{}", storage.storage_type(), storage.connection_id())
+}
+
+impl BookmarkStore {
+    pub fn list(&self, key: &str) -> &[Bookmark] {
+        self.connections.get(key).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn list_mut(&mut self, key: &str) -> &mut [Bookmark] {
+        self.connections
+            .get_mut(key)
+            .map(Vec::as_mut_slice)
+            .unwrap_or(&mut [])
+    }
+
+    /// Bookmarks `path`, or relabels it if it is already bookmarked.
+    pub fn add(&mut self, key: &str, path: &str, label: &str, now: u64) -> Bookmark {
+        let bookmarks = self.connections.entry(key.to_string()).or_default();
+        if let Some(existing) = bookmarks.iter_mut().find(|b| b.path == path) {
+            existing.label = label.to_string();
+            existing.stale = false;
+            return existing.clone();
+        }
+
+        self.next_id += 1;
+        let bookmark = Bookmark {
+            id: self.next_id,
+            path: path.to_string(),
+            label: label.to_string(),
+            created_at: now,
+            stale: false,
+        };
+        bookmarks.push(bookmark.clone());
+        bookmark
+    }
+
+    /// Returns whether a bookmark with `id` existed.
+    pub fn remove(&mut self, key: &str, id: u64) -> bool {
+        let Some(bookmarks) = self.connections.get_mut(key) else {
+            return false;
+        };
+        let before = bookmarks.len();
+        bookmarks.retain(|b| b.id != id);
+        let removed = bookmarks.len() != before;
+        if bookmarks.is_empty() {
+            self.connections.remove(key);
+        }
+        removed
+    }
+}
+
+pub fn bookmarks_path(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join(BOOKMARKS_FILE)
+}
+
+/// Reads the store at `path`; see [`utils::load_json_or_default`].
+pub fn load_bookmarks(path: &Path) -> BookmarkStore {
+    utils::load_json_or_default(path)
+}
+
+pub fn save_bookmarks(path: &Path, store: &BookmarkStore) -> std::io::Result<()> {
+    utils::save_json_atomic(path, store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_file() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = bookmarks_path(dir.path());
+        (dir, path)
+    }
+
+    #[test]
+    fn test_add_keeps_connections_apart() {
+        let mut store = BookmarkStore::default();
+        let a = store.add("ec2:ubuntu@host:22", "/photos/2023", "2023", 1);
+        let b = store.add(
+            "github:git@github.com:me/pics.git#main",
+            "/photos/2023",
+            "Repo",
+            2,
+        );
+        assert_ne!(a.id, b.id);
+        assert_eq!(store.list("ec2:ubuntu@host:22"), &[a]);
+        assert_eq!(store.list("github:git@github.com:me/pics.git#main"), &[b]);
+        assert!(store.list("ec2:other@host:22").is_empty());
+    }
+
+    #[test]
+    fn test_add_same_path_relabels() {
+        let mut store = BookmarkStore::default();
+        let first = store.add("k", "/a", "A", 1);
+        let second = store.add("k", "/a", "Renamed", 2);
+        assert_eq!(first.id, second.id);
+        assert_eq!(store.list("k").len(), 1);
+        assert_eq!(store.list("k")[0].label, "Renamed");
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut store = BookmarkStore::default();
+        let bookmark = store.add("k", "/a", "A", 1);
+        assert!(!store.remove("k", bookmark.id + 1));
+        assert!(store.remove("k", bookmark.id));
+        assert!(!store.remove("k", bookmark.id));
+        assert!(store.list("k").is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let (_dir, path) = temp_file();
+        assert_eq!(load_bookmarks(&path), BookmarkStore::default());
+
+        let mut store = BookmarkStore::default();
+        store.add("k", "/deep/dir", "Deep", 1700000000);
+        save_bookmarks(&path, &store).unwrap();
+        let mut loaded = load_bookmarks(&path);
+        assert_eq!(loaded, store);
+        // Ids keep increasing after a reload.
+        assert_eq!(loaded.add("k", "/other", "Other", 1).id, 2);
+    }
+
+    #[test]
+    fn test_load_recovers_from_corruption() {
+        let (_dir, path) = temp_file();
+        fs::write(&path, b"{\"connections\": [not json").unwrap();
+
+        assert_eq!(load_bookmarks(&path), BookmarkStore::default());
+        assert!(!path.exists());
+        assert!(path.with_extension("json.corrupt").exists());
+    }
+}
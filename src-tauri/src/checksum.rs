@@ -0,0 +1,214 @@
+use crate::storage::Storage;
+use openssl::hash::{Hasher, MessageDigest};
+use serde::{Deserialize, Serialize};
+use shell_escape::escape;
+use std::collections::HashMap;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    Md5,
+}
+
+impl ChecksumAlgorithm {
+    /// The coreutils binary that computes this digest on the remote host.
+    pub fn remote_tool(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256sum",
+            ChecksumAlgorithm::Md5 => "md5sum",
+        }
+    }
+
+    fn hex_len(&self) -> usize {
+        match self {
+            ChecksumAlgorithm::Sha256 => 64,
+            ChecksumAlgorithm::Md5 => 32,
+        }
+    }
+
+    fn message_digest(&self) -> MessageDigest {
+        match self {
+            ChecksumAlgorithm::Sha256 => MessageDigest::sha256(),
+            ChecksumAlgorithm::Md5 => MessageDigest::md5(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FileChecksum {
+    pub algorithm: ChecksumAlgorithm,
+    /// Lowercase hex digest.
+    pub digest: String,
+    pub size: u64,
+}
+
+/// Extracts the digest from `sha256sum`/`md5sum` output. Returns `None` when
+/// the output is not a digest line, e.g. because the tool is missing.
+pub fn parse_checksum_output(output: &str, algorithm: ChecksumAlgorithm) -> Option<String> {
+    let line = output.lines().next()?;
+    // Names containing a backslash or newline make the tools prefix the line
+    // with a backslash.
+    let line = line.strip_prefix('\\').unwrap_or(line);
+    let digest = line.split_whitespace().next()?;
+    if digest.len() == algorithm.hex_len() && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(digest.to_lowercase())
+    } else {
+        None
+    }
+}
+
+/// Builds one `sha256sum`/`md5sum` invocation over all `absolute_paths`.
+/// Unreadable files are left out of the output.
+pub fn batch_checksum_command(absolute_paths: &[String], algorithm: ChecksumAlgorithm) -> String {
+    let quoted: Vec<String> = absolute_paths
+        .iter()
+        .map(|p| escape(p.as_str().into()).into_owned())
+        .collect();
+    format!(
+        "{} -- {} 2>/dev/null",
+        algorithm.remote_tool(),
+        quoted.join(" ")
+    )
+}
+
+/// Maps each file named in multi-line `sha256sum`/`md5sum` output to its
+/// digest, undoing the tools' escaping of backslashes and newlines.
+pub fn parse_checksum_lines(output: &str, algorithm: ChecksumAlgorithm) -> HashMap<String, String> {
+    let mut digests = HashMap::new();
+    for line in output.lines() {
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let Some((digest, name)) = line.split_once("  ").or_else(|| line.split_once(" *")) else {
+            continue;
+        };
+        if digest.len() != algorithm.hex_len() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            continue;
+        }
+        let name = if escaped {
+            unescape_name(name)
+        } else {
+            name.to_string()
+        };
+        digests.insert(name, digest.to_lowercase());
+    }
+    digests
+}
+
+fn unescape_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Hashes `path` locally while streaming it from `storage`.
+pub fn hash_streamed(
+    storage: &dyn Storage,
+    path: &str,
+    algorithm: ChecksumAlgorithm,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut hasher = Hasher::new(algorithm.message_digest())?;
+    let mut hash_error = None;
+    storage.read_file_streamed(path, &mut |chunk, _| match hasher.update(chunk) {
+        Ok(()) => true,
+        Err(e) => {
+            hash_error = Some(e);
+            false
+        }
+    })?;
+    if let Some(e) = hash_error {
+        return Err(e.into());
+    }
+    Ok(to_hex(&hasher.finish()?))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256_EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn test_parse_checksum_output_with_spaces_in_name() {
+        let output = format!("{}  /home/ubuntu/my photos/IMG 0001.jpg\n", SHA256_EMPTY);
+        assert_eq!(
+            parse_checksum_output(&output, ChecksumAlgorithm::Sha256),
+            Some(SHA256_EMPTY.to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_checksum_output_escaped_name() {
+        let output = "\\d41d8cd98f00b204e9800998ecf8427e  /srv/odd\\nname.jpg\n";
+        assert_eq!(
+            parse_checksum_output(output, ChecksumAlgorithm::Md5),
+            Some("d41d8cd98f00b204e9800998ecf8427e".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_checksum_output_rejects_non_digest() {
+        assert_eq!(parse_checksum_output("", ChecksumAlgorithm::Sha256), None);
+        assert_eq!(
+            parse_checksum_output(
+                "bash: sha256sum: command not found\n",
+                ChecksumAlgorithm::Sha256
+            ),
+            None
+        );
+        // An md5 digest is not accepted where sha256 was requested.
+        assert_eq!(
+            parse_checksum_output(
+                "d41d8cd98f00b204e9800998ecf8427e  a.jpg",
+                ChecksumAlgorithm::Sha256
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_batch_checksum_command_quotes_paths() {
+        let paths = vec!["/srv/a b.jpg".to_string(), "/srv/it's.jpg".to_string()];
+        assert_eq!(
+            batch_checksum_command(&paths, ChecksumAlgorithm::Md5),
+            "md5sum -- '/srv/a b.jpg' '/srv/it'\\''s.jpg' 2>/dev/null"
+        );
+    }
+
+    #[test]
+    fn test_parse_checksum_lines() {
+        let output = format!(
+            "{0}  /srv/a b.jpg\n\\{0}  /srv/odd\\nname\\\\x.jpg\nmd5sum: /srv/gone.jpg: No such file\n",
+            SHA256_EMPTY
+        );
+        let digests = parse_checksum_lines(&output, ChecksumAlgorithm::Sha256);
+        assert_eq!(digests.len(), 2);
+        assert_eq!(digests["/srv/a b.jpg"], SHA256_EMPTY);
+        assert_eq!(digests["/srv/odd\nname\\x.jpg"], SHA256_EMPTY);
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x0f]), "00ab0f");
+    }
+}
@@ -1,14 +1,73 @@
-use crate::ec2::Ec2Storage;
-use crate::github::GitHubStorage;
-use crate::storage::{FileInfo, Storage};
+use crate::archive::{self, ArchiveFormat};
+use crate::bookmarks::{self, Bookmark};
+use crate::checksum::{ChecksumAlgorithm, FileChecksum};
+use crate::connection_events::{self, DisconnectReason, EventSink};
+use crate::display::{DisplayProfile, DisplaySettings};
+use crate::duplicates::{self, DuplicateScan};
+use crate::ec2::{AuthMethod, Ec2Config, Ec2Storage, SftpConfig};
+use crate::error::{Context, StorageError};
+use crate::gdrive::{self, DriveAuthFlow, DriveAuthStatus, DriveConfig, DriveStorage, OAuthClient};
+use crate::github::{
+    Branch, BranchSwitch, DeletedFilesPage, FileRevision, GitHubConfig, GitHubStorage, GitStatus,
+    RefreshSummary,
+};
+use crate::github_api::{GitHubApiConfig, GitHubApiStorage};
+use crate::health::{self, HealthStatus};
+use crate::host_keys::{self, HostKeyError, OfferedHostKey};
+use crate::http_index::{HttpIndexConfig, HttpIndexStorage};
+use crate::image_decode;
+use crate::image_edit::{self, ImageTransform, TransformOptions};
+use crate::local::LocalStorage;
+use crate::local_tree;
+use crate::logging::{self, LogEntry, LogLevel};
+use crate::media_probe::{self, MediaProbe};
+use crate::metrics::MetricsSnapshot;
+use crate::paths::RemotePath;
+use crate::prefetch::{self, PrefetchCache, PrefetchCacheStats};
+use crate::profiles::{self, Profile, ProfileConfig, ProfileSecrets};
+use crate::recents::{self, RecentFile, RecentFiles, MAX_RECENT_FILES};
+use crate::secrets;
+use crate::similar::{self, SimilarScan};
+use crate::ssh_config::{self, SshConfigHost};
+use crate::storage::{
+    detect_mime_type, paginate, parse_mode, sniff_file_mime_type, ConnectionLostHandler,
+    ContentSearchResult, CreateDirectoryResult, DeleteDirectoryResult, DirectoryPage,
+    DirectoryUsage, FileInfo, FileTooLargeError, ListOptions, MediaFilter, RecursiveListing,
+    SearchResult, Storage, StorageType, DEFAULT_MAX_IN_MEMORY_READ, DEFAULT_SEARCH_LIMIT,
+    MAX_RECURSIVE_ENTRIES,
+};
+use crate::thumbnail_cache::{self, ThumbnailCache, ThumbnailCacheStats};
+use crate::thumbnails::{self, RemoteThumbnailer, Thumbnail, ThumbnailBatch, ThumbnailOptions};
+use crate::trash::{self, TrashEntry};
+use crate::tunnel::JumpHostConfig;
 use crate::utils;
+use crate::validation::ValidationResult;
+use crate::view_prefs::{self, ViewPrefs};
+use crate::watch::{self, FsChangeEvent};
+use crate::webdav::{WebDavConfig, WebDavStorage};
 use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
-use tauri::State;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+/// Minimum number of bytes between two `download://progress` or
+/// `transfer://progress` events.
+const PROGRESS_INTERVAL_BYTES: u64 = 1024 * 1024;
+/// Entries per `listing://batch` event unless the caller asks otherwise.
+pub const DEFAULT_LISTING_BATCH_SIZE: usize = 500;
 
 pub enum StorageBackend {
     Ec2(Ec2Storage),
     GitHub(GitHubStorage),
+    GitHubApi(GitHubApiStorage),
+    GoogleDrive(DriveStorage),
+    HttpIndex(HttpIndexStorage),
+    Local(LocalStorage),
+    WebDav(WebDavStorage),
 }
 
 impl StorageBackend {
@@ -16,6 +75,11 @@ impl StorageBackend {
         match self {
             StorageBackend::Ec2(s) => s,
             StorageBackend::GitHub(s) => s,
+            StorageBackend::GitHubApi(s) => s,
+            StorageBackend::GoogleDrive(s) => s,
+            StorageBackend::HttpIndex(s) => s,
+            StorageBackend::Local(s) => s,
+            StorageBackend::WebDav(s) => s,
         }
     }
 
@@ -23,18 +87,287 @@ impl StorageBackend {
         match self {
             StorageBackend::Ec2(s) => s,
             StorageBackend::GitHub(s) => s,
+            StorageBackend::GitHubApi(s) => s,
+            StorageBackend::GoogleDrive(s) => s,
+            StorageBackend::HttpIndex(s) => s,
+            StorageBackend::Local(s) => s,
+            StorageBackend::WebDav(s) => s,
+        }
+    }
+
+    /// A new connection with the same config. Commands may still be using
+    /// this one, so it is replaced rather than reconnected in place.
+    fn reconnected(&self, on_lost: ConnectionLostHandler) -> Result<StorageBackend, StorageError> {
+        Ok(match self {
+            StorageBackend::Ec2(s) => {
+                let mut storage = Ec2Storage::new(s.config().clone());
+                storage.set_connection_lost_handler(on_lost);
+                storage.connect()?;
+                StorageBackend::Ec2(storage)
+            }
+            StorageBackend::GitHub(s) => {
+                let mut storage = GitHubStorage::new(s.config());
+                storage.set_connection_lost_handler(on_lost);
+                storage.connect()?;
+                StorageBackend::GitHub(storage)
+            }
+            StorageBackend::GitHubApi(s) => {
+                let mut storage = GitHubApiStorage::new(s.config().clone());
+                storage.connect()?;
+                StorageBackend::GitHubApi(storage)
+            }
+            StorageBackend::GoogleDrive(s) => {
+                let mut storage =
+                    DriveStorage::new(s.config().clone(), s.tokens_path().to_path_buf());
+                storage.connect()?;
+                StorageBackend::GoogleDrive(storage)
+            }
+            StorageBackend::HttpIndex(s) => {
+                let mut storage = HttpIndexStorage::new(s.config().clone());
+                storage.connect()?;
+                StorageBackend::HttpIndex(storage)
+            }
+            StorageBackend::Local(s) => {
+                let mut storage = LocalStorage::new(s.base_path().to_path_buf());
+                storage.connect()?;
+                StorageBackend::Local(storage)
+            }
+            StorageBackend::WebDav(s) => {
+                let mut storage = WebDavStorage::new(s.config().clone());
+                storage.connect()?;
+                StorageBackend::WebDav(storage)
+            }
+        })
+    }
+}
+
+// Backends are shared through `Arc`s, so the session closes when the last
+// command using it finishes.
+impl Drop for StorageBackend {
+    fn drop(&mut self) {
+        self.storage_mut().disconnect();
+    }
+}
+
+/// Emits `storage://disconnected` when the connection in `slot` is found
+/// dead.
+fn connection_lost_handler(app: &AppHandle, slot: BackendSlot) -> ConnectionLostHandler {
+    connection_events::connection_lost_handler(Arc::new(app.clone()), slot)
+}
+
+/// Runs `op` on `backend`, and if it failed because the connection dropped
+/// and the backend allows it, connects again and runs it exactly once more
+/// on the new connection, which also replaces `backend` in `slot`. A failed
+/// reconnect is reported as `storage://error`.
+fn with_reconnect<T>(
+    app: &AppHandle,
+    state: &AppState,
+    slot: BackendSlot,
+    backend: &Arc<StorageBackend>,
+    op: impl Fn(&dyn Storage) -> Result<T, StorageError>,
+) -> Result<T, StorageError> {
+    match op(backend.storage()) {
+        Err(e) if e.is_transport() => {
+            backend.storage().connection_lost(&e);
+            if !backend.storage().auto_reconnect() {
+                return Err(e);
+            }
+            let fresh = match backend.reconnected(connection_lost_handler(app, slot)) {
+                Ok(fresh) => Arc::new(fresh),
+                Err(reconnect_error) => {
+                    let error = StorageError::ConnectionLost(format!(
+                        "{} (reconnect failed: {})",
+                        e, reconnect_error
+                    ));
+                    connection_events::emit_error(app, slot, error.clone());
+                    return Err(error);
+                }
+            };
+            // Keep a connection made elsewhere in the meantime.
+            if let Ok(mut current) = state.slot(slot).write() {
+                if current.as_ref().is_some_and(|c| Arc::ptr_eq(c, backend)) {
+                    state.retire_metrics(slot, backend);
+                    *current = Some(fresh.clone());
+                }
+            }
+            let storage = fresh.storage();
+            let _ = app.emit(
+                "storage://reconnected",
+                ReconnectedEvent {
+                    storage_type: storage.storage_type(),
+                    connection_id: storage.connection_id(),
+                },
+            );
+            op(storage)
         }
+        result => result,
     }
 }
 
+/// Runs `work` on the blocking thread pool. Backends do their network I/O
+/// synchronously, which would otherwise stall every other command and
+/// timer sharing the async runtime.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, StorageError> {
+    Ok(tauri::async_runtime::spawn_blocking(work).await?)
+}
+
+/// Payload of the `storage://reconnected` event.
+#[derive(Debug, Serialize, Clone)]
+pub struct ReconnectedEvent {
+    pub storage_type: StorageType,
+    pub connection_id: String,
+}
+
+/// Which connection a command addresses: the one being browsed, or the
+/// secondary one files can be transferred to and from.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendSlot {
+    #[default]
+    Primary,
+    Target,
+}
+
+/// Commands clone the backend `Arc` out of its slot and do I/O on the
+/// blocking thread pool without holding the lock, so a slow transfer never
+/// blocks other commands.
 pub struct AppState {
-    pub storage: Mutex<Option<StorageBackend>>,
+    pub storage: RwLock<Option<Arc<StorageBackend>>>,
+    /// Second connection used by `transfer_file`.
+    pub transfer_target: RwLock<Option<Arc<StorageBackend>>>,
+    pub display_profile: Mutex<DisplayProfile>,
+    pub downloads: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    /// Cancel flags of the running `list_files_streamed` calls, by stream id.
+    pub listings: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    /// Cancel flags of the running commands given an operation id, by id.
+    pub operations: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+    pub recent_files: Mutex<RecentFiles>,
+    /// Stop flags of the running directory watchers, by canonical path.
+    pub watchers: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    /// Stop flags of the running health monitors, by slot.
+    pub health_monitors: Mutex<HashMap<BackendSlot, Arc<AtomicBool>>>,
+    /// Counters of the slots' earlier connections this session.
+    pub retired_metrics: Mutex<HashMap<BackendSlot, MetricsSnapshot>>,
+    /// Host keys offered by servers not yet in any known_hosts, by host,
+    /// until the user accepts them with `accept_host_key`.
+    pub pending_host_keys: Mutex<HashMap<String, OfferedHostKey>>,
+    /// The Google Drive sign-in begun by [`gdrive_auth_start`].
+    pub drive_auth: Mutex<Option<Arc<DriveAuthFlow>>>,
+    /// Set at startup when the app cache dir is available.
+    pub thumbnail_cache: OnceLock<ThumbnailCache>,
+    /// Files read ahead by `prefetch_files`, served by `read_file`.
+    pub prefetch_cache: Arc<PrefetchCache>,
+    /// Set once [`shutdown`] has run.
+    shut_down: AtomicBool,
 }
 
 impl AppState {
     pub fn new() -> Self {
         Self {
-            storage: Mutex::new(None),
+            storage: RwLock::new(None),
+            transfer_target: RwLock::new(None),
+            display_profile: Mutex::new(DisplayProfile::default()),
+            downloads: Mutex::new(HashMap::new()),
+            listings: Mutex::new(HashMap::new()),
+            operations: Arc::new(Mutex::new(HashMap::new())),
+            recent_files: Mutex::new(RecentFiles::default()),
+            watchers: Mutex::new(HashMap::new()),
+            health_monitors: Mutex::new(HashMap::new()),
+            retired_metrics: Mutex::new(HashMap::new()),
+            pending_host_keys: Mutex::new(HashMap::new()),
+            drive_auth: Mutex::new(None),
+            thumbnail_cache: OnceLock::new(),
+            prefetch_cache: Arc::new(PrefetchCache::new(prefetch::DEFAULT_PREFETCH_CACHE_BYTES)),
+            shut_down: AtomicBool::new(false),
+        }
+    }
+
+    fn slot(&self, slot: BackendSlot) -> &RwLock<Option<Arc<StorageBackend>>> {
+        match slot {
+            BackendSlot::Primary => &self.storage,
+            BackendSlot::Target => &self.transfer_target,
+        }
+    }
+
+    /// The backend connected in `slot`, if any.
+    fn backend(&self, slot: BackendSlot) -> Result<Option<Arc<StorageBackend>>, StorageError> {
+        Ok(self.slot(slot).read()?.clone())
+    }
+
+    fn is_connected(&self) -> Result<bool, StorageError> {
+        Ok(self
+            .backend(BackendSlot::Primary)?
+            .is_some_and(|b| b.storage().is_connected()))
+    }
+
+    fn set_backend(
+        &self,
+        slot: BackendSlot,
+        backend: Option<StorageBackend>,
+    ) -> Result<(), StorageError> {
+        let previous = {
+            let mut current = self.slot(slot).write()?;
+            if let Some(previous) = current.as_deref() {
+                self.retire_metrics(slot, previous);
+            }
+            std::mem::replace(&mut *current, backend.map(Arc::new))
+        };
+        // Disconnecting does network I/O, and may even remove a git clone,
+        // so the old backend is released off the lock and the async runtime.
+        if let Some(previous) = previous {
+            tauri::async_runtime::spawn_blocking(move || drop(previous));
+        }
+        Ok(())
+    }
+
+    /// Adds the counters of `backend`, leaving `slot`, to the slot's totals.
+    fn retire_metrics(&self, slot: BackendSlot, backend: &StorageBackend) {
+        if let Ok(mut retired) = self.retired_metrics.lock() {
+            let total = retired.entry(slot).or_default();
+            *total = total.combined(&backend.storage().metrics().snapshot());
+        }
+    }
+}
+
+/// A running command that `cancel_operation` can stop by its id. It stays
+/// registered until dropped; without an id it cannot be cancelled.
+struct Operation {
+    registry: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+    id: Option<String>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Operation {
+    fn start(state: &AppState, id: Option<String>) -> Result<Self, StorageError> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        if let Some(id) = &id {
+            let mut operations = state.operations.lock()?;
+            if operations.contains_key(id) {
+                return Err(StorageError::InvalidInput(format!(
+                    "Operation {} is already running",
+                    id
+                )));
+            }
+            operations.insert(id.clone(), cancelled.clone());
+        }
+        Ok(Self {
+            registry: state.operations.clone(),
+            id,
+            cancelled,
+        })
+    }
+
+    fn cancelled(&self) -> &AtomicBool {
+        &self.cancelled
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        if let (Some(id), Ok(mut operations)) = (&self.id, self.registry.lock()) {
+            operations.remove(id);
         }
     }
 }
@@ -45,21 +378,247 @@ impl Default for AppState {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Ec2ConnectRequest {
+    /// May be left empty when `ssh_config_host` supplies it.
+    #[serde(default)]
     pub host: String,
+    #[serde(default)]
     pub username: String,
+    #[serde(default)]
     pub pem_content: String,
     pub port: Option<u16>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub auth_method: AuthMethod,
+    #[serde(default)]
+    pub key_passphrase: Option<String>,
+    /// See [`Ec2Config::keepalive_secs`].
+    #[serde(default)]
+    pub keepalive_secs: Option<u64>,
+    #[serde(default)]
+    pub auto_reconnect: bool,
+    #[serde(default)]
+    pub remote_thumbnails: bool,
+    #[serde(default)]
+    pub jump_host: Option<JumpHostConfig>,
+    /// `Host` alias from `~/.ssh/config` filling in the host, user, port and
+    /// key that the request leaves unset.
+    #[serde(default)]
+    pub ssh_config_host: Option<String>,
+    /// Take the key, password and passphrases from the keyring entry of
+    /// `profile_name` instead of the request.
+    #[serde(default)]
+    pub use_stored_secret: bool,
+    #[serde(default)]
+    pub profile_name: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The keyring secrets a connect request asks for, if any.
+fn requested_secret(
+    use_stored_secret: bool,
+    profile_name: Option<&str>,
+) -> Result<Option<ProfileSecrets>, StorageError> {
+    if !use_stored_secret {
+        return Ok(None);
+    }
+    let profile = profile_name.ok_or_else(|| {
+        StorageError::InvalidInput("A stored secret needs a profile name".to_string())
+    })?;
+    match secrets::load_secret(profile)? {
+        Some(secrets) => Ok(Some(secrets)),
+        None => Err(StorageError::NotFound(format!(
+            "No secret is stored for profile {}",
+            profile
+        ))),
+    }
+}
+
+impl Ec2ConnectRequest {
+    fn set_secrets(&mut self, secrets: ProfileSecrets) {
+        self.pem_content = secrets.key_content;
+        self.password = secrets.password;
+        self.key_passphrase = secrets.key_passphrase;
+        if let Some(jump) = self.jump_host.as_mut() {
+            jump.pem_content = secrets.jump_key_content;
+            jump.key_passphrase = secrets.jump_key_passphrase;
+        }
+    }
+
+    /// Fills unset fields from the request's `ssh_config_host`, reading the
+    /// alias's IdentityFile as the key.
+    fn apply_ssh_config(&mut self) -> Result<(), StorageError> {
+        let Some(alias) = self.ssh_config_host.as_deref() else {
+            return Ok(());
+        };
+        let entry = ssh_config::find_host(alias);
+        if self.host.is_empty() {
+            self.host = entry.host_name.unwrap_or_else(|| alias.to_string());
+        }
+        if self.username.is_empty() {
+            self.username = entry.user.unwrap_or_default();
+        }
+        self.port = self.port.or(entry.port);
+        if let (true, Some(identity_file)) = (self.pem_content.is_empty(), entry.identity_file) {
+            let key = std::fs::read(&identity_file)
+                .with_context(|| format!("Failed to read IdentityFile {}", identity_file))?;
+            self.pem_content = utils::base64_encode(&key);
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Ec2ConnectRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ec2ConnectRequest")
+            .field("host", &self.host)
+            .field("username", &self.username)
+            .field("port", &self.port)
+            .field("auth_method", &self.auth_method)
+            .field("jump_host", &self.jump_host)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LocalConnectRequest {
+    pub base_path: String,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct GitHubConnectRequest {
     pub repo_url: String,
     pub username: String,
     pub ssh_key_content: String,
+    /// `None` checks out the repository's default branch.
     pub branch: Option<String>,
     pub local_path: Option<String>,
+    #[serde(default)]
+    pub key_passphrase: Option<String>,
+    #[serde(default)]
+    pub keepalive_secs: Option<u64>,
+    #[serde(default)]
+    pub auto_reconnect: bool,
+    #[serde(default)]
+    pub remote_thumbnails: bool,
+    #[serde(default)]
+    pub author_name: Option<String>,
+    #[serde(default)]
+    pub author_email: Option<String>,
+    #[serde(default)]
+    pub clone_depth: Option<u32>,
+    #[serde(default)]
+    pub partial_clone: bool,
+    #[serde(default)]
+    pub sparse_paths: Vec<String>,
+    #[serde(default)]
+    pub api_token: Option<String>,
+    #[serde(default)]
+    pub min_free_space_mb: Option<u64>,
+    /// Clone even if the remote host looks short of disk space.
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub manual_commits: bool,
+    #[serde(default)]
+    pub manage_lfs_tracking: bool,
+    #[serde(default)]
+    pub lfs_patterns: Vec<String>,
+    #[serde(default)]
+    pub replace_existing: bool,
+    #[serde(default)]
+    pub remove_clone_on_disconnect: bool,
+    /// See [`Ec2ConnectRequest::use_stored_secret`].
+    #[serde(default)]
+    pub use_stored_secret: bool,
+    #[serde(default)]
+    pub profile_name: Option<String>,
+}
+
+impl std::fmt::Debug for GitHubConnectRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GitHubConnectRequest")
+            .field("repo_url", &self.repo_url)
+            .field("username", &self.username)
+            .field("branch", &self.branch)
+            .field("local_path", &self.local_path)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DownloadProgress {
+    pub download_id: String,
+    pub bytes_done: u64,
+    pub bytes_total: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ListingBatch {
+    pub stream_id: String,
+    pub files: Vec<FileInfo>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ListingDone {
+    pub stream_id: String,
+    /// Entries sent before the listing ended.
+    pub total: usize,
+    pub cancelled: bool,
+    pub error: Option<StorageError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileRange {
+    /// Base64-encoded bytes.
+    pub data: String,
+    pub offset: u64,
+    pub eof: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TransferProgress {
+    pub transfer_id: String,
+    pub bytes_done: u64,
+    pub bytes_total: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferResult {
+    pub bytes: u64,
+    /// Nothing was copied; `bytes` is the source size.
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UploadProgress {
+    pub current_file: String,
+    /// 1-based position of `current_file` among the files to upload.
+    pub index: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct UploadSummary {
+    /// Remote paths written.
+    pub succeeded: Vec<String>,
+    pub failed: Vec<UploadFailure>,
+}
+
+/// One entry of a `read_files` batch; exactly one of `data` (base64) and
+/// `error` is set.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileReadResult {
+    pub path: String,
+    pub data: Option<String>,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -68,143 +627,3359 @@ pub struct ConnectResponse {
     pub message: String,
     pub storage_type: Option<String>,
     pub root_path: Option<String>,
+    /// Machine-readable failure reason, the [`StorageError::code`] of the
+    /// failure, e.g. [`utils::PASSPHRASE_REQUIRED`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    /// SHA256 fingerprint of the server's host key when `error_code` is
+    /// [`host_keys::HOST_KEY_UNKNOWN`] or [`host_keys::HOST_KEY_MISMATCH`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_key_fingerprint: Option<String>,
+    /// Tool making thumbnails on the remote host; `None` when they are made
+    /// locally.
+    #[serde(default)]
+    pub remote_thumbnailer: Option<RemoteThumbnailer>,
+    /// The branch checked out, which GitHub storage detects when none was
+    /// asked for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
 }
 
-#[tauri::command]
-pub async fn connect_ec2(
-    state: State<'_, AppState>,
-    request: Ec2ConnectRequest,
-) -> Result<ConnectResponse, String> {
-    let mut storage = Ec2Storage::new(crate::ec2::Ec2Config {
+impl ConnectResponse {
+    /// Also remembers an unknown host key so `accept_host_key` can trust it.
+    fn failed(state: &AppState, context: &str, error: StorageError) -> Self {
+        let host_key_fingerprint = remember_host_key(state, &error);
+        ConnectResponse {
+            success: false,
+            message: format!("{}: {}", context, error),
+            storage_type: None,
+            root_path: None,
+            error_code: Some(error.code().to_string()),
+            host_key_fingerprint,
+            remote_thumbnailer: None,
+            branch: None,
+        }
+    }
+}
+
+/// The fingerprint of the host key `error` rejected, if it was one,
+/// remembering an unknown key so `accept_host_key` can trust it.
+fn remember_host_key(state: &AppState, error: &StorageError) -> Option<String> {
+    let StorageError::HostKey(host_key_error) = error else {
+        return None;
+    };
+    let offered = host_key_error.offered();
+    if let (HostKeyError::Unknown(_), Ok(mut pending)) =
+        (host_key_error, state.pending_host_keys.lock())
+    {
+        pending.insert(offered.host.clone(), offered.clone());
+    }
+    Some(offered.fingerprint.clone())
+}
+
+fn app_known_hosts_file(app: &AppHandle) -> Option<PathBuf> {
+    app.path()
+        .app_data_dir()
+        .ok()
+        .map(|dir| dir.join(host_keys::APP_KNOWN_HOSTS_FILE))
+}
+
+/// The config `request` describes, with stored secrets and `~/.ssh/config`
+/// settings filled in.
+async fn ec2_config(
+    app: &AppHandle,
+    mut request: Ec2ConnectRequest,
+) -> Result<Ec2Config, StorageError> {
+    let (use_stored_secret, profile_name) =
+        (request.use_stored_secret, request.profile_name.clone());
+    if let Some(secrets) =
+        blocking(move || requested_secret(use_stored_secret, profile_name.as_deref())).await??
+    {
+        request.set_secrets(secrets);
+    }
+    request.apply_ssh_config()?;
+    if request.host.is_empty() || request.username.is_empty() {
+        return Err(StorageError::InvalidInput(
+            "A host and username are required".to_string(),
+        ));
+    }
+    Ok(Ec2Config {
         host: request.host,
         username: request.username,
         pem_content: request.pem_content,
         port: request.port.unwrap_or(22),
-    });
+        password: request.password,
+        auth_method: request.auth_method,
+        key_passphrase: request.key_passphrase,
+        known_hosts_file: app_known_hosts_file(app),
+        keepalive_secs: request.keepalive_secs,
+        auto_reconnect: request.auto_reconnect,
+        remote_thumbnails: request.remote_thumbnails,
+        jump_host: request.jump_host,
+        initial_path: None,
+        generic_sftp: false,
+    })
+}
+
+#[tauri::command]
+pub async fn connect_ec2(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    request: Ec2ConnectRequest,
+    slot: Option<BackendSlot>,
+) -> Result<ConnectResponse, StorageError> {
+    let config = ec2_config(&app, request).await?;
+    connect_ssh(&app, &state, config, slot.unwrap_or_default()).await
+}
+
+/// Connects to any SFTP server, rooted at `config.initial_path` or else
+/// the directory the server logs in to.
+#[tauri::command]
+pub async fn connect_sftp(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    config: SftpConfig,
+    slot: Option<BackendSlot>,
+) -> Result<ConnectResponse, StorageError> {
+    if config.host.is_empty() || config.username.is_empty() {
+        return Err(StorageError::InvalidInput(
+            "A host and username are required".to_string(),
+        ));
+    }
+    let config = config.into_ec2_config(app_known_hosts_file(&app));
+    connect_ssh(&app, &state, config, slot.unwrap_or_default()).await
+}
 
-    match storage.connect() {
+async fn connect_ssh(
+    app: &AppHandle,
+    state: &AppState,
+    config: Ec2Config,
+    slot: BackendSlot,
+) -> Result<ConnectResponse, StorageError> {
+    // Reject missing credentials before opening a TCP connection.
+    config.validate_credentials()?;
+    let mut storage = Ec2Storage::new(config);
+    storage.set_connection_lost_handler(connection_lost_handler(app, slot));
+
+    let (storage, connected) = blocking(move || {
+        let result = storage.connect();
+        (storage, result)
+    })
+    .await?;
+    let storage_type = storage.storage_type();
+    let name = match storage_type {
+        StorageType::Sftp => "SFTP",
+        _ => "EC2",
+    };
+    match connected {
         Ok(()) => {
-            let root_path = storage.get_root_path();
-            let mut conn = state.storage.lock().map_err(|e| e.to_string())?;
-            *conn = Some(StorageBackend::Ec2(storage));
+            let remote_thumbnailer = storage.remote_thumbnailer();
+            let root_path = storage
+                .path_translator()
+                .from_absolute(&storage.get_root_path())
+                .unwrap_or_else(RemotePath::root)
+                .to_string();
+            state.set_backend(slot, Some(StorageBackend::Ec2(storage)))?;
+            connection_events::emit_connected(app, slot, storage_type, &root_path);
             Ok(ConnectResponse {
                 success: true,
-                message: "Connected to EC2 successfully".to_string(),
-                storage_type: Some("ec2".to_string()),
+                message: format!("Connected to {} successfully", name),
+                storage_type: Some(storage_type.to_string()),
                 root_path: Some(root_path),
+                error_code: None,
+                host_key_fingerprint: None,
+                remote_thumbnailer,
+                branch: None,
             })
         }
-        Err(e) => Ok(ConnectResponse {
-            success: false,
-            message: format!("EC2 connection failed: {}", e),
-            storage_type: None,
-            root_path: None,
-        }),
+        Err(e) => Ok(ConnectResponse::failed(
+            state,
+            &format!("{} connection failed", name),
+            e,
+        )),
     }
 }
 
-#[tauri::command]
-pub async fn connect_github(
-    state: State<'_, AppState>,
-    request: GitHubConnectRequest,
-) -> Result<ConnectResponse, String> {
-    let mut storage = GitHubStorage::new(crate::github::GitHubConfig {
+/// The config `request` describes, with stored secrets filled in.
+async fn github_config(
+    app: &AppHandle,
+    mut request: GitHubConnectRequest,
+) -> Result<GitHubConfig, StorageError> {
+    let (use_stored_secret, profile_name) =
+        (request.use_stored_secret, request.profile_name.clone());
+    if let Some(secrets) =
+        blocking(move || requested_secret(use_stored_secret, profile_name.as_deref())).await??
+    {
+        request.ssh_key_content = secrets.key_content;
+        request.key_passphrase = secrets.key_passphrase;
+    }
+    Ok(GitHubConfig {
         repo_url: request.repo_url,
         username: request.username,
         ssh_key_content: request.ssh_key_content,
-        branch: request.branch.unwrap_or_else(|| "main".to_string()),
+        branch: request.branch.unwrap_or_default(),
         local_path: request.local_path.unwrap_or_else(|| "/tmp/image-repo".to_string()),
-    });
+        key_passphrase: request.key_passphrase,
+        known_hosts_file: app_known_hosts_file(app),
+        keepalive_secs: request.keepalive_secs,
+        auto_reconnect: request.auto_reconnect,
+        remote_thumbnails: request.remote_thumbnails,
+        author_name: request.author_name,
+        author_email: request.author_email,
+        clone_depth: request.clone_depth,
+        partial_clone: request.partial_clone,
+        sparse_paths: request.sparse_paths,
+        api_token: request.api_token,
+        min_free_space_mb: request.min_free_space_mb,
+        skip_disk_check: request.force,
+        manual_commits: request.manual_commits,
+        manage_lfs_tracking: request.manage_lfs_tracking,
+        lfs_patterns: request.lfs_patterns,
+        replace_existing: request.replace_existing,
+        remove_clone_on_disconnect: request.remove_clone_on_disconnect,
+    })
+}
+
+#[tauri::command]
+pub async fn connect_github(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    request: GitHubConnectRequest,
+    slot: Option<BackendSlot>,
+) -> Result<ConnectResponse, StorageError> {
+    let mut storage = GitHubStorage::new(github_config(&app, request).await?);
+    let slot = slot.unwrap_or_default();
+    storage.set_connection_lost_handler(connection_lost_handler(&app, slot));
+    let events = app.clone();
+    storage.set_progress_handler(Arc::new(move |operation, progress| {
+        let _ = events.emit(operation.event(), progress);
+    }));
+
+    let (storage, connected) = blocking(move || {
+        let result = storage.connect();
+        (storage, result)
+    })
+    .await?;
+    match connected {
+        Ok(()) => {
+            let remote_thumbnailer = storage.remote_thumbnailer();
+            let branch = storage.config().branch;
+            let root_path = storage
+                .path_translator()
+                .to_remote(&storage.get_root_path())
+                .to_string();
+            state.set_backend(slot, Some(StorageBackend::GitHub(storage)))?;
+            connection_events::emit_connected(&app, slot, StorageType::GitHub, &root_path);
+            Ok(ConnectResponse {
+                success: true,
+                message: "Connected to git repository successfully".to_string(),
+                storage_type: Some("github".to_string()),
+                root_path: Some(root_path),
+                error_code: None,
+                host_key_fingerprint: None,
+                remote_thumbnailer,
+                branch: Some(branch),
+            })
+        }
+        Err(e) => Ok(ConnectResponse::failed(&state, "Git connection failed", e)),
+    }
+}
+
+/// Opens the directory `request.base_path` on this machine as storage.
+#[tauri::command]
+pub async fn connect_local(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    request: LocalConnectRequest,
+    slot: Option<BackendSlot>,
+) -> Result<ConnectResponse, StorageError> {
+    if request.base_path.is_empty() {
+        return Err(StorageError::InvalidInput(
+            "A base path is required".to_string(),
+        ));
+    }
+    let mut storage = LocalStorage::new(PathBuf::from(request.base_path));
+    let slot = slot.unwrap_or_default();
+
+    let (storage, connected) = blocking(move || {
+        let result = storage.connect();
+        (storage, result)
+    })
+    .await?;
+    match connected {
+        Ok(()) => {
+            let root_path = storage
+                .path_translator()
+                .from_absolute(&storage.get_root_path())
+                .unwrap_or_else(RemotePath::root)
+                .to_string();
+            state.set_backend(slot, Some(StorageBackend::Local(storage)))?;
+            connection_events::emit_connected(&app, slot, StorageType::Local, &root_path);
+            Ok(ConnectResponse {
+                success: true,
+                message: "Opened local directory successfully".to_string(),
+                storage_type: Some("local".to_string()),
+                root_path: Some(root_path),
+                error_code: None,
+                host_key_fingerprint: None,
+                remote_thumbnailer: None,
+                branch: None,
+            })
+        }
+        Err(e) => Ok(ConnectResponse::failed(
+            &state,
+            "Opening local directory failed",
+            e,
+        )),
+    }
+}
+
+/// Connects to the WebDAV collection at `config.url`.
+#[tauri::command]
+pub async fn connect_webdav(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    config: WebDavConfig,
+    slot: Option<BackendSlot>,
+) -> Result<ConnectResponse, StorageError> {
+    if config.url.is_empty() {
+        return Err(StorageError::InvalidInput(
+            "A WebDAV URL is required".to_string(),
+        ));
+    }
+    let mut storage = WebDavStorage::new(config);
+    let slot = slot.unwrap_or_default();
+
+    let (storage, connected) = blocking(move || {
+        let result = storage.connect();
+        (storage, result)
+    })
+    .await?;
+    match connected {
+        Ok(()) => {
+            let root_path = storage
+                .path_translator()
+                .from_absolute(&storage.get_root_path())
+                .unwrap_or_else(RemotePath::root)
+                .to_string();
+            state.set_backend(slot, Some(StorageBackend::WebDav(storage)))?;
+            connection_events::emit_connected(&app, slot, StorageType::WebDav, &root_path);
+            Ok(ConnectResponse {
+                success: true,
+                message: "Connected to WebDAV server successfully".to_string(),
+                storage_type: Some("webdav".to_string()),
+                root_path: Some(root_path),
+                error_code: None,
+                host_key_fingerprint: None,
+                remote_thumbnailer: None,
+                branch: None,
+            })
+        }
+        Err(e) => Ok(ConnectResponse::failed(
+            &state,
+            "WebDAV connection failed",
+            e,
+        )),
+    }
+}
+
+/// Connects read-only to `config.owner/config.repo` through the GitHub
+/// REST API, without the SSH host [`connect_github`] needs.
+#[tauri::command]
+pub async fn connect_github_api(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    config: GitHubApiConfig,
+    slot: Option<BackendSlot>,
+) -> Result<ConnectResponse, StorageError> {
+    let mut storage = GitHubApiStorage::new(config);
+    let slot = slot.unwrap_or_default();
 
-    match storage.connect() {
+    let (storage, connected) = blocking(move || {
+        let result = storage.connect();
+        (storage, result)
+    })
+    .await?;
+    match connected {
         Ok(()) => {
             let root_path = storage.get_root_path();
-            let mut conn = state.storage.lock().map_err(|e| e.to_string())?;
-            *conn = Some(StorageBackend::GitHub(storage));
+            state.set_backend(slot, Some(StorageBackend::GitHubApi(storage)))?;
+            connection_events::emit_connected(&app, slot, StorageType::GitHubApi, &root_path);
             Ok(ConnectResponse {
                 success: true,
                 message: "Connected to GitHub repository successfully".to_string(),
-                storage_type: Some("github".to_string()),
+                storage_type: Some("github_api".to_string()),
                 root_path: Some(root_path),
+                error_code: None,
+                host_key_fingerprint: None,
+                remote_thumbnailer: None,
+                branch: None,
             })
         }
-        Err(e) => Ok(ConnectResponse {
-            success: false,
-            message: format!("GitHub connection failed: {}", e),
-            storage_type: None,
-            root_path: None,
-        }),
+        Err(e) => Ok(ConnectResponse::failed(
+            &state,
+            "GitHub API connection failed",
+            e,
+        )),
     }
 }
 
+fn drive_tokens_file(app: &AppHandle) -> Result<PathBuf, StorageError> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| gdrive::tokens_path(&dir))
+        .context("Failed to locate app data directory")
+}
+
+/// Begins signing in to Google Drive with `client`, replacing a sign-in in
+/// progress. Returns the page to open in the browser; poll
+/// [`gdrive_auth_poll`] until the user is done there.
 #[tauri::command]
-pub async fn list_files(
+pub async fn gdrive_auth_start(
     state: State<'_, AppState>,
-    path: String,
-) -> Result<Vec<FileInfo>, String> {
-    let conn = state.storage.lock().map_err(|e| e.to_string())?;
+    client: OAuthClient,
+) -> Result<String, StorageError> {
+    let flow = DriveAuthFlow::start(client)?;
+    let url = flow.url.clone();
+    *state.drive_auth.lock()? = Some(Arc::new(flow));
+    Ok(url)
+}
 
-    match conn.as_ref() {
-        Some(backend) => backend
-            .storage()
-            .list_directory(&path)
-            .map_err(|e| format!("Failed to list directory: {}", e)),
-        None => Err("Not connected to any storage".to_string()),
+/// Whether the sign-in begun by [`gdrive_auth_start`] has finished, saving
+/// the account's token when it has.
+#[tauri::command]
+pub async fn gdrive_auth_poll(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<DriveAuthStatus, StorageError> {
+    let flow = state.drive_auth.lock()?.clone().ok_or_else(|| {
+        StorageError::InvalidInput("No Google Drive sign-in is in progress".to_string())
+    })?;
+    let tokens = drive_tokens_file(&app)?;
+    let outcome = blocking(move || {
+        let signed_in = flow.poll()?;
+        if let Some((account, token)) = &signed_in {
+            gdrive::save_token(&tokens, account, token)?;
+        }
+        Ok::<_, StorageError>(signed_in)
+    })
+    .await?;
+    if !matches!(outcome, Ok(None)) {
+        *state.drive_auth.lock()? = None;
     }
+    Ok(match outcome? {
+        None => DriveAuthStatus::Pending,
+        Some((account, _)) => DriveAuthStatus::Complete { account },
+    })
 }
 
+/// Connects read-only to Google Drive as `config.account`, who must have
+/// signed in with [`gdrive_auth_start`] before.
 #[tauri::command]
-pub async fn read_file(state: State<'_, AppState>, path: String) -> Result<String, String> {
-    let conn = state.storage.lock().map_err(|e| e.to_string())?;
+pub async fn connect_gdrive(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    config: DriveConfig,
+    slot: Option<BackendSlot>,
+) -> Result<ConnectResponse, StorageError> {
+    let mut storage = DriveStorage::new(config, drive_tokens_file(&app)?);
+    let slot = slot.unwrap_or_default();
 
-    match conn.as_ref() {
-        Some(backend) => backend
-            .storage()
-            .read_file(&path)
-            .map(|bytes| utils::base64_encode(&bytes))
-            .map_err(|e| format!("Failed to read file: {}", e)),
-        None => Err("Not connected to any storage".to_string()),
+    let (storage, connected) = blocking(move || {
+        let result = storage.connect();
+        (storage, result)
+    })
+    .await?;
+    match connected {
+        Ok(()) => {
+            let root_path = storage.get_root_path();
+            state.set_backend(slot, Some(StorageBackend::GoogleDrive(storage)))?;
+            connection_events::emit_connected(&app, slot, StorageType::GoogleDrive, &root_path);
+            Ok(ConnectResponse {
+                success: true,
+                message: "Connected to Google Drive successfully".to_string(),
+                storage_type: Some("gdrive".to_string()),
+                root_path: Some(root_path),
+                error_code: None,
+                host_key_fingerprint: None,
+                remote_thumbnailer: None,
+                branch: None,
+            })
+        }
+        Err(e) => Ok(ConnectResponse::failed(
+            &state,
+            "Google Drive connection failed",
+            e,
+        )),
     }
 }
 
+/// Connects read-only to the directory listing at `config.base_url`, as
+/// served by nginx's or Apache's autoindex.
 #[tauri::command]
-pub async fn get_file_thumbnail(
+pub async fn connect_http(
+    app: AppHandle,
     state: State<'_, AppState>,
-    path: String,
-    max_size: Option<u32>,
-) -> Result<String, String> {
-    let conn = state.storage.lock().map_err(|e| e.to_string())?;
-    let max = max_size.unwrap_or(200); // Default 200px
+    config: HttpIndexConfig,
+    slot: Option<BackendSlot>,
+) -> Result<ConnectResponse, StorageError> {
+    if config.base_url.is_empty() {
+        return Err(StorageError::InvalidInput("A URL is required".to_string()));
+    }
+    let mut storage = HttpIndexStorage::new(config);
+    let slot = slot.unwrap_or_default();
 
-    match conn.as_ref() {
-        Some(backend) => backend
-            .storage()
-            .get_file_thumbnail(&path, max)
-            .map_err(|e| format!("Failed to get thumbnail: {}", e)),
-        None => Err("Not connected to any storage".to_string()),
+    let (storage, connected) = blocking(move || {
+        let result = storage.connect();
+        (storage, result)
+    })
+    .await?;
+    match connected {
+        Ok(()) => {
+            let root_path = storage.get_root_path();
+            state.set_backend(slot, Some(StorageBackend::HttpIndex(storage)))?;
+            connection_events::emit_connected(&app, slot, StorageType::HttpIndex, &root_path);
+            Ok(ConnectResponse {
+                success: true,
+                message: "Connected to directory listing successfully".to_string(),
+                storage_type: Some("http".to_string()),
+                root_path: Some(root_path),
+                error_code: None,
+                host_key_fingerprint: None,
+                remote_thumbnailer: None,
+                branch: None,
+            })
+        }
+        Err(e) => Ok(ConnectResponse::failed(
+            &state,
+            "Fetching the directory listing failed",
+            e,
+        )),
+    }
+}
+
+/// Tests `request` by connecting and authenticating, then disconnecting.
+/// The current connections are left alone.
+#[tauri::command]
+pub async fn validate_ec2_connection(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    request: Ec2ConnectRequest,
+) -> Result<ValidationResult, StorageError> {
+    let storage = Ec2Storage::new(ec2_config(&app, request).await?);
+    let mut result = blocking(move || storage.validate()).await?;
+    if let Some(error) = &result.error {
+        result.host_key_fingerprint = remember_host_key(&state, error);
     }
+    Ok(result)
 }
 
+/// Tests `request` by connecting and checking with `git ls-remote` that the
+/// repository and branch are readable, without cloning. The current
+/// connections are left alone.
 #[tauri::command]
-pub async fn disconnect(state: State<'_, AppState>) -> Result<(), String> {
-    let mut conn = state.storage.lock().map_err(|e| e.to_string())?;
-    if let Some(mut backend) = conn.take() {
-        backend.storage_mut().disconnect();
+pub async fn validate_github_connection(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    request: GitHubConnectRequest,
+) -> Result<ValidationResult, StorageError> {
+    let storage = GitHubStorage::new(github_config(&app, request).await?);
+    let mut result = blocking(move || storage.validate()).await?;
+    if let Some(error) = &result.error {
+        result.host_key_fingerprint = remember_host_key(&state, error);
     }
+    Ok(result)
+}
+
+/// Host aliases from `~/.ssh/config` with their resolved settings.
+#[tauri::command]
+pub async fn load_ssh_config_hosts() -> Result<Vec<SshConfigHost>, StorageError> {
+    Ok(ssh_config::load_ssh_config_hosts())
+}
+
+/// Trusts the host key `host` offered on the last failed connect, provided
+/// it still has `fingerprint`, by adding it to the app's known_hosts.
+#[tauri::command]
+pub async fn accept_host_key(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    host: String,
+    fingerprint: String,
+) -> Result<(), StorageError> {
+    let mut pending = state.pending_host_keys.lock()?;
+    let offered = match pending.get(&host) {
+        Some(offered) if offered.fingerprint == fingerprint => offered,
+        Some(_) => {
+            return Err(StorageError::InvalidInput(format!(
+                "Host key for {} does not match {}",
+                host, fingerprint
+            )))
+        }
+        None => {
+            return Err(StorageError::InvalidInput(format!(
+                "No host key from {} is awaiting approval",
+                host
+            )))
+        }
+    };
+    let path = app_known_hosts_file(&app).ok_or(StorageError::Internal(
+        "Failed to locate app data directory".to_string(),
+    ))?;
+    host_keys::remember_host_key(&path, offered).context("Failed to save host key")?;
+    pending.remove(&host);
     Ok(())
 }
 
 #[tauri::command]
-pub async fn get_storage_type(state: State<'_, AppState>) -> Result<Option<String>, String> {
-    let conn = state.storage.lock().map_err(|e| e.to_string())?;
-    Ok(conn.as_ref().map(|b| b.storage().storage_type().to_string()))
+pub async fn list_files(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    use_stored_prefs: Option<bool>,
+    options: Option<ListOptions>,
+) -> Result<DirectoryPage, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+    let options = options.unwrap_or_default();
+
+    blocking(move || {
+        let state = app.state::<AppState>();
+        match conn.as_ref() {
+            Some(backend) => {
+                let (mut files, total) =
+                    with_reconnect(&app, &state, BackendSlot::Primary, backend, |storage| {
+                        if use_stored_prefs.unwrap_or(false) {
+                            // Stored prefs may re-sort, so page only after applying them.
+                            let everything = ListOptions {
+                                offset: 0,
+                                limit: usize::MAX,
+                                ..options
+                            };
+                            let (files, _) = storage.list_directory_page(&path, &everything)?;
+                            let files = view_prefs::load_view_prefs(storage, &path).apply(files);
+                            Ok((paginate(&files, options.offset, options.limit), files.len()))
+                        } else {
+                            storage.list_directory_page(&path, &options)
+                        }
+                    })
+                    .context("Failed to list directory")?;
+                if options.sniff {
+                    add_sniffed_mime_types(backend.storage(), &mut files);
+                }
+                if options.include_dimensions {
+                    add_dimensions(backend.storage(), &mut files);
+                }
+                Ok(DirectoryPage {
+                    files,
+                    total,
+                    offset: options.offset,
+                })
+            }
+            None => Err(StorageError::NotConnected),
+        }
+    })
+    .await?
+}
+
+/// Starts listing `path` in the background and returns the id of the
+/// stream. Entries arrive unsorted, in the order they are read, as
+/// `listing://batch` events of at most `batch_size` (default
+/// [`DEFAULT_LISTING_BATCH_SIZE`]); `listing://done` follows with the total.
+#[tauri::command]
+pub async fn list_files_streamed(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    batch_size: Option<usize>,
+    options: Option<ListOptions>,
+) -> Result<String, StorageError> {
+    static NEXT_STREAM: AtomicU64 = AtomicU64::new(0);
+
+    let Some(backend) = state.backend(BackendSlot::Primary)? else {
+        return Err(StorageError::NotConnected);
+    };
+    let options = options.unwrap_or_default();
+    let batch_size = batch_size.unwrap_or(DEFAULT_LISTING_BATCH_SIZE);
+    let stream_id = format!("listing-{}", NEXT_STREAM.fetch_add(1, Ordering::Relaxed));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .listings
+        .lock()?
+        .insert(stream_id.clone(), cancelled.clone());
+
+    let id = stream_id.clone();
+    std::thread::spawn(move || {
+        let mut total = 0;
+        let result =
+            backend
+                .storage()
+                .list_directory_streamed(&path, &options, batch_size, &mut |files| {
+                    if cancelled.load(Ordering::Relaxed) {
+                        return false;
+                    }
+                    total += files.len();
+                    let batch = ListingBatch {
+                        stream_id: id.clone(),
+                        files,
+                    };
+                    let _ = app.emit("listing://batch", batch);
+                    !cancelled.load(Ordering::Relaxed)
+                });
+        if let Ok(mut listings) = app.state::<AppState>().listings.lock() {
+            listings.remove(&id);
+        }
+        let done = ListingDone {
+            stream_id: id,
+            total,
+            cancelled: cancelled.load(Ordering::Relaxed),
+            error: result.err().map(|e| e.context("Failed to list directory")),
+        };
+        let _ = app.emit("listing://done", done);
+    });
+    Ok(stream_id)
+}
+
+/// Stops the command started with `operation_id`. Returns whether it was
+/// running.
+#[tauri::command]
+pub async fn cancel_operation(
+    state: State<'_, AppState>,
+    operation_id: String,
+) -> Result<bool, StorageError> {
+    let operations = state.operations.lock()?;
+    match operations.get(&operation_id) {
+        Some(cancelled) => {
+            cancelled.store(true, Ordering::Relaxed);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
 }
 
+/// Stops a `list_files_streamed` listing. Returns whether it was running.
 #[tauri::command]
-pub async fn is_connected(state: State<'_, AppState>) -> Result<bool, String> {
-    let conn = state.storage.lock().map_err(|e| e.to_string())?;
-    Ok(conn
-        .as_ref()
-        .map(|b| b.storage().is_connected())
-        .unwrap_or(false))
+pub async fn cancel_listing(
+    state: State<'_, AppState>,
+    stream_id: String,
+) -> Result<bool, StorageError> {
+    let listings = state.listings.lock()?;
+    match listings.get(&stream_id) {
+        Some(cancelled) => {
+            cancelled.store(true, Ordering::Relaxed);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Missing paths fail with the `NOT_FOUND` code so the UI can tell them
+/// apart from connection failures.
+#[tauri::command]
+pub async fn stat_file(state: State<'_, AppState>, path: String) -> Result<FileInfo, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend.storage().stat(&path).context("Failed to stat file"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Width, height, duration and codec of `path`, read from its header.
+/// Values that cannot be determined are null.
+#[tauri::command]
+pub async fn probe_media(
+    state: State<'_, AppState>,
+    path: String,
+) -> Result<MediaProbe, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .probe_media(&path)
+            .context("Failed to probe media"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Identifies the files in `files` without a known type from their first
+/// bytes, a few at a time.
+fn add_sniffed_mime_types(storage: &dyn Storage, files: &mut [FileInfo]) {
+    let unknown: Vec<usize> = (0..files.len())
+        .filter(|i| !files[*i].is_dir && files[*i].mime_type.is_none())
+        .collect();
+    let sniffed = Mutex::new(Vec::new());
+    thumbnails::for_each_parallel(&unknown, thumbnails::DEFAULT_THUMBNAIL_WORKERS, |i| {
+        if let Ok(Some(mime)) = sniff_file_mime_type(storage, &files[*i].path) {
+            if let Ok(mut sniffed) = sniffed.lock() {
+                sniffed.push((*i, mime));
+            }
+        }
+    });
+    for (i, mime) in sniffed.into_inner().unwrap_or_default() {
+        files[i].mime_type = Some(mime);
+    }
+}
+
+/// Records the dimensions of the images in `files`, probing a few at a
+/// time. Files that cannot be probed are left as they are.
+fn add_dimensions(storage: &dyn Storage, files: &mut [FileInfo]) {
+    let images: Vec<usize> = (0..files.len())
+        .filter(|i| {
+            let file = &files[*i];
+            !file.is_dir
+                && file
+                    .mime_type
+                    .as_deref()
+                    .is_some_and(|mime| mime.starts_with("image/"))
+        })
+        .collect();
+    let probes = Mutex::new(Vec::new());
+    thumbnails::for_each_parallel(&images, thumbnails::DEFAULT_THUMBNAIL_WORKERS, |i| {
+        if let Ok(probe) = storage.probe_media(&files[*i].path) {
+            if let Ok(mut probes) = probes.lock() {
+                probes.push((*i, probe));
+            }
+        }
+    });
+    for (i, probe) in probes.into_inner().unwrap_or_default() {
+        if let (Some(width), Some(height)) = (probe.width, probe.height) {
+            let extra = &mut files[i].extra;
+            extra.insert(media_probe::WIDTH_KEY.to_string(), width.to_string());
+            extra.insert(media_probe::HEIGHT_KEY.to_string(), height.to_string());
+        }
+    }
+}
+
+/// Applies an octal mode such as "644" to `path`.
+/// Groups byte-identical files below `root_path`, emitting
+/// `duplicates://progress` while hashing.
+#[tauri::command]
+pub async fn find_duplicates(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    root_path: String,
+    max_files: Option<usize>,
+) -> Result<DuplicateScan, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => duplicates::find_duplicates(
+            backend.storage(),
+            &root_path,
+            max_files.unwrap_or(MAX_RECURSIVE_ENTRIES),
+            &mut |progress| {
+                let _ = app.emit("duplicates://progress", progress);
+            },
+        )
+        .context("Failed to find duplicates"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Groups images below `root_path` whose perceptual hashes differ in at
+/// most `threshold` bits, emitting `similar://progress` while hashing.
+/// Hashes are cached per connection in the app cache directory.
+#[tauri::command]
+pub async fn find_similar_images(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    root_path: String,
+    threshold: Option<u32>,
+    max_files: Option<usize>,
+) -> Result<SimilarScan, StorageError> {
+    let cache_dir = app
+        .path()
+        .app_cache_dir()
+        .context("Failed to locate app cache directory")?;
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => {
+            let storage = backend.storage();
+            let cache_path =
+                similar::hash_cache_path(&cache_dir, &thumbnail_cache::storage_id(storage));
+            let mut cache = similar::load_hash_cache(&cache_path);
+            let scan = similar::find_similar(
+                storage,
+                &root_path,
+                threshold.unwrap_or(similar::DEFAULT_SIMILARITY_THRESHOLD),
+                max_files.unwrap_or(MAX_RECURSIVE_ENTRIES),
+                &mut cache,
+                &|progress| {
+                    let _ = app.emit("similar://progress", progress);
+                },
+            )
+            .context("Failed to find similar images")?;
+            let _ = similar::save_hash_cache(&cache_path, &cache);
+            Ok(scan)
+        }
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// The perceptual hash of the image at `path`, as 16 hex digits.
+#[tauri::command]
+pub async fn get_image_hash(
+    state: State<'_, AppState>,
+    path: String,
+) -> Result<String, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => similar::image_hash(backend.storage(), &path)
+            .map(|hash| format!("{:016x}", hash))
+            .context("Failed to hash image"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn set_permissions(
+    state: State<'_, AppState>,
+    path: String,
+    mode: String,
+) -> Result<FileInfo, StorageError> {
+    let mode = parse_mode(&mode)?;
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .set_permissions(&path, mode)
+            .context("Failed to set permissions"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn set_file_modified(
+    state: State<'_, AppState>,
+    path: String,
+    mtime: u64,
+) -> Result<FileInfo, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .set_modified(&path, mtime)
+            .context("Failed to set modified time"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Writes a copy of the image at `src_path` in `format` (such as "jpg" or
+/// "webp") to `dest_path`. `quality` applies to JPEG output.
+#[tauri::command]
+pub async fn convert_image(
+    app: AppHandle,
+    src_path: String,
+    dest_path: String,
+    format: String,
+    quality: Option<u8>,
+    overwrite: Option<bool>,
+    keep_exif: Option<bool>,
+) -> Result<FileInfo, StorageError> {
+    let output_format = image_edit::parse_format(&format)
+        .ok_or_else(|| StorageError::InvalidInput(format!("Unknown image format: {}", format)))?;
+    let conn = app.state::<AppState>().backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => {
+            let storage = backend.storage();
+            if !overwrite.unwrap_or(false)
+                && storage
+                    .exists(&dest_path)
+                    .context("Failed to convert image")?
+            {
+                return Err(StorageError::InvalidInput(format!(
+                    "Destination already exists: {}",
+                    dest_path
+                )));
+            }
+            let result = image_edit::convert_file(
+                storage,
+                &src_path,
+                &dest_path,
+                output_format,
+                quality.unwrap_or(image_edit::DEFAULT_CONVERT_QUALITY),
+                keep_exif.unwrap_or(false),
+            );
+            if let Some(cache) = app.state::<AppState>().thumbnail_cache.get() {
+                cache.invalidate(&thumbnail_cache::storage_id(storage), &dest_path);
+            }
+            result.context("Failed to convert image")
+        }
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Rotates or flips the image at `path` and writes it back, dropping its
+/// cached thumbnails.
+#[tauri::command]
+pub async fn transform_image(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    operation: ImageTransform,
+    options: Option<TransformOptions>,
+) -> Result<FileInfo, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => {
+            let storage = backend.storage();
+            let result =
+                image_edit::transform_file(storage, &path, operation, options.unwrap_or_default());
+            if let Some(cache) = app.state::<AppState>().thumbnail_cache.get() {
+                cache.invalidate(&thumbnail_cache::storage_id(storage), &path);
+            }
+            result.context("Failed to transform image")
+        }
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn get_directory_size(
+    state: State<'_, AppState>,
+    path: String,
+) -> Result<DirectoryUsage, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .directory_size(&path)
+            .context("Failed to measure directory"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Unpacks a remote archive into `destination` and returns its top-level
+/// entries. A missing extractor fails with the `TOOL_MISSING` code, e.g.
+/// "unzip is not installed on the remote host".
+#[tauri::command]
+pub async fn extract_archive(
+    state: State<'_, AppState>,
+    path: String,
+    destination: String,
+) -> Result<Vec<String>, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .extract_archive(&path, &destination)
+            .context("Failed to extract archive"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn get_file_checksum(
+    state: State<'_, AppState>,
+    path: String,
+    algorithm: Option<ChecksumAlgorithm>,
+) -> Result<FileChecksum, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .checksum(&path, algorithm.unwrap_or_default())
+            .context("Failed to compute checksum"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn delete_file(state: State<'_, AppState>, path: String) -> Result<(), StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .delete_file(&path)
+            .context("Failed to delete file"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn delete_directory(
+    state: State<'_, AppState>,
+    path: String,
+    recursive: Option<bool>,
+) -> Result<DeleteDirectoryResult, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .delete_directory(&path, recursive.unwrap_or(false))
+            .context("Failed to delete directory"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn move_to_trash(
+    state: State<'_, AppState>,
+    path: String,
+) -> Result<TrashEntry, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => {
+            trash::move_to_trash(backend.storage(), &path).context("Failed to move to trash")
+        }
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn list_trash(state: State<'_, AppState>) -> Result<Vec<TrashEntry>, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => trash::list_trash(backend.storage()).context("Failed to list trash"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn restore_from_trash(
+    state: State<'_, AppState>,
+    entry: String,
+) -> Result<FileInfo, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => trash::restore_from_trash(backend.storage(), &entry)
+            .context("Failed to restore from trash"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Permanently deletes trashed entries, only those older than
+/// `older_than_days` when given. Returns the number removed.
+#[tauri::command]
+pub async fn empty_trash(
+    state: State<'_, AppState>,
+    older_than_days: Option<u64>,
+) -> Result<usize, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => {
+            trash::empty_trash(backend.storage(), older_than_days).context("Failed to empty trash")
+        }
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn rename_file(
+    state: State<'_, AppState>,
+    from: String,
+    to: String,
+    overwrite: Option<bool>,
+) -> Result<FileInfo, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => {
+            let storage = backend.storage();
+            if !overwrite.unwrap_or(false)
+                && storage.exists(&to).context("Failed to rename file")?
+            {
+                return Err(StorageError::InvalidInput(format!(
+                    "Destination already exists: {}",
+                    to
+                )));
+            }
+            storage.rename(&from, &to).context("Failed to rename file")
+        }
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn copy_file(
+    state: State<'_, AppState>,
+    from: String,
+    to: String,
+    overwrite: Option<bool>,
+) -> Result<FileInfo, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => {
+            let storage = backend.storage();
+            if !overwrite.unwrap_or(false) && storage.exists(&to).context("Failed to copy file")? {
+                return Err(StorageError::InvalidInput(format!(
+                    "Destination already exists: {}",
+                    to
+                )));
+            }
+            storage.copy_file(&from, &to).context("Failed to copy file")
+        }
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn create_directory(
+    state: State<'_, AppState>,
+    path: String,
+    recursive: Option<bool>,
+) -> Result<CreateDirectoryResult, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .create_directory(&path, recursive.unwrap_or(false))
+            .context("Failed to create directory"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn find_deleted_files(
+    state: State<'_, AppState>,
+    path_prefix: String,
+    since: Option<u64>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<DeletedFilesPage, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => storage
+            .find_deleted_files(
+                &path_prefix,
+                since,
+                offset.unwrap_or(0),
+                limit.unwrap_or(100),
+            )
+            .context("Failed to scan deleted files"),
+        Some(_) => Err(StorageError::Unsupported(
+            "Deleted-file recovery is only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn recover_deleted_file(
+    state: State<'_, AppState>,
+    path: String,
+    commit: String,
+    destination: Option<String>,
+) -> Result<FileInfo, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+    let destination = destination.unwrap_or_else(|| path.clone());
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => {
+            storage
+                .recover_deleted_file(&path, &commit, &destination)
+                .context("Failed to recover file")?;
+            let size = storage
+                .read_file(&destination)
+                .map(|c| c.len() as u64)
+                .unwrap_or(0);
+            let destination = storage.path_translator().to_remote(&destination);
+            Ok(FileInfo::for_file(
+                destination.as_str(),
+                size,
+                Some(utils::unix_now()),
+            ))
+        }
+        Some(_) => Err(StorageError::Unsupported(
+            "Deleted-file recovery is only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn get_file_history(
+    state: State<'_, AppState>,
+    path: String,
+    limit: Option<usize>,
+) -> Result<Vec<FileRevision>, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => storage
+            .get_file_history(&path, limit.unwrap_or(100))
+            .context("Failed to read file history"),
+        Some(_) => Err(StorageError::Unsupported(
+            "File history is only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Returns the file as it was at `commit`, base64-encoded like `read_file`.
+#[tauri::command]
+pub async fn read_file_at_revision(
+    state: State<'_, AppState>,
+    path: String,
+    commit: String,
+) -> Result<String, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => storage
+            .read_file_at_revision(&path, &commit)
+            .map(|data| utils::base64_encode(&data))
+            .context("Failed to read file revision"),
+        Some(_) => Err(StorageError::Unsupported(
+            "File history is only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn restore_file_version(
+    state: State<'_, AppState>,
+    path: String,
+    commit: String,
+) -> Result<(), StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => storage
+            .restore_file_version(&path, &commit)
+            .context("Failed to restore file version"),
+        Some(_) => Err(StorageError::Unsupported(
+            "File history is only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Downloads the LFS content of `paths` so later reads are local.
+#[tauri::command]
+pub async fn prefetch_lfs(
+    state: State<'_, AppState>,
+    paths: Vec<String>,
+) -> Result<(), StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => {
+            let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+            storage
+                .prefetch_lfs(&paths)
+                .context("Failed to fetch LFS files")
+        }
+        Some(_) => Err(StorageError::Unsupported(
+            "LFS prefetching is only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Deletes the GitHub clone from the remote host and disconnects.
+#[tauri::command]
+pub async fn cleanup_remote_clone(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => storage
+            .cleanup_remote_clone()
+            .context("Failed to remove the clone"),
+        Some(_) => Err(StorageError::Unsupported(
+            "Removing the clone is only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await??;
+    disconnect_slot(&app, &state, BackendSlot::Primary)
+}
+
+/// Patterns the repository stores with Git LFS, from its `.gitattributes`.
+#[tauri::command]
+pub async fn get_lfs_tracking(state: State<'_, AppState>) -> Result<Vec<String>, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => storage
+            .get_lfs_tracking()
+            .context("Failed to read LFS tracking"),
+        Some(_) => Err(StorageError::Unsupported(
+            "LFS tracking is only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn git_status(state: State<'_, AppState>) -> Result<GitStatus, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => {
+            storage.git_status().context("Failed to read git status")
+        }
+        Some(_) => Err(StorageError::Unsupported(
+            "Git commands are only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Returns the new commit's hash.
+#[tauri::command]
+pub async fn git_commit(
+    state: State<'_, AppState>,
+    message: String,
+    paths: Option<Vec<String>>,
+) -> Result<String, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => {
+            let paths: Option<Vec<&str>> = paths
+                .as_ref()
+                .map(|p| p.iter().map(String::as_str).collect());
+            storage
+                .git_commit(&message, paths.as_deref())
+                .context("Failed to commit")
+        }
+        Some(_) => Err(StorageError::Unsupported(
+            "Git commands are only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn git_push(state: State<'_, AppState>) -> Result<(), StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => storage.git_push().context("Failed to push"),
+        Some(_) => Err(StorageError::Unsupported(
+            "Git commands are only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Fetches and fast-forwards the checked-out branch, dropping the cached
+/// thumbnails of changed files. Lists the changed files within `path`.
+#[tauri::command]
+pub async fn refresh_repository(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: Option<String>,
+) -> Result<RefreshSummary, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => {
+            let summary = storage
+                .refresh_repository()
+                .context("Failed to refresh the repository")?;
+            if let Some(cache) = app.state::<AppState>().thumbnail_cache.get() {
+                let storage_id = thumbnail_cache::storage_id(storage);
+                for changed in &summary.changed_paths {
+                    cache.invalidate(&storage_id, changed);
+                }
+            }
+            Ok(summary.within(&RemotePath::new(path.as_deref().unwrap_or("/"))))
+        }
+        Some(_) => Err(StorageError::Unsupported(
+            "Git commands are only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn set_sparse_paths(
+    state: State<'_, AppState>,
+    paths: Vec<String>,
+) -> Result<(), StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => storage
+            .set_sparse_paths(&paths)
+            .context("Failed to change the sparse checkout"),
+        Some(_) => Err(StorageError::Unsupported(
+            "Sparse checkouts are only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn list_branches(state: State<'_, AppState>) -> Result<Vec<Branch>, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => {
+            storage.list_branches().context("Failed to list branches")
+        }
+        Some(_) => Err(StorageError::Unsupported(
+            "Branches are only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn switch_branch(
+    state: State<'_, AppState>,
+    name: String,
+) -> Result<BranchSwitch, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(StorageBackend::GitHub(storage)) => storage
+            .switch_branch(&name)
+            .context("Failed to switch branch"),
+        Some(_) => Err(StorageError::Unsupported(
+            "Branches are only available for git storage".to_string(),
+        )),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// `cancel_operation(operation_id)` stops the listing.
+#[tauri::command]
+pub async fn list_files_recursive(
+    state: State<'_, AppState>,
+    path: String,
+    max_depth: Option<usize>,
+    operation_id: Option<String>,
+) -> Result<RecursiveListing, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+    let operation = Operation::start(&state, operation_id)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .list_directory_recursive(&path, max_depth.unwrap_or(0), operation.cancelled())
+            .context("Failed to list directory"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// `cancel_operation(operation_id)` stops the search.
+#[tauri::command]
+pub async fn search_files(
+    state: State<'_, AppState>,
+    query: String,
+    path: Option<String>,
+    case_sensitive: Option<bool>,
+    limit: Option<usize>,
+    operation_id: Option<String>,
+) -> Result<SearchResult, StorageError> {
+    if query.is_empty() {
+        return Err(StorageError::InvalidInput(
+            "Search query must not be empty".to_string(),
+        ));
+    }
+    let conn = state.backend(BackendSlot::Primary)?;
+    let operation = Operation::start(&state, operation_id)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .search(
+                path.as_deref().unwrap_or("/"),
+                &query,
+                case_sensitive.unwrap_or(false),
+                limit.unwrap_or(DEFAULT_SEARCH_LIMIT),
+                operation.cancelled(),
+            )
+            .context("Failed to search files"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Searches inside remote text files; `query` is a fixed string unless
+/// `regex` is set.
+#[tauri::command]
+pub async fn search_file_contents(
+    state: State<'_, AppState>,
+    root: String,
+    query: String,
+    regex: Option<bool>,
+    include: Option<String>,
+    limit: Option<usize>,
+) -> Result<ContentSearchResult, StorageError> {
+    if query.is_empty() {
+        return Err(StorageError::InvalidInput(
+            "Search query must not be empty".to_string(),
+        ));
+    }
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .search_contents(
+                &root,
+                &query,
+                regex.unwrap_or(false),
+                include.as_deref(),
+                limit.unwrap_or(DEFAULT_SEARCH_LIMIT),
+            )
+            .context("Failed to search file contents"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+fn bookmarks_file(app: &AppHandle) -> Result<PathBuf, StorageError> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| bookmarks::bookmarks_path(&dir))
+        .context("Failed to locate app data directory")
+}
+
+/// Bookmarks `path` on the current connection. The label defaults to the
+/// directory name.
+#[tauri::command]
+pub async fn add_bookmark(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    label: Option<String>,
+) -> Result<Bookmark, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+    let storage = conn.as_ref().ok_or(StorageError::NotConnected)?.storage();
+
+    let path = storage.path_translator().to_remote(&path);
+    let label = label
+        .filter(|l| !l.trim().is_empty())
+        .unwrap_or_else(|| path.file_name().unwrap_or("/").to_string());
+    let file = bookmarks_file(&app)?;
+    let mut store = bookmarks::load_bookmarks(&file);
+    let bookmark = store.add(
+        &bookmarks::connection_key(storage),
+        path.as_str(),
+        &label,
+        utils::unix_now(),
+    );
+    bookmarks::save_bookmarks(&file, &store).context("Failed to save bookmarks")?;
+    Ok(bookmark)
+}
+
+#[tauri::command]
+pub async fn remove_bookmark(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    id: u64,
+) -> Result<(), StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+    let storage = conn.as_ref().ok_or(StorageError::NotConnected)?.storage();
+
+    let file = bookmarks_file(&app)?;
+    let mut store = bookmarks::load_bookmarks(&file);
+    if !store.remove(&bookmarks::connection_key(storage), id) {
+        return Err(StorageError::InvalidInput(format!(
+            "Bookmark not found: {}",
+            id
+        )));
+    }
+    bookmarks::save_bookmarks(&file, &store).context("Failed to save bookmarks")
+}
+
+/// Lists the current connection's bookmarks, flagging those whose path no
+/// longer exists as stale instead of dropping them.
+#[tauri::command]
+pub async fn list_bookmarks(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<Vec<Bookmark>, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+    let backend = conn.ok_or(StorageError::NotConnected)?;
+
+    let file = bookmarks_file(&app)?;
+    blocking(move || {
+        let storage = backend.storage();
+        let mut store = bookmarks::load_bookmarks(&file);
+        let key = bookmarks::connection_key(storage);
+        let mut changed = false;
+        for bookmark in store.list_mut(&key) {
+            // Only a definite "not found" marks a bookmark stale.
+            let stale = matches!(storage.stat(&bookmark.path), Err(StorageError::NotFound(_)));
+            changed |= stale != bookmark.stale;
+            bookmark.stale = stale;
+        }
+        if changed {
+            bookmarks::save_bookmarks(&file, &store).context("Failed to save bookmarks")?;
+        }
+        Ok(store.list(&key).to_vec())
+    })
+    .await?
+}
+
+fn profiles_file(app: &AppHandle) -> Result<PathBuf, StorageError> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| profiles::profiles_path(&dir))
+        .context("Failed to locate app config directory")
+}
+
+/// Saves a connection profile of `storage_type` ("ec2" or "github"). Key
+/// material and passwords in `config` are not stored.
+#[tauri::command]
+pub async fn save_profile(
+    app: AppHandle,
+    name: String,
+    storage_type: String,
+    config: serde_json::Value,
+    overwrite: Option<bool>,
+) -> Result<Profile, StorageError> {
+    let config = ProfileConfig::parse(&storage_type, config)?;
+    let file = profiles_file(&app)?;
+    let mut store = profiles::load_profiles(&file)?;
+    let profile = store.save(&name, config, overwrite.unwrap_or(false))?;
+    profiles::save_profiles(&file, &store).context("Failed to save profiles")?;
+    Ok(profile)
+}
+
+#[tauri::command]
+pub async fn list_profiles(app: AppHandle) -> Result<Vec<Profile>, StorageError> {
+    Ok(profiles::load_profiles(&profiles_file(&app)?)?.list())
+}
+
+/// Returns whether the profile existed. Its stored secret, if any, goes
+/// with it.
+#[tauri::command]
+pub async fn delete_profile(app: AppHandle, name: String) -> Result<bool, StorageError> {
+    let file = profiles_file(&app)?;
+    let mut store = profiles::load_profiles(&file)?;
+    if !store.remove(&name) {
+        return Ok(false);
+    }
+    // Without a keyring there is nothing to delete.
+    let _ = secrets::delete_secret(&name);
+    profiles::save_profiles(&file, &store).context("Failed to save profiles")?;
+    Ok(true)
+}
+
+/// Connects with the saved profile `name`, using `secrets` for the
+/// credentials the profile leaves out, or those stored in the keyring when
+/// `secrets` is omitted.
+#[tauri::command]
+pub async fn connect_profile(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    name: String,
+    secrets: Option<ProfileSecrets>,
+    slot: Option<BackendSlot>,
+) -> Result<ConnectResponse, StorageError> {
+    let store = profiles::load_profiles(&profiles_file(&app)?)?;
+    let profile = store
+        .get(&name)
+        .ok_or_else(|| StorageError::NotFound(format!("Profile {}", name)))?;
+    let use_stored_secret = secrets.is_none();
+    let secrets = secrets.unwrap_or_default();
+    match profile.config {
+        ProfileConfig::Ec2(config) => {
+            let mut request = config.connect_request(secrets);
+            request.use_stored_secret = use_stored_secret;
+            request.profile_name = Some(profile.name);
+            connect_ec2(app, state, request, slot).await
+        }
+        ProfileConfig::GitHub(config) => {
+            let mut request = config.connect_request(secrets);
+            request.use_stored_secret = use_stored_secret;
+            request.profile_name = Some(profile.name);
+            connect_github(app, state, request, slot).await
+        }
+    }
+}
+
+/// Puts `secret` in the OS keyring for `profile_name`, replacing any
+/// stored before.
+#[tauri::command]
+pub async fn store_secret(
+    profile_name: String,
+    secret: ProfileSecrets,
+) -> Result<(), StorageError> {
+    blocking(move || secrets::store_secret(&profile_name, &secret)).await?
+}
+
+/// Returns whether a secret was stored for `profile_name`.
+#[tauri::command]
+pub async fn delete_secret(profile_name: String) -> Result<bool, StorageError> {
+    blocking(move || secrets::delete_secret(&profile_name)).await?
+}
+
+#[tauri::command]
+pub async fn get_view_prefs(
+    state: State<'_, AppState>,
+    dir: String,
+) -> Result<ViewPrefs, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => Ok(view_prefs::load_view_prefs(backend.storage(), &dir)),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn set_view_prefs(
+    state: State<'_, AppState>,
+    dir: String,
+    prefs: ViewPrefs,
+) -> Result<(), StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => view_prefs::save_view_prefs(backend.storage(), &dir, &prefs)
+            .context("Failed to save view preferences"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Puts `path` at the front of the recent files and persists the history.
+/// Failing to save it never fails the read that triggered it.
+fn record_recent_file(app: &AppHandle, state: &AppState, storage: &dyn Storage, path: &str) {
+    let path = storage.path_translator().to_remote(path);
+    let Ok(mut recents) = state.recent_files.lock() else {
+        return;
+    };
+    recents.record(RecentFile {
+        storage_type: storage.storage_type(),
+        connection_id: storage.connection_id(),
+        mime_type: path.file_name().and_then(detect_mime_type),
+        path: path.to_string(),
+        opened_at: utils::unix_now(),
+    });
+    if let Ok(dir) = app.path().app_data_dir() {
+        let _ = recents::save_recent_files(&recents::recents_path(&dir), &recents);
+    }
+}
+
+/// Payload of `read://progress`. The first event carries the file size
+/// when the backend knows it.
+#[derive(Debug, Serialize, Clone)]
+pub struct ReadProgress {
+    pub path: String,
+    pub bytes_done: u64,
+    pub bytes_total: Option<u64>,
+}
+
+/// Streams `path` into memory, emitting `read://progress` and refusing
+/// files larger than `max_bytes` before buffering them.
+fn read_into_memory(
+    app: &AppHandle,
+    storage: &dyn Storage,
+    path: &str,
+    max_bytes: u64,
+    cancelled: &AtomicBool,
+) -> Result<Vec<u8>, StorageError> {
+    let mut data = Vec::new();
+    let mut last_reported = None;
+    let mut too_large = None;
+    let mut known_total = None;
+    let emit = |bytes_done: u64, bytes_total: Option<u64>| {
+        let _ = app.emit(
+            "read://progress",
+            ReadProgress {
+                path: path.to_string(),
+                bytes_done,
+                bytes_total,
+            },
+        );
+    };
+    let completed = storage.read_file_streamed(path, &mut |chunk, bytes_total| {
+        if cancelled.load(Ordering::Relaxed) {
+            return false;
+        }
+        let bytes_done = (data.len() + chunk.len()) as u64;
+        if let Err(e) = FileTooLargeError::check(bytes_done, bytes_total, max_bytes) {
+            too_large = Some(e);
+            return false;
+        }
+        if last_reported.is_none() {
+            data.reserve(bytes_total.unwrap_or(0) as usize);
+        }
+        data.extend_from_slice(chunk);
+        known_total = bytes_total;
+        let due = match last_reported {
+            None => true,
+            Some(last) => bytes_done - last >= PROGRESS_INTERVAL_BYTES,
+        };
+        if due || Some(bytes_done) == bytes_total {
+            last_reported = Some(bytes_done);
+            emit(bytes_done, bytes_total);
+        }
+        true
+    })?;
+    if let Some(e) = too_large {
+        return Err(e.into());
+    }
+    if !completed {
+        return Err(StorageError::Cancelled);
+    }
+    let bytes_done = data.len() as u64;
+    if last_reported != Some(bytes_done) {
+        emit(bytes_done, known_total);
+    }
+    Ok(data)
+}
+
+/// Reads a whole file as base64, from the prefetch cache when it holds the
+/// current version. Files over `max_bytes` (default
+/// [`DEFAULT_MAX_IN_MEMORY_READ`]) are refused; use `download_file` instead.
+/// `cancel_operation(operation_id)` stops the read.
+#[tauri::command]
+pub async fn read_file(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    max_bytes: Option<u64>,
+    operation_id: Option<String>,
+) -> Result<String, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+    let max_bytes = max_bytes.unwrap_or(DEFAULT_MAX_IN_MEMORY_READ);
+    let operation = Operation::start(&state, operation_id)?;
+
+    blocking(move || {
+        let state = app.state::<AppState>();
+        match conn.as_ref() {
+            Some(backend) => {
+                let storage = backend.storage();
+                let prefetched = state
+                    .prefetch_cache
+                    .get(&thumbnail_cache::storage_id(storage), &path, || {
+                        storage.stat(&path).ok()
+                    })
+                    .filter(|data| data.len() as u64 <= max_bytes);
+                let encoded = match prefetched {
+                    Some(data) => utils::base64_encode(&data),
+                    None => {
+                        let bytes = with_reconnect(
+                            &app,
+                            &state,
+                            BackendSlot::Primary,
+                            backend,
+                            |storage| {
+                                read_into_memory(
+                                    &app,
+                                    storage,
+                                    &path,
+                                    max_bytes,
+                                    operation.cancelled(),
+                                )
+                            },
+                        )
+                        .context("Failed to read file")?;
+                        utils::base64_encode(&bytes)
+                    }
+                };
+                record_recent_file(&app, &state, storage, &path);
+                Ok(encoded)
+            }
+            None => Err(StorageError::NotConnected),
+        }
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn get_recent_files(
+    state: State<'_, AppState>,
+    limit: Option<usize>,
+) -> Result<Vec<RecentFile>, StorageError> {
+    let recents = state.recent_files.lock()?;
+    Ok(recents.list(limit.unwrap_or(MAX_RECENT_FILES)))
+}
+
+#[tauri::command]
+pub async fn clear_recent_files(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), StorageError> {
+    let mut recents = state.recent_files.lock()?;
+    recents.clear();
+    let dir = app
+        .path()
+        .app_data_dir()
+        .context("Failed to locate app data directory")?;
+    recents::save_recent_files(&recents::recents_path(&dir), &recents)
+        .context("Failed to save recent files")
+}
+
+/// Reads `length` bytes at `offset`; lengths over
+/// [`DEFAULT_MAX_IN_MEMORY_READ`] are refused.
+#[tauri::command]
+pub async fn read_file_range(
+    state: State<'_, AppState>,
+    path: String,
+    offset: u64,
+    length: u64,
+) -> Result<FileRange, StorageError> {
+    if length > DEFAULT_MAX_IN_MEMORY_READ {
+        return Err(StorageError::InvalidInput(format!(
+            "Range of {} bytes exceeds the limit of {} bytes",
+            length, DEFAULT_MAX_IN_MEMORY_READ
+        )));
+    }
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => backend
+            .storage()
+            .read_file_range(&path, offset, length)
+            .map(|(bytes, eof)| FileRange {
+                data: utils::base64_encode(&bytes),
+                offset,
+                eof,
+            })
+            .context("Failed to read file"),
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn read_files(
+    state: State<'_, AppState>,
+    paths: Vec<String>,
+) -> Result<Vec<FileReadResult>, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => {
+            let results = backend
+                .storage()
+                .read_files(&paths)
+                .context("Failed to read files")?;
+            Ok(paths
+                .into_iter()
+                .zip(results)
+                .map(|(path, result)| match result {
+                    Ok(bytes) => FileReadResult {
+                        path,
+                        data: Some(utils::base64_encode(&bytes)),
+                        error: None,
+                    },
+                    Err(e) => FileReadResult {
+                        path,
+                        data: None,
+                        error: Some(e),
+                    },
+                })
+                .collect())
+        }
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn upload_file(
+    state: State<'_, AppState>,
+    path: String,
+    content: String,
+) -> Result<FileInfo, StorageError> {
+    let data = utils::base64_decode(&content)
+        .map_err(|e| StorageError::InvalidInput(format!("Invalid file content: {}", e)))?;
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => {
+            backend
+                .storage()
+                .write_file(&path, &data)
+                .context("Failed to upload file")?;
+            let path = backend.storage().path_translator().to_remote(&path);
+            Ok(FileInfo::for_file(
+                path.as_str(),
+                data.len() as u64,
+                Some(utils::unix_now()),
+            ))
+        }
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Mirrors a local directory under `remote_path`, one file at a time.
+/// Individual failures are collected in the summary instead of aborting.
+#[tauri::command]
+pub async fn upload_directory(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    local_path: String,
+    remote_path: String,
+    include: Option<String>,
+    follow_symlinks: Option<bool>,
+) -> Result<UploadSummary, StorageError> {
+    let include = include
+        .map(|pattern| glob::Pattern::new(&pattern))
+        .transpose()
+        .map_err(|e| StorageError::InvalidInput(format!("Invalid include pattern: {}", e)))?;
+    let root = PathBuf::from(&local_path);
+    if !root.is_dir() {
+        return Err(StorageError::InvalidInput(format!(
+            "Not a directory: {}",
+            local_path
+        )));
+    }
+    let remote_root = RemotePath::new(&remote_path);
+    let conn = state.backend(BackendSlot::Primary)?;
+    let backend = conn.ok_or(StorageError::NotConnected)?;
+
+    blocking(move || {
+        let storage = backend.storage();
+        let tree =
+            local_tree::scan_local_tree(&root, include.as_ref(), follow_symlinks.unwrap_or(false));
+
+        let mut summary = UploadSummary::default();
+        for (path, error) in tree.errors {
+            summary.failed.push(UploadFailure { path, error });
+        }
+
+        storage
+            .create_directory(remote_root.as_str(), true)
+            .context("Failed to create directory")?;
+        for dir in &tree.dirs {
+            // With a filter, only create directories that will receive files.
+            let prefix = format!("{}/", dir);
+            if include.is_some() && !tree.files.iter().any(|f| f.starts_with(&prefix)) {
+                continue;
+            }
+            if let Err(e) = storage.create_directory(remote_root.join(dir).as_str(), true) {
+                summary.failed.push(UploadFailure {
+                    path: remote_root.join(dir).to_string(),
+                    error: e.to_string(),
+                });
+            }
+        }
+
+        let total = tree.files.len();
+        for (index, file) in tree.files.iter().enumerate() {
+            let _ = app.emit(
+                "upload://progress",
+                UploadProgress {
+                    current_file: file.clone(),
+                    index: index + 1,
+                    total,
+                },
+            );
+            let destination = remote_root.join(file).to_string();
+            let result = std::fs::read(root.join(file))
+                .map_err(|e| e.to_string())
+                .and_then(|data| {
+                    storage
+                        .write_file(&destination, &data)
+                        .map_err(|e| e.to_string())
+                });
+            match result {
+                Ok(()) => summary.succeeded.push(destination),
+                Err(error) => summary.failed.push(UploadFailure {
+                    path: destination,
+                    error,
+                }),
+            }
+        }
+
+        Ok(summary)
+    })
+    .await?
+}
+
+/// Copies a file from one connected backend to the other inside the app,
+/// so the bytes never pass through the webview. Files over
+/// [`DEFAULT_MAX_IN_MEMORY_READ`] are refused.
+#[tauri::command]
+pub async fn transfer_file(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    transfer_id: String,
+    source_path: String,
+    dest_backend: BackendSlot,
+    dest_path: String,
+    dry_run: Option<bool>,
+) -> Result<TransferResult, StorageError> {
+    let primary = state.backend(BackendSlot::Primary)?;
+    let target = state.backend(BackendSlot::Target)?;
+    let (source, destination) = match dest_backend {
+        BackendSlot::Target => (primary, target),
+        BackendSlot::Primary => (target, primary),
+    };
+    let source = source.ok_or(StorageError::NotConnected)?;
+    let destination = destination.ok_or(StorageError::NotConnected)?;
+
+    blocking(move || {
+        let (source, destination) = (source.storage(), destination.storage());
+        let info = source
+            .stat(&source_path)
+            .context("Failed to transfer file")?;
+        if info.is_dir {
+            return Err(StorageError::InvalidInput(
+                "Failed to transfer file: is a directory".to_string(),
+            ));
+        }
+        // Backends only accept whole-file writes, so the file is collected
+        // in memory and has to fit there.
+        FileTooLargeError::check(0, Some(info.size), DEFAULT_MAX_IN_MEMORY_READ)
+            .map_err(StorageError::from)
+            .context("Failed to transfer file")?;
+        if dry_run.unwrap_or(false) {
+            return Ok(TransferResult {
+                bytes: info.size,
+                dry_run: true,
+            });
+        }
+
+        // Collected here rather than in the frontend.
+        let mut data = Vec::new();
+        let mut last_reported = 0u64;
+        let mut too_large = None;
+        source
+            .read_file_streamed(&source_path, &mut |chunk, bytes_total| {
+                let bytes_read = (data.len() + chunk.len()) as u64;
+                if let Err(e) =
+                    FileTooLargeError::check(bytes_read, bytes_total, DEFAULT_MAX_IN_MEMORY_READ)
+                {
+                    too_large = Some(e);
+                    return false;
+                }
+                data.extend_from_slice(chunk);
+                let bytes_done = data.len() as u64;
+                if bytes_done - last_reported >= PROGRESS_INTERVAL_BYTES
+                    || Some(bytes_done) == bytes_total
+                {
+                    last_reported = bytes_done;
+                    let _ = app.emit(
+                        "transfer://progress",
+                        TransferProgress {
+                            transfer_id: transfer_id.clone(),
+                            bytes_done,
+                            bytes_total,
+                        },
+                    );
+                }
+                true
+            })
+            .context("Failed to transfer file")?;
+        if let Some(e) = too_large {
+            return Err(StorageError::from(e).context("Failed to transfer file"));
+        }
+
+        destination
+            .write_file(&dest_path, &data)
+            .context("Failed to transfer file")?;
+        Ok(TransferResult {
+            bytes: data.len() as u64,
+            dry_run: false,
+        })
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn download_file(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    download_id: String,
+    remote_path: String,
+    local_path: String,
+) -> Result<u64, StorageError> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .downloads
+        .lock()?
+        .insert(download_id.clone(), cancelled.clone());
+
+    let result = {
+        let download_id = download_id.clone();
+        blocking(move || {
+            write_local_file(&local_path, |file| {
+                stream_to_local_file(
+                    &app,
+                    &download_id,
+                    &remote_path,
+                    &local_path,
+                    file,
+                    &cancelled,
+                )
+            })
+        })
+        .await
+        .and_then(|result| result)
+    };
+
+    if let Ok(mut downloads) = state.downloads.lock() {
+        downloads.remove(&download_id);
+    }
+    result
+}
+
+/// Runs `write` against a temporary file next to `local_path` and moves it
+/// into place once it succeeds, so a failed or cancelled download never
+/// truncates or deletes a file the user already has.
+fn write_local_file(
+    local_path: &str,
+    write: impl FnOnce(&mut std::fs::File) -> Result<u64, StorageError>,
+) -> Result<u64, StorageError> {
+    static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+    let target = std::path::Path::new(local_path);
+    let name = target
+        .file_name()
+        .ok_or_else(|| StorageError::InvalidInput(format!("Not a file path: {}", local_path)))?;
+    let temp = target.with_file_name(format!(
+        ".{}.{}.{}.part",
+        name.to_string_lossy(),
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file =
+        std::fs::File::create(&temp).with_context(|| format!("Failed to create {}", local_path))?;
+    let written = write(&mut file).and_then(|bytes| {
+        drop(file);
+        std::fs::rename(&temp, target)
+            .with_context(|| format!("Failed to write {}", local_path))?;
+        Ok(bytes)
+    });
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    written
+}
+
+fn stream_to_local_file(
+    app: &AppHandle,
+    download_id: &str,
+    remote_path: &str,
+    local_path: &str,
+    file: &mut std::fs::File,
+    cancelled: &AtomicBool,
+) -> Result<u64, StorageError> {
+    let conn = app.state::<AppState>().backend(BackendSlot::Primary)?;
+    let backend = conn.as_ref().ok_or(StorageError::NotConnected)?;
+
+    let mut bytes_done = 0u64;
+    let mut last_reported = 0u64;
+    let mut write_error = None;
+    let completed = backend
+        .storage()
+        .read_file_streamed(remote_path, &mut |chunk, bytes_total| {
+            if cancelled.load(Ordering::Relaxed) {
+                return false;
+            }
+            if let Err(e) = file.write_all(chunk) {
+                write_error = Some(e);
+                return false;
+            }
+            bytes_done += chunk.len() as u64;
+            // The first chunk is always reported so the UI learns the size.
+            if last_reported == 0
+                || bytes_done - last_reported >= PROGRESS_INTERVAL_BYTES
+                || Some(bytes_done) == bytes_total
+            {
+                last_reported = bytes_done;
+                let _ = app.emit(
+                    "download://progress",
+                    DownloadProgress {
+                        download_id: download_id.to_string(),
+                        bytes_done,
+                        bytes_total,
+                    },
+                );
+            }
+            true
+        })
+        .context("Failed to download file")?;
+
+    if let Some(e) = write_error {
+        return Err(e).with_context(|| format!("Failed to write {}", local_path));
+    }
+    if !completed {
+        return Err(StorageError::Cancelled);
+    }
+    file.flush()
+        .with_context(|| format!("Failed to write {}", local_path))?;
+    Ok(bytes_done)
+}
+
+/// Downloads a remote directory as a single archive. Progress and
+/// cancellation share the `download_file` machinery; a zip is built on the
+/// client when the remote host has no `zip` binary.
+#[tauri::command]
+pub async fn download_directory_as_zip(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    download_id: String,
+    remote_path: String,
+    local_zip_path: String,
+    format: Option<ArchiveFormat>,
+) -> Result<u64, StorageError> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .downloads
+        .lock()?
+        .insert(download_id.clone(), cancelled.clone());
+
+    let result = {
+        let download_id = download_id.clone();
+        blocking(move || {
+            write_local_file(&local_zip_path, |file| {
+                archive_to_local_file(
+                    &app,
+                    &download_id,
+                    &remote_path,
+                    &local_zip_path,
+                    file,
+                    format.unwrap_or_default(),
+                    &cancelled,
+                )
+            })
+        })
+        .await
+        .and_then(|result| result)
+    };
+
+    if let Ok(mut downloads) = state.downloads.lock() {
+        downloads.remove(&download_id);
+    }
+    result
+}
+
+fn archive_to_local_file(
+    app: &AppHandle,
+    download_id: &str,
+    remote_path: &str,
+    local_path: &str,
+    file: &mut std::fs::File,
+    format: ArchiveFormat,
+    cancelled: &AtomicBool,
+) -> Result<u64, StorageError> {
+    let conn = app.state::<AppState>().backend(BackendSlot::Primary)?;
+    let storage = conn.as_ref().ok_or(StorageError::NotConnected)?.storage();
+
+    let mut last_reported = 0u64;
+    let mut report = |bytes_done: u64| {
+        if bytes_done - last_reported >= PROGRESS_INTERVAL_BYTES {
+            last_reported = bytes_done;
+            let _ = app.emit(
+                "download://progress",
+                DownloadProgress {
+                    download_id: download_id.to_string(),
+                    bytes_done,
+                    bytes_total: None,
+                },
+            );
+        }
+    };
+
+    let mut bytes_done = 0u64;
+    let mut write_error = None;
+    let streamed = storage.archive_directory(remote_path, format, &mut |chunk, _| {
+        if cancelled.load(Ordering::Relaxed) {
+            return false;
+        }
+        if let Err(e) = file.write_all(chunk) {
+            write_error = Some(e);
+            return false;
+        }
+        bytes_done += chunk.len() as u64;
+        report(bytes_done);
+        true
+    });
+
+    let completed = match streamed {
+        Ok(completed) => completed,
+        Err(StorageError::ToolMissing(_)) if format == ArchiveFormat::Zip => {
+            let completed =
+                archive::zip_directory(storage, remote_path, &mut *file, cancelled, &mut report)
+                    .context("Failed to archive directory")?;
+            bytes_done = file
+                .metadata()
+                .with_context(|| format!("Failed to write {}", local_path))?
+                .len();
+            completed
+        }
+        Err(e) => return Err(e.context("Failed to archive directory")),
+    };
+
+    if let Some(e) = write_error {
+        return Err(e).with_context(|| format!("Failed to write {}", local_path));
+    }
+    if !completed {
+        return Err(StorageError::Cancelled);
+    }
+    file.flush()
+        .with_context(|| format!("Failed to write {}", local_path))?;
+    Ok(bytes_done)
+}
+
+#[tauri::command]
+pub async fn cancel_download(
+    state: State<'_, AppState>,
+    download_id: String,
+) -> Result<bool, StorageError> {
+    let downloads = state.downloads.lock()?;
+    match downloads.get(&download_id) {
+        Some(cancelled) => {
+            cancelled.store(true, Ordering::Relaxed);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Polls `path` every `interval_secs` seconds (5 by default, at least 1) and
+/// emits `fs://created`, `fs://removed` and `fs://modified` for each entry
+/// that changed. Watching a directory again restarts its watcher.
+#[tauri::command]
+pub async fn watch_directory(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    interval_secs: Option<u64>,
+) -> Result<(), StorageError> {
+    let backend = state
+        .backend(BackendSlot::Primary)?
+        .ok_or(StorageError::NotConnected)?;
+    let (dir, initial) = blocking(move || {
+        let storage = backend.storage();
+        let dir = storage.path_translator().to_remote(&path).to_string();
+        let files = storage
+            .list_directory(&dir, MediaFilter::All)
+            .context("Failed to watch directory")?;
+        Ok::<_, StorageError>((dir, watch::snapshot(files)))
+    })
+    .await??;
+    let interval = interval_secs
+        .map(Duration::from_secs)
+        .unwrap_or(watch::DEFAULT_WATCH_INTERVAL)
+        .max(watch::MIN_WATCH_INTERVAL);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let previous = state.watchers.lock()?.insert(dir.clone(), stop.clone());
+    if let Some(previous) = previous {
+        previous.store(true, Ordering::Relaxed);
+    }
+    std::thread::spawn(move || poll_directory(app, dir, interval, initial, stop));
+    Ok(())
+}
+
+fn poll_directory(
+    app: AppHandle,
+    dir: String,
+    interval: Duration,
+    mut previous: watch::Snapshot,
+    stop: Arc<AtomicBool>,
+) {
+    const STOP_CHECK: Duration = Duration::from_millis(250);
+    let state = app.state::<AppState>();
+
+    'poll: loop {
+        let mut waited = Duration::ZERO;
+        while waited < interval {
+            if stop.load(Ordering::Relaxed) {
+                break 'poll;
+            }
+            std::thread::sleep(STOP_CHECK);
+            waited += STOP_CHECK;
+        }
+
+        let listing = match state.backend(BackendSlot::Primary) {
+            Ok(Some(backend)) => backend.storage().list_directory(&dir, MediaFilter::All),
+            _ => break,
+        };
+        // A failed poll is retried on the next tick.
+        let Ok(files) = listing else {
+            continue;
+        };
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+
+        let current = watch::snapshot(files);
+        let changes = watch::diff_snapshots(&previous, &current);
+        for (event, files) in [
+            ("fs://created", changes.created),
+            ("fs://removed", changes.removed),
+            ("fs://modified", changes.modified),
+        ] {
+            for file in files {
+                let _ = app.emit(
+                    event,
+                    FsChangeEvent {
+                        dir: dir.clone(),
+                        file,
+                    },
+                );
+            }
+        }
+        previous = current;
+    }
+
+    // Forget this watcher unless it has already been replaced.
+    let watchers = state.watchers.lock();
+    if let Ok(mut watchers) = watchers {
+        if watchers.get(&dir).is_some_and(|w| Arc::ptr_eq(w, &stop)) {
+            watchers.remove(&dir);
+        }
+    }
+}
+
+/// Stops the watcher on `path`. Returns whether one was running.
+#[tauri::command]
+pub async fn unwatch_directory(
+    state: State<'_, AppState>,
+    path: String,
+) -> Result<bool, StorageError> {
+    let dir = match state.backend(BackendSlot::Primary)?.as_deref() {
+        Some(backend) => backend.storage().path_translator().to_remote(&path),
+        None => RemotePath::new(&path),
+    }
+    .to_string();
+    let mut watchers = state.watchers.lock()?;
+    match watchers.remove(&dir) {
+        Some(stop) => {
+            stop.store(true, Ordering::Relaxed);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// `max_size` CSS pixels scaled for the display and snapped to a cached
+/// size, or the display profile's default.
+fn thumbnail_size(state: &AppState, max_size: Option<u32>) -> Result<u32, StorageError> {
+    let profile = state.display_profile.lock()?;
+    Ok(match max_size {
+        Some(size) => profile.device_thumbnail_size(size),
+        None => profile.thumbnail_size(),
+    })
+}
+
+/// Generates thumbnails for `paths` on up to `concurrency` workers
+/// (default [`thumbnails::DEFAULT_THUMBNAIL_WORKERS`]). Each one arrives as
+/// a `thumbnail://ready` or `thumbnail://error` event as soon as it is done.
+/// With `include_placeholders`, each also carries a BlurHash.
+/// `cancel_operation(operation_id)` stops the thumbnails not yet started.
+#[tauri::command]
+pub async fn get_thumbnails_batch(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    paths: Vec<String>,
+    max_size: Option<u32>,
+    concurrency: Option<usize>,
+    include_placeholders: Option<bool>,
+    operation_id: Option<String>,
+) -> Result<ThumbnailBatch, StorageError> {
+    let max = thumbnail_size(&state, max_size)?;
+    let conn = state.backend(BackendSlot::Primary)?;
+    let backend = conn.ok_or(StorageError::NotConnected)?;
+    let operation = Operation::start(&state, operation_id)?;
+
+    blocking(move || {
+        let state = app.state::<AppState>();
+        let batch = thumbnails::generate_batch(
+            backend.storage(),
+            state.thumbnail_cache.get(),
+            Some(&state.prefetch_cache),
+            &paths,
+            max,
+            ThumbnailOptions {
+                placeholder: include_placeholders.unwrap_or(false),
+                ..ThumbnailOptions::default()
+            },
+            concurrency.unwrap_or(thumbnails::DEFAULT_THUMBNAIL_WORKERS),
+            &|ready| {
+                let _ = app.emit("thumbnail://ready", ready);
+            },
+            &|error| {
+                let _ = app.emit("thumbnail://error", error);
+            },
+            operation.cancelled(),
+        );
+        if operation.cancelled().load(Ordering::Relaxed) {
+            return Err(StorageError::Cancelled);
+        }
+        Ok(batch)
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn get_file_thumbnail(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    max_size: Option<u32>,
+    include_placeholder: Option<bool>,
+) -> Result<Thumbnail, StorageError> {
+    let max = thumbnail_size(&state, max_size)?;
+    let options = ThumbnailOptions {
+        placeholder: include_placeholder.unwrap_or(false),
+        ..ThumbnailOptions::default()
+    };
+    let conn = state.backend(BackendSlot::Primary)?;
+
+    blocking(move || match conn.as_deref() {
+        Some(backend) => {
+            let state = app.state::<AppState>();
+            let thumbnail = thumbnail_cache::cached_thumbnail(
+                state.thumbnail_cache.get(),
+                Some(&state.prefetch_cache),
+                backend.storage(),
+                &path,
+                max,
+                options,
+            )
+            .context("Failed to get thumbnail")?;
+            record_recent_file(&app, &state, backend.storage(), &path);
+            Ok(thumbnail)
+        }
+        None => Err(StorageError::NotConnected),
+    })
+    .await?
+}
+
+/// Refuses to decode images with more than `max_pixels` pixels, which
+/// otherwise default to [`image_decode::DEFAULT_MAX_IMAGE_PIXELS`].
+#[tauri::command]
+pub async fn set_max_image_pixels(max_pixels: u64) -> Result<(), StorageError> {
+    if max_pixels == 0 {
+        return Err(StorageError::InvalidInput(
+            "The pixel limit must be positive".to_string(),
+        ));
+    }
+    image_decode::set_max_image_pixels(max_pixels);
+    Ok(())
+}
+
+fn thumbnail_cache(state: &AppState) -> Result<&ThumbnailCache, StorageError> {
+    state
+        .thumbnail_cache
+        .get()
+        .ok_or_else(|| StorageError::Internal("Thumbnail cache is not available".to_string()))
+}
+
+#[tauri::command]
+pub async fn get_thumbnail_cache_stats(
+    state: State<'_, AppState>,
+) -> Result<ThumbnailCacheStats, StorageError> {
+    Ok(thumbnail_cache(&state)?.stats())
+}
+
+#[tauri::command]
+pub async fn clear_thumbnail_cache(state: State<'_, AppState>) -> Result<(), StorageError> {
+    thumbnail_cache(&state)?
+        .clear()
+        .context("Failed to clear thumbnail cache")
+}
+
+/// Starts reading `paths`, in order, into the prefetch cache in the
+/// background, e.g. the next images of a slideshow. Only as many as the
+/// display profile's prefetch count are read. Returns immediately and stops
+/// any prefetch still running.
+#[tauri::command]
+pub async fn prefetch_files(
+    state: State<'_, AppState>,
+    mut paths: Vec<String>,
+) -> Result<(), StorageError> {
+    let Some(backend) = state.backend(BackendSlot::Primary)? else {
+        return Err(StorageError::NotConnected);
+    };
+    paths.truncate(state.display_profile.lock()?.prefetch_count() as usize);
+    let cache = state.prefetch_cache.clone();
+    let generation = cache.begin();
+    std::thread::spawn(move || {
+        let storage = backend.storage();
+        let storage_id = thumbnail_cache::storage_id(storage);
+        prefetch::prefetch(&cache, storage, &storage_id, &paths, generation);
+    });
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_prefetch_cache_stats(
+    state: State<'_, AppState>,
+) -> Result<PrefetchCacheStats, StorageError> {
+    Ok(state.prefetch_cache.stats())
+}
+
+/// Caps the thumbnail cache at `max_bytes`, evicting the least recently
+/// used entries beyond it.
+#[tauri::command]
+pub async fn set_thumbnail_cache_limit(
+    state: State<'_, AppState>,
+    max_bytes: u64,
+) -> Result<ThumbnailCacheStats, StorageError> {
+    let cache = thumbnail_cache(&state)?;
+    cache.set_max_bytes(max_bytes);
+    Ok(cache.stats())
+}
+
+#[tauri::command]
+pub async fn set_display_profile(
+    state: State<'_, AppState>,
+    scale_factor: f64,
+    grid_cell_px: u32,
+    viewport_cells: u32,
+) -> Result<DisplaySettings, StorageError> {
+    let mut profile = state.display_profile.lock()?;
+    *profile = DisplayProfile {
+        scale_factor,
+        grid_cell_px,
+        viewport_cells,
+    };
+    Ok(profile.settings())
+}
+
+/// Drops the connection in `slot`, announcing it with
+/// `storage://disconnected` if there was one.
+fn disconnect_slot(
+    events: &dyn EventSink,
+    state: &AppState,
+    slot: BackendSlot,
+) -> Result<(), StorageError> {
+    let was_connected = state.backend(slot)?.is_some();
+    // The session closes once commands still using it have finished.
+    state.set_backend(slot, None)?;
+    if slot == BackendSlot::Primary {
+        for (_, stop) in state.watchers.lock()?.drain() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+    if was_connected {
+        connection_events::emit_disconnected(events, slot, DisconnectReason::UserRequest, None);
+    }
+    Ok(())
+}
+
+/// Longest the app waits on exit for connections to close.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Closes every connection on exit. Running commands are cancelled first;
+/// those that cannot be, like a GitHub commit and push, are waited for so
+/// they are not cut off halfway. Gives up after `timeout`, leaving the rest
+/// to be dropped with the process. Returns whether everything closed; runs
+/// only once.
+pub fn shutdown(state: &AppState, timeout: Duration) -> bool {
+    if state.shut_down.swap(true, Ordering::SeqCst) {
+        return true;
+    }
+    let start = Instant::now();
+    for flags in [
+        &state.downloads,
+        &state.listings,
+        &*state.operations,
+        &state.watchers,
+    ] {
+        if let Ok(flags) = flags.lock() {
+            for cancelled in flags.values() {
+                cancelled.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+    if let Ok(monitors) = state.health_monitors.lock() {
+        for stop in monitors.values() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+    let backends: Vec<_> = [BackendSlot::Primary, BackendSlot::Target]
+        .into_iter()
+        .filter_map(|slot| state.slot(slot).write().ok()?.take())
+        .collect();
+
+    // Closing may hang on a dead network, so it gets its own thread.
+    let deadline = start + timeout;
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for mut backend in backends {
+            // Dropping the last reference disconnects.
+            while let Err(shared) = Arc::try_unwrap(backend) {
+                if Instant::now() >= deadline {
+                    return;
+                }
+                backend = shared;
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        }
+        let _ = tx.send(());
+    });
+    let clean = rx
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .is_ok();
+    if clean {
+        log::info!("Shut down cleanly in {:?}", start.elapsed());
+    } else {
+        log::warn!(
+            "Shutdown timed out after {:?} with connections still busy",
+            start.elapsed()
+        );
+    }
+    clean
+}
+
+#[tauri::command]
+pub async fn disconnect(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    slot: Option<BackendSlot>,
+) -> Result<(), StorageError> {
+    disconnect_slot(&app, &state, slot.unwrap_or_default())
+}
+
+#[tauri::command]
+pub async fn get_storage_type(state: State<'_, AppState>) -> Result<Option<String>, StorageError> {
+    let conn = state.backend(BackendSlot::Primary)?;
+    Ok(conn.as_ref().map(|b| b.storage().storage_type().to_string()))
+}
+
+#[tauri::command]
+pub async fn is_connected(state: State<'_, AppState>) -> Result<bool, StorageError> {
+    state.is_connected()
+}
+
+/// The last `limit` (default 500) diagnostics log entries at `level`
+/// (default info) or more severe, oldest first.
+#[tauri::command]
+pub async fn get_logs(
+    level: Option<LogLevel>,
+    limit: Option<usize>,
+) -> Result<Vec<LogEntry>, StorageError> {
+    Ok(logging::logger().entries(
+        level.unwrap_or(LogLevel::Info),
+        limit.unwrap_or(logging::DEFAULT_LOG_LIMIT),
+    ))
+}
+
+/// Sets the least severe level recorded from now on.
+#[tauri::command]
+pub async fn set_log_level(level: LogLevel) -> Result<(), StorageError> {
+    logging::set_level(level);
+    Ok(())
+}
+
+/// Turns writing the log to `image.log` in the app log dir on or off,
+/// moving it aside once it reaches `max_bytes` (default 5 MiB). Returns the
+/// file's path while enabled.
+#[tauri::command]
+pub async fn set_log_file(
+    app: AppHandle,
+    enabled: bool,
+    max_bytes: Option<u64>,
+) -> Result<Option<String>, StorageError> {
+    let path = if enabled {
+        Some(logging::log_path(&app.path().app_log_dir()?))
+    } else {
+        None
+    };
+    logging::logger()
+        .set_file(
+            path.as_deref(),
+            max_bytes.unwrap_or(logging::DEFAULT_MAX_LOG_FILE_BYTES),
+        )
+        .context("Failed to open the log file")?;
+    Ok(path.map(|p| p.display().to_string()))
+}
+
+/// Transfer counters of the connection in `slot` (primary by default),
+/// which start over with each connection. With `persistent`, those of the
+/// slot's earlier connections this session are added.
+#[tauri::command]
+pub async fn get_storage_metrics(
+    state: State<'_, AppState>,
+    slot: Option<BackendSlot>,
+    persistent: Option<bool>,
+) -> Result<MetricsSnapshot, StorageError> {
+    storage_metrics(
+        &state,
+        slot.unwrap_or_default(),
+        persistent.unwrap_or(false),
+    )
+}
+
+fn storage_metrics(
+    state: &AppState,
+    slot: BackendSlot,
+    persistent: bool,
+) -> Result<MetricsSnapshot, StorageError> {
+    let current = state
+        .backend(slot)?
+        .map(|b| b.storage().metrics().snapshot())
+        .unwrap_or_default();
+    if !persistent {
+        return Ok(current);
+    }
+    let retired = state.retired_metrics.lock()?;
+    Ok(current.combined(&retired.get(&slot).copied().unwrap_or_default()))
+}
+
+/// Pings `backend`, marking it lost when the ping fails because the
+/// connection is gone or hangs.
+fn check_health(backend: &Arc<StorageBackend>) -> HealthStatus {
+    let pinged = backend.clone();
+    let status = health::timed_ping(move || pinged.storage().ping(), health::PING_TIMEOUT);
+    if let Some(error) = status.error.as_ref().filter(|e| e.is_transport()) {
+        backend.storage().connection_lost(error);
+    }
+    status
+}
+
+/// Measures a round trip to the server in `slot`. A dead connection makes
+/// `is_connected` false from then on.
+#[tauri::command]
+pub async fn ping_storage(
+    state: State<'_, AppState>,
+    slot: Option<BackendSlot>,
+) -> Result<HealthStatus, StorageError> {
+    let backend = state
+        .backend(slot.unwrap_or_default())?
+        .ok_or(StorageError::NotConnected)?;
+    blocking(move || check_health(&backend)).await
+}
+
+/// Pings the connection in `slot` every `interval_secs` (default 30),
+/// emitting each result as `storage://health`. Replaces a monitor already
+/// running on the slot.
+#[tauri::command]
+pub async fn start_health_monitor(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    slot: Option<BackendSlot>,
+    interval_secs: Option<u64>,
+) -> Result<(), StorageError> {
+    let slot = slot.unwrap_or_default();
+    let interval = interval_secs
+        .map(Duration::from_secs)
+        .unwrap_or(health::DEFAULT_HEALTH_INTERVAL)
+        .max(health::MIN_HEALTH_INTERVAL);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let previous = state.health_monitors.lock()?.insert(slot, stop.clone());
+    if let Some(previous) = previous {
+        previous.store(true, Ordering::Relaxed);
+    }
+    std::thread::spawn(move || monitor_health(app, slot, interval, stop));
+    Ok(())
+}
+
+fn monitor_health(app: AppHandle, slot: BackendSlot, interval: Duration, stop: Arc<AtomicBool>) {
+    const STOP_CHECK: Duration = Duration::from_millis(250);
+    let state = app.state::<AppState>();
+
+    'monitor: loop {
+        let status = match state.backend(slot) {
+            Ok(Some(backend)) => check_health(&backend),
+            Ok(None) => HealthStatus {
+                ok: false,
+                latency_ms: 0,
+                error: Some(StorageError::NotConnected),
+            },
+            Err(_) => break,
+        };
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        health::emit_health(&app, slot, status);
+
+        let mut waited = Duration::ZERO;
+        while waited < interval {
+            if stop.load(Ordering::Relaxed) {
+                break 'monitor;
+            }
+            std::thread::sleep(STOP_CHECK);
+            waited += STOP_CHECK;
+        }
+    }
+
+    // Forget this monitor unless it has already been replaced.
+    let monitors = state.health_monitors.lock();
+    if let Ok(mut monitors) = monitors {
+        if monitors.get(&slot).is_some_and(|m| Arc::ptr_eq(m, &stop)) {
+            monitors.remove(&slot);
+        }
+    }
+}
+
+/// Stops the health monitor on `slot`. Returns whether one was running.
+#[tauri::command]
+pub async fn stop_health_monitor(
+    state: State<'_, AppState>,
+    slot: Option<BackendSlot>,
+) -> Result<bool, StorageError> {
+    let stop = state
+        .health_monitors
+        .lock()?
+        .remove(&slot.unwrap_or_default());
+    if let Some(stop) = &stop {
+        stop.store(true, Ordering::Relaxed);
+    }
+    Ok(stop.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection_events::tests::Recorder;
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    fn ec2_backend() -> StorageBackend {
+        StorageBackend::Ec2(Ec2Storage::new(Ec2Config {
+            host: "localhost".to_string(),
+            username: "testuser".to_string(),
+            pem_content: String::new(),
+            port: 22,
+            password: None,
+            auth_method: AuthMethod::Auto,
+            key_passphrase: None,
+            known_hosts_file: None,
+            keepalive_secs: None,
+            auto_reconnect: false,
+            remote_thumbnails: false,
+            jump_host: None,
+            initial_path: None,
+            generic_sftp: false,
+        }))
+    }
+
+    #[test]
+    fn test_metadata_does_not_wait_for_slow_operation() {
+        let state = AppState::new();
+        state
+            .set_backend(BackendSlot::Primary, Some(ec2_backend()))
+            .unwrap();
+
+        let (started, wait_started) = mpsc::channel();
+        std::thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                let backend = state.backend(BackendSlot::Primary).unwrap().unwrap();
+                started.send(()).unwrap();
+                // Stands in for a long transfer on the connection.
+                std::thread::sleep(Duration::from_millis(500));
+                backend.storage().storage_type()
+            });
+            wait_started.recv().unwrap();
+
+            let start = Instant::now();
+            let backend = state.backend(BackendSlot::Primary).unwrap().unwrap();
+            assert_eq!(backend.storage().storage_type(), StorageType::Ec2);
+            assert!(!backend.storage().is_connected());
+            drop(backend);
+            state.set_backend(BackendSlot::Primary, None).unwrap();
+            assert!(state.backend(BackendSlot::Primary).unwrap().is_none());
+            assert!(start.elapsed() < Duration::from_millis(100));
+
+            // The operation keeps its backend after the disconnect.
+            assert_eq!(worker.join().unwrap(), StorageType::Ec2);
+        });
+    }
+
+    #[test]
+    fn test_slow_reads_do_not_block_runtime() {
+        let state: &'static AppState = Box::leak(Box::new(AppState::new()));
+        state
+            .set_backend(BackendSlot::Primary, Some(ec2_backend()))
+            .unwrap();
+
+        tauri::async_runtime::block_on(async {
+            // More slow reads than the runtime has worker threads.
+            let workers = std::thread::available_parallelism().map_or(8, |n| n.get());
+            let reads: Vec<_> = (0..workers * 2)
+                .map(|_| {
+                    let backend = state.backend(BackendSlot::Primary).unwrap().unwrap();
+                    tauri::async_runtime::spawn(blocking(move || {
+                        // Stands in for a slow read on the connection.
+                        std::thread::sleep(Duration::from_millis(500));
+                        backend.storage().storage_type()
+                    }))
+                })
+                .collect();
+
+            let start = Instant::now();
+            let connected = tauri::async_runtime::spawn(async { state.is_connected() })
+                .await
+                .unwrap();
+            assert!(!connected.unwrap());
+            assert!(start.elapsed() < Duration::from_millis(100));
+
+            for read in reads {
+                assert_eq!(read.await.unwrap().unwrap(), StorageType::Ec2);
+            }
+        });
+    }
+
+    #[test]
+    fn test_metrics_persist_across_connections() {
+        let state = AppState::new();
+        for _ in 0..2 {
+            state
+                .set_backend(BackendSlot::Primary, Some(ec2_backend()))
+                .unwrap();
+            let backend = state.backend(BackendSlot::Primary).unwrap().unwrap();
+            backend
+                .storage()
+                .metrics()
+                .record_read(100, Duration::from_millis(10));
+        }
+        let current = storage_metrics(&state, BackendSlot::Primary, false).unwrap();
+        assert_eq!(current.bytes_downloaded, 100);
+        let total = storage_metrics(&state, BackendSlot::Primary, true).unwrap();
+        assert_eq!(total.bytes_downloaded, 200);
+        assert_eq!(total.files_read, 2);
+
+        disconnect_slot(&Recorder::default(), &state, BackendSlot::Primary).unwrap();
+        let current = storage_metrics(&state, BackendSlot::Primary, false).unwrap();
+        assert_eq!(current, MetricsSnapshot::default());
+        let total = storage_metrics(&state, BackendSlot::Primary, true).unwrap();
+        assert_eq!(total.bytes_downloaded, 200);
+        let other = storage_metrics(&state, BackendSlot::Target, true).unwrap();
+        assert_eq!(other.files_read, 0);
+    }
+
+    #[test]
+    fn test_shutdown_waits_for_running_commands() {
+        let state = AppState::new();
+        let operation = Operation::start(&state, Some("read-1".to_string())).unwrap();
+        state
+            .set_backend(BackendSlot::Primary, Some(ec2_backend()))
+            .unwrap();
+        let in_use = state.backend(BackendSlot::Primary).unwrap();
+
+        assert!(!shutdown(&state, Duration::from_millis(200)));
+        assert!(operation.cancelled().load(Ordering::Relaxed));
+        assert!(state.backend(BackendSlot::Primary).unwrap().is_none());
+        drop(in_use);
+
+        let state = AppState::new();
+        state
+            .set_backend(BackendSlot::Target, Some(ec2_backend()))
+            .unwrap();
+        assert!(shutdown(&state, SHUTDOWN_TIMEOUT));
+        assert!(state.backend(BackendSlot::Target).unwrap().is_none());
+        assert!(shutdown(&state, SHUTDOWN_TIMEOUT));
+    }
+
+    #[test]
+    fn test_ping_without_session() {
+        let backend = Arc::new(ec2_backend());
+        let status = check_health(&backend);
+        assert!(!status.ok);
+        assert_eq!(status.error.unwrap().code(), "NOT_CONNECTED");
+    }
+
+    #[test]
+    fn test_operation_registry() {
+        let state = AppState::new();
+        let operation = Operation::start(&state, Some("read-1".to_string())).unwrap();
+        let err = Operation::start(&state, Some("read-1".to_string())).err();
+        assert_eq!(err.unwrap().code(), "INVALID_INPUT");
+        // Operations without an id are not registered.
+        let _anonymous = Operation::start(&state, None).unwrap();
+        assert_eq!(state.operations.lock().unwrap().len(), 1);
+
+        state.operations.lock().unwrap()["read-1"].store(true, Ordering::Relaxed);
+        assert!(operation.cancelled().load(Ordering::Relaxed));
+        drop(operation);
+        assert!(state.operations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_disconnect_emits_event() {
+        let events = Recorder::default();
+        let state = AppState::new();
+        state
+            .set_backend(BackendSlot::Target, Some(ec2_backend()))
+            .unwrap();
+
+        disconnect_slot(&events, &state, BackendSlot::Target).unwrap();
+        // Nothing left to disconnect.
+        disconnect_slot(&events, &state, BackendSlot::Target).unwrap();
+
+        let events = events.0.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        let (event, payload) = &events[0];
+        assert_eq!(event, connection_events::DISCONNECTED_EVENT);
+        assert_eq!(payload["slot"], "target");
+        assert_eq!(payload["reason"], "user_request");
+        assert!(payload["error"].is_null());
+    }
+
+    #[test]
+    fn test_stored_secret_request() {
+        let mut request: Ec2ConnectRequest = serde_json::from_value(serde_json::json!({
+            "host": "bastion-target",
+            "port": null,
+            "pem_content": "ignored",
+            "jump_host": {"host": "bastion", "username": "ec2-user", "pem_content": ""},
+            "use_stored_secret": true,
+        }))
+        .unwrap();
+        let err = requested_secret(request.use_stored_secret, request.profile_name.as_deref())
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), "INVALID_INPUT");
+        assert!(requested_secret(false, None).unwrap().is_none());
+
+        request.set_secrets(ProfileSecrets {
+            key_content: "a2V5".to_string(),
+            jump_key_content: "anVtcA==".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(request.pem_content, "a2V5");
+        assert_eq!(request.jump_host.unwrap().pem_content, "anVtcA==");
+    }
 }
@@ -0,0 +1,180 @@
+//! Events telling the frontend when a connection comes up, goes away or
+//! fails, so it does not have to poll `is_connected`.
+
+use crate::commands::BackendSlot;
+use crate::error::StorageError;
+use crate::storage::{ConnectionLostHandler, StorageType};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter};
+
+pub const CONNECTED_EVENT: &str = "storage://connected";
+pub const DISCONNECTED_EVENT: &str = "storage://disconnected";
+pub const ERROR_EVENT: &str = "storage://error";
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    /// The `disconnect` command.
+    UserRequest,
+    NetworkError,
+    /// The server stopped accepting the credentials.
+    AuthExpired,
+}
+
+impl DisconnectReason {
+    /// Why a connection found dead through `error` is gone.
+    pub fn of(error: &StorageError) -> Self {
+        match error {
+            StorageError::AuthFailed { .. }
+            | StorageError::PassphraseRequired
+            | StorageError::HostKey(_) => DisconnectReason::AuthExpired,
+            _ => DisconnectReason::NetworkError,
+        }
+    }
+}
+
+/// Payload of `storage://connected`.
+#[derive(Debug, Serialize, Clone)]
+pub struct ConnectedEvent {
+    pub slot: BackendSlot,
+    pub storage_type: StorageType,
+    pub root_path: String,
+}
+
+/// Payload of `storage://disconnected`.
+#[derive(Debug, Serialize, Clone)]
+pub struct DisconnectedEvent {
+    pub slot: BackendSlot,
+    pub reason: DisconnectReason,
+    /// What showed the connection was gone; `None` for user requests.
+    pub error: Option<StorageError>,
+}
+
+/// Payload of `storage://error`: a connection failure that was not
+/// recovered from, such as a failed reconnect.
+#[derive(Debug, Serialize, Clone)]
+pub struct ConnectionErrorEvent {
+    pub slot: BackendSlot,
+    pub error: StorageError,
+}
+
+/// Where events go: the app, or a recorder in tests.
+pub trait EventSink: Send + Sync {
+    fn send(&self, event: &str, payload: serde_json::Value);
+}
+
+impl EventSink for AppHandle {
+    fn send(&self, event: &str, payload: serde_json::Value) {
+        let _ = self.emit(event, payload);
+    }
+}
+
+fn send(sink: &dyn EventSink, event: &str, payload: impl Serialize) {
+    if let Ok(payload) = serde_json::to_value(payload) {
+        sink.send(event, payload);
+    }
+}
+
+pub fn emit_connected(
+    sink: &dyn EventSink,
+    slot: BackendSlot,
+    storage_type: StorageType,
+    root_path: &str,
+) {
+    let event = ConnectedEvent {
+        slot,
+        storage_type,
+        root_path: root_path.to_string(),
+    };
+    send(sink, CONNECTED_EVENT, event);
+}
+
+pub fn emit_disconnected(
+    sink: &dyn EventSink,
+    slot: BackendSlot,
+    reason: DisconnectReason,
+    error: Option<StorageError>,
+) {
+    send(
+        sink,
+        DISCONNECTED_EVENT,
+        DisconnectedEvent {
+            slot,
+            reason,
+            error,
+        },
+    );
+}
+
+pub fn emit_error(sink: &dyn EventSink, slot: BackendSlot, error: StorageError) {
+    send(sink, ERROR_EVENT, ConnectionErrorEvent { slot, error });
+}
+
+/// A handler for the backend in `slot` emitting `storage://disconnected`
+/// the first time the connection is found dead, by the keepalive or by an
+/// operation.
+pub fn connection_lost_handler(
+    sink: Arc<dyn EventSink>,
+    slot: BackendSlot,
+) -> ConnectionLostHandler {
+    let reported = AtomicBool::new(false);
+    Arc::new(move |error: &StorageError| {
+        if !reported.swap(true, Ordering::Relaxed) {
+            emit_disconnected(
+                sink.as_ref(),
+                slot,
+                DisconnectReason::of(error),
+                Some(error.clone()),
+            );
+        }
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Keeps the events sent to it.
+    #[derive(Default)]
+    pub(crate) struct Recorder(pub Mutex<Vec<(String, serde_json::Value)>>);
+
+    impl EventSink for Recorder {
+        fn send(&self, event: &str, payload: serde_json::Value) {
+            self.0.lock().unwrap().push((event.to_string(), payload));
+        }
+    }
+
+    #[test]
+    fn test_connection_lost_handler_reports_once() {
+        let recorder = Arc::new(Recorder::default());
+        let handler = connection_lost_handler(recorder.clone(), BackendSlot::Target);
+        handler(&StorageError::ConnectionLost("socket recv failure".into()));
+        handler(&StorageError::Timeout);
+
+        let events = recorder.0.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        let (event, payload) = &events[0];
+        assert_eq!(event, DISCONNECTED_EVENT);
+        assert_eq!(payload["slot"], "target");
+        assert_eq!(payload["reason"], "network_error");
+        assert_eq!(payload["error"]["code"], "CONNECTION_LOST");
+    }
+
+    #[test]
+    fn test_disconnect_reason() {
+        let expired = StorageError::AuthFailed {
+            reason: "key rejected".into(),
+        };
+        assert_eq!(
+            DisconnectReason::of(&expired),
+            DisconnectReason::AuthExpired
+        );
+        assert_eq!(
+            DisconnectReason::of(&StorageError::Timeout),
+            DisconnectReason::NetworkError
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+
+/// Thumbnail sizes are snapped to these buckets so small UI size changes keep
+/// hitting the same cached renditions.
+pub const THUMBNAIL_BUCKETS: [u32; 4] = [128, 256, 512, 1024];
+
+const DEFAULT_THUMBNAIL_SIZE: u32 = 200;
+const DEFAULT_VIEWPORT_CELLS: u32 = 24;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct DisplayProfile {
+    pub scale_factor: f64,
+    pub grid_cell_px: u32,
+    pub viewport_cells: u32,
+}
+
+impl Default for DisplayProfile {
+    fn default() -> Self {
+        DisplayProfile {
+            scale_factor: 1.0,
+            grid_cell_px: DEFAULT_THUMBNAIL_SIZE,
+            viewport_cells: DEFAULT_VIEWPORT_CELLS,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct DisplaySettings {
+    pub thumbnail_size: u32,
+    pub prefetch_count: u32,
+}
+
+impl DisplayProfile {
+    /// Device pixels of one grid cell, snapped to a bucket.
+    pub fn thumbnail_size(&self) -> u32 {
+        self.device_thumbnail_size(self.grid_cell_px)
+    }
+
+    /// Device pixels of a thumbnail `css_px` CSS pixels wide, snapped to a
+    /// bucket.
+    pub fn device_thumbnail_size(&self, css_px: u32) -> u32 {
+        let scale = if self.scale_factor.is_finite() && self.scale_factor > 0.0 {
+            self.scale_factor
+        } else {
+            1.0
+        };
+        quantize_thumbnail_size((css_px as f64 * scale).round() as u32)
+    }
+
+    /// One visible screen plus one screen of read-ahead.
+    pub fn prefetch_count(&self) -> u32 {
+        self.viewport_cells.max(1).saturating_mul(2)
+    }
+
+    pub fn settings(&self) -> DisplaySettings {
+        DisplaySettings {
+            thumbnail_size: self.thumbnail_size(),
+            prefetch_count: self.prefetch_count(),
+        }
+    }
+}
+
+/// Returns the smallest bucket that covers `size`, or the largest bucket.
+pub fn quantize_thumbnail_size(size: u32) -> u32 {
+    THUMBNAIL_BUCKETS
+        .iter()
+        .copied()
+        .find(|bucket| *bucket >= size)
+        .unwrap_or(THUMBNAIL_BUCKETS[THUMBNAIL_BUCKETS.len() - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantize_thumbnail_size() {
+        assert_eq!(quantize_thumbnail_size(0), 128);
+        assert_eq!(quantize_thumbnail_size(128), 128);
+        assert_eq!(quantize_thumbnail_size(129), 256);
+        assert_eq!(quantize_thumbnail_size(200), 256);
+        assert_eq!(quantize_thumbnail_size(500), 512);
+        assert_eq!(quantize_thumbnail_size(4000), 1024);
+    }
+
+    #[test]
+    fn test_thumbnail_size_accounts_for_scale_factor() {
+        let profile = DisplayProfile {
+            scale_factor: 2.0,
+            grid_cell_px: 256,
+            viewport_cells: 20,
+        };
+        assert_eq!(profile.thumbnail_size(), 512);
+        assert_eq!(profile.device_thumbnail_size(200), 512);
+        assert_eq!(profile.device_thumbnail_size(100), 256);
+        assert_eq!(profile.prefetch_count(), 40);
+    }
+
+    #[test]
+    fn test_invalid_scale_factor_is_ignored() {
+        let profile = DisplayProfile {
+            scale_factor: f64::NAN,
+            grid_cell_px: 120,
+            viewport_cells: 0,
+        };
+        assert_eq!(profile.thumbnail_size(), 128);
+        assert_eq!(profile.prefetch_count(), 2);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::checksum::ChecksumAlgorithm;
+use crate::storage::{FileInfo, Storage};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::AtomicBool;
+
+/// Directory names whose contents are never reported as duplicates.
+const SKIPPED_DIRS: &[&str] = &[".git", ".imagetrash"];
+/// Files hashed per remote call.
+const CHECKSUM_BATCH: usize = 64;
+
+/// Byte-identical files, ordered by path.
+#[derive(Debug, Serialize, Clone)]
+pub struct DuplicateGroup {
+    pub digest: String,
+    pub size: u64,
+    pub files: Vec<FileInfo>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DuplicateScan {
+    /// Groups with the most wasted space first.
+    pub groups: Vec<DuplicateGroup>,
+    pub files_scanned: usize,
+    /// The tree held more files than were scanned.
+    pub truncated: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DuplicateProgress {
+    pub hashed: usize,
+    pub total: usize,
+}
+
+pub(crate) fn is_skipped(path: &str) -> bool {
+    path.split('/')
+        .any(|segment| SKIPPED_DIRS.contains(&segment))
+}
+
+/// Buckets files by size and keeps the buckets with more than one file.
+/// Empty files are ignored.
+fn size_collisions(files: Vec<FileInfo>) -> Vec<Vec<FileInfo>> {
+    let mut by_size: BTreeMap<u64, Vec<FileInfo>> = BTreeMap::new();
+    for file in files.into_iter().filter(|f| f.size > 0) {
+        by_size.entry(file.size).or_default().push(file);
+    }
+    by_size.into_values().filter(|b| b.len() > 1).collect()
+}
+
+/// Splits a size bucket by digest. Files without a digest are dropped.
+fn group_by_digest(bucket: Vec<FileInfo>, digests: &[Option<String>]) -> Vec<DuplicateGroup> {
+    let mut by_digest: HashMap<String, Vec<FileInfo>> = HashMap::new();
+    for (file, digest) in bucket.into_iter().zip(digests) {
+        if let Some(digest) = digest {
+            by_digest.entry(digest.clone()).or_default().push(file);
+        }
+    }
+    by_digest
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(digest, mut files)| {
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            DuplicateGroup {
+                digest,
+                size: files[0].size,
+                files,
+            }
+        })
+        .collect()
+}
+
+/// Finds byte-identical files below `root`, scanning at most `max_files`.
+/// Only files sharing a size are hashed, in batches on the remote host.
+pub fn find_duplicates(
+    storage: &dyn Storage,
+    root: &str,
+    max_files: usize,
+    on_progress: &mut dyn FnMut(DuplicateProgress),
+) -> Result<DuplicateScan, Box<dyn std::error::Error>> {
+    let listing = storage.list_directory_recursive(root, 0, &AtomicBool::new(false))?;
+    let mut truncated = listing.truncated;
+    let mut files: Vec<FileInfo> = listing
+        .files
+        .into_iter()
+        .filter(|f| !f.is_dir && !is_skipped(&f.path))
+        .collect();
+    if files.len() > max_files {
+        files.truncate(max_files);
+        truncated = true;
+    }
+    let files_scanned = files.len();
+
+    let buckets = size_collisions(files);
+    let total = buckets.iter().map(Vec::len).sum();
+    let mut hashed = 0;
+    on_progress(DuplicateProgress { hashed, total });
+
+    let mut groups = Vec::new();
+    for bucket in buckets {
+        let paths: Vec<String> = bucket.iter().map(|f| f.path.clone()).collect();
+        let mut digests = Vec::with_capacity(paths.len());
+        for batch in paths.chunks(CHECKSUM_BATCH) {
+            digests.extend(storage.checksums(batch, ChecksumAlgorithm::Sha256)?);
+            hashed += batch.len();
+            on_progress(DuplicateProgress { hashed, total });
+        }
+        groups.extend(group_by_digest(bucket, &digests));
+    }
+
+    groups.sort_by(|a, b| {
+        let wasted = |g: &DuplicateGroup| g.size * (g.files.len() as u64 - 1);
+        wasted(b)
+            .cmp(&wasted(a))
+            .then_with(|| a.files[0].path.cmp(&b.files[0].path))
+    });
+    Ok(DuplicateScan {
+        groups,
+        files_scanned,
+        truncated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: u64) -> FileInfo {
+        FileInfo::for_file(path, size, None)
+    }
+
+    #[test]
+    fn test_is_skipped() {
+        assert!(is_skipped("/.git/objects/ab"));
+        assert!(is_skipped("/photos/.imagetrash/files/a.jpg"));
+        assert!(!is_skipped("/photos/.github/a.jpg"));
+    }
+
+    #[test]
+    fn test_size_collisions_ignores_unique_and_empty() {
+        let buckets = size_collisions(vec![
+            file("/a.jpg", 10),
+            file("/b.jpg", 10),
+            file("/c.jpg", 20),
+            file("/d.txt", 0),
+            file("/e.txt", 0),
+        ]);
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].len(), 2);
+    }
+
+    #[test]
+    fn test_group_by_digest_confirms_matches() {
+        let bucket = vec![
+            file("/b.jpg", 10),
+            file("/a.jpg", 10),
+            file("/c.jpg", 10),
+            file("/d.jpg", 10),
+        ];
+        let digests = vec![
+            Some("aa".to_string()),
+            Some("aa".to_string()),
+            Some("bb".to_string()),
+            None,
+        ];
+        let groups = group_by_digest(bucket, &digests);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].digest, "aa");
+        let paths: Vec<&str> = groups[0].files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["/a.jpg", "/b.jpg"]);
+    }
+}
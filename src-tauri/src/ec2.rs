@@ -1,26 +1,215 @@
-use crate::storage::{detect_mime_type, FileInfo, Storage, StorageType};
-use crate::utils;
-use image::GenericImageView;
+use crate::archive::{self, ArchiveFormat, ExtractFormat};
+use crate::checksum::{self, ChecksumAlgorithm, FileChecksum};
+use crate::error::StorageError;
+use crate::host_keys;
+use crate::media_probe::{self, MediaProbe};
+use crate::metrics::{self, StorageMetrics};
+use crate::paths::{self, PathTranslator, RemotePath, ABSOLUTE_PATH_KEY};
+use crate::ssh_util;
+use crate::storage::{
+    self, BatchCallback, ChunkCallback, ConnectionLostHandler, ContentSearchResult,
+    CreateDirectoryResult, DeleteDirectoryResult, DirectoryUsage, FileInfo, FileReadOutcome,
+    ListOptions, ListingBatcher, RecursiveListing, SearchResult, SortKey, Storage, StorageType,
+    MAX_RECURSIVE_ENTRIES, STREAM_CHUNK_SIZE,
+};
+use crate::thumbnails::{self, RemoteThumbnailer, Thumbnail, ThumbnailOptions};
+use crate::tunnel::{JumpHostConfig, JumpTunnel};
+use crate::utils::{self, Keepalive};
+use crate::validation::{ConnectionStep, Step, StepError, ValidationResult, VALIDATION_TIMEOUT};
 use serde::{Deserialize, Serialize};
-use ssh2::Session;
-use std::io::Read;
-use std::net::TcpStream;
-use std::path::Path;
-use std::time::Duration;
+use shell_escape::escape;
+use ssh2::{FileStat, RenameFlags, Session, Sftp};
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 const CONNECTION_TIMEOUT_SECS: u64 = 30;
+const DIRECTORY_MODE: i32 = 0o755;
+/// libssh2's LIBSSH2_ERROR_FILE, which readdir returns past the last entry.
+const END_OF_DIRECTORY: i32 = -16;
 
-#[derive(Debug, Serialize, Deserialize)]
+fn shell_quote(s: &str) -> Cow<'_, str> {
+    escape(s.into())
+}
+
+/// Which credentials `connect` offers to the server.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    Key,
+    Password,
+    /// The key if one is configured, then the password.
+    #[default]
+    Auto,
+    /// The keys held by the local SSH agent.
+    Agent,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Ec2Config {
     pub host: String,
     pub username: String,
+    /// Base64-encoded private key; empty when only a password is used.
+    #[serde(default)]
     pub pem_content: String,
     pub port: u16,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub auth_method: AuthMethod,
+    /// Passphrase protecting `pem_content`, if it is encrypted.
+    #[serde(default)]
+    pub key_passphrase: Option<String>,
+    /// App-managed known_hosts, checked after `~/.ssh/known_hosts`.
+    #[serde(skip)]
+    pub known_hosts_file: Option<PathBuf>,
+    /// Seconds between keepalives; `0` disables them. Defaults to
+    /// [`utils::DEFAULT_KEEPALIVE_SECS`].
+    #[serde(default)]
+    pub keepalive_secs: Option<u64>,
+    /// Reconnect and retry once when an operation hits a dropped connection.
+    #[serde(default)]
+    pub auto_reconnect: bool,
+    /// Let a thumbnailing tool on the remote host make thumbnails, see
+    /// [`crate::thumbnails::RemoteThumbnailer`].
+    #[serde(default)]
+    pub remote_thumbnails: bool,
+    /// Bastion to tunnel the connection through.
+    #[serde(default)]
+    pub jump_host: Option<JumpHostConfig>,
+    /// Absolute directory used as the storage root instead of the home
+    /// directory.
+    #[serde(default)]
+    pub initial_path: Option<String>,
+    /// Any SFTP server rather than an EC2 host: no home directory layout is
+    /// assumed, and the storage type is [`StorageType::Sftp`].
+    #[serde(default)]
+    pub generic_sftp: bool,
+}
+
+// Written by hand so credentials never end up in logs.
+impl fmt::Debug for Ec2Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ec2Config")
+            .field("host", &self.host)
+            .field("username", &self.username)
+            .field("port", &self.port)
+            .field("auth_method", &self.auth_method)
+            .field("jump_host", &self.jump_host)
+            .field("initial_path", &self.initial_path)
+            .finish_non_exhaustive()
+    }
 }
 
+impl Ec2Config {
+    fn uses_key(&self) -> bool {
+        matches!(self.auth_method, AuthMethod::Key | AuthMethod::Auto)
+            && !self.pem_content.is_empty()
+    }
+
+    fn uses_password(&self) -> bool {
+        matches!(self.auth_method, AuthMethod::Password | AuthMethod::Auto)
+            && self.password.is_some()
+    }
+
+    /// Checks that the selected auth method has a credential to offer.
+    pub fn validate_credentials(&self) -> Result<(), String> {
+        if self.auth_method == AuthMethod::Agent || self.uses_key() || self.uses_password() {
+            return Ok(());
+        }
+        Err(match self.auth_method {
+            AuthMethod::Key => "A private key is required for key authentication",
+            AuthMethod::Password => "A password is required for password authentication",
+            AuthMethod::Auto | AuthMethod::Agent => "Provide a private key or a password",
+        }
+        .to_string())
+    }
+}
+
+/// The credentials `connect_sftp` offers.
+#[derive(Deserialize, Clone)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum SftpAuth {
+    Key {
+        /// Base64-encoded private key.
+        pem_content: String,
+        #[serde(default)]
+        key_passphrase: Option<String>,
+    },
+    Password {
+        password: String,
+    },
+    Agent,
+}
+
+/// A generic SFTP server, such as a NAS, as opposed to an EC2 host.
+#[derive(Deserialize, Clone)]
+pub struct SftpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: String,
+    pub auth: SftpAuth,
+    /// Absolute directory to use as the storage root. Defaults to the
+    /// directory the server logs in to.
+    #[serde(default)]
+    pub initial_path: Option<String>,
+}
+
+impl SftpConfig {
+    /// The connection settings for [`Ec2Storage`], which serves both.
+    pub fn into_ec2_config(self, known_hosts_file: Option<PathBuf>) -> Ec2Config {
+        let (auth_method, pem_content, key_passphrase, password) = match self.auth {
+            SftpAuth::Key {
+                pem_content,
+                key_passphrase,
+            } => (AuthMethod::Key, pem_content, key_passphrase, None),
+            SftpAuth::Password { password } => {
+                (AuthMethod::Password, String::new(), None, Some(password))
+            }
+            SftpAuth::Agent => (AuthMethod::Agent, String::new(), None, None),
+        };
+        Ec2Config {
+            host: self.host,
+            username: self.username,
+            pem_content,
+            port: self.port.unwrap_or(22),
+            password,
+            auth_method,
+            key_passphrase,
+            known_hosts_file,
+            keepalive_secs: None,
+            auto_reconnect: false,
+            remote_thumbnails: false,
+            jump_host: None,
+            initial_path: self.initial_path,
+            generic_sftp: true,
+        }
+    }
+}
+
+/// An SSH host browsed over SFTP: an EC2 instance, or any SFTP server when
+/// [`Ec2Config::generic_sftp`] is set.
 pub struct Ec2Storage {
     config: Ec2Config,
     session: Option<Session>,
+    /// SFTP channel shared by all operations; see [`Ec2Storage::with_sftp`].
+    sftp: ChannelCache<Sftp>,
+    keepalive: Option<Keepalive>,
+    on_connection_lost: Option<ConnectionLostHandler>,
+    /// Set once the keepalive or an operation finds the session dead.
+    lost: Arc<AtomicBool>,
+    metrics: StorageMetrics,
+    /// Set when connected through a bastion; outlives `session`.
+    tunnel: Option<JumpTunnel>,
+    /// Probed on connect when [`Ec2Config::remote_thumbnails`] is set.
+    remote_thumbnailer: Option<RemoteThumbnailer>,
+    /// The directory a generic SFTP server logged in to, asked on connect.
+    login_dir: Option<String>,
 }
 
 impl Ec2Storage {
@@ -28,131 +217,1150 @@ impl Ec2Storage {
         Ec2Storage {
             config,
             session: None,
+            sftp: ChannelCache::default(),
+            keepalive: None,
+            on_connection_lost: None,
+            lost: Arc::new(AtomicBool::new(false)),
+            metrics: StorageMetrics::default(),
+            tunnel: None,
+            remote_thumbnailer: None,
+            login_dir: None,
         }
     }
+
+    pub fn config(&self) -> &Ec2Config {
+        &self.config
+    }
+
+    /// Runs `op` on the cached SFTP channel, opening it on first use. The
+    /// channel is dropped when `op` fails because the connection broke, so
+    /// the next call opens a fresh one.
+    fn with_sftp<T>(
+        &self,
+        op: impl FnOnce(&Sftp) -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        let open = || {
+            let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+            log::debug!("Opening an SFTP channel to {}", self.config.host);
+            Ok(session.sftp()?)
+        };
+        let result = self.sftp.run(open, op);
+        if let Err(e) = &result {
+            log::debug!("SFTP operation failed: {}", e);
+            if e.is_transport() {
+                self.connection_lost(e);
+            }
+        }
+        result
+    }
+
+    /// Connects and authenticates, giving each step up to `timeout`. The
+    /// tunnel, when there is one, must outlive the session.
+    fn open_session(&self, timeout: Duration) -> Result<(Session, Option<JumpTunnel>), StepError> {
+        let host = &self.config.host;
+        log::info!(
+            "Connecting to {}@{}:{}",
+            self.config.username,
+            host,
+            self.config.port
+        );
+        let opened: Result<_, StepError> = (|| {
+            self.config
+                .validate_credentials()
+                .map_err(StorageError::InvalidInput)
+                .step(ConnectionStep::Credentials)?;
+            let (tcp, tunnel) = match &self.config.jump_host {
+                Some(jump) => {
+                    log::info!("Opening a tunnel through {}@{}", jump.username, jump.host);
+                    let (tunnel, tcp) = JumpTunnel::open(
+                        jump,
+                        host,
+                        self.config.port,
+                        self.config.known_hosts_file.as_deref(),
+                        timeout,
+                    )
+                    .step(ConnectionStep::JumpHost)?;
+                    (tcp, Some(tunnel))
+                }
+                None => (
+                    utils::connect_tcp(host, self.config.port, timeout)
+                        .step(ConnectionStep::Tcp)?,
+                    None,
+                ),
+            };
+
+            let mut session = Session::new().step(ConnectionStep::Handshake)?;
+            session.set_tcp_stream(tcp);
+            session.set_timeout(timeout.as_millis().min(u32::MAX as u128) as u32);
+            session.handshake().step(ConnectionStep::Handshake)?;
+            host_keys::verify_host_key(
+                &session,
+                host,
+                self.config.port,
+                self.config.known_hosts_file.as_deref(),
+            )
+            .step(ConnectionStep::HostKey)?;
+            self.authenticate(&session)
+                .step(ConnectionStep::Authentication)?;
+            // Only connecting is limited; transfers may take as long as
+            // they need.
+            session.set_timeout(0);
+            Ok((session, tunnel))
+        })();
+        match &opened {
+            Ok(_) => log::info!("Authenticated as {} on {}", self.config.username, host),
+            Err(e) => log::warn!("Connecting to {} failed at {}: {}", host, e.step, e.error),
+        }
+        opened
+    }
+
+    /// Connects and authenticates, then disconnects again, for testing the
+    /// settings without touching the current connection.
+    pub fn validate(&self) -> ValidationResult {
+        let started = Instant::now();
+        let result = self
+            .open_session(VALIDATION_TIMEOUT)
+            .map(|(session, tunnel)| {
+                let _ = session.disconnect(None, "Connection test done", None);
+                drop(session);
+                drop(tunnel);
+            });
+        ValidationResult::new(result, started)
+    }
+
+    /// Offers the configured key and/or password, reporting which of them
+    /// the server refused.
+    fn authenticate(&self, session: &Session) -> Result<(), StorageError> {
+        self.config.validate_credentials()?;
+        let username = &self.config.username;
+        if self.config.auth_method == AuthMethod::Agent {
+            return match session.userauth_agent(username) {
+                Ok(()) if session.authenticated() => Ok(()),
+                Ok(()) => Err("no agent key accepted".to_string()),
+                Err(e) => Err(format!("agent rejected ({})", e.message())),
+            }
+            .map_err(|reason| StorageError::AuthFailed { reason });
+        }
+        let mut failures = Vec::new();
+
+        if self.config.uses_key() {
+            let passphrase = self.config.key_passphrase.as_deref();
+            let pem_str = utils::decode_private_key(&self.config.pem_content, passphrase)?;
+            match session.userauth_pubkey_memory(username, None, &pem_str, passphrase) {
+                Ok(()) if session.authenticated() => return Ok(()),
+                Ok(()) => failures.push("key rejected".to_string()),
+                Err(e) => failures.push(format!("key rejected ({})", e.message())),
+            }
+        }
+        if let Some(password) = self
+            .config
+            .password
+            .as_deref()
+            .filter(|_| self.config.uses_password())
+        {
+            match session.userauth_password(username, password) {
+                Ok(()) if session.authenticated() => return Ok(()),
+                _ => failures.push("wrong password".to_string()),
+            }
+        }
+
+        Err(StorageError::AuthFailed {
+            reason: failures.join(", "),
+        })
+    }
+
+    fn stream_remote_command(
+        &self,
+        cmd: &str,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        self.metrics.record_command();
+        ssh_util::ssh_exec_streamed(session, cmd, None, on_chunk)
+    }
+
+    fn execute_remote_command_bytes(&self, cmd: &str) -> Result<Vec<u8>, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        self.metrics.record_command();
+        Ok(ssh_util::ssh_exec(session, cmd)?.stdout)
+    }
+
+    fn execute_remote_command_cancellable(
+        &self,
+        cmd: &str,
+        cancelled: &AtomicBool,
+    ) -> Result<Vec<u8>, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        self.metrics.record_command();
+        ssh_util::ssh_exec_cancellable(session, cmd, cancelled)
+    }
 }
 
-impl Storage for Ec2Storage {
-    fn connect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let addr = format!("{}:{}", self.config.host, self.config.port);
-        let tcp = TcpStream::connect_timeout(
-            &addr.parse()?,
-            Duration::from_secs(CONNECTION_TIMEOUT_SECS),
-        )?;
+/// Builds a `find` command listing entries under `root` whose name contains
+/// `pattern`, printing at most `max_results` stat records.
+fn find_command(root: &str, pattern: &str, case_sensitive: bool, max_results: usize) -> String {
+    let escaped: String = pattern
+        .chars()
+        .flat_map(|c| match c {
+            '*' | '?' | '[' | ']' | '\\' => vec!['\\', c],
+            _ => vec![c],
+        })
+        .collect();
+    format!(
+        "find {} -mindepth 1 {} {} -printf '%y\\t%s\\t%T@\\t%p\\0' 2>/dev/null | head -z -n {}",
+        shell_quote(root),
+        if case_sensitive { "-name" } else { "-iname" },
+        shell_quote(&format!("*{}*", escaped)),
+        max_results
+    )
+}
 
-        let mut session = Session::new()?;
-        session.set_tcp_stream(tcp);
-        session.handshake()?;
+/// Replaces `stat`, as readdir reports it, with the stat of the target when
+/// the entry is a symlink, returning whether it did. Links the server
+/// cannot follow, broken or looping, are left alone.
+fn resolve_symlink(sftp: &Sftp, entry_path: &Path, stat: &mut FileStat) -> bool {
+    if !stat.file_type().is_symlink() {
+        return false;
+    }
+    match sftp.stat(entry_path) {
+        Ok(target) => {
+            *stat = target;
+            true
+        }
+        Err(_) => false,
+    }
+}
 
-        let pem_bytes = utils::base64_decode(&self.config.pem_content)?;
-        let pem_str = String::from_utf8(pem_bytes)?;
+/// [`resolve_symlink`] for every entry of a listing, returning the links
+/// resolved.
+fn resolve_symlinks(sftp: &Sftp, entries: &mut [(PathBuf, FileStat)]) -> HashSet<PathBuf> {
+    entries
+        .iter_mut()
+        .filter_map(|(entry_path, stat)| {
+            resolve_symlink(sftp, entry_path, stat).then(|| entry_path.clone())
+        })
+        .collect()
+}
 
-        session.userauth_pubkey_memory(&self.config.username, None, &pem_str, None)?;
+/// Marks `info` as a symlink and records where it points.
+fn add_link_target(sftp: &Sftp, entry_path: &Path, info: &mut FileInfo) {
+    info.is_symlink = true;
+    info.link_target = sftp
+        .readlink(entry_path)
+        .ok()
+        .map(|target| target.to_string_lossy().into_owned());
+}
 
-        if !session.authenticated() {
-            return Err("Authentication failed".into());
+/// Moves `source` onto the existing `destination`, setting the old
+/// destination aside first and putting it back if the move fails.
+fn replace_by_rename(sftp: &Sftp, source: &Path, destination: &Path) -> Result<(), StorageError> {
+    let aside = aside_path(destination);
+    sftp.rename(destination, &aside, None)?;
+    if let Err(e) = sftp.rename(source, destination, None) {
+        if let Err(restore) = sftp.rename(&aside, destination, None) {
+            log::error!(
+                "Could not restore {} from {}: {}",
+                destination.display(),
+                aside.display(),
+                restore
+            );
         }
+        return Err(e.into());
+    }
+    if let Err(e) = sftp.unlink(&aside) {
+        log::warn!("Could not remove {}: {}", aside.display(), e);
+    }
+    Ok(())
+}
+
+/// A hidden sibling of `path` holding it while it is being replaced.
+fn aside_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map_or_else(|| "file".into(), |name| name.to_string_lossy());
+    path.with_file_name(format!(".{}.replaced-{}", name, std::process::id()))
+}
+
+fn entry_info(translator: &PathTranslator, entry_path: &Path, stat: &FileStat) -> FileInfo {
+    let absolute_path = entry_path.to_string_lossy().to_string();
+    // Entries are only ever listed below the base.
+    let remote = translator
+        .from_absolute(&absolute_path)
+        .unwrap_or_else(|| RemotePath::new(&absolute_path));
+    let mut info = utils::sftp_file_info(remote.as_str(), stat);
+    info.extra
+        .insert(ABSOLUTE_PATH_KEY.to_string(), absolute_path);
+    info
+}
 
+fn sort_key<'a>(name: &'a str, stat: &FileStat) -> SortKey<'a> {
+    SortKey {
+        name,
+        is_dir: stat.is_dir(),
+        size: stat.size.unwrap_or(0),
+        modified: stat.mtime,
+    }
+}
+
+/// A channel opened on first use and shared by later operations until one
+/// of them finds the connection broken.
+struct ChannelCache<C> {
+    channel: Mutex<Option<Arc<C>>>,
+}
+
+impl<C> Default for ChannelCache<C> {
+    fn default() -> Self {
+        ChannelCache {
+            channel: Mutex::new(None),
+        }
+    }
+}
+
+impl<C> ChannelCache<C> {
+    /// Runs `op` on the cached channel, opening one with `open` if there is
+    /// none. The channel is dropped when `op` fails with a transport error,
+    /// so the next call opens a fresh one.
+    fn run<T>(
+        &self,
+        open: impl FnOnce() -> Result<C, StorageError>,
+        op: impl FnOnce(&C) -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        let channel = {
+            let mut cached = self.channel.lock().map_err(|e| e.to_string())?;
+            match cached.as_ref() {
+                Some(channel) => channel.clone(),
+                None => {
+                    let channel = Arc::new(open()?);
+                    *cached = Some(channel.clone());
+                    channel
+                }
+            }
+        };
+        let result = op(&channel);
+        if result.as_ref().is_err_and(StorageError::is_transport) {
+            self.clear();
+        }
+        result
+    }
+
+    fn clear(&self) {
+        if let Ok(mut cached) = self.channel.lock() {
+            *cached = None;
+        }
+    }
+}
+
+/// The SFTP calls a directory delete makes, so it can be tested without a
+/// server.
+trait RemoveOps {
+    fn readdir(&self, dir: &Path) -> Result<Vec<(PathBuf, FileStat)>, ssh2::Error>;
+    fn lstat(&self, path: &Path) -> Result<FileStat, ssh2::Error>;
+    fn unlink(&self, path: &Path) -> Result<(), ssh2::Error>;
+    fn rmdir(&self, path: &Path) -> Result<(), ssh2::Error>;
+}
+
+impl RemoveOps for Sftp {
+    fn readdir(&self, dir: &Path) -> Result<Vec<(PathBuf, FileStat)>, ssh2::Error> {
+        Sftp::readdir(self, dir)
+    }
+
+    fn lstat(&self, path: &Path) -> Result<FileStat, ssh2::Error> {
+        Sftp::lstat(self, path)
+    }
+
+    fn unlink(&self, path: &Path) -> Result<(), ssh2::Error> {
+        Sftp::unlink(self, path)
+    }
+
+    fn rmdir(&self, path: &Path) -> Result<(), ssh2::Error> {
+        Sftp::rmdir(self, path)
+    }
+}
+
+/// Deletes the directory at `absolute`. A symlink is refused rather than
+/// followed, so nothing outside the requested tree is removed.
+fn delete_tree(
+    sftp: &impl RemoveOps,
+    absolute: &Path,
+    recursive: bool,
+) -> Result<DeleteDirectoryResult, StorageError> {
+    if !sftp.lstat(absolute)?.is_dir() {
+        return Err(StorageError::InvalidInput("not a directory".to_string()));
+    }
+    if !recursive && !sftp.readdir(absolute)?.is_empty() {
+        return Err(StorageError::InvalidInput(
+            "Directory not empty".to_string(),
+        ));
+    }
+    let mut result = DeleteDirectoryResult::default();
+    remove_tree(sftp, absolute, &mut result)?;
+    Ok(result)
+}
+
+/// Deletes `dir` bottom-up with unlink/rmdir. Symlinks are removed, never
+/// followed; servers that resolve them in readdir are checked with lstat.
+fn remove_tree(
+    sftp: &impl RemoveOps,
+    dir: &Path,
+    result: &mut DeleteDirectoryResult,
+) -> Result<(), ssh2::Error> {
+    for (entry_path, stat) in sftp.readdir(dir)? {
+        if stat.is_dir() && sftp.lstat(&entry_path)?.is_dir() {
+            remove_tree(sftp, &entry_path, result)?;
+        } else {
+            sftp.unlink(&entry_path)?;
+            result.files_removed += 1;
+        }
+    }
+    sftp.rmdir(dir)?;
+    result.dirs_removed += 1;
+    Ok(())
+}
+
+impl Storage for Ec2Storage {
+    fn connect(&mut self) -> Result<(), StorageError> {
+        let (session, tunnel) = self
+            .open_session(Duration::from_secs(CONNECTION_TIMEOUT_SECS))
+            .map_err(|e| e.error)?;
+        // A fresh flag, so a late report about the old session is ignored.
+        self.lost = Arc::new(AtomicBool::new(false));
+        self.keepalive = Keepalive::start(
+            &session,
+            self.config
+                .keepalive_secs
+                .unwrap_or(utils::DEFAULT_KEEPALIVE_SECS),
+            Some(utils::flag_lost(
+                &self.lost,
+                self.on_connection_lost.clone(),
+            )),
+        );
+        self.remote_thumbnailer = if self.config.remote_thumbnails {
+            thumbnails::probe_remote_thumbnailer(&session)
+        } else {
+            None
+        };
         self.session = Some(session);
+        self.tunnel = tunnel;
+        if self.config.generic_sftp && self.config.initial_path.is_none() {
+            self.login_dir = self
+                .with_sftp(|sftp| Ok(sftp.realpath(Path::new("."))?))
+                .map(|dir| dir.to_string_lossy().into_owned())
+                .inspect_err(|e| log::warn!("Could not ask for the login directory: {}", e))
+                .ok();
+        }
         Ok(())
     }
 
     fn disconnect(&mut self) {
+        self.keepalive = None;
+        self.remote_thumbnailer = None;
+        self.sftp.clear();
         if let Some(session) = self.session.take() {
             let _ = session.disconnect(None, "Closing connection", None);
         }
+        // The target session runs over the tunnel, so it goes second.
+        self.tunnel = None;
     }
 
+    // `session` is only stored once authenticated. Asking the session itself
+    // would wait behind any transfer holding its lock.
     fn is_connected(&self) -> bool {
-        self.session.as_ref().is_some_and(|s| s.authenticated())
+        self.session.is_some() && !self.lost.load(Ordering::Relaxed)
+    }
+
+    fn set_connection_lost_handler(&mut self, handler: ConnectionLostHandler) {
+        self.on_connection_lost = Some(handler);
+    }
+
+    fn connection_lost(&self, error: &StorageError) {
+        if !self.lost.swap(true, Ordering::Relaxed) {
+            log::warn!("Connection to {} lost: {}", self.config.host, error);
+        }
+        if let Some(on_lost) = &self.on_connection_lost {
+            on_lost(error);
+        }
     }
 
-    fn list_directory(&self, path: &str) -> Result<Vec<FileInfo>, Box<dyn std::error::Error>> {
-        let session = self.session.as_ref().ok_or("Not connected")?;
-        let sftp = session.sftp()?;
-        let entries = sftp.readdir(Path::new(path))?;
+    fn auto_reconnect(&self) -> bool {
+        self.config.auto_reconnect
+    }
 
-        let mut files = Vec::new();
-        for (entry_path, stat) in entries {
-            let name = entry_path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("unknown")
-                .to_string();
+    fn ping(&self) -> Result<(), StorageError> {
+        self.execute_remote_command_bytes("true").map(|_| ())
+    }
+
+    fn metrics(&self) -> &StorageMetrics {
+        &self.metrics
+    }
+
+    fn list_directory_page(
+        &self,
+        path: &str,
+        options: &ListOptions,
+    ) -> Result<(Vec<FileInfo>, usize), StorageError> {
+        self.with_sftp(|sftp| {
+            let translator = self.path_translator();
+            let mut entries = sftp.readdir(Path::new(&translator.resolve(path)))?;
 
-            let mime_type = if stat.is_dir() {
-                None
+            entries.retain(|(entry_path, _)| {
+                let name = entry_path.file_name().unwrap_or_default().to_string_lossy();
+                storage::is_listed(&name, options.show_hidden, &[])
+            });
+            let resolved = if options.resolve_symlinks {
+                resolve_symlinks(sftp, &mut entries)
             } else {
-                detect_mime_type(&name)
+                HashSet::new()
             };
-
-            files.push(FileInfo {
-                name: name.clone(),
-                path: entry_path.to_string_lossy().to_string(),
-                size: stat.size.unwrap_or(0),
-                is_dir: stat.is_dir(),
-                modified: stat.mtime,
-                mime_type,
-                thumbnail: None,
+            entries.retain(|(entry_path, stat)| {
+                let name = entry_path.file_name().unwrap_or_default().to_string_lossy();
+                options.filter.matches_entry(&name, stat.is_dir())
             });
-        }
+            entries.sort_by(|(a, a_stat), (b, b_stat)| {
+                let a_name = a.file_name().unwrap_or_default().to_string_lossy();
+                let b_name = b.file_name().unwrap_or_default().to_string_lossy();
+                storage::compare_entries(
+                    &sort_key(&a_name, a_stat),
+                    &sort_key(&b_name, b_stat),
+                    options.sort_by,
+                    options.sort_order,
+                )
+            });
+
+            // Only the requested slice is converted into `FileInfo`s.
+            let files = entries
+                .iter()
+                .skip(options.offset)
+                .take(options.limit)
+                .map(|(entry_path, stat)| {
+                    let mut info = entry_info(&translator, entry_path, stat);
+                    if info.is_symlink || resolved.contains(entry_path) {
+                        add_link_target(sftp, entry_path, &mut info);
+                    }
+                    info
+                })
+                .collect();
+            Ok((files, entries.len()))
+        })
+    }
+
+    fn list_directory_streamed(
+        &self,
+        path: &str,
+        options: &ListOptions,
+        batch_size: usize,
+        on_batch: &mut BatchCallback<'_>,
+    ) -> Result<usize, StorageError> {
+        self.with_sftp(|sftp| {
+            let translator = self.path_translator();
+            let dir = PathBuf::from(translator.resolve(path));
+            let mut handle = sftp.opendir(&dir)?;
+            let mut batcher = ListingBatcher::new(batch_size, on_batch);
+            loop {
+                let (name, mut stat) = match handle.readdir() {
+                    Ok(entry) => entry,
+                    Err(e) if e.code() == ssh2::ErrorCode::Session(END_OF_DIRECTORY) => break,
+                    Err(e) => return Err(e.into()),
+                };
+                let name = name.to_string_lossy();
+                if name == "."
+                    || name == ".."
+                    || !storage::is_listed(&name, options.show_hidden, &[])
+                {
+                    continue;
+                }
+                let entry_path = dir.join(name.as_ref());
+                let resolved =
+                    options.resolve_symlinks && resolve_symlink(sftp, &entry_path, &mut stat);
+                if !options.filter.matches_entry(&name, stat.is_dir()) {
+                    continue;
+                }
+                let mut info = entry_info(&translator, &entry_path, &stat);
+                if info.is_symlink || resolved {
+                    add_link_target(sftp, &entry_path, &mut info);
+                }
+                if !batcher.push(info) {
+                    break;
+                }
+            }
+            Ok(batcher.finish())
+        })
+    }
+
+    fn list_directory_recursive(
+        &self,
+        path: &str,
+        max_depth: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<RecursiveListing, StorageError> {
+        self.with_sftp(|sftp| {
+            let translator = self.path_translator();
+            let root = PathBuf::from(translator.resolve(path));
+
+            let mut files = Vec::new();
+            let mut pending = VecDeque::from([(root.clone(), 1usize)]);
+            'walk: while let Some((dir, depth)) = pending.pop_front() {
+                if cancelled.load(Ordering::Relaxed) {
+                    return Err(StorageError::Cancelled);
+                }
+                let entries = match sftp.readdir(&dir) {
+                    Ok(entries) => entries,
+                    Err(e) if dir == root => return Err(e.into()),
+                    // Unreadable subdirectories are skipped rather than failing the walk.
+                    Err(_) => continue,
+                };
+                for (entry_path, stat) in entries {
+                    if files.len() > MAX_RECURSIVE_ENTRIES {
+                        break 'walk;
+                    }
+                    // readdir reports symlinks unresolved, so they are never queued.
+                    if stat.is_dir() && (max_depth == 0 || depth < max_depth) {
+                        pending.push_back((entry_path.clone(), depth + 1));
+                    }
+                    files.push(entry_info(&translator, &entry_path, &stat));
+                }
+            }
+
+            Ok(RecursiveListing::capped(files))
+        })
+    }
+
+    fn search(
+        &self,
+        root: &str,
+        pattern: &str,
+        case_sensitive: bool,
+        limit: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<SearchResult, StorageError> {
+        let translator = self.path_translator();
+        let find_cmd = find_command(
+            &translator.resolve(root),
+            pattern,
+            case_sensitive,
+            limit.saturating_add(1),
+        );
+        let output = self.execute_remote_command_cancellable(&find_cmd, cancelled)?;
+        let files = utils::parse_stat_records(&output)
+            .iter()
+            .map(|(path, stat)| entry_info(&translator, Path::new(path), stat))
+            .collect();
+        Ok(SearchResult::capped(files, limit))
+    }
 
-        files.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-            (true, false) => std::cmp::Ordering::Less,
-            (false, true) => std::cmp::Ordering::Greater,
-            _ => a.name.cmp(&b.name),
+    fn search_contents(
+        &self,
+        root: &str,
+        query: &str,
+        regex: bool,
+        include: Option<&str>,
+        limit: usize,
+    ) -> Result<ContentSearchResult, StorageError> {
+        let translator = self.path_translator();
+        let grep_cmd = utils::grep_command(
+            &translator.resolve(root),
+            query,
+            regex,
+            include,
+            None,
+            limit.saturating_add(1),
+        );
+        let mut matches = utils::parse_grep_output(&self.execute_remote_command_bytes(&grep_cmd)?);
+        matches.retain_mut(|m| match translator.from_absolute(&m.path) {
+            Some(path) => {
+                m.path = path.to_string();
+                true
+            }
+            None => false,
         });
+        let truncated = matches.len() > limit;
+        matches.truncate(limit);
+        Ok(ContentSearchResult { matches, truncated })
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, StorageError> {
+        metrics::metered_read(&self.metrics, || {
+            self.with_sftp(|sftp| {
+                let mut file = sftp.open(Path::new(&self.path_translator().resolve(path)))?;
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents)?;
+                Ok(contents)
+            })
+        })
+    }
 
-        Ok(files)
+    fn read_file_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, bool), StorageError> {
+        let start = Instant::now();
+        let (data, at_end) = self.with_sftp(|sftp| {
+            let mut file = sftp.open(Path::new(&self.path_translator().resolve(path)))?;
+            file.seek(SeekFrom::Start(offset))?;
+
+            // One extra byte tells whether anything follows the range.
+            let mut data = Vec::new();
+            file.take(length.saturating_add(1)).read_to_end(&mut data)?;
+            Ok(storage::finish_range_read(data, length))
+        })?;
+        self.metrics.record_read(data.len() as u64, start.elapsed());
+        Ok((data, at_end))
     }
 
-    fn read_file(&self, path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let session = self.session.as_ref().ok_or("Not connected")?;
-        let sftp = session.sftp()?;
-        let mut file = sftp.open(Path::new(path))?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)?;
-        Ok(contents)
+    fn read_files(&self, paths: &[String]) -> Result<Vec<FileReadOutcome>, StorageError> {
+        self.with_sftp(|sftp| {
+            let translator = self.path_translator();
+            Ok(paths
+                .iter()
+                .map(|path| {
+                    metrics::metered_read(&self.metrics, || {
+                        let mut file = sftp
+                            .open(Path::new(&translator.resolve(path)))
+                            .map_err(|e| e.to_string())?;
+                        let mut contents = Vec::new();
+                        file.read_to_end(&mut contents).map_err(|e| e.to_string())?;
+                        Ok(contents)
+                    })
+                })
+                .collect())
+        })
     }
 
-    fn get_file_thumbnail(
+    fn read_file_streamed(
         &self,
         path: &str,
-        max_size: u32,
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        let content = self.read_file(path)?;
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        let start = Instant::now();
+        let mut read = 0u64;
+        let result = self.with_sftp(|sftp| {
+            let mut file = sftp.open(Path::new(&self.path_translator().resolve(path)))?;
+            let total = file.stat()?.size;
 
-        let mime = detect_mime_type(path).unwrap_or_else(|| "application/octet-stream".to_string());
+            let mut buf = vec![0u8; STREAM_CHUNK_SIZE];
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    return Ok(true);
+                }
+                read += n as u64;
+                if !on_chunk(&buf[..n], total) {
+                    return Ok(false);
+                }
+            }
+        });
+        if result.is_ok() {
+            self.metrics.record_read(read, start.elapsed());
+        }
+        result
+    }
 
-        if mime.starts_with("image/") {
-            let img = image::load_from_memory(&content)?;
-            let (width, height) = img.dimensions();
-            let scale = if width > height {
-                max_size as f32 / width as f32
-            } else {
-                max_size as f32 / height as f32
+    fn write_file(&self, path: &str, data: &[u8]) -> Result<(), StorageError> {
+        metrics::metered_write(&self.metrics, data.len(), || {
+            self.with_sftp(|sftp| {
+                let path = self.path_translator().resolve(path);
+                if let Some(parent) = utils::parent_path(&path) {
+                    if sftp.stat(Path::new(parent)).is_err() {
+                        return Err(StorageError::NotFound(parent.to_string()));
+                    }
+                }
+                let mut file = sftp.create(Path::new(&path))?;
+                file.write_all(data)?;
+                Ok(())
+            })
+        })
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.with_sftp(|sftp| {
+            Ok(utils::sftp_exists(
+                sftp,
+                Path::new(&self.path_translator().resolve(path)),
+            )?)
+        })
+    }
+
+    fn stat(&self, path: &str) -> Result<FileInfo, StorageError> {
+        self.with_sftp(|sftp| {
+            let translator = self.path_translator();
+            let absolute = translator.resolve(path);
+            match sftp.stat(Path::new(&absolute)) {
+                Ok(stat) => Ok(entry_info(&translator, Path::new(&absolute), &stat)),
+                Err(e) if utils::is_sftp_not_found(&e) => Err(StorageError::NotFound(
+                    translator.to_remote(path).to_string(),
+                )),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn set_permissions(&self, path: &str, mode: u32) -> Result<FileInfo, StorageError> {
+        self.with_sftp(|sftp| {
+            let absolute = self.path_translator().resolve(path);
+            sftp.setstat(
+                Path::new(&absolute),
+                FileStat {
+                    size: None,
+                    uid: None,
+                    gid: None,
+                    perm: Some(mode),
+                    atime: None,
+                    mtime: None,
+                },
+            )?;
+            self.stat(path)
+        })
+    }
+
+    fn set_modified(&self, path: &str, mtime: u64) -> Result<FileInfo, StorageError> {
+        self.with_sftp(|sftp| {
+            let translator = self.path_translator();
+            let absolute = translator.resolve(path);
+            let current = match sftp.stat(Path::new(&absolute)) {
+                Ok(stat) => stat,
+                Err(e) if utils::is_sftp_not_found(&e) => {
+                    return Err(StorageError::NotFound(
+                        translator.to_remote(path).to_string(),
+                    ))
+                }
+                Err(e) => return Err(e.into()),
             };
-            let new_width = (width as f32 * scale) as u32;
-            let new_height = (height as f32 * scale) as u32;
-            let resized = img.resize(new_width, new_height, image::imageops::FilterType::Lanczos3);
-
-            let mut buf = Vec::new();
-            let format = match Path::new(path).extension().and_then(|e| e.to_str()) {
-                Some("png") => image::ImageFormat::Png,
-                Some("gif") => image::ImageFormat::Gif,
-                Some("webp") => image::ImageFormat::WebP,
-                _ => image::ImageFormat::Jpeg,
+            // SFTP sets both times together, so carry the access time over.
+            sftp.setstat(
+                Path::new(&absolute),
+                FileStat {
+                    size: None,
+                    uid: None,
+                    gid: None,
+                    perm: None,
+                    atime: Some(current.atime.unwrap_or(mtime)),
+                    mtime: Some(mtime),
+                },
+            )?;
+            self.stat(path)
+        })
+    }
+
+    fn directory_size(&self, path: &str) -> Result<DirectoryUsage, StorageError> {
+        let root = self.path_translator().resolve(path);
+        let output = self.execute_remote_command_bytes(&utils::disk_usage_command(&root, None))?;
+        if let Some(usage) = utils::parse_disk_usage(&String::from_utf8_lossy(&output)) {
+            return Ok(usage);
+        }
+
+        // Without du/find on the host, walk the tree over SFTP instead.
+        self.with_sftp(|sftp| {
+            let root = PathBuf::from(root);
+            let mut usage = DirectoryUsage::default();
+            let mut visited = 0usize;
+            let mut pending = VecDeque::from([root.clone()]);
+            while let Some(dir) = pending.pop_front() {
+                let entries = match sftp.readdir(&dir) {
+                    Ok(entries) => entries,
+                    Err(e) if dir == root => return Err(e.into()),
+                    Err(_) => continue,
+                };
+                for (entry_path, stat) in entries {
+                    visited += 1;
+                    if visited > MAX_RECURSIVE_ENTRIES {
+                        usage.truncated = true;
+                        return Ok(usage);
+                    }
+                    // readdir does not resolve symlinks, so a link to a directory
+                    // is counted as a file and never descended into.
+                    if stat.is_dir() {
+                        usage.dir_count += 1;
+                        pending.push_back(entry_path);
+                    } else {
+                        usage.file_count += 1;
+                        usage.total_bytes += stat.size.unwrap_or(0);
+                    }
+                }
+            }
+            Ok(usage)
+        })
+    }
+
+    fn archive_directory(
+        &self,
+        path: &str,
+        format: ArchiveFormat,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        let output =
+            self.execute_remote_command_bytes(&archive::tool_check_command(format.remote_tool()))?;
+        if !String::from_utf8_lossy(&output).contains("yes") {
+            return Err(StorageError::ToolMissing(format.remote_tool()));
+        }
+        let archive_cmd =
+            archive::archive_command(&self.path_translator().resolve(path), format, None);
+        self.stream_remote_command(&archive_cmd, on_chunk)
+    }
+
+    fn extract_archive(&self, path: &str, destination: &str) -> Result<Vec<String>, StorageError> {
+        let format = ExtractFormat::from_path(path)
+            .ok_or_else(|| format!("Unsupported archive type: {}", path))?;
+        let output =
+            self.execute_remote_command_bytes(&archive::tool_check_command(format.remote_tool()))?;
+        if !String::from_utf8_lossy(&output).contains("yes") {
+            return Err(StorageError::ToolMissing(format.remote_tool()));
+        }
+
+        let translator = self.path_translator();
+        let extract_cmd = archive::extract_command(
+            &translator.resolve(path),
+            &translator.resolve(destination),
+            format,
+        );
+        let output = self.execute_remote_command_bytes(&extract_cmd)?;
+        let entries = archive::parse_extract_output(&String::from_utf8_lossy(&output))?;
+        Ok(entries)
+    }
+
+    fn checksum(
+        &self,
+        path: &str,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<FileChecksum, StorageError> {
+        let info = self.stat(path)?;
+        if info.is_dir {
+            return Err(StorageError::InvalidInput("is a directory".to_string()));
+        }
+        let hash_cmd = format!(
+            "{} -- {} 2>/dev/null",
+            algorithm.remote_tool(),
+            shell_quote(&self.path_translator().resolve(path))
+        );
+        let output = self.execute_remote_command_bytes(&hash_cmd)?;
+        let digest =
+            match checksum::parse_checksum_output(&String::from_utf8_lossy(&output), algorithm) {
+                Some(digest) => digest,
+                None => checksum::hash_streamed(self, path, algorithm)?,
             };
-            resized.write_to(&mut std::io::Cursor::new(&mut buf), format)?;
-            let base64_content = utils::base64_encode(&buf);
-            Ok(format!("data:{};base64,{}", mime, base64_content))
-        } else {
-            let base64_content = utils::base64_encode(&content);
-            Ok(format!("data:{};base64,{}", mime, base64_content))
+        Ok(FileChecksum {
+            algorithm,
+            digest,
+            size: info.size,
+        })
+    }
+
+    fn checksums(
+        &self,
+        paths: &[String],
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<Vec<Option<String>>, StorageError> {
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        let translator = self.path_translator();
+        let absolute: Vec<String> = paths.iter().map(|p| translator.resolve(p)).collect();
+        let output = self.execute_remote_command_bytes(&checksum::batch_checksum_command(
+            &absolute, algorithm,
+        ))?;
+        let digests = checksum::parse_checksum_lines(&String::from_utf8_lossy(&output), algorithm);
+        if digests.is_empty() {
+            // Most likely the tool is missing; hash each file locally instead.
+            return Ok(paths
+                .iter()
+                .map(|p| checksum::hash_streamed(self, p, algorithm).ok())
+                .collect());
         }
+        Ok(absolute.iter().map(|p| digests.get(p).cloned()).collect())
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        self.with_sftp(|sftp| {
+            let path = self.path_translator().resolve(path);
+            if sftp.stat(Path::new(&path))?.is_dir() {
+                return Err(StorageError::InvalidInput("is a directory".to_string()));
+            }
+            sftp.unlink(Path::new(&path))?;
+            Ok(())
+        })
+    }
+
+    fn delete_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+    ) -> Result<DeleteDirectoryResult, StorageError> {
+        self.with_sftp(|sftp| {
+            let translator = self.path_translator();
+            let dir = paths::guard_directory_delete(&translator, path)?;
+            let absolute = PathBuf::from(translator.to_absolute(&dir));
+            delete_tree(sftp, &absolute, recursive)
+        })
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<FileInfo, StorageError> {
+        self.with_sftp(|sftp| {
+            let translator = self.path_translator();
+            let source = translator.resolve(from);
+            let destination = translator.to_remote(to);
+            let destination_abs = translator.to_absolute(&destination);
+            let (source_path, destination_path) = (Path::new(&source), Path::new(&destination_abs));
+
+            // Checked before the destination is touched.
+            match sftp.stat(source_path) {
+                Ok(_) => {}
+                Err(e) if utils::is_sftp_not_found(&e) => {
+                    return Err(StorageError::NotFound(
+                        translator.to_remote(from).to_string(),
+                    ))
+                }
+                Err(e) => return Err(e.into()),
+            }
+            if source != destination_abs {
+                if let Some(parent) = utils::parent_path(&destination_abs) {
+                    if !utils::sftp_exists(sftp, Path::new(parent))? {
+                        return Err(StorageError::NotFound(parent.to_string()));
+                    }
+                }
+                let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+                match sftp.rename(source_path, destination_path, Some(flags)) {
+                    Ok(()) => {}
+                    // SFTP v3 servers ignore the flags and refuse to rename
+                    // onto an existing file.
+                    Err(_) if utils::sftp_exists(sftp, destination_path)? => {
+                        replace_by_rename(sftp, source_path, destination_path)?
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            let stat = sftp.stat(Path::new(&destination_abs))?;
+            let mut info = utils::sftp_file_info(destination.as_str(), &stat);
+            info.extra
+                .insert(ABSOLUTE_PATH_KEY.to_string(), destination_abs);
+            Ok(info)
+        })
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> Result<FileInfo, StorageError> {
+        self.with_sftp(|sftp| {
+            let translator = self.path_translator();
+            let source = translator.resolve(from);
+            let destination = translator.to_remote(to);
+            let destination_abs = translator.to_absolute(&destination);
+
+            if sftp.stat(Path::new(&source))?.is_dir() {
+                return Err(StorageError::InvalidInput(
+                    "is a directory; use copy_directory".to_string(),
+                ));
+            }
+            if let Some(parent) = utils::parent_path(&destination_abs) {
+                if !utils::sftp_exists(sftp, Path::new(parent))? {
+                    return Err(StorageError::NotFound(parent.to_string()));
+                }
+            }
+
+            // cp is silent on success, so anything it prints is an error.
+            let cp_cmd = format!(
+                "cp -p -- {} {} 2>&1",
+                shell_quote(&source),
+                shell_quote(&destination_abs)
+            );
+            let output = self.execute_remote_command_bytes(&cp_cmd)?;
+            if !output.is_empty() {
+                return Err(String::from_utf8_lossy(&output).trim().to_string().into());
+            }
+
+            let stat = sftp.stat(Path::new(&destination_abs))?;
+            Ok(entry_info(&translator, Path::new(&destination_abs), &stat))
+        })
+    }
+
+    fn create_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+    ) -> Result<CreateDirectoryResult, StorageError> {
+        self.with_sftp(|sftp| {
+            let translator = self.path_translator();
+            let remote = translator.to_remote(path);
+            let absolute = translator.to_absolute(&remote);
+
+            let existed = match sftp.stat(Path::new(&absolute)) {
+                Ok(stat) if stat.is_dir() => true,
+                Ok(_) => {
+                    return Err(StorageError::InvalidInput(format!(
+                        "Path exists and is not a directory: {}",
+                        absolute
+                    )))
+                }
+                Err(_) => false,
+            };
+
+            if !existed {
+                if recursive {
+                    let mut current = String::new();
+                    for segment in absolute.split('/').filter(|s| !s.is_empty()) {
+                        current = format!("{}/{}", current, segment);
+                        if !utils::sftp_exists(sftp, Path::new(&current))? {
+                            sftp.mkdir(Path::new(&current), DIRECTORY_MODE)?;
+                        }
+                    }
+                } else {
+                    if let Some(parent) = utils::parent_path(&absolute) {
+                        if !utils::sftp_exists(sftp, Path::new(parent))? {
+                            return Err(
+                                format!("Parent directory does not exist: {}", parent).into()
+                            );
+                        }
+                    }
+                    sftp.mkdir(Path::new(&absolute), DIRECTORY_MODE)?;
+                }
+            }
+
+            let stat = sftp.stat(Path::new(&absolute))?;
+            let mut directory = utils::sftp_file_info(remote.as_str(), &stat);
+            directory
+                .extra
+                .insert(ABSOLUTE_PATH_KEY.to_string(), absolute);
+            Ok(CreateDirectoryResult { directory, existed })
+        })
+    }
+
+    fn get_file_thumbnail(
+        &self,
+        path: &str,
+        max_size: u32,
+        options: ThumbnailOptions,
+    ) -> Result<Thumbnail, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        thumbnails::remote_file_thumbnail(
+            self,
+            session,
+            self.remote_thumbnailer,
+            &self.path_translator().resolve(path),
+            path,
+            max_size,
+            options,
+        )
+        .map_err(StorageError::from)
+    }
+
+    fn remote_thumbnailer(&self) -> Option<RemoteThumbnailer> {
+        self.remote_thumbnailer
+    }
+
+    fn probe_media(&self, path: &str) -> Result<MediaProbe, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        media_probe::probe_remote(self, session, &self.path_translator().resolve(path), path)
+            .map_err(StorageError::from)
     }
 
     fn get_root_path(&self) -> String {
+        if let Some(path) = &self.config.initial_path {
+            return RemotePath::new(path).to_string();
+        }
+        if let Some(dir) = &self.login_dir {
+            return dir.clone();
+        }
+        if self.config.generic_sftp {
+            return "/".to_string();
+        }
         if self.config.username == "root" {
             "/root".to_string()
         } else {
@@ -160,14 +1368,40 @@ impl Storage for Ec2Storage {
         }
     }
 
+    fn path_translator(&self) -> PathTranslator {
+        PathTranslator::new(&self.get_root_path())
+    }
+
     fn storage_type(&self) -> StorageType {
-        StorageType::Ec2
+        if self.config.generic_sftp {
+            StorageType::Sftp
+        } else {
+            StorageType::Ec2
+        }
+    }
+
+    fn connection_id(&self) -> String {
+        format!(
+            "{}@{}:{}",
+            self.config.username, self.config.host, self.config.port
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_aside_path() {
+        assert_eq!(
+            aside_path(Path::new("/data/photos/a.jpg")),
+            PathBuf::from(format!(
+                "/data/photos/.a.jpg.replaced-{}",
+                std::process::id()
+            ))
+        );
+    }
     use base64::Engine;
 
     fn create_test_config() -> Ec2Config {
@@ -176,9 +1410,182 @@ mod tests {
             username: "testuser".to_string(),
             pem_content: base64::engine::general_purpose::STANDARD.encode(b"test key"),
             port: 22,
+            password: None,
+            auth_method: AuthMethod::Auto,
+            key_passphrase: None,
+            known_hosts_file: None,
+            keepalive_secs: None,
+            auto_reconnect: false,
+            remote_thumbnails: false,
+            jump_host: None,
+            initial_path: None,
+            generic_sftp: false,
+        }
+    }
+
+    #[test]
+    fn test_find_command_escapes_pattern() {
+        assert_eq!(
+            find_command("/home/ubuntu/my photos", "IMG_2023", false, 501),
+            "find '/home/ubuntu/my photos' -mindepth 1 -iname '*IMG_2023*' \
+             -printf '%y\\t%s\\t%T@\\t%p\\0' 2>/dev/null | head -z -n 501"
+        );
+        assert_eq!(
+            find_command("/srv", "it's [1]*", true, 11),
+            "find /srv -mindepth 1 -name '*it'\\''s \\[1\\]\\**' \
+             -printf '%y\\t%s\\t%T@\\t%p\\0' 2>/dev/null | head -z -n 11"
+        );
+    }
+
+    #[test]
+    fn test_validate_credentials() {
+        let mut config = create_test_config();
+        assert!(config.validate_credentials().is_ok());
+
+        config.auth_method = AuthMethod::Password;
+        assert!(config.validate_credentials().is_err());
+        config.password = Some("hunter2".to_string());
+        assert!(config.validate_credentials().is_ok());
+
+        config.pem_content.clear();
+        config.auth_method = AuthMethod::Key;
+        assert!(config.validate_credentials().is_err());
+        config.auth_method = AuthMethod::Auto;
+        assert!(config.validate_credentials().is_ok());
+    }
+
+    #[test]
+    fn test_with_sftp_requires_connection() {
+        let storage = Ec2Storage::new(create_test_config());
+        let result = storage.with_sftp(|_| Ok(()));
+        assert!(matches!(result, Err(StorageError::NotConnected)));
+        assert!(storage.sftp.channel.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_channel_cache_reuses_channel() {
+        let cache = ChannelCache::default();
+        let opened = std::cell::Cell::new(0);
+        let open = || {
+            opened.set(opened.get() + 1);
+            Ok(opened.get())
+        };
+        assert_eq!(cache.run(open, |channel| Ok(*channel)).unwrap(), 1);
+        assert_eq!(cache.run(open, |channel| Ok(*channel)).unwrap(), 1);
+        // Failures that leave the connection usable keep the channel.
+        let failed: Result<(), _> =
+            cache.run(open, |_| Err(StorageError::NotFound("/a.jpg".to_string())));
+        assert!(failed.is_err());
+        assert_eq!(cache.run(open, |channel| Ok(*channel)).unwrap(), 1);
+        assert_eq!(opened.get(), 1);
+    }
+
+    #[test]
+    fn test_channel_cache_reopens_after_transport_error() {
+        let cache = ChannelCache::default();
+        let opened = std::cell::Cell::new(0);
+        let open = || {
+            opened.set(opened.get() + 1);
+            Ok(opened.get())
+        };
+        let failed: Result<(), _> = cache.run(open, |_| {
+            Err(StorageError::ConnectionLost("reset".to_string()))
+        });
+        assert!(matches!(failed, Err(StorageError::ConnectionLost(_))));
+        assert!(cache.channel.lock().unwrap().is_none());
+        assert_eq!(cache.run(open, |channel| Ok(*channel)).unwrap(), 2);
+        cache.clear();
+        assert_eq!(cache.run(open, |channel| Ok(*channel)).unwrap(), 3);
+    }
+
+    /// A server whose readdir follows symlinks, backed by the local disk.
+    #[cfg(unix)]
+    struct LocalSftp;
+
+    #[cfg(unix)]
+    impl RemoveOps for LocalSftp {
+        fn readdir(&self, dir: &Path) -> Result<Vec<(PathBuf, FileStat)>, ssh2::Error> {
+            let entries = std::fs::read_dir(dir).map_err(local_error)?;
+            entries
+                .map(|entry| {
+                    let path = entry.map_err(local_error)?.path();
+                    let stat = file_stat(std::fs::metadata(&path).map_err(local_error)?);
+                    Ok((path, stat))
+                })
+                .collect()
+        }
+
+        fn lstat(&self, path: &Path) -> Result<FileStat, ssh2::Error> {
+            Ok(file_stat(
+                std::fs::symlink_metadata(path).map_err(local_error)?,
+            ))
+        }
+
+        fn unlink(&self, path: &Path) -> Result<(), ssh2::Error> {
+            std::fs::remove_file(path).map_err(local_error)
+        }
+
+        fn rmdir(&self, path: &Path) -> Result<(), ssh2::Error> {
+            std::fs::remove_dir(path).map_err(local_error)
         }
     }
 
+    #[cfg(unix)]
+    fn file_stat(metadata: std::fs::Metadata) -> FileStat {
+        use std::os::unix::fs::MetadataExt;
+        FileStat {
+            size: Some(metadata.len()),
+            uid: None,
+            gid: None,
+            perm: Some(metadata.mode()),
+            atime: None,
+            mtime: None,
+        }
+    }
+
+    #[cfg(unix)]
+    fn local_error(_: std::io::Error) -> ssh2::Error {
+        ssh2::Error::new(ssh2::ErrorCode::SFTP(4), "local operation failed")
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_delete_tree_does_not_follow_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("keep.jpg"), b"keep").unwrap();
+        let photos = dir.path().join("photos");
+        std::fs::create_dir(&photos).unwrap();
+        std::fs::write(photos.join("a.jpg"), b"a").unwrap();
+        symlink(&outside, photos.join("link")).unwrap();
+        symlink(&outside, dir.path().join("top")).unwrap();
+
+        assert!(matches!(
+            delete_tree(&LocalSftp, &dir.path().join("top"), true),
+            Err(StorageError::InvalidInput(_))
+        ));
+        let result = delete_tree(&LocalSftp, &photos, true).unwrap();
+        assert_eq!((result.files_removed, result.dirs_removed), (2, 1));
+        assert!(!photos.exists());
+        assert!(outside.join("keep.jpg").exists());
+    }
+
+    #[test]
+    fn test_config_debug_hides_credentials() {
+        let config = Ec2Config {
+            password: Some("hunter2".to_string()),
+            ..create_test_config()
+        };
+        let debug = format!("{:?}", config);
+        assert!(debug.contains("localhost"));
+        assert!(!debug.contains("hunter2"));
+        assert!(!debug.contains("key_passphrase"));
+        assert!(!debug.contains(&config.pem_content));
+    }
+
     #[test]
     fn test_ec2_storage_creation() {
         let config = create_test_config();
@@ -194,6 +1601,16 @@ mod tests {
             username: "ubuntu".to_string(),
             pem_content: "dGVzdA==".to_string(),
             port: 22,
+            password: None,
+            auth_method: AuthMethod::Auto,
+            key_passphrase: None,
+            known_hosts_file: None,
+            keepalive_secs: None,
+            auto_reconnect: false,
+            remote_thumbnails: false,
+            jump_host: None,
+            initial_path: None,
+            generic_sftp: false,
         };
         let storage = Ec2Storage::new(config);
         assert_eq!(storage.get_root_path(), "/home/ubuntu");
@@ -206,11 +1623,81 @@ mod tests {
             username: "root".to_string(),
             pem_content: "dGVzdA==".to_string(),
             port: 22,
+            password: None,
+            auth_method: AuthMethod::Auto,
+            key_passphrase: None,
+            known_hosts_file: None,
+            keepalive_secs: None,
+            auto_reconnect: false,
+            remote_thumbnails: false,
+            jump_host: None,
+            initial_path: None,
+            generic_sftp: false,
         };
         let storage = Ec2Storage::new(config);
         assert_eq!(storage.get_root_path(), "/root");
     }
 
+    #[test]
+    fn test_path_translator_is_rooted_at_home() {
+        let storage = Ec2Storage::new(create_test_config());
+        let translator = storage.path_translator();
+        assert_eq!(translator.resolve("/"), "/home/testuser");
+        assert_eq!(
+            translator.resolve("/photos/a.jpg"),
+            "/home/testuser/photos/a.jpg"
+        );
+        // Listing paths are emitted root-relative and must resolve back to
+        // the same absolute path when fed into read_file.
+        let listed = translator
+            .from_absolute("/home/testuser/photos/a.jpg")
+            .unwrap();
+        assert_eq!(listed.as_str(), "/photos/a.jpg");
+        assert_eq!(
+            translator.resolve(listed.as_str()),
+            "/home/testuser/photos/a.jpg"
+        );
+    }
+
+    #[test]
+    fn test_sftp_config() {
+        let config: SftpConfig = serde_json::from_value(serde_json::json!({
+            "host": "nas.local",
+            "port": null,
+            "username": "admin",
+            "auth": { "method": "agent" },
+        }))
+        .unwrap();
+        let config = config.into_ec2_config(None);
+        assert_eq!(config.port, 22);
+        assert_eq!(config.auth_method, AuthMethod::Agent);
+        assert!(config.validate_credentials().is_ok());
+
+        let storage = Ec2Storage::new(config.clone());
+        assert_eq!(storage.storage_type(), StorageType::Sftp);
+        // Until the server reports its login directory.
+        assert_eq!(storage.get_root_path(), "/");
+        let storage = Ec2Storage::new(Ec2Config {
+            initial_path: Some("/volume1/photo/".to_string()),
+            ..config
+        });
+        assert_eq!(
+            storage.path_translator().resolve("/a.jpg"),
+            "/volume1/photo/a.jpg"
+        );
+
+        let config: SftpConfig = serde_json::from_value(serde_json::json!({
+            "host": "nas.local",
+            "port": 2222,
+            "username": "admin",
+            "auth": { "method": "password", "password": "hunter2" },
+        }))
+        .unwrap();
+        let config = config.into_ec2_config(None);
+        assert!(config.uses_password() && !config.uses_key());
+        assert!(!format!("{:?}", config).contains("hunter2"));
+    }
+
     #[test]
     fn test_disconnect_when_not_connected() {
         let config = create_test_config();
@@ -0,0 +1,420 @@
+//! The error type of storage backends and commands, serialized for the
+//! frontend as `{ code, message }` so it can tell failures apart without
+//! matching on messages.
+
+use crate::host_keys::HostKeyError;
+use crate::image_decode::{ImageTooLargeError, UnsupportedFormatError};
+use crate::ssh_util::ExecError;
+use crate::storage::FileTooLargeError;
+use crate::utils::{PassphraseRequiredError, PASSPHRASE_REQUIRED};
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::error::Error;
+use std::io;
+use std::sync::PoisonError;
+
+/// libssh2 session errors meaning the connection dropped: socket none,
+/// send, disconnect and recv.
+const CONNECTION_LOST_CODES: &[i32] = &[-1, -7, -13, -43];
+/// libssh2's timeout and socket timeout.
+const TIMEOUT_CODES: &[i32] = &[-9, -30];
+/// libssh2's authentication failed and public key unverified.
+const AUTH_FAILED_CODES: &[i32] = &[-18, -19];
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum StorageError {
+    #[error("Not connected to any storage")]
+    NotConnected,
+    /// Holds the missing path, as opposed to the transport or permission
+    /// errors a backend can also report.
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("Authentication failed: {reason}")]
+    AuthFailed { reason: String },
+    #[error("{}", PassphraseRequiredError)]
+    PassphraseRequired,
+    #[error(transparent)]
+    HostKey(HostKeyError),
+    #[error("Operation timed out")]
+    Timeout,
+    /// The connection dropped; the operation may work after reconnecting.
+    #[error("Connection lost: {0}")]
+    ConnectionLost(String),
+    /// A file or image exceeds the limit of the operation.
+    #[error("{0}")]
+    TooLarge(String),
+    /// The file type or the operation is not supported.
+    #[error("{0}")]
+    Unsupported(String),
+    /// The server's TLS certificate was not trusted, as when it is
+    /// self-signed or expired.
+    #[error("Untrusted server certificate: {0}")]
+    Tls(String),
+    /// The server refused more requests for now.
+    #[error("{0}")]
+    RateLimited(String),
+    /// A storage or usage quota of the account is used up.
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+    /// The remote host lacks a tool the operation runs, such as an archiver.
+    #[error("{0} is not installed on the remote host")]
+    ToolMissing(&'static str),
+    /// There is no OS credential store to keep secrets in, as on headless
+    /// Linux without a Secret Service provider.
+    #[error("No OS keyring is available: {0}")]
+    KeyringUnavailable(String),
+    /// A request argument was rejected before reaching the backend.
+    #[error("{0}")]
+    InvalidInput(String),
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("{0}")]
+    Protocol(String),
+    #[error("{0}")]
+    Io(String),
+    /// A file the app saved could not be read back.
+    #[error("{0}")]
+    CorruptData(String),
+    /// The remote branch has commits the local one lacks; pulling first
+    /// lets the push go through.
+    #[error("Push rejected: the remote branch is at {remote}, which local commit {local} does not include")]
+    PushRejected { local: String, remote: String },
+    /// The local branch has commits the remote lacks and the other way
+    /// round, so it cannot be fast-forwarded.
+    #[error("Branch {0} has diverged from the remote and cannot be fast-forwarded")]
+    Diverged(String),
+    /// The git host refused the credentials or does not know the
+    /// repository.
+    #[error("No access to repository {0}")]
+    RepoAccessDenied(String),
+    /// The remote host lacks room for a clone.
+    #[error("Not enough disk space: {} MB needed, {} MB free", required / (1024 * 1024), available / (1024 * 1024))]
+    InsufficientDiskSpace { required: u64, available: u64 },
+    /// The path exists in the repository but lies outside the sparse
+    /// checkout, so the clone does not have it.
+    #[error("Not checked out: {0}")]
+    NotCheckedOut(String),
+    /// The clone's local path holds something other than a clone of the
+    /// repository.
+    #[error("{0} exists and is not the expected repository")]
+    LocalPathConflict(String),
+    /// A failure within the app itself, such as a poisoned lock.
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl StorageError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            StorageError::NotConnected => "NOT_CONNECTED",
+            StorageError::NotFound(_) => "NOT_FOUND",
+            StorageError::PermissionDenied(_) => "PERMISSION_DENIED",
+            StorageError::AuthFailed { .. } => "AUTH_FAILED",
+            StorageError::PassphraseRequired => PASSPHRASE_REQUIRED,
+            StorageError::HostKey(e) => e.code(),
+            StorageError::Timeout => "TIMEOUT",
+            StorageError::ConnectionLost(_) => "CONNECTION_LOST",
+            StorageError::TooLarge(_) => "TOO_LARGE",
+            StorageError::Unsupported(_) => "UNSUPPORTED",
+            StorageError::Tls(_) => "TLS_ERROR",
+            StorageError::RateLimited(_) => "RATE_LIMITED",
+            StorageError::QuotaExceeded(_) => "QUOTA_EXCEEDED",
+            StorageError::ToolMissing(_) => "TOOL_MISSING",
+            StorageError::KeyringUnavailable(_) => "KEYRING_UNAVAILABLE",
+            StorageError::InvalidInput(_) => "INVALID_INPUT",
+            StorageError::Cancelled => "CANCELLED",
+            StorageError::Protocol(_) => "PROTOCOL",
+            StorageError::Io(_) => "IO",
+            StorageError::CorruptData(_) => "CORRUPT_DATA",
+            StorageError::PushRejected { .. } => "PUSH_REJECTED",
+            StorageError::Diverged(_) => "DIVERGED",
+            StorageError::NotCheckedOut(_) => "NOT_CHECKED_OUT",
+            StorageError::LocalPathConflict(_) => "LOCAL_PATH_CONFLICT",
+            StorageError::RepoAccessDenied(_) => "REPO_ACCESS_DENIED",
+            StorageError::InsufficientDiskSpace { .. } => "INSUFFICIENT_DISK_SPACE",
+            StorageError::Internal(_) => "INTERNAL",
+        }
+    }
+
+    /// Whether the connection itself failed, so reconnecting may help.
+    pub fn is_transport(&self) -> bool {
+        matches!(
+            self,
+            StorageError::ConnectionLost(_) | StorageError::Timeout
+        )
+    }
+
+    /// Prefixes the message of an unclassified failure with `action`, such
+    /// as "Failed to read file". Classified errors explain themselves.
+    pub fn context(self, action: &str) -> Self {
+        match self {
+            StorageError::Protocol(message) => {
+                StorageError::Protocol(format!("{}: {}", action, message))
+            }
+            StorageError::Io(message) => StorageError::Io(format!("{}: {}", action, message)),
+            StorageError::Internal(message) => {
+                StorageError::Internal(format!("{}: {}", action, message))
+            }
+            e => e,
+        }
+    }
+
+    /// Classifies `e`, recognizing the typed errors of the backends and of
+    /// ssh2 and std.
+    pub fn classify(e: &(dyn Error + 'static)) -> Self {
+        if let Some(e) = e.downcast_ref::<ssh2::Error>() {
+            return from_ssh2(e);
+        }
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            return from_io(e);
+        }
+        if let Some(e) = e.downcast_ref::<HostKeyError>() {
+            return StorageError::HostKey(e.clone());
+        }
+        if e.is::<PassphraseRequiredError>() {
+            StorageError::PassphraseRequired
+        } else if e.is::<FileTooLargeError>() || e.is::<ImageTooLargeError>() {
+            StorageError::TooLarge(e.to_string())
+        } else if e.is::<UnsupportedFormatError>() {
+            StorageError::Unsupported(e.to_string())
+        } else {
+            StorageError::Protocol(e.to_string())
+        }
+    }
+}
+
+fn from_ssh2(e: &ssh2::Error) -> StorageError {
+    let message = e.message().to_string();
+    match e.code() {
+        // LIBSSH2_FX_NO_SUCH_FILE and NO_SUCH_PATH.
+        ssh2::ErrorCode::SFTP(2 | 10) => StorageError::NotFound(message),
+        // LIBSSH2_FX_PERMISSION_DENIED and WRITE_PROTECT.
+        ssh2::ErrorCode::SFTP(3 | 12) => StorageError::PermissionDenied(message),
+        // LIBSSH2_FX_NO_CONNECTION and CONNECTION_LOST.
+        ssh2::ErrorCode::SFTP(6 | 7) => StorageError::ConnectionLost(message),
+        // LIBSSH2_FX_OP_UNSUPPORTED.
+        ssh2::ErrorCode::SFTP(8) => StorageError::Unsupported(message),
+        ssh2::ErrorCode::Session(code) if CONNECTION_LOST_CODES.contains(&code) => {
+            StorageError::ConnectionLost(message)
+        }
+        ssh2::ErrorCode::Session(code) if TIMEOUT_CODES.contains(&code) => StorageError::Timeout,
+        ssh2::ErrorCode::Session(code) if AUTH_FAILED_CODES.contains(&code) => {
+            StorageError::AuthFailed { reason: message }
+        }
+        _ => StorageError::Protocol(e.to_string()),
+    }
+}
+
+fn from_io(e: &io::Error) -> StorageError {
+    match e.kind() {
+        io::ErrorKind::NotFound => StorageError::NotFound(e.to_string()),
+        io::ErrorKind::PermissionDenied => StorageError::PermissionDenied(e.to_string()),
+        io::ErrorKind::TimedOut => StorageError::Timeout,
+        io::ErrorKind::BrokenPipe
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::NotConnected
+        | io::ErrorKind::UnexpectedEof => StorageError::ConnectionLost(e.to_string()),
+        _ => StorageError::Io(e.to_string()),
+    }
+}
+
+impl From<Box<dyn Error>> for StorageError {
+    fn from(e: Box<dyn Error>) -> Self {
+        match e.downcast::<StorageError>() {
+            Ok(e) => *e,
+            Err(e) => StorageError::classify(e.as_ref()),
+        }
+    }
+}
+
+impl From<ssh2::Error> for StorageError {
+    fn from(e: ssh2::Error) -> Self {
+        from_ssh2(&e)
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        from_io(&e)
+    }
+}
+
+impl From<reqwest::Error> for StorageError {
+    fn from(e: reqwest::Error) -> Self {
+        crate::http::transport_error(&e)
+    }
+}
+
+impl From<FileTooLargeError> for StorageError {
+    fn from(e: FileTooLargeError) -> Self {
+        StorageError::TooLarge(e.to_string())
+    }
+}
+
+impl From<ExecError> for StorageError {
+    fn from(e: ExecError) -> Self {
+        StorageError::Protocol(e.to_string())
+    }
+}
+
+impl<T> From<PoisonError<T>> for StorageError {
+    fn from(e: PoisonError<T>) -> Self {
+        StorageError::Internal(e.to_string())
+    }
+}
+
+impl From<tauri::Error> for StorageError {
+    fn from(e: tauri::Error) -> Self {
+        StorageError::Internal(e.to_string())
+    }
+}
+
+impl From<String> for StorageError {
+    fn from(message: String) -> Self {
+        StorageError::Protocol(message)
+    }
+}
+
+impl From<&str> for StorageError {
+    fn from(message: &str) -> Self {
+        StorageError::Protocol(message.to_string())
+    }
+}
+
+impl Serialize for StorageError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let fields = match self {
+            StorageError::PushRejected { .. } | StorageError::InsufficientDiskSpace { .. } => 4,
+            _ => 2,
+        };
+        let mut error = serializer.serialize_struct("StorageError", fields)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.to_string())?;
+        match self {
+            // The UI offers to pull and push again with these.
+            StorageError::PushRejected { local, remote } => {
+                error.serialize_field("local_commit", local)?;
+                error.serialize_field("remote_commit", remote)?;
+            }
+            StorageError::InsufficientDiskSpace {
+                required,
+                available,
+            } => {
+                error.serialize_field("required_bytes", required)?;
+                error.serialize_field("available_bytes", available)?;
+            }
+            _ => {}
+        }
+        error.end()
+    }
+}
+
+/// Classifies the error of a failed operation and describes it with
+/// [`StorageError::context`].
+pub trait Context<T> {
+    fn context(self, action: &str) -> Result<T, StorageError>;
+
+    /// Like [`Context::context`], for actions naming their arguments.
+    fn with_context(self, action: impl FnOnce() -> String) -> Result<T, StorageError>;
+}
+
+impl<T, E: Into<StorageError>> Context<T> for Result<T, E> {
+    fn context(self, action: &str) -> Result<T, StorageError> {
+        self.map_err(|e| e.into().context(action))
+    }
+
+    fn with_context(self, action: impl FnOnce() -> String) -> Result<T, StorageError> {
+        self.map_err(|e| e.into().context(&action()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serializes_code_and_message() {
+        let json = serde_json::to_value(StorageError::NotFound("/a.jpg".into())).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"code": "NOT_FOUND", "message": "Not found: /a.jpg"})
+        );
+    }
+
+    #[test]
+    fn test_serializes_push_rejected_commits() {
+        let error = StorageError::PushRejected {
+            local: "aaa".into(),
+            remote: "bbb".into(),
+        };
+        let json = serde_json::to_value(error).unwrap();
+        assert_eq!(json["code"], "PUSH_REJECTED");
+        assert_eq!(json["local_commit"], "aaa");
+        assert_eq!(json["remote_commit"], "bbb");
+    }
+
+    #[test]
+    fn test_serializes_disk_space() {
+        let error = StorageError::InsufficientDiskSpace {
+            required: 3 * 1024 * 1024 * 1024,
+            available: 512 * 1024 * 1024,
+        };
+        let json = serde_json::to_value(error).unwrap();
+        assert_eq!(json["code"], "INSUFFICIENT_DISK_SPACE");
+        assert_eq!(
+            json["message"],
+            "Not enough disk space: 3072 MB needed, 512 MB free"
+        );
+        assert_eq!(json["available_bytes"], 512 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_classifies_boxed_errors() {
+        let boxed: Box<dyn Error> = Box::new(StorageError::NotFound("/a".into()));
+        assert!(matches!(StorageError::from(boxed), StorageError::NotFound(p) if p == "/a"));
+
+        let boxed: Box<dyn Error> = io::Error::from(io::ErrorKind::ConnectionReset).into();
+        assert!(StorageError::from(boxed).is_transport());
+
+        let boxed: Box<dyn Error> = Box::new(FileTooLargeError { size: 2, limit: 1 });
+        assert_eq!(StorageError::from(boxed).code(), "TOO_LARGE");
+
+        let boxed: Box<dyn Error> = "remote said no".into();
+        assert_eq!(StorageError::from(boxed).code(), "PROTOCOL");
+    }
+
+    #[test]
+    fn test_is_transport() {
+        let dropped = ssh2::Error::new(ssh2::ErrorCode::Session(-43), "socket recv failure");
+        let denied = ssh2::Error::new(ssh2::ErrorCode::SFTP(3), "permission denied");
+        assert!(StorageError::from(dropped).is_transport());
+        assert!(!StorageError::from(denied).is_transport());
+        assert!(StorageError::from(io::Error::from(io::ErrorKind::BrokenPipe)).is_transport());
+        assert!(!StorageError::from(io::Error::from(io::ErrorKind::NotFound)).is_transport());
+        assert!(!StorageError::from("Not a directory").is_transport());
+    }
+
+    #[test]
+    fn test_maps_ssh2_codes() {
+        let sftp = |code| ssh2::Error::new(ssh2::ErrorCode::SFTP(code), "sftp");
+        let session = |code| ssh2::Error::new(ssh2::ErrorCode::Session(code), "session");
+        assert_eq!(StorageError::from(sftp(2)).code(), "NOT_FOUND");
+        assert_eq!(StorageError::from(sftp(3)).code(), "PERMISSION_DENIED");
+        assert_eq!(StorageError::from(sftp(7)).code(), "CONNECTION_LOST");
+        assert_eq!(StorageError::from(session(-13)).code(), "CONNECTION_LOST");
+        assert_eq!(StorageError::from(session(-9)).code(), "TIMEOUT");
+        assert_eq!(StorageError::from(session(-18)).code(), "AUTH_FAILED");
+        assert_eq!(StorageError::from(session(-31)).code(), "PROTOCOL");
+    }
+
+    #[test]
+    fn test_context_only_prefixes_unclassified_errors() {
+        let e = StorageError::Protocol("exit 1".into()).context("Failed to copy file");
+        assert_eq!(e.to_string(), "Failed to copy file: exit 1");
+        let e = StorageError::NotFound("/a".into()).context("Failed to copy file");
+        assert_eq!(e.to_string(), "Not found: /a");
+    }
+}
@@ -0,0 +1,1313 @@
+//! A read-only Google Drive backend over the Drive v3 REST API, for
+//! browsing photo folders shared through Drive. Drive addresses files by
+//! id, so paths are resolved folder by folder through an index of the
+//! folders listed so far.
+//!
+//! Signing in uses OAuth's loopback flow: [`DriveAuthFlow`] listens on a
+//! local port for the browser's redirect. Google's device-code flow cannot
+//! grant read access to shared folders. Tokens are kept per account in
+//! [`DRIVE_TOKENS_FILE`] and refreshed when they expire.
+
+use crate::archive::ArchiveFormat;
+use crate::checksum::{self, ChecksumAlgorithm, FileChecksum};
+use crate::error::StorageError;
+use crate::http::{self, HttpAuth};
+use crate::metrics::{self, StorageMetrics};
+use crate::paths::{PathTranslator, RemotePath, ABSOLUTE_PATH_KEY};
+use crate::storage::{
+    self, ChunkCallback, ContentSearchResult, CreateDirectoryResult, DeleteDirectoryResult,
+    DirectoryUsage, FileInfo, FileReadOutcome, ListOptions, RecursiveListing, SearchResult,
+    Storage, StorageType,
+};
+use crate::thumbnails::{self, Thumbnail, ThumbnailOptions};
+use crate::utils;
+use base64::Engine;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// File in the app data directory holding the tokens, by account.
+pub const DRIVE_TOKENS_FILE: &str = "gdrive_tokens.json";
+
+const API_URL: &str = "https://www.googleapis.com/drive/v3";
+const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const SCOPE: &str = "https://www.googleapis.com/auth/drive.readonly";
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+/// Prefix of the Docs, Sheets and other files that only exist within Google
+/// Workspace and have no content to download.
+const WORKSPACE_MIME_PREFIX: &str = "application/vnd.google-apps.";
+const LIST_FIELDS: &str = "nextPageToken,files(id,name,mimeType,size,modifiedTime,\
+    thumbnailLink,md5Checksum,sha256Checksum,shortcutDetails(targetId,targetMimeType))";
+/// Access tokens this close to expiring are refreshed before use.
+const TOKEN_EXPIRY_MARGIN: u64 = 60;
+/// How long the user has to sign in before polling gives up.
+const AUTH_FLOW_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Where the connection is rooted.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DriveRoot {
+    #[default]
+    MyDrive,
+    SharedDrive {
+        id: String,
+    },
+    /// Any folder the account can see, such as one shared with it.
+    Folder {
+        id: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DriveConfig {
+    /// Email address of an account signed in with [`DriveAuthFlow`].
+    pub account: String,
+    #[serde(default)]
+    pub root: DriveRoot,
+}
+
+/// A desktop OAuth client registered with Google. Google does not treat
+/// the secret of installed apps as confidential, but it is still required.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OAuthClient {
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+}
+
+/// The tokens of one account, with the client they were issued to.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DriveToken {
+    pub client: OAuthClient,
+    pub refresh_token: String,
+    #[serde(default)]
+    pub access_token: String,
+    /// Unix time the access token expires at.
+    #[serde(default)]
+    pub expires_at: u64,
+}
+
+// Written by hand so tokens never end up in logs.
+impl fmt::Debug for DriveToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DriveToken")
+            .field("client_id", &self.client.client_id)
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+pub fn tokens_path(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join(DRIVE_TOKENS_FILE)
+}
+
+fn load_token(path: &Path, account: &str) -> Option<DriveToken> {
+    let mut tokens: BTreeMap<String, DriveToken> = utils::load_json_or_default(path);
+    tokens.remove(account)
+}
+
+/// Saves `token` for `account`, keeping the other accounts'.
+pub fn save_token(path: &Path, account: &str, token: &DriveToken) -> io::Result<()> {
+    let mut tokens: BTreeMap<String, DriveToken> = utils::load_json_or_default(path);
+    tokens.insert(account.to_string(), token.clone());
+    utils::save_json_atomic(path, &tokens)
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OAuthErrorBody {
+    error: String,
+    #[serde(default)]
+    error_description: String,
+}
+
+/// The tokens granted by `response`, telling revoked grants apart.
+fn token_response(response: Response) -> Result<TokenResponse, StorageError> {
+    let status = response.status();
+    if status == StatusCode::BAD_REQUEST || status == StatusCode::UNAUTHORIZED {
+        let body: Option<OAuthErrorBody> = serde_json::from_str(&response.text()?).ok();
+        let reason = match body {
+            Some(body) if body.error == "invalid_grant" => {
+                "Google access expired or was revoked; sign in again".to_string()
+            }
+            Some(body) => format!(
+                "Google refused the sign-in: {} {}",
+                body.error, body.error_description
+            )
+            .trim_end()
+            .to_string(),
+            None => format!("Google refused the sign-in (HTTP {})", status),
+        };
+        return Err(StorageError::AuthFailed { reason });
+    }
+    parse_json(http::check_status(response, "Google token")?)
+}
+
+fn parse_json<T: DeserializeOwned>(response: Response) -> Result<T, StorageError> {
+    serde_json::from_str(&response.text()?)
+        .map_err(|e| StorageError::Protocol(format!("Unexpected Google Drive response: {}", e)))
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    errors: Vec<ErrorReason>,
+}
+
+#[derive(Deserialize)]
+struct ErrorReason {
+    #[serde(default)]
+    reason: String,
+}
+
+/// The error for a failed request about `what`. Drive reports rate limits,
+/// used-up quotas and missing permissions all as 403, told apart by the
+/// reason in the body.
+fn drive_error(status: StatusCode, body: &str, what: &str) -> StorageError {
+    let Ok(ErrorBody { error }) = serde_json::from_str::<ErrorBody>(body) else {
+        return http::status_error(status, what);
+    };
+    let reason = error.errors.first().map_or("", |e| e.reason.as_str());
+    match reason {
+        "userRateLimitExceeded" | "rateLimitExceeded" => StorageError::RateLimited(error.message),
+        "storageQuotaExceeded"
+        | "quotaExceeded"
+        | "dailyLimitExceeded"
+        | "downloadQuotaExceeded" => StorageError::QuotaExceeded(error.message),
+        "insufficientFilePermissions"
+        | "insufficientPermissions"
+        | "forbidden"
+        | "domainPolicy"
+        | "appNotAuthorizedToFile"
+        | "cannotDownloadAbusiveFile" => {
+            StorageError::PermissionDenied(format!("{}: {}", what, error.message))
+        }
+        _ => http::status_error(status, what),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DriveFile {
+    id: String,
+    #[serde(default)]
+    name: String,
+    mime_type: String,
+    /// Drive sends 64-bit numbers as strings.
+    #[serde(default)]
+    size: Option<String>,
+    #[serde(default)]
+    modified_time: Option<String>,
+    #[serde(default)]
+    thumbnail_link: Option<String>,
+    #[serde(default)]
+    md5_checksum: Option<String>,
+    #[serde(default)]
+    sha256_checksum: Option<String>,
+    #[serde(default)]
+    shortcut_details: Option<ShortcutDetails>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ShortcutDetails {
+    #[serde(default)]
+    target_id: String,
+    #[serde(default)]
+    target_mime_type: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileList {
+    #[serde(default)]
+    files: Vec<DriveFile>,
+    #[serde(default)]
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct About {
+    user: AboutUser,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AboutUser {
+    email_address: String,
+}
+
+/// A file or folder in a listing. Shortcuts, which shared folders are often
+/// added to My Drive as, stand for their target.
+#[derive(Debug, Clone, PartialEq)]
+struct DriveEntry {
+    id: String,
+    name: String,
+    mime_type: String,
+    size: u64,
+    modified: Option<u64>,
+    thumbnail_link: Option<String>,
+    md5: Option<String>,
+    sha256: Option<String>,
+    shortcut: bool,
+}
+
+impl From<DriveFile> for DriveEntry {
+    fn from(file: DriveFile) -> Self {
+        let (id, mime_type, shortcut) = match file.shortcut_details {
+            Some(target) => (target.target_id, target.target_mime_type, true),
+            None => (file.id, file.mime_type, false),
+        };
+        DriveEntry {
+            id,
+            name: file.name,
+            mime_type,
+            size: file.size.and_then(|s| s.parse().ok()).unwrap_or(0),
+            modified: file.modified_time.as_deref().and_then(utils::parse_rfc3339),
+            thumbnail_link: file.thumbnail_link,
+            md5: file.md5_checksum,
+            sha256: file.sha256_checksum,
+            shortcut,
+        }
+    }
+}
+
+impl DriveEntry {
+    fn is_dir(&self) -> bool {
+        self.mime_type == FOLDER_MIME_TYPE
+    }
+
+    fn digest(&self, algorithm: ChecksumAlgorithm) -> Option<String> {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => self.sha256.clone(),
+            ChecksumAlgorithm::Md5 => self.md5.clone(),
+        }
+    }
+}
+
+/// Makes the names in one folder usable as path segments. Drive allows
+/// slashes in names and several files of the same name in a folder; later
+/// ones get a number like "IMG_0001 (2).jpg".
+fn dedupe_names(entries: &mut [DriveEntry]) {
+    let mut seen = HashSet::new();
+    for entry in entries {
+        entry.name = entry.name.replace('/', "_");
+        if seen.insert(entry.name.clone()) {
+            continue;
+        }
+        let (stem, extension) = match entry.name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() && !entry.is_dir() => {
+                (stem.to_string(), format!(".{}", extension))
+            }
+            _ => (entry.name.clone(), String::new()),
+        };
+        let mut n = 2;
+        while !seen.insert(format!("{} ({}){}", stem, n, extension)) {
+            n += 1;
+        }
+        entry.name = format!("{} ({}){}", stem, n, extension);
+    }
+}
+
+/// `link` asking for a thumbnail `size` pixels on the longer side. Drive's
+/// thumbnail links end in a size such as "=s220".
+fn sized_thumbnail_link(link: &str, size: u32) -> String {
+    match link.rfind("=s") {
+        Some(i) if link.len() > i + 2 && link[i + 2..].bytes().all(|b| b.is_ascii_digit()) => {
+            format!("{}=s{}", &link[..i], size)
+        }
+        _ => link.to_string(),
+    }
+}
+
+fn media_url(id: &str) -> String {
+    format!("{}/files/{}?alt=media&supportsAllDrives=true", API_URL, id)
+}
+
+fn read_only() -> StorageError {
+    StorageError::Unsupported("The Google Drive connection is read-only".to_string())
+}
+
+/// Folders listed so far.
+#[derive(Default)]
+struct DriveIndex {
+    ids: HashMap<RemotePath, String>,
+    paths: HashMap<String, RemotePath>,
+    /// Entries by folder id.
+    listings: HashMap<String, Arc<Vec<DriveEntry>>>,
+}
+
+/// What connecting found out.
+struct Session {
+    client: Client,
+    token: Mutex<DriveToken>,
+    root_id: String,
+    /// The `corpora` to list in and, for shared drives, the drive.
+    corpora: &'static str,
+    drive_id: Option<String>,
+}
+
+pub struct DriveStorage {
+    config: DriveConfig,
+    tokens_path: PathBuf,
+    /// Set by `connect`; `None` while disconnected.
+    session: Option<Session>,
+    index: Mutex<DriveIndex>,
+    metrics: StorageMetrics,
+}
+
+impl DriveStorage {
+    /// A backend using the tokens saved in `tokens_path`.
+    pub fn new(config: DriveConfig, tokens_path: PathBuf) -> Self {
+        DriveStorage {
+            config,
+            tokens_path,
+            session: None,
+            index: Mutex::new(DriveIndex::default()),
+            metrics: StorageMetrics::default(),
+        }
+    }
+
+    pub fn config(&self) -> &DriveConfig {
+        &self.config
+    }
+
+    pub fn tokens_path(&self) -> &Path {
+        &self.tokens_path
+    }
+
+    fn session(&self) -> Result<&Session, StorageError> {
+        self.session.as_ref().ok_or(StorageError::NotConnected)
+    }
+
+    /// A current access token, refreshed first when it is about to expire
+    /// or `force` is set.
+    fn access_token(&self, session: &Session, force: bool) -> Result<String, StorageError> {
+        let mut token = session.token.lock()?;
+        if force || token.expires_at <= utils::unix_now() + TOKEN_EXPIRY_MARGIN {
+            let granted = token_response(
+                session
+                    .client
+                    .post(TOKEN_URL)
+                    .form(&[
+                        ("client_id", token.client.client_id.as_str()),
+                        ("client_secret", token.client.client_secret.as_str()),
+                        ("refresh_token", token.refresh_token.as_str()),
+                        ("grant_type", "refresh_token"),
+                    ])
+                    .send()?,
+            )?;
+            token.access_token = granted.access_token;
+            token.expires_at = utils::unix_now() + granted.expires_in;
+            if let Some(refresh_token) = granted.refresh_token {
+                token.refresh_token = refresh_token;
+            }
+            if let Err(e) = save_token(&self.tokens_path, &self.config.account, &token) {
+                log::warn!("Failed to save Google Drive token: {}", e);
+            }
+        }
+        Ok(token.access_token.clone())
+    }
+
+    /// Sends the request `build` makes with a client and access token. An
+    /// expired token is refreshed and the request repeated, as are rate
+    /// limited requests after backing off.
+    fn send(
+        &self,
+        what: &str,
+        build: impl Fn(&Client, &str) -> RequestBuilder,
+    ) -> Result<Response, StorageError> {
+        let session = self.session()?;
+        let mut refreshed = false;
+        let mut backoff = Duration::from_secs(1);
+        let mut attempt = 1;
+        loop {
+            let token = self.access_token(session, false)?;
+            let response = build(&session.client, &token).send()?;
+            let status = response.status();
+            if status.is_success() {
+                return Ok(response);
+            }
+            if status == StatusCode::UNAUTHORIZED && !refreshed {
+                refreshed = true;
+                self.access_token(session, true)?;
+                continue;
+            }
+            let error = drive_error(status, &response.text().unwrap_or_default(), what);
+            if !matches!(error, StorageError::RateLimited(_))
+                || attempt >= http::RATE_LIMIT_ATTEMPTS
+            {
+                return Err(error);
+            }
+            log::warn!("Rate limited by Google Drive, retrying in {:?}", backoff);
+            std::thread::sleep(backoff);
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, url: &str, what: &str) -> Result<T, StorageError> {
+        parse_json(self.send(what, |client, token| client.get(url).bearer_auth(token))?)
+    }
+
+    /// Lists the folder `id` afresh, indexing the paths of its subfolders.
+    fn fetch_listing(&self, id: &str) -> Result<Arc<Vec<DriveEntry>>, StorageError> {
+        let session = self.session()?;
+        let dir = self
+            .index
+            .lock()?
+            .paths
+            .get(id)
+            .cloned()
+            .ok_or_else(|| StorageError::Internal(format!("Folder {} is not indexed", id)))?;
+        let mut query = vec![
+            ("q", format!("'{}' in parents and trashed = false", id)),
+            ("fields", LIST_FIELDS.to_string()),
+            ("pageSize", "1000".to_string()),
+            ("orderBy", "folder,name,createdTime".to_string()),
+            ("supportsAllDrives", "true".to_string()),
+            ("includeItemsFromAllDrives", "true".to_string()),
+            ("corpora", session.corpora.to_string()),
+        ];
+        if let Some(drive_id) = &session.drive_id {
+            query.push(("driveId", drive_id.clone()));
+        }
+        let mut entries: Vec<DriveEntry> = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let page: FileList = parse_json(self.send(dir.as_str(), |client, token| {
+                let request = client
+                    .get(format!("{}/files", API_URL))
+                    .bearer_auth(token)
+                    .query(&query);
+                match &page_token {
+                    Some(page_token) => request.query(&[("pageToken", page_token)]),
+                    None => request,
+                }
+            })?)?;
+            entries.extend(page.files.into_iter().map(DriveEntry::from));
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+        dedupe_names(&mut entries);
+
+        let listing = Arc::new(entries);
+        let mut index = self.index.lock()?;
+        for entry in listing.iter().filter(|e| e.is_dir()) {
+            let path = dir.join(&entry.name);
+            index.ids.insert(path.clone(), entry.id.clone());
+            // A folder reachable through a shortcut keeps its first path.
+            index.paths.entry(entry.id.clone()).or_insert(path);
+        }
+        index.listings.insert(id.to_string(), listing.clone());
+        Ok(listing)
+    }
+
+    fn listing(&self, id: &str) -> Result<Arc<Vec<DriveEntry>>, StorageError> {
+        if let Some(listing) = self.index.lock()?.listings.get(id) {
+            return Ok(listing.clone());
+        }
+        self.fetch_listing(id)
+    }
+
+    /// The id of the folder at `dir`, listing its ancestors as needed.
+    fn folder_id(&self, dir: &RemotePath) -> Result<String, StorageError> {
+        if let Some(id) = self.index.lock()?.ids.get(dir) {
+            return Ok(id.clone());
+        }
+        let entry = self.entry(dir)?;
+        if !entry.is_dir() {
+            return Err(StorageError::InvalidInput(format!(
+                "Not a directory: {}",
+                dir
+            )));
+        }
+        // Listing the parent indexed it, unless it is reached through a
+        // shortcut to a folder indexed elsewhere.
+        self.index.lock()?.ids.insert(dir.clone(), entry.id.clone());
+        Ok(entry.id)
+    }
+
+    /// The entry at `path`, from its folder's listing. Listings are reused
+    /// until a name is missing from one, which is then fetched again.
+    fn entry(&self, path: &RemotePath) -> Result<DriveEntry, StorageError> {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(DriveEntry {
+                id: self.session()?.root_id.clone(),
+                name: String::new(),
+                mime_type: FOLDER_MIME_TYPE.to_string(),
+                size: 0,
+                modified: None,
+                thumbnail_link: None,
+                md5: None,
+                sha256: None,
+                shortcut: false,
+            });
+        };
+        let parent_id = self.folder_id(&parent)?;
+        let find = |listing: &[DriveEntry]| listing.iter().find(|e| e.name == name).cloned();
+        if let Some(entry) = find(&self.listing(&parent_id)?) {
+            return Ok(entry);
+        }
+        find(&self.fetch_listing(&parent_id)?)
+            .ok_or_else(|| StorageError::NotFound(path.to_string()))
+    }
+
+    /// The entry at `path` when it has content to download.
+    fn downloadable(&self, path: &RemotePath) -> Result<DriveEntry, StorageError> {
+        let entry = self.entry(path)?;
+        if entry.is_dir() {
+            return Err(StorageError::InvalidInput("is a directory".to_string()));
+        }
+        if entry.mime_type.starts_with(WORKSPACE_MIME_PREFIX) {
+            return Err(StorageError::Unsupported(format!(
+                "{} is a Google Workspace document and cannot be downloaded",
+                entry.name
+            )));
+        }
+        Ok(entry)
+    }
+
+    fn file_info(&self, path: &RemotePath, entry: &DriveEntry) -> FileInfo {
+        let is_dir = entry.is_dir();
+        FileInfo {
+            name: entry.name.clone(),
+            path: path.to_string(),
+            size: entry.size,
+            is_dir,
+            is_symlink: entry.shortcut,
+            link_target: None,
+            modified: entry.modified,
+            mime_type: if is_dir {
+                None
+            } else {
+                storage::detect_mime_type(&entry.name).or_else(|| Some(entry.mime_type.clone()))
+            },
+            thumbnail: None,
+            permissions: None,
+            owner: None,
+            extra: BTreeMap::from([(ABSOLUTE_PATH_KEY.to_string(), path.to_string())]),
+        }
+    }
+
+    /// A thumbnail made from the one Drive rendered, sparing the download
+    /// of the original.
+    fn drive_thumbnail(
+        &self,
+        link: &str,
+        max_size: u32,
+        options: ThumbnailOptions,
+    ) -> Result<Thumbnail, StorageError> {
+        let url = sized_thumbnail_link(link, max_size);
+        let data = self
+            .send("thumbnail", |client, token| {
+                client.get(&url).bearer_auth(token)
+            })?
+            .bytes()?;
+        // Drive renders JPEGs or PNGs whatever the original was, so the
+        // format is sniffed rather than taken from the name.
+        let thumbnail = thumbnails::generate_thumbnail(&data, "thumbnail", max_size, options)
+            .map_err(StorageError::from)?;
+        Ok(thumbnail.into())
+    }
+}
+
+impl Storage for DriveStorage {
+    fn connect(&mut self) -> Result<(), StorageError> {
+        let account = &self.config.account;
+        let token =
+            load_token(&self.tokens_path, account).ok_or_else(|| StorageError::AuthFailed {
+                reason: format!("Not signed in to Google Drive as {}", account),
+            })?;
+        self.session = Some(Session {
+            client: http::client(&HttpAuth::None, false)?,
+            token: Mutex::new(token),
+            root_id: String::new(),
+            corpora: "user",
+            drive_id: None,
+        });
+        let resolved = match self.config.root.clone() {
+            DriveRoot::MyDrive => {
+                let root: DriveFile = self.get_json(
+                    &format!("{}/files/root?fields=id,mimeType", API_URL),
+                    "My Drive",
+                )?;
+                Ok((root.id, "user", None))
+            }
+            DriveRoot::SharedDrive { id } => {
+                let url = format!("{}/drives/{}?fields=id", API_URL, id);
+                self.get_json::<serde_json::Value>(&url, &format!("shared drive {}", id))?;
+                Ok((id.clone(), "drive", Some(id)))
+            }
+            DriveRoot::Folder { id } => {
+                let url = format!(
+                    "{}/files/{}?fields=id,mimeType,shortcutDetails&supportsAllDrives=true",
+                    API_URL, id
+                );
+                let folder =
+                    DriveEntry::from(self.get_json::<DriveFile>(&url, &format!("folder {}", id))?);
+                if folder.is_dir() {
+                    Ok((folder.id, "allDrives", None))
+                } else {
+                    Err(StorageError::InvalidInput(format!(
+                        "{} is not a folder",
+                        id
+                    )))
+                }
+            }
+        };
+        let (root_id, corpora, drive_id) = match resolved {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                self.session = None;
+                return Err(e);
+            }
+        };
+        if let Some(session) = self.session.as_mut() {
+            session.root_id = root_id.clone();
+            session.corpora = corpora;
+            session.drive_id = drive_id;
+        }
+        let mut index = self.index.lock()?;
+        *index = DriveIndex::default();
+        index.ids.insert(RemotePath::new("/"), root_id.clone());
+        index.paths.insert(root_id, RemotePath::new("/"));
+        log::info!("Connected to Google Drive as {}", account);
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.session = None;
+    }
+
+    fn is_connected(&self) -> bool {
+        self.session.is_some()
+    }
+
+    fn ping(&self) -> Result<(), StorageError> {
+        self.get_json::<serde_json::Value>(
+            &format!("{}/about?fields=kind", API_URL),
+            "Google Drive",
+        )?;
+        Ok(())
+    }
+
+    fn metrics(&self) -> &StorageMetrics {
+        &self.metrics
+    }
+
+    fn list_directory_page(
+        &self,
+        path: &str,
+        options: &ListOptions,
+    ) -> Result<(Vec<FileInfo>, usize), StorageError> {
+        let dir = self.path_translator().to_remote(path);
+        let id = self.folder_id(&dir)?;
+        let mut files: Vec<FileInfo> = self
+            .fetch_listing(&id)?
+            .iter()
+            .filter(|entry| {
+                storage::is_listed(&entry.name, options.show_hidden, &[])
+                    && options.filter.matches_entry(&entry.name, entry.is_dir())
+            })
+            .map(|entry| self.file_info(&dir.join(&entry.name), entry))
+            .collect();
+        storage::sort_files(&mut files, options.sort_by, options.sort_order);
+        let total = files.len();
+        Ok((
+            storage::paginate(&files, options.offset, options.limit),
+            total,
+        ))
+    }
+
+    fn list_directory_recursive(
+        &self,
+        path: &str,
+        max_depth: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<RecursiveListing, StorageError> {
+        storage::list_by_walking(self, path, max_depth, cancelled)
+    }
+
+    /// Drive's own name search only matches word prefixes, so folders are
+    /// walked instead to match like the other backends.
+    fn search(
+        &self,
+        root: &str,
+        pattern: &str,
+        case_sensitive: bool,
+        limit: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<SearchResult, StorageError> {
+        storage::search_by_walking(self, root, pattern, case_sensitive, limit, cancelled)
+    }
+
+    fn search_contents(
+        &self,
+        _root: &str,
+        _query: &str,
+        _regex: bool,
+        _include: Option<&str>,
+        _limit: usize,
+    ) -> Result<ContentSearchResult, StorageError> {
+        Err(StorageError::Unsupported(
+            "Content search is not supported on Google Drive".to_string(),
+        ))
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, StorageError> {
+        let path = self.path_translator().to_remote(path);
+        let url = media_url(&self.downloadable(&path)?.id);
+        metrics::metered_read(&self.metrics, || {
+            Ok(self
+                .send(path.as_str(), |client, token| {
+                    client.get(&url).bearer_auth(token)
+                })?
+                .bytes()?
+                .to_vec())
+        })
+    }
+
+    fn read_file_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, bool), StorageError> {
+        let start = Instant::now();
+        let path = self.path_translator().to_remote(path);
+        let entry = self.downloadable(&path)?;
+        let session = self.session()?;
+        let token = self.access_token(session, false)?;
+        let request = session.client.get(media_url(&entry.id)).bearer_auth(token);
+        let (data, at_end) = http::read_range(request, path.as_str(), offset, length)?;
+        self.metrics.record_read(data.len() as u64, start.elapsed());
+        Ok((data, at_end))
+    }
+
+    fn read_files(&self, paths: &[String]) -> Result<Vec<FileReadOutcome>, StorageError> {
+        let mut outcomes = Vec::with_capacity(paths.len());
+        for path in paths {
+            outcomes.push(match self.read_file(path) {
+                Err(e) if e.is_transport() || matches!(e, StorageError::NotConnected) => {
+                    return Err(e)
+                }
+                result => result.map_err(|e| e.to_string()),
+            });
+        }
+        Ok(outcomes)
+    }
+
+    fn read_file_streamed(
+        &self,
+        path: &str,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        let start = Instant::now();
+        let path = self.path_translator().to_remote(path);
+        let url = media_url(&self.downloadable(&path)?.id);
+        let response = self.send(path.as_str(), |client, token| {
+            client.get(&url).bearer_auth(token)
+        })?;
+        let (read, completed) = http::stream_body(response, on_chunk)?;
+        self.metrics.record_read(read, start.elapsed());
+        Ok(completed)
+    }
+
+    fn write_file(&self, _path: &str, _data: &[u8]) -> Result<(), StorageError> {
+        Err(read_only())
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        match self.entry(&self.path_translator().to_remote(path)) {
+            Ok(_) => Ok(true),
+            Err(StorageError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn stat(&self, path: &str) -> Result<FileInfo, StorageError> {
+        let path = self.path_translator().to_remote(path);
+        Ok(self.file_info(&path, &self.entry(&path)?))
+    }
+
+    fn set_permissions(&self, _path: &str, _mode: u32) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn set_modified(&self, _path: &str, _mtime: u64) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn directory_size(&self, path: &str) -> Result<DirectoryUsage, StorageError> {
+        storage::size_by_walking(self, path)
+    }
+
+    /// Nothing can be run on Drive's side, so the caller builds zips itself.
+    fn archive_directory(
+        &self,
+        _path: &str,
+        format: ArchiveFormat,
+        _on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        Err(StorageError::ToolMissing(format.remote_tool()))
+    }
+
+    fn extract_archive(
+        &self,
+        _path: &str,
+        _destination: &str,
+    ) -> Result<Vec<String>, StorageError> {
+        Err(read_only())
+    }
+
+    /// Uses the digest Drive keeps of uploaded files when it has one of the
+    /// requested kind.
+    fn checksum(
+        &self,
+        path: &str,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<FileChecksum, StorageError> {
+        let entry = self.downloadable(&self.path_translator().to_remote(path))?;
+        let digest = match entry.digest(algorithm) {
+            Some(digest) => digest,
+            None => checksum::hash_streamed(self, path, algorithm)?,
+        };
+        Ok(FileChecksum {
+            algorithm,
+            digest,
+            size: entry.size,
+        })
+    }
+
+    fn checksums(
+        &self,
+        paths: &[String],
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<Vec<Option<String>>, StorageError> {
+        Ok(paths
+            .iter()
+            .map(|p| self.checksum(p, algorithm).ok().map(|c| c.digest))
+            .collect())
+    }
+
+    fn delete_file(&self, _path: &str) -> Result<(), StorageError> {
+        Err(read_only())
+    }
+
+    fn delete_directory(
+        &self,
+        _path: &str,
+        _recursive: bool,
+    ) -> Result<DeleteDirectoryResult, StorageError> {
+        Err(read_only())
+    }
+
+    fn rename(&self, _from: &str, _to: &str) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn copy_file(&self, _from: &str, _to: &str) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn create_directory(
+        &self,
+        _path: &str,
+        _recursive: bool,
+    ) -> Result<CreateDirectoryResult, StorageError> {
+        Err(read_only())
+    }
+
+    fn get_file_thumbnail(
+        &self,
+        path: &str,
+        max_size: u32,
+        options: ThumbnailOptions,
+    ) -> Result<Thumbnail, StorageError> {
+        let entry = self.entry(&self.path_translator().to_remote(path))?;
+        if let Some(link) = &entry.thumbnail_link {
+            match self.drive_thumbnail(link, max_size, options) {
+                Ok(thumbnail) => return Ok(thumbnail),
+                Err(e) => log::debug!("Drive's thumbnail of {} is unusable: {}", path, e),
+            }
+        }
+        thumbnails::fetched_file_thumbnail(self, path, max_size, options)
+            .map_err(StorageError::from)
+    }
+
+    fn get_root_path(&self) -> String {
+        "/".to_string()
+    }
+
+    fn path_translator(&self) -> PathTranslator {
+        PathTranslator::new("/")
+    }
+
+    fn storage_type(&self) -> StorageType {
+        StorageType::GoogleDrive
+    }
+
+    fn connection_id(&self) -> String {
+        let root = match &self.config.root {
+            DriveRoot::MyDrive => "my-drive".to_string(),
+            DriveRoot::SharedDrive { id } => format!("drive/{}", id),
+            DriveRoot::Folder { id } => format!("folder/{}", id),
+        };
+        format!("gdrive:{}:{}", self.config.account, root)
+    }
+}
+
+/// Outcome of [`DriveAuthFlow::poll`].
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DriveAuthStatus {
+    Pending,
+    /// `account` can now be connected to.
+    Complete {
+        account: String,
+    },
+}
+
+/// A sign-in in progress: the user signs in at `url` in their browser,
+/// which then redirects to the port this listens on.
+pub struct DriveAuthFlow {
+    client: OAuthClient,
+    listener: TcpListener,
+    redirect_uri: String,
+    /// PKCE verifier, proving the code is redeemed by who asked for it.
+    verifier: String,
+    state: String,
+    started: Instant,
+    pub url: String,
+}
+
+fn random_token() -> Result<String, StorageError> {
+    let mut bytes = [0u8; 32];
+    openssl::rand::rand_bytes(&mut bytes)
+        .map_err(|e| StorageError::Internal(format!("No randomness available: {}", e)))?;
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+}
+
+impl DriveAuthFlow {
+    pub fn start(client: OAuthClient) -> Result<Self, StorageError> {
+        if client.client_id.is_empty() {
+            return Err(StorageError::InvalidInput(
+                "A Google OAuth client id is required".to_string(),
+            ));
+        }
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        listener.set_nonblocking(true)?;
+        let redirect_uri = format!("http://127.0.0.1:{}", listener.local_addr()?.port());
+        let verifier = random_token()?;
+        let state = random_token()?;
+        let challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(openssl::sha::sha256(verifier.as_bytes()));
+        let url = reqwest::Url::parse_with_params(
+            AUTH_URL,
+            &[
+                ("client_id", client.client_id.as_str()),
+                ("redirect_uri", &redirect_uri),
+                ("response_type", "code"),
+                ("scope", SCOPE),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+                ("state", &state),
+                // Asks for a refresh token, which Google only hands out on
+                // consent.
+                ("access_type", "offline"),
+                ("prompt", "consent"),
+            ],
+        )
+        .map_err(|e| StorageError::Internal(e.to_string()))?
+        .to_string();
+        Ok(DriveAuthFlow {
+            client,
+            listener,
+            redirect_uri,
+            verifier,
+            state,
+            started: Instant::now(),
+            url,
+        })
+    }
+
+    /// Takes the browser's redirect if it came, redeeming its code. Returns
+    /// the account signed in to with its token, or `None` while the user is
+    /// still signing in.
+    pub fn poll(&self) -> Result<Option<(String, DriveToken)>, StorageError> {
+        if self.started.elapsed() > AUTH_FLOW_TIMEOUT {
+            return Err(StorageError::Timeout);
+        }
+        let mut stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let Some(params) = redirect_params(&read_request_target(&mut stream)?) else {
+            // Something other than the redirect, such as a favicon request.
+            let _ = respond(&mut stream, "404 Not Found", "");
+            return Ok(None);
+        };
+        let result = self.redeem(&params);
+        let page = match &result {
+            Ok(_) => "Signed in to Google Drive. You can close this tab.".to_string(),
+            Err(e) => format!("Signing in to Google Drive failed: {}", e),
+        };
+        let _ = respond(&mut stream, "200 OK", &page);
+        result.map(Some)
+    }
+
+    fn redeem(
+        &self,
+        params: &HashMap<String, String>,
+    ) -> Result<(String, DriveToken), StorageError> {
+        if params.get("state") != Some(&self.state) {
+            return Err(StorageError::AuthFailed {
+                reason: "the sign-in response did not match the request".to_string(),
+            });
+        }
+        if let Some(error) = params.get("error") {
+            return Err(StorageError::AuthFailed {
+                reason: format!("Google sign-in failed: {}", error),
+            });
+        }
+        let code = params.get("code").ok_or_else(|| {
+            StorageError::Protocol("Google sent no authorization code".to_string())
+        })?;
+        let client = http::client(&HttpAuth::None, false)?;
+        let granted = token_response(
+            client
+                .post(TOKEN_URL)
+                .form(&[
+                    ("client_id", self.client.client_id.as_str()),
+                    ("client_secret", self.client.client_secret.as_str()),
+                    ("code", code),
+                    ("code_verifier", &self.verifier),
+                    ("redirect_uri", &self.redirect_uri),
+                    ("grant_type", "authorization_code"),
+                ])
+                .send()?,
+        )?;
+        let refresh_token = granted
+            .refresh_token
+            .ok_or_else(|| StorageError::Protocol("Google granted no refresh token".to_string()))?;
+        let about: About = parse_json(http::send(
+            client
+                .get(format!("{}/about?fields=user(emailAddress)", API_URL))
+                .bearer_auth(&granted.access_token),
+            "Google account",
+        )?)?;
+        Ok((
+            about.user.email_address,
+            DriveToken {
+                client: self.client.clone(),
+                refresh_token,
+                access_token: granted.access_token,
+                expires_at: utils::unix_now() + granted.expires_in,
+            },
+        ))
+    }
+}
+
+/// The target of the HTTP request coming in on `stream`, such as
+/// "/?state=..&code=..".
+fn read_request_target(stream: &mut TcpStream) -> Result<String, StorageError> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(2).any(|w| w == b"\r\n") && request.len() < 16 * 1024 {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    Ok(request
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string())
+}
+
+/// The query of the redirect to `target`, or `None` when it is not one.
+fn redirect_params(target: &str) -> Option<HashMap<String, String>> {
+    let url = reqwest::Url::parse(&format!("http://127.0.0.1{}", target)).ok()?;
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    params.contains_key("state").then_some(params)
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GET /drive/v3/files?q='..' in parents, trimmed to two photos, a
+    /// folder and a shortcut to a folder shared with the account.
+    const FILE_LIST: &str = r#"{
+      "files": [
+        {"id": "1Fam", "name": "Summer 2023", "mimeType": "application/vnd.google-apps.folder",
+         "modifiedTime": "2023-09-02T08:15:00.000Z"},
+        {"id": "1Sc", "name": "Grandma's photos", "mimeType": "application/vnd.google-apps.shortcut",
+         "shortcutDetails": {"targetId": "1Gm", "targetMimeType": "application/vnd.google-apps.folder"}},
+        {"id": "1Ph", "name": "IMG_0001.HEIC", "mimeType": "image/heif", "size": "2483921",
+         "modifiedTime": "2023-05-01T12:34:56.789Z",
+         "thumbnailLink": "https://lh3.googleusercontent.com/drive-storage/AJQWtBN=s220",
+         "md5Checksum": "9e107d9d372bb6826bd81d3542a419d6"},
+        {"id": "1Pi", "name": "IMG_0001.HEIC", "mimeType": "image/heif", "size": "1024"}
+      ]
+    }"#;
+
+    fn entries() -> Vec<DriveEntry> {
+        let list: FileList = serde_json::from_str(FILE_LIST).unwrap();
+        assert!(list.next_page_token.is_none());
+        let mut entries: Vec<DriveEntry> = list.files.into_iter().map(DriveEntry::from).collect();
+        dedupe_names(&mut entries);
+        entries
+    }
+
+    #[test]
+    fn test_file_list_entries() {
+        let entries = entries();
+        assert!(entries[0].is_dir() && !entries[0].shortcut);
+        assert_eq!(entries[1].id, "1Gm");
+        assert!(entries[1].is_dir() && entries[1].shortcut);
+        assert_eq!(entries[2].size, 2483921);
+        assert_eq!(entries[2].modified, Some(1682944496));
+        assert_eq!(
+            entries[2].digest(ChecksumAlgorithm::Md5).as_deref(),
+            Some("9e107d9d372bb6826bd81d3542a419d6")
+        );
+        assert_eq!(entries[2].digest(ChecksumAlgorithm::Sha256), None);
+        assert_eq!(entries[3].name, "IMG_0001 (2).HEIC");
+    }
+
+    #[test]
+    fn test_dedupe_names() {
+        let entry = |name: &str, mime_type: &str| DriveEntry {
+            id: name.to_string(),
+            name: name.to_string(),
+            mime_type: mime_type.to_string(),
+            size: 0,
+            modified: None,
+            thumbnail_link: None,
+            md5: None,
+            sha256: None,
+            shortcut: false,
+        };
+        let mut entries = vec![
+            entry("a.jpg", "image/jpeg"),
+            entry("a.jpg", "image/jpeg"),
+            entry("a (2).jpg", "image/jpeg"),
+            entry("v1.0", FOLDER_MIME_TYPE),
+            entry("v1.0", FOLDER_MIME_TYPE),
+            entry("x/y", "image/png"),
+        ];
+        dedupe_names(&mut entries);
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "a.jpg",
+                "a (2).jpg",
+                "a (2) (2).jpg",
+                "v1.0",
+                "v1.0 (2)",
+                "x_y"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sized_thumbnail_link() {
+        assert_eq!(
+            sized_thumbnail_link("https://lh3.googleusercontent.com/d/AJQ=s220", 512),
+            "https://lh3.googleusercontent.com/d/AJQ=s512"
+        );
+        assert_eq!(
+            sized_thumbnail_link("https://lh3.googleusercontent.com/d/AJQ", 512),
+            "https://lh3.googleusercontent.com/d/AJQ"
+        );
+    }
+
+    #[test]
+    fn test_drive_errors() {
+        let body = |reason: &str| {
+            format!(
+                r#"{{"error": {{"code": 403, "message": "{0} message",
+                    "errors": [{{"domain": "usageLimits", "reason": "{0}"}}]}}}}"#,
+                reason
+            )
+        };
+        let code =
+            |reason: &str| drive_error(StatusCode::FORBIDDEN, &body(reason), "/a.jpg").code();
+        assert_eq!(code("userRateLimitExceeded"), "RATE_LIMITED");
+        assert_eq!(code("storageQuotaExceeded"), "QUOTA_EXCEEDED");
+        assert_eq!(code("downloadQuotaExceeded"), "QUOTA_EXCEEDED");
+        assert_eq!(code("insufficientFilePermissions"), "PERMISSION_DENIED");
+        assert_eq!(
+            drive_error(StatusCode::NOT_FOUND, "not json", "/a.jpg").code(),
+            "NOT_FOUND"
+        );
+    }
+
+    #[test]
+    fn test_auth_flow_redirect() {
+        let flow = DriveAuthFlow::start(OAuthClient {
+            client_id: "123.apps.googleusercontent.com".to_string(),
+            client_secret: String::new(),
+        })
+        .unwrap();
+        let url = reqwest::Url::parse(&flow.url).unwrap();
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        assert_eq!(query["redirect_uri"], flow.redirect_uri);
+        assert_eq!(query["code_challenge_method"], "S256");
+        assert_eq!(query["state"], flow.state);
+        assert!(flow.poll().unwrap().is_none());
+
+        let denied =
+            redirect_params(&format!("/?state={}&error=access_denied", flow.state)).unwrap();
+        assert_eq!(flow.redeem(&denied).unwrap_err().code(), "AUTH_FAILED");
+        let forged = redirect_params("/?state=other&code=abc").unwrap();
+        assert_eq!(flow.redeem(&forged).unwrap_err().code(), "AUTH_FAILED");
+        assert!(redirect_params("/favicon.ico").is_none());
+    }
+}
@@ -1,114 +1,632 @@
-use crate::storage::{detect_mime_type, FileInfo, Storage, StorageType};
-use crate::utils;
-use image::ImageFormat;
+use crate::archive::{self, ArchiveFormat, ExtractFormat};
+use crate::checksum::{self, ChecksumAlgorithm, FileChecksum};
+use crate::error::StorageError;
+use crate::git_remote::GitRemote;
+use crate::github_api;
+use crate::host_keys;
+use crate::media_probe::{self, MediaProbe};
+use crate::metrics::{self, StorageMetrics};
+use crate::paths::{self, PathTranslator, RemotePath};
+use crate::ssh_util;
+use crate::storage::{
+    self, detect_mime_type, name_matches, BatchCallback, ChunkCallback, ConnectionLostHandler,
+    ContentSearchResult, CreateDirectoryResult, DeleteDirectoryResult, DirectoryUsage, FileInfo,
+    FileReadOutcome, ListOptions, ListingBatcher, MediaFilter, RecursiveListing, SearchResult,
+    Storage, StorageType, MAX_RECURSIVE_ENTRIES,
+};
+use crate::thumbnails::{self, RemoteThumbnailer, Thumbnail, ThumbnailOptions};
+use crate::utils::{self, Keepalive};
+use crate::validation::{ConnectionStep, Step, StepError, ValidationResult, VALIDATION_TIMEOUT};
 use serde::{Deserialize, Serialize};
 use shell_escape::escape;
 use ssh2::Session;
 use std::borrow::Cow;
-use std::io::{Cursor, Read};
-use std::net::TcpStream;
-use std::time::Duration;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
 const CONNECTION_TIMEOUT_SECS: u64 = 30;
+/// How long a directory listing is reused across page requests.
+const LISTING_CACHE_TTL: Duration = Duration::from_secs(5);
+/// Free space a clone needs when the repository's size is unknown.
+pub const DEFAULT_MIN_FREE_SPACE_MB: u64 = 1024;
+/// Space a clone takes per byte of git data GitHub reports: the packed
+/// history plus the checked-out tree.
+const CLONE_SPACE_FACTOR: u64 = 2;
+/// Bytes in a MiB, the unit of [`GitHubConfig::min_free_space_mb`].
+const MIB: u64 = 1024 * 1024;
+/// Git does not track empty directories, so new ones get this placeholder.
+const GITKEEP_FILE: &str = ".gitkeep";
+
+/// Media tracked with LFS when [`GitHubConfig::lfs_patterns`] is empty.
+pub const DEFAULT_LFS_PATTERNS: &[&str] = &[
+    "*.jpg", "*.jpeg", "*.png", "*.gif", "*.webp", "*.heic", "*.tif", "*.tiff", "*.raw", "*.dng",
+    "*.mp4", "*.mov", "*.mkv", "*.avi", "*.webm",
+];
 
 fn shell_quote(s: &str) -> Cow<'_, str> {
     escape(s.into())
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// A repository cloned over SSH. Named for GitHub, which it started out
+/// with, but any git server with LFS works.
+#[derive(Serialize, Deserialize, Clone)]
 pub struct GitHubConfig {
+    /// Any remote [`GitRemote::parse`] accepts. The SSH connection goes to
+    /// its host, and to its port for `ssh://` URLs.
     pub repo_url: String,
     pub username: String,
     pub ssh_key_content: String,
+    /// Empty for the repository's default branch, detected on connect.
     pub branch: String,
     pub local_path: String,
+    /// Passphrase protecting `ssh_key_content`, if it is encrypted.
+    #[serde(default)]
+    pub key_passphrase: Option<String>,
+    /// App-managed known_hosts, checked after `~/.ssh/known_hosts`.
+    #[serde(skip)]
+    pub known_hosts_file: Option<PathBuf>,
+    /// Seconds between keepalives; `0` disables them. Defaults to
+    /// [`utils::DEFAULT_KEEPALIVE_SECS`].
+    #[serde(default)]
+    pub keepalive_secs: Option<u64>,
+    /// Reconnect and retry once when an operation hits a dropped connection.
+    #[serde(default)]
+    pub auto_reconnect: bool,
+    /// Let a thumbnailing tool on the remote host make thumbnails, see
+    /// [`crate::thumbnails::RemoteThumbnailer`].
+    #[serde(default)]
+    pub remote_thumbnails: bool,
+    /// Commit author name; the remote host's git config applies if unset.
+    #[serde(default)]
+    pub author_name: Option<String>,
+    #[serde(default)]
+    pub author_email: Option<String>,
+    /// Clone and fetch only this many commits of history.
+    #[serde(default)]
+    pub clone_depth: Option<u32>,
+    /// Clone without blobs, fetching them as checkouts need them.
+    #[serde(default)]
+    pub partial_clone: bool,
+    /// Directories to check out, leaving the rest of the repository out of
+    /// the clone. Empty checks out everything.
+    #[serde(default)]
+    pub sparse_paths: Vec<String>,
+    /// GitHub token used to look up the repository's size before cloning.
+    #[serde(default)]
+    pub api_token: Option<String>,
+    /// Free space required for a clone when its size is unknown. Defaults to
+    /// [`DEFAULT_MIN_FREE_SPACE_MB`].
+    #[serde(default)]
+    pub min_free_space_mb: Option<u64>,
+    /// Clone even if the disk looks too small.
+    #[serde(default)]
+    pub skip_disk_check: bool,
+    /// Only stage writes, leaving commits and pushes to
+    /// [`GitHubStorage::git_commit`] and [`GitHubStorage::git_push`].
+    #[serde(default)]
+    pub manual_commits: bool,
+    /// Track [`Self::lfs_patterns`] with Git LFS, committing the change to
+    /// `.gitattributes` before the first write. Off leaves the repository's
+    /// own LFS setup alone.
+    #[serde(default)]
+    pub manage_lfs_tracking: bool,
+    /// Patterns tracked by [`Self::manage_lfs_tracking`]. Defaults to
+    /// [`DEFAULT_LFS_PATTERNS`].
+    #[serde(default)]
+    pub lfs_patterns: Vec<String>,
+    /// Delete the clone at `local_path` when it is of another repository
+    /// than `repo_url`, instead of failing.
+    #[serde(default)]
+    pub replace_existing: bool,
+    /// Delete the clone from the remote host on disconnect.
+    #[serde(default)]
+    pub remove_clone_on_disconnect: bool,
+}
+
+// Written by hand so credentials never end up in logs.
+impl fmt::Debug for GitHubConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GitHubConfig")
+            .field("repo_url", &self.repo_url)
+            .field("username", &self.username)
+            .field("branch", &self.branch)
+            .field("local_path", &self.local_path)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DeletedFile {
+    pub path: String,
+    pub commit: String,
+    pub author: String,
+    pub deleted_at: u64,
+    /// The path exists again at HEAD, so it is not actually missing.
+    pub readded: bool,
+}
+
+/// A change `git status` reports for one side of a path: the index or the
+/// working tree.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GitChange {
+    Modified,
+    TypeChanged,
+    Added,
+    Deleted,
+    Renamed,
+    Copied,
+    Unmerged,
+    Untracked,
+}
+
+impl GitChange {
+    /// Parses one of the `XY` status letters, `None` for an unchanged side.
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            b'M' => Some(GitChange::Modified),
+            b'T' => Some(GitChange::TypeChanged),
+            b'A' => Some(GitChange::Added),
+            b'D' => Some(GitChange::Deleted),
+            b'R' => Some(GitChange::Renamed),
+            b'C' => Some(GitChange::Copied),
+            b'U' => Some(GitChange::Unmerged),
+            b'?' => Some(GitChange::Untracked),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GitStatusEntry {
+    pub path: String,
+    /// Where a renamed or copied file came from.
+    pub original_path: Option<String>,
+    /// The change staged for the next commit.
+    pub staged: Option<GitChange>,
+    /// The change in the working tree that is not staged.
+    pub unstaged: Option<GitChange>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct GitStatus {
+    /// `None` on a detached HEAD.
+    pub branch: Option<String>,
+    pub upstream: Option<String>,
+    /// Commits not yet pushed.
+    pub ahead: u32,
+    /// Commits on the remote not yet pulled.
+    pub behind: u32,
+    pub entries: Vec<GitStatusEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FileRevision {
+    pub commit: String,
+    pub author: String,
+    pub date: u64,
+    /// Subject line of the commit message.
+    pub message: String,
+    /// The file's path in this revision, which differs after a rename.
+    pub path: String,
+}
+
+/// Emitted with a [`GitProgress`] payload while cloning or pulling.
+pub const CLONE_PROGRESS_EVENT: &str = "github://clone_progress";
+/// Emitted with a [`GitProgress`] payload while fetching LFS content.
+pub const LFS_PROGRESS_EVENT: &str = "github://lfs_progress";
+
+/// A long-running git operation reporting progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GitOperation {
+    Clone,
+    Lfs,
+}
+
+impl GitOperation {
+    pub fn event(self) -> &'static str {
+        match self {
+            GitOperation::Clone => CLONE_PROGRESS_EVENT,
+            GitOperation::Lfs => LFS_PROGRESS_EVENT,
+        }
+    }
+}
+
+/// One `--progress` line, such as `Receiving objects:  42% (420/1000)`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GitProgress {
+    pub phase: String,
+    pub percent: u8,
+    pub received: u64,
+    pub total: u64,
+}
+
+pub type GitProgressHandler = Arc<dyn Fn(GitOperation, &GitProgress) + Send + Sync>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Branch {
+    pub name: String,
+    /// Head commit on the remote.
+    pub commit: String,
+    /// The clone has this branch checked out.
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BranchSwitch {
+    pub branch: String,
+    /// HEAD of the clone after switching.
+    pub head: String,
+}
+
+/// What [`GitHubStorage::refresh_repository`] brought in.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RefreshSummary {
+    pub old_head: String,
+    pub new_head: String,
+    /// Files added, changed or removed anywhere in the repository.
+    pub changed_count: usize,
+    pub changed_paths: Vec<String>,
+}
+
+impl RefreshSummary {
+    /// Keeps only the changed paths within `dir`.
+    pub fn within(mut self, dir: &RemotePath) -> Self {
+        self.changed_paths
+            .retain(|path| RemotePath::new(path).is_within(dir));
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeletedFilesPage {
+    pub files: Vec<DeletedFile>,
+    /// Commit offset for the next page, `None` once history is exhausted.
+    pub next_offset: Option<usize>,
+}
+
+struct CachedListing {
+    dir: RemotePath,
+    filter: MediaFilter,
+    show_hidden: bool,
+    fetched_at: Instant,
+    files: Vec<FileInfo>,
 }
 
+impl CachedListing {
+    fn is_fresh(&self, dir: &RemotePath, options: &ListOptions) -> bool {
+        self.dir == *dir
+            && self.filter == options.filter
+            && self.show_hidden == options.show_hidden
+            && self.fetched_at.elapsed() < LISTING_CACHE_TTL
+    }
+}
+
+/// Storage backed by a clone of [`GitHubConfig::repo_url`]. Its storage
+/// type stays `github` so saved profiles and the UI keep working.
 pub struct GitHubStorage {
     config: GitHubConfig,
     session: Option<Session>,
+    keepalive: Option<Keepalive>,
+    on_connection_lost: Option<ConnectionLostHandler>,
+    on_progress: Option<GitProgressHandler>,
+    /// Set once the keepalive or an operation finds the session dead.
+    lost: Arc<AtomicBool>,
+    metrics: StorageMetrics,
     repo_cloned: bool,
+    /// The checked-out branch, starting as [`GitHubConfig::branch`]. Held
+    /// for the whole of a switch so pushes never target a half-switched
+    /// clone.
+    branch: Mutex<String>,
+    /// The sparse checkout cone, starting as [`GitHubConfig::sparse_paths`].
+    sparse_paths: Mutex<Vec<RemotePath>>,
+    listing_cache: Mutex<Option<CachedListing>>,
+    /// Repo-relative paths known to hold content rather than an LFS pointer.
+    lfs_materialized: Mutex<HashSet<String>>,
+    /// [`GitHubConfig::lfs_patterns`] are tracked on the checked-out branch.
+    lfs_tracking_ready: AtomicBool,
+    /// Probed on connect when [`GitHubConfig::remote_thumbnails`] is set.
+    remote_thumbnailer: Option<RemoteThumbnailer>,
 }
 
 impl GitHubStorage {
     pub fn new(config: GitHubConfig) -> Self {
         GitHubStorage {
+            branch: Mutex::new(config.branch.clone()),
+            sparse_paths: Mutex::new(sparse_cone(&config.sparse_paths)),
             config,
             session: None,
+            keepalive: None,
+            on_connection_lost: None,
+            on_progress: None,
+            lost: Arc::new(AtomicBool::new(false)),
+            metrics: StorageMetrics::default(),
             repo_cloned: false,
+            listing_cache: Mutex::new(None),
+            lfs_materialized: Mutex::new(HashSet::new()),
+            lfs_tracking_ready: AtomicBool::new(false),
+            remote_thumbnailer: None,
         }
     }
 
-    fn get_github_host(&self) -> String {
-        if self.config.repo_url.contains("github.com") {
-            "github.com".to_string()
-        } else {
-            self.config
-                .repo_url
-                .split('/')
-                .next()
-                .unwrap_or("github.com")
-                .replace("git@", "")
-                .split(':')
-                .next()
-                .unwrap_or("github.com")
-                .to_string()
+    /// The config, with the branch and sparse paths switched to since
+    /// connecting.
+    pub fn config(&self) -> GitHubConfig {
+        GitHubConfig {
+            branch: self.branch(),
+            sparse_paths: self
+                .sparse_paths()
+                .iter()
+                .map(RemotePath::to_string)
+                .collect(),
+            ..self.config.clone()
+        }
+    }
+
+    fn sparse_paths(&self) -> Vec<RemotePath> {
+        self.sparse_paths
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// `git` with the configured author, for commands that commit.
+    fn git_with_author(&self) -> String {
+        let mut git = "git".to_string();
+        let settings = [
+            ("user.name", &self.config.author_name),
+            ("user.email", &self.config.author_email),
+        ];
+        for (key, value) in settings {
+            if let Some(value) = value {
+                git.push_str(" -c ");
+                git.push_str(&shell_quote(&format!("{}={}", key, value)));
+            }
+        }
+        git
+    }
+
+    fn branch(&self) -> String {
+        self.branch
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn invalidate_listing_cache(&self) {
+        if let Ok(mut cache) = self.listing_cache.lock() {
+            *cache = None;
+        }
+    }
+
+    /// Forgets the materialized LFS files, for when a checkout may have put
+    /// pointers back.
+    fn forget_materialized(&self) {
+        if let Ok(mut materialized) = self.lfs_materialized.lock() {
+            materialized.clear();
+        }
+    }
+
+    /// The host and port the SSH connection is made to: the git server
+    /// named by [`GitHubConfig::repo_url`], on any host.
+    fn ssh_target(&self) -> Result<(String, u16), StorageError> {
+        let remote = GitRemote::parse(&self.config.repo_url).ok_or_else(|| {
+            StorageError::InvalidInput(format!("Not a git remote URL: {}", self.config.repo_url))
+        })?;
+        let port = remote.ssh_port();
+        Ok((remote.host, port))
+    }
+
+    /// Connects and authenticates, giving each step up to `timeout`.
+    fn open_session(&self, timeout: Duration) -> Result<Session, StepError> {
+        let (host, port) = self.ssh_target().step(ConnectionStep::Tcp)?;
+        log::info!(
+            "Connecting to {} as {} for {}",
+            host,
+            self.config.username,
+            self.config.repo_url
+        );
+        let opened: Result<_, StepError> = (|| {
+            let tcp = utils::connect_tcp(&host, port, timeout).step(ConnectionStep::Tcp)?;
+            let mut session = Session::new().step(ConnectionStep::Handshake)?;
+            session.set_tcp_stream(tcp);
+            session.set_timeout(timeout.as_millis().min(u32::MAX as u128) as u32);
+            session.handshake().step(ConnectionStep::Handshake)?;
+            host_keys::verify_host_key(
+                &session,
+                &host,
+                port,
+                self.config.known_hosts_file.as_deref(),
+            )
+            .step(ConnectionStep::HostKey)?;
+
+            let passphrase = self.config.key_passphrase.as_deref();
+            let key_str = utils::decode_private_key(&self.config.ssh_key_content, passphrase)
+                .step(ConnectionStep::Credentials)?;
+            session
+                .userauth_pubkey_memory(&self.config.username, None, &key_str, passphrase)
+                .step(ConnectionStep::Authentication)?;
+            if !session.authenticated() {
+                return Err(StorageError::AuthFailed {
+                    reason: "the key was rejected".to_string(),
+                })
+                .step(ConnectionStep::Authentication);
+            }
+            // Only connecting is limited; clones may take as long as they
+            // need.
+            session.set_timeout(0);
+            Ok(session)
+        })();
+        match &opened {
+            Ok(_) => log::info!("Authenticated as {} on {}", self.config.username, host),
+            Err(e) => log::warn!("Connecting to {} failed at {}: {}", host, e.step, e.error),
         }
+        opened
+    }
+
+    /// Connects, checks that the repository is readable and has the
+    /// configured branch, then disconnects again. Nothing is cloned.
+    pub fn validate(&self) -> ValidationResult {
+        let started = Instant::now();
+        let branch = self.branch();
+        let result = self.open_session(VALIDATION_TIMEOUT).and_then(|session| {
+            session.set_timeout(VALIDATION_TIMEOUT.as_millis() as u32);
+            let command = ls_remote_command(&self.config.repo_url, &branch);
+            let checked = ssh_util::ssh_exec(&session, &command)
+                .step(ConnectionStep::RepoAccess)
+                .and_then(|output| match output.exit_status {
+                    0 => Ok(()),
+                    LS_REMOTE_NO_MATCH => Err(StorageError::NotFound(format!(
+                        "Branch {} in {}",
+                        if branch.is_empty() { "HEAD" } else { &branch },
+                        self.config.repo_url
+                    )))
+                    .step(ConnectionStep::Branch),
+                    _ => output
+                        .checked(&command)
+                        .map(|_| ())
+                        .step(ConnectionStep::RepoAccess),
+                });
+            let _ = session.disconnect(None, "Connection test done", None);
+            checked
+        });
+        ValidationResult::new(result, started)
+    }
+
+    /// Runs `cmd` and returns its stdout whatever its exit status; for
+    /// probes that report through their output.
+    fn execute_remote_command(&self, cmd: &str) -> Result<String, StorageError> {
+        let output = self.execute_remote_command_bytes(cmd)?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    /// Runs `cmd`, failing with its stderr if it exits non-zero.
+    fn execute_remote_command_checked(&self, cmd: &str) -> Result<String, StorageError> {
+        let stdout = self.execute_remote_command_bytes_checked(cmd)?;
+        Ok(String::from_utf8_lossy(&stdout).into_owned())
+    }
+
+    /// Binary-safe variant of [`Self::execute_remote_command_checked`] for
+    /// file content. stderr is kept out of the returned bytes.
+    fn execute_remote_command_bytes_checked(&self, cmd: &str) -> Result<Vec<u8>, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        self.metrics.record_command();
+        Ok(ssh_util::ssh_exec(session, cmd)?.checked(cmd)?)
     }
 
-    fn execute_remote_command(&self, cmd: &str) -> Result<String, Box<dyn std::error::Error>> {
-        let session = self.session.as_ref().ok_or("Not connected to SSH")?;
-        let mut channel = session.channel_session()?;
-        channel.exec(cmd)?;
+    fn execute_remote_command_bytes(&self, cmd: &str) -> Result<Vec<u8>, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        self.metrics.record_command();
+        Ok(ssh_util::ssh_exec(session, cmd)?.stdout)
+    }
 
-        let mut output = String::new();
-        channel.read_to_string(&mut output)?;
+    fn execute_remote_command_cancellable(
+        &self,
+        cmd: &str,
+        cancelled: &AtomicBool,
+    ) -> Result<Vec<u8>, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        self.metrics.record_command();
+        ssh_util::ssh_exec_cancellable(session, cmd, cancelled)
+    }
 
-        channel.wait_eof()?;
-        channel.close()?;
-        channel.wait_close()?;
+    /// Runs `cmd`, reporting the progress lines it prints to the progress
+    /// handler as `operation`.
+    fn execute_with_progress(
+        &self,
+        cmd: &str,
+        operation: GitOperation,
+    ) -> Result<(), StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        self.metrics.record_command();
+        let mut pending = Vec::new();
+        let mut last = None;
+        let mut on_output = |chunk: &[u8]| {
+            let Some(on_progress) = &self.on_progress else {
+                return;
+            };
+            pending.extend_from_slice(chunk);
+            // git redraws a progress line with `\r`; a partial line waits
+            // for the next chunk.
+            let Some(end) = pending.iter().rposition(|b| matches!(b, b'\r' | b'\n')) else {
+                return;
+            };
+            let lines: Vec<u8> = pending.drain(..=end).collect();
+            for line in String::from_utf8_lossy(&lines).split(['\r', '\n']) {
+                if let Some(progress) = parse_progress_line(line) {
+                    let key = (progress.phase.clone(), progress.percent);
+                    if last.as_ref() != Some(&key) {
+                        on_progress(operation, &progress);
+                        last = Some(key);
+                    }
+                }
+            }
+        };
+        ssh_util::ssh_exec_merged(session, cmd, &mut on_output)?.checked(cmd)?;
+        Ok(())
+    }
 
-        Ok(output)
+    /// Runs `cmd` and hands its stdout to `on_chunk` as it arrives.
+    fn stream_remote_command(
+        &self,
+        cmd: &str,
+        total: Option<u64>,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        self.metrics.record_command();
+        ssh_util::ssh_exec_streamed(session, cmd, total, on_chunk)
     }
 
-    fn ensure_repo_exists(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn ensure_repo_exists(&mut self) -> Result<(), StorageError> {
         if self.repo_cloned {
             return Ok(());
         }
 
         let repo_path = &self.config.local_path;
-        let branch = &self.config.branch;
+        let branch = &self.branch();
 
         let check_cmd = format!(
-            "[ -d {} ] && echo 'exists' || echo 'not_exists'",
-            shell_quote(repo_path)
+            "if [ ! -e {p} ]; then echo missing; elif [ -d {p}/.git ]; then echo git; \
+             elif [ -d {p} ] && [ -z \"$(ls -A {p})\" ]; then echo empty; else echo other; fi",
+            p = shell_quote(repo_path)
         );
-        let result = self.execute_remote_command(&check_cmd)?;
-
-        if result.trim() == "exists" {
-            let check_git = format!(
-                "[ -d {}/.git ] && echo 'git' || echo 'not_git'",
+        let result = self.execute_remote_command_checked(&check_cmd)?;
+
+        let state = result.trim();
+        if state == "git" && self.origin_matches()? {
+            // Clones made before LFS files were fetched lazily still
+            // smudge on checkout until told not to.
+            let lfs_install = format!(
+                "cd {} && git lfs install --local --skip-smudge",
                 shell_quote(repo_path)
             );
-            let git_result = self.execute_remote_command(&check_git)?;
-
-            if git_result.trim() == "git" {
-                let pull_cmd = format!(
-                    "cd {} && git fetch origin && git checkout {} && git pull origin {}",
-                    shell_quote(repo_path),
-                    shell_quote(branch),
-                    shell_quote(branch)
-                );
-                self.execute_remote_command(&pull_cmd)?;
-
-                let lfs_pull = format!("cd {} && git lfs pull", shell_quote(repo_path));
-                self.execute_remote_command(&lfs_pull)?;
-            } else {
-                let rm_cmd = format!("rm -rf {}", shell_quote(repo_path));
-                self.execute_remote_command(&rm_cmd)?;
-                self.clone_repository()?;
-            }
+            self.execute_remote_command(&lfs_install)?;
+            self.apply_sparse_paths(&self.sparse_paths())?;
+
+            // Fetched by refspec, as a shallow clone tracks only the
+            // branch it was cloned with.
+            let pull_cmd = format!(
+                "cd {repo} && export GIT_LFS_SKIP_SMUDGE=1 && git fetch --progress{depth} origin {refspec} && git checkout {branch} && git pull --progress{depth} origin {branch}",
+                repo = shell_quote(repo_path),
+                depth = self.depth_arg(),
+                refspec = shell_quote(&fetch_refspec(branch)),
+                branch = shell_quote(branch)
+            );
+            self.execute_with_progress(&pull_cmd, GitOperation::Clone)?;
         } else {
+            // Never delete someone's data over a mistyped path.
+            if state != "missing" && state != "empty" {
+                if !self.config.replace_existing {
+                    return Err(StorageError::LocalPathConflict(repo_path.clone()));
+                }
+                self.remove_clone()?;
+            }
             self.clone_repository()?;
         }
 
@@ -116,243 +634,2164 @@ impl GitHubStorage {
         Ok(())
     }
 
-    fn clone_repository(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Whether the existing clone's `origin` is [`GitHubConfig::repo_url`].
+    fn origin_matches(&self) -> Result<bool, StorageError> {
+        let origin = self.execute_remote_command(&format!(
+            "cd {} && git remote get-url origin",
+            shell_quote(&self.config.local_path)
+        ))?;
+        let origin = origin.trim();
+        let matches = match (
+            GitRemote::parse(origin),
+            GitRemote::parse(&self.config.repo_url),
+        ) {
+            (Some(origin), Some(expected)) => origin.same_repository(&expected),
+            _ => origin == self.config.repo_url,
+        };
+        if !matches {
+            log::warn!(
+                "{} is a clone of {:?}, not {}",
+                self.config.local_path,
+                origin,
+                self.config.repo_url
+            );
+        }
+        Ok(matches)
+    }
+
+    fn clone_repository(&mut self) -> Result<(), StorageError> {
+        if !self.config.skip_disk_check {
+            self.check_disk_space()?;
+        }
         let repo_path = &self.config.local_path;
         let repo_url = &self.config.repo_url;
-        let branch = &self.config.branch;
+        let branch = &self.branch();
 
         let mkdir_cmd = format!("mkdir -p {}", shell_quote(repo_path));
-        self.execute_remote_command(&mkdir_cmd)?;
+        self.execute_remote_command_checked(&mkdir_cmd)?;
 
+        // LFS files are left as pointers and fetched when first read.
+        let filter_arg = if self.config.partial_clone {
+            " --filter=blob:none"
+        } else {
+            ""
+        };
+        // A sparse clone is checked out once the cone is set.
+        let sparse_paths = self.sparse_paths();
+        let checkout_arg = if sparse_paths.is_empty() {
+            ""
+        } else {
+            " --no-checkout"
+        };
         let clone_cmd = format!(
-            "git clone --branch {} {} {}",
+            "GIT_LFS_SKIP_SMUDGE=1 git clone --progress{}{}{} --branch {} {} {}",
+            self.depth_arg(),
+            filter_arg,
+            checkout_arg,
             shell_quote(branch),
             shell_quote(repo_url),
             shell_quote(repo_path)
         );
-        self.execute_remote_command(&clone_cmd)?;
+        self.execute_with_progress(&clone_cmd, GitOperation::Clone)?;
 
-        let lfs_install = format!("cd {} && git lfs install", shell_quote(repo_path));
+        let lfs_install = format!(
+            "cd {} && git lfs install --local --skip-smudge",
+            shell_quote(repo_path)
+        );
         self.execute_remote_command(&lfs_install)?;
 
-        let lfs_pull = format!("cd {} && git lfs pull", shell_quote(repo_path));
-        self.execute_remote_command(&lfs_pull)?;
+        if !sparse_paths.is_empty() {
+            self.apply_sparse_paths(&sparse_paths)?;
+            let checkout_cmd = format!(
+                "cd {} && GIT_LFS_SKIP_SMUDGE=1 git checkout {}",
+                shell_quote(repo_path),
+                shell_quote(branch)
+            );
+            self.execute_remote_command_checked(&checkout_cmd)?;
+        }
 
         Ok(())
     }
 
-    fn get_lfs_file_content(&self, file_path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let full_path = format!("{}/{}", self.config.local_path, file_path);
-
-        let check_lfs = format!(
-            "cd {} && git lfs ls-files | grep -q {} && echo 'lfs' || echo 'regular'",
-            shell_quote(&self.config.local_path),
-            shell_quote(file_path)
-        );
-        let result = self.execute_remote_command(&check_lfs)?;
+    /// Limits the clone's checkout to `paths`, or lifts the limit when
+    /// `paths` is empty.
+    fn apply_sparse_paths(&self, paths: &[RemotePath]) -> Result<(), StorageError> {
+        let repo_path = shell_quote(&self.config.local_path);
+        let sparse_cmd = if paths.is_empty() {
+            format!(
+                "cd {} && if [ \"$(git config --get core.sparseCheckout)\" = true ]; then GIT_LFS_SKIP_SMUDGE=1 git sparse-checkout disable; fi",
+                repo_path
+            )
+        } else {
+            let quoted: Vec<Cow<'_, str>> =
+                paths.iter().map(|p| shell_quote(p.relative())).collect();
+            format!(
+                "cd {} && export GIT_LFS_SKIP_SMUDGE=1 && git sparse-checkout init --cone && git sparse-checkout set {}",
+                repo_path,
+                quoted.join(" ")
+            )
+        };
+        self.execute_remote_command_checked(&sparse_cmd)?;
+        Ok(())
+    }
 
-        if result.trim() == "lfs" {
-            let cat_cmd = format!(
-                "cd {} && git lfs smudge < {}",
-                shell_quote(&self.config.local_path),
-                shell_quote(file_path)
-            );
-            let output = self.execute_remote_command(&cat_cmd)?;
-            return Ok(output.into_bytes());
+    /// Deletes the clone from the remote host, leaving the storage to be
+    /// reconnected before further use.
+    pub fn cleanup_remote_clone(&self) -> Result<(), StorageError> {
+        // Only a clone connecting verified as this repository is deleted.
+        if !self.repo_cloned {
+            return Err(StorageError::NotConnected);
         }
-
-        let cat_cmd = format!("cat {}", shell_quote(&full_path));
-        let output = self.execute_remote_command(&cat_cmd)?;
-        Ok(output.into_bytes())
+        let _branch = self.branch.lock()?;
+        self.invalidate_listing_cache();
+        self.forget_materialized();
+        self.remove_clone()?;
+        log::info!("Removed clone {}", self.config.local_path);
+        Ok(())
     }
 
-    fn setup_lfs_tracking(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Deletes `local_path` from the remote host, refusing shallow paths and
+    /// anything that is not a git working tree.
+    fn remove_clone(&self) -> Result<(), StorageError> {
         let repo_path = &self.config.local_path;
-
-        let track_all = format!(
-            "cd {} && git lfs track \"*\" && git lfs track \"**/*\"",
-            shell_quote(repo_path)
+        paths::guard_absolute_delete(repo_path).map_err(StorageError::InvalidInput)?;
+        let rm_cmd = format!(
+            "if [ -d {p}/.git ]; then rm -rf {p} && echo removed; fi",
+            p = shell_quote(repo_path)
         );
-        self.execute_remote_command(&track_all)?;
+        if self.execute_remote_command_checked(&rm_cmd)?.trim() != "removed" {
+            return Err(StorageError::LocalPathConflict(repo_path.clone()));
+        }
+        Ok(())
+    }
 
-        let add_attributes = format!(
-            "cd {} && git add .gitattributes 2>/dev/null || true",
-            shell_quote(repo_path)
-        );
-        self.execute_remote_command(&add_attributes)?;
+    /// Reports clone, pull and LFS progress to `handler`.
+    pub fn set_progress_handler(&mut self, handler: GitProgressHandler) {
+        self.on_progress = Some(handler);
+    }
 
+    /// Changes the directories the clone checks out, see
+    /// [`GitHubConfig::sparse_paths`].
+    pub fn set_sparse_paths(&self, paths: &[String]) -> Result<(), StorageError> {
+        let cone = sparse_cone(paths);
+        let mut sparse_paths = self.sparse_paths.lock()?;
+        self.apply_sparse_paths(&cone)?;
+        *sparse_paths = cone;
+        self.invalidate_listing_cache();
+        self.forget_materialized();
         Ok(())
     }
-}
-
-impl Storage for GitHubStorage {
-    fn connect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let host = self.get_github_host();
-        let addr = format!("{}:22", host);
 
-        let tcp = TcpStream::connect_timeout(
-            &addr.parse()?,
-            Duration::from_secs(CONNECTION_TIMEOUT_SECS),
-        )?;
-        let mut session = Session::new()?;
-        session.set_tcp_stream(tcp);
-        session.handshake()?;
+    /// Fails for directories outside the sparse checkout, which would
+    /// otherwise list as empty.
+    fn ensure_checked_out(&self, dir: &RemotePath) -> Result<(), StorageError> {
+        let sparse_paths = self.sparse_paths.lock()?;
+        if sparse_paths.is_empty()
+            || sparse_paths
+                .iter()
+                .any(|p| dir.is_within(p) || p.is_within(dir))
+        {
+            Ok(())
+        } else {
+            Err(StorageError::NotCheckedOut(dir.to_string()))
+        }
+    }
 
-        let key_bytes = utils::base64_decode(&self.config.ssh_key_content)?;
-        let key_str = String::from_utf8(key_bytes)?;
+    /// Checks that the configured branch exists on the remote before
+    /// cloning it, or picks the remote's default branch if none is set.
+    fn resolve_branch(&mut self) -> Result<(), StorageError> {
+        let ls_cmd = format!(
+            "git ls-remote --symref {} HEAD 'refs/heads/*'",
+            shell_quote(&self.config.repo_url)
+        );
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        self.metrics.record_command();
+        let output = ssh_util::ssh_exec(session, &ls_cmd)?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.success() && is_access_denied(&stderr) {
+            return Err(StorageError::RepoAccessDenied(format!(
+                "{}: {}",
+                self.config.repo_url,
+                stderr.trim().lines().next().unwrap_or_default()
+            )));
+        }
+        let output = String::from_utf8_lossy(&output.checked(&ls_cmd)?).into_owned();
+        let branch = self.branch.get_mut()?;
+        *branch = pick_branch(&output, branch)?;
+        Ok(())
+    }
 
-        session.userauth_pubkey_memory(&self.config.username, None, &key_str, None)?;
+    /// Fails if the filesystem of the clone lacks room for the repository,
+    /// as far as its size can be told beforehand.
+    fn check_disk_space(&self) -> Result<(), StorageError> {
+        // The clone's directory may not exist yet.
+        let df_cmd = format!(
+            "p={}; while [ ! -e \"$p\" ]; do p=$(dirname \"$p\"); done; df -B1 --output=avail \"$p\" | tail -n 1",
+            shell_quote(&self.config.local_path)
+        );
+        let Ok(available) = self.execute_remote_command(&df_cmd)?.trim().parse::<u64>() else {
+            log::warn!(
+                "Could not tell the free space for {}",
+                self.config.local_path
+            );
+            return Ok(());
+        };
+        let required = self.estimated_clone_size().unwrap_or_else(|| {
+            self.config
+                .min_free_space_mb
+                .unwrap_or(DEFAULT_MIN_FREE_SPACE_MB)
+                * MIB
+        });
+        if available < required {
+            return Err(StorageError::InsufficientDiskSpace {
+                required,
+                available,
+            });
+        }
+        Ok(())
+    }
 
-        if !session.authenticated() {
-            return Err("GitHub SSH authentication failed".into());
+    /// Space the clone should take, if the API token can look it up.
+    fn estimated_clone_size(&self) -> Option<u64> {
+        let token = self.config.api_token.as_deref().filter(|t| !t.is_empty())?;
+        let (owner, repo) = GitRemote::parse(&self.config.repo_url)?.github_slug()?;
+        match github_api::repository_size(&owner, &repo, token) {
+            Ok(size) => Some(size * CLONE_SPACE_FACTOR),
+            Err(e) => {
+                log::warn!("Could not look up the size of {}/{}: {}", owner, repo, e);
+                None
+            }
         }
+    }
 
-        self.session = Some(session);
-        self.ensure_repo_exists()?;
-        self.setup_lfs_tracking()?;
+    /// The `--depth` argument of clones and fetches, if limited.
+    fn depth_arg(&self) -> String {
+        self.config
+            .clone_depth
+            .filter(|depth| *depth > 0)
+            .map(|depth| format!(" --depth {}", depth))
+            .unwrap_or_default()
+    }
 
+    /// Fails for a shallow clone, whose history ends early.
+    fn ensure_full_history(&self) -> Result<(), StorageError> {
+        let shallow_cmd = format!(
+            "cd {} && git rev-parse --is-shallow-repository",
+            shell_quote(&self.config.local_path)
+        );
+        if self.execute_remote_command_checked(&shallow_cmd)?.trim() == "true" {
+            return Err(StorageError::Unsupported(
+                "History is unavailable in a shallow clone; connect without a clone depth to see it"
+                    .to_string(),
+            ));
+        }
         Ok(())
     }
 
-    fn disconnect(&mut self) {
-        if let Some(session) = self.session.take() {
-            let _ = session.disconnect(None, "Closing connection", None);
+    fn get_lfs_file_content(&self, file_path: &str) -> Result<Vec<u8>, StorageError> {
+        let cat_cmd = self.file_content_command(file_path)?;
+        self.execute_remote_command_bytes_checked(&cat_cmd)
+    }
+
+    /// Returns the command printing a file's content, fetching it first if
+    /// it is still an LFS pointer.
+    fn file_content_command(&self, file_path: &str) -> Result<String, StorageError> {
+        self.materialize_lfs(&[file_path])?;
+        Ok(format!(
+            "cat {}",
+            shell_quote(&self.repo_file_path(file_path))
+        ))
+    }
+
+    /// Replaces the LFS pointers among the repo-relative `rel_paths` in the
+    /// clone with their content, downloading only those objects.
+    fn materialize_lfs(&self, rel_paths: &[&str]) -> Result<(), StorageError> {
+        let pending: Vec<&str> = {
+            let materialized = self.lfs_materialized.lock()?;
+            rel_paths
+                .iter()
+                .copied()
+                .filter(|p| !materialized.contains(*p))
+                .collect()
+        };
+        for (i, chunk) in pending.chunks(LFS_MATERIALIZE_BATCH).enumerate() {
+            let cmd = materialize_command(
+                &self.config.local_path,
+                chunk,
+                i * LFS_MATERIALIZE_BATCH,
+                pending.len(),
+            );
+            self.execute_with_progress(&cmd, GitOperation::Lfs)?;
+            self.lfs_materialized
+                .lock()?
+                .extend(chunk.iter().map(|p| p.to_string()));
         }
-        self.repo_cloned = false;
+        Ok(())
     }
 
-    fn is_connected(&self) -> bool {
-        self.session.as_ref().is_some_and(|s| s.authenticated())
+    /// Fetches the LFS content of `paths` ahead of reading them.
+    pub fn prefetch_lfs(&self, paths: &[&str]) -> Result<(), StorageError> {
+        let rel_paths: Vec<RemotePath> = paths.iter().map(|p| self.repo_path(p)).collect();
+        let rel_paths: Vec<&str> = rel_paths.iter().map(|p| p.relative()).collect();
+        self.materialize_lfs(&rel_paths)
     }
 
-    fn list_directory(&self, path: &str) -> Result<Vec<FileInfo>, Box<dyn std::error::Error>> {
-        let _ = self.session.as_ref().ok_or("Not connected")?;
+    fn repo_path(&self, path: &str) -> RemotePath {
+        self.path_translator().to_remote(path)
+    }
 
-        let full_path = if path.is_empty() || path == "/" {
-            self.config.local_path.clone()
-        } else {
-            format!(
-                "{}/{}",
-                self.config.local_path,
-                path.trim_start_matches('/')
-            )
-        };
+    fn repo_file_path(&self, path: &str) -> String {
+        self.path_translator().resolve(path)
+    }
 
-        let ls_cmd = format!(
-            "ls -la --time-style=+%s {} 2>/dev/null || echo 'DIR_NOT_FOUND'",
-            shell_quote(&full_path)
+    fn stage_paths(&self, paths: &[&str]) -> Result<(), StorageError> {
+        self.ensure_lfs_tracking()?;
+        let add_cmd = format!(
+            "cd {} && export GIT_LITERAL_PATHSPECS=1 && git add -A -- {}",
+            shell_quote(&self.config.local_path),
+            quote_repo_paths(paths)
+        );
+        self.execute_remote_command_checked(&add_cmd)?;
+        Ok(())
+    }
+
+    /// Commits the already-staged `paths` and pushes the checked-out
+    /// branch, unless [`GitHubConfig::manual_commits`] leaves that to the
+    /// user.
+    fn commit_and_push(&self, paths: &[&str], message: &str) -> Result<(), StorageError> {
+        let branch = self.branch.lock()?;
+        self.invalidate_listing_cache();
+        if self.config.manual_commits {
+            return Ok(());
+        }
+        // Nothing staged (e.g. a mode change git ignores) is not an error.
+        let commit_cmd = format!(
+            "cd {} && export GIT_LITERAL_PATHSPECS=1 && {{ git diff --cached --quiet -- {} || {} commit -m {} -- {}; }}",
+            shell_quote(&self.config.local_path),
+            quote_repo_paths(paths),
+            self.git_with_author(),
+            shell_quote(message),
+            quote_repo_paths(paths)
         );
-        let output = self.execute_remote_command(&ls_cmd)?;
+        self.execute_remote_command_checked(&commit_cmd)?;
+        self.push_branch(&branch)
+    }
 
-        if output.contains("DIR_NOT_FOUND") {
-            return Ok(vec![]);
+    /// Pushes `branch`, telling a push the remote rejected for lacking its
+    /// commits apart from other failures.
+    fn push_branch(&self, branch: &str) -> Result<(), StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let repo_path = shell_quote(&self.config.local_path);
+        let push_cmd = format!(
+            "cd {} && git push origin {}",
+            repo_path,
+            shell_quote(branch)
+        );
+        self.metrics.record_command();
+        let output = ssh_util::ssh_exec(session, &push_cmd)?;
+        if output.success() {
+            return Ok(());
+        }
+        if !is_push_rejected(&String::from_utf8_lossy(&output.stderr)) {
+            output.checked(&push_cmd)?;
         }
 
-        let mut files = Vec::new();
+        let local = self
+            .execute_remote_command_checked(&format!("cd {} && git rev-parse HEAD", repo_path))?;
+        let remote = self.execute_remote_command_checked(&format!(
+            "cd {} && git ls-remote origin {}",
+            repo_path,
+            shell_quote(&format!("refs/heads/{}", branch))
+        ))?;
+        Err(StorageError::PushRejected {
+            local: local.trim().to_string(),
+            remote: remote
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        })
+    }
 
-        for line in output.lines().skip(1) {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() < 9 {
-                continue;
-            }
+    /// Reports the checked-out branch, how far it is from its upstream and
+    /// the changed paths.
+    pub fn git_status(&self) -> Result<GitStatus, StorageError> {
+        let status_cmd = format!(
+            "cd {} && git status --porcelain -z --branch",
+            shell_quote(&self.config.local_path)
+        );
+        let output = self.execute_remote_command_bytes_checked(&status_cmd)?;
+        Ok(parse_git_status(&output))
+    }
 
-            let name = parts[8..].join(" ");
-            if name == "." || name == ".." || name == ".git" || name == ".gitattributes" {
-                continue;
+    /// Commits `paths`, staging them first, or everything changed when
+    /// `paths` is `None`. Returns the new commit's hash.
+    pub fn git_commit(
+        &self,
+        message: &str,
+        paths: Option<&[&str]>,
+    ) -> Result<String, StorageError> {
+        if message.trim().is_empty() {
+            return Err(StorageError::InvalidInput(
+                "A commit message is required".to_string(),
+            ));
+        }
+        let _branch = self.branch.lock()?;
+        let repo_path = shell_quote(&self.config.local_path);
+        let scope = match paths {
+            Some(paths) => {
+                self.stage_paths(paths)?;
+                format!(" -- {}", quote_repo_paths(paths))
             }
+            None => {
+                self.ensure_lfs_tracking()?;
+                self.execute_remote_command_checked(&format!("cd {} && git add -A", repo_path))?;
+                String::new()
+            }
+        };
 
-            let is_dir = parts[0].starts_with('d');
-            let size: u64 = parts[4].parse().unwrap_or(0);
+        let commit_cmd = format!(
+            "cd {repo} && export GIT_LITERAL_PATHSPECS=1 && if git diff --cached --quiet{scope}; then echo 'clean'; \
+             else {git} commit -q -m {message}{scope} && git rev-parse HEAD; fi",
+            repo = repo_path,
+            scope = scope,
+            git = self.git_with_author(),
+            message = shell_quote(message)
+        );
+        let output = self.execute_remote_command_checked(&commit_cmd)?;
+        match output.trim() {
+            "clean" => Err(StorageError::InvalidInput("Nothing to commit".to_string())),
+            head => Ok(head.to_string()),
+        }
+    }
 
-            let file_path = if path.is_empty() || path == "/" {
-                format!("/{}", name)
-            } else {
-                format!("{}/{}", path, name)
-            };
+    /// Pushes the checked-out branch to `origin`.
+    pub fn git_push(&self) -> Result<(), StorageError> {
+        let branch = self.branch.lock()?;
+        self.push_branch(&branch)
+    }
 
-            let mime_type = if is_dir {
-                None
-            } else {
-                detect_mime_type(&name)
-            };
+    /// Fetches the checked-out branch and fast-forwards the clone to it,
+    /// fetching LFS content again for changed files that had it.
+    pub fn refresh_repository(&self) -> Result<RefreshSummary, StorageError> {
+        let branch = self.branch.lock()?;
+        let repo_path = shell_quote(&self.config.local_path);
+        let head_cmd = format!("cd {} && git rev-parse HEAD", repo_path);
+        let old_head = self.execute_remote_command_checked(&head_cmd)?;
+        let old_head = old_head.trim();
+
+        self.execute_with_progress(
+            &format!(
+                "cd {} && git fetch --progress{} origin {}",
+                repo_path,
+                self.depth_arg(),
+                shell_quote(&fetch_refspec(&branch))
+            ),
+            GitOperation::Clone,
+        )?;
 
-            files.push(FileInfo {
-                name,
-                path: file_path,
-                size,
-                is_dir,
-                modified: None,
-                mime_type,
-                thumbnail: None,
-            });
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let merge_cmd = format!(
+            "cd {repo} && git merge-base --is-ancestor HEAD {remote}; case $? in \
+             0) GIT_LFS_SKIP_SMUDGE=1 git merge -q --ff-only {remote} ;; \
+             1) exit {diverged} ;; *) exit 1 ;; esac",
+            repo = repo_path,
+            remote = shell_quote(&format!("refs/remotes/origin/{}", branch)),
+            diverged = MERGE_DIVERGED
+        );
+        self.metrics.record_command();
+        let output = ssh_util::ssh_exec(session, &merge_cmd)?;
+        self.invalidate_listing_cache();
+        if output.exit_status == MERGE_DIVERGED {
+            return Err(StorageError::Diverged(branch.clone()));
         }
+        output.checked(&merge_cmd)?;
 
-        files.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-            (true, false) => std::cmp::Ordering::Less,
-            (false, true) => std::cmp::Ordering::Greater,
-            _ => a.name.cmp(&b.name),
-        });
+        let new_head = self.execute_remote_command_checked(&head_cmd)?;
+        let new_head = new_head.trim();
+        let changed = if new_head == old_head {
+            Vec::new()
+        } else {
+            let diff = self.execute_remote_command_bytes_checked(&format!(
+                "cd {} && git diff --name-only --no-renames -z {} {}",
+                repo_path,
+                shell_quote(old_head),
+                shell_quote(new_head)
+            ))?;
+            diff.split(|&b| b == 0)
+                .filter(|name| !name.is_empty())
+                .map(|name| String::from_utf8_lossy(name).into_owned())
+                .collect()
+        };
 
-        Ok(files)
-    }
+        // The merge put pointers back in place of their content.
+        let rematerialize: Vec<&str> = {
+            let mut materialized = self.lfs_materialized.lock()?;
+            changed
+                .iter()
+                .filter(|path| materialized.remove(path.as_str()))
+                .map(String::as_str)
+                .collect()
+        };
+        self.materialize_lfs(&rematerialize)?;
 
-    fn read_file(&self, path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let clean_path = path.trim_start_matches('/');
-        self.get_lfs_file_content(clean_path)
+        log::info!(
+            "Refreshed {} from {} to {}",
+            self.config.local_path,
+            old_head,
+            new_head
+        );
+        Ok(RefreshSummary {
+            old_head: old_head.to_string(),
+            new_head: new_head.to_string(),
+            changed_count: changed.len(),
+            changed_paths: changed.iter().map(|path| format!("/{}", path)).collect(),
+        })
     }
 
-    fn get_file_thumbnail(
+    /// Lists `dir` in the clone, unsorted.
+    fn fetch_directory(
         &self,
-        path: &str,
-        max_size: u32,
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        let content = self.read_file(path)?;
-
-        let format = image::guess_format(&content)?;
-        let img = image::load_from_memory(&content)?;
-
-        let thumbnail = img.thumbnail(max_size, max_size);
+        dir: &RemotePath,
+        filter: MediaFilter,
+        show_hidden: bool,
+    ) -> Result<Vec<FileInfo>, StorageError> {
+        let output = self.execute_remote_command_bytes(&self.listing_command(dir))?;
+        Ok(parse_listing(
+            &output,
+            dir,
+            filter,
+            show_hidden,
+            &mut HashMap::new(),
+        ))
+    }
 
-        let mut buffer = Cursor::new(Vec::new());
+    fn listing_command(&self, dir: &RemotePath) -> String {
+        // A missing directory prints nothing, like an empty one, and is
+        // not an error.
+        let dir = shell_quote(&self.path_translator().to_absolute(dir)).into_owned();
+        format!(
+            "{{ find {dir} -mindepth 1 -maxdepth 1 -type f -size -{max}c -exec awk {script} {{}} +; \
+             find {dir} -mindepth 1 -maxdepth 1 -printf {format}; }} 2>/dev/null || true",
+            dir = dir,
+            max = MAX_LFS_POINTER_SIZE + 1,
+            script = POINTER_SIZE_SCRIPT,
+            format = LISTING_FORMAT
+        )
+    }
 
-        let mime_type = match format {
-            ImageFormat::Png => {
-                thumbnail.write_to(&mut buffer, ImageFormat::Png)?;
-                "image/png"
-            }
-            ImageFormat::Gif => {
-                thumbnail.write_to(&mut buffer, ImageFormat::Gif)?;
-                "image/gif"
-            }
-            ImageFormat::WebP => {
-                thumbnail.write_to(&mut buffer, ImageFormat::WebP)?;
-                "image/webp"
-            }
-            ImageFormat::Jpeg | ImageFormat::Bmp | ImageFormat::Tiff => {
-                thumbnail.write_to(&mut buffer, ImageFormat::Jpeg)?;
-                "image/jpeg"
-            }
-            _ => {
-                thumbnail.write_to(&mut buffer, ImageFormat::Jpeg)?;
-                "image/jpeg"
+    fn write_to_clone(&self, path: &str, data: &[u8]) -> Result<(), StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let sftp = session.sftp()?;
+        let full_path = self.repo_file_path(path);
+        if let Some(parent) = utils::parent_path(&full_path) {
+            if sftp.stat(Path::new(parent)).is_err() {
+                return Err(StorageError::NotFound(parent.to_string()));
             }
+        }
+        let mut file = sftp.create(Path::new(&full_path))?;
+        file.write_all(data)?;
+        Ok(())
+    }
+
+    /// Lists deletions under `path_prefix`, newest first, paging over commits.
+    pub fn find_deleted_files(
+        &self,
+        path_prefix: &str,
+        since: Option<u64>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<DeletedFilesPage, StorageError> {
+        self.ensure_full_history()?;
+        let prefix_path = self.repo_path(path_prefix);
+        let prefix = match prefix_path.relative() {
+            "" => ".",
+            p => p,
         };
+        let since_arg = since
+            .map(|s| format!(" --since=@{}", s))
+            .unwrap_or_default();
+        let log_cmd = format!(
+            "cd {} && export GIT_LITERAL_PATHSPECS=1 && git log --diff-filter=D --name-only -z --format=%x1e%H%x1f%an%x1f%at --skip={} --max-count={}{} -- {}",
+            shell_quote(&self.config.local_path),
+            offset,
+            limit,
+            since_arg,
+            shell_quote(prefix)
+        );
+        let log_output = self.execute_remote_command_bytes_checked(&log_cmd)?;
 
-        let base64_content = utils::base64_encode(&buffer.into_inner());
-        Ok(format!("data:{};base64,{}", mime_type, base64_content))
+        let tree_cmd = format!(
+            "cd {} && export GIT_LITERAL_PATHSPECS=1 && git ls-tree -r -z --name-only HEAD -- {}",
+            shell_quote(&self.config.local_path),
+            shell_quote(prefix)
+        );
+        let tree_output = self.execute_remote_command_bytes_checked(&tree_cmd)?;
+        let existing: HashSet<String> = tree_output
+            .split(|b| *b == 0)
+            .filter(|p| !p.is_empty())
+            .map(|p| String::from_utf8_lossy(p).into_owned())
+            .collect();
+
+        let (files, commit_count) = parse_deleted_files_log(&log_output, &existing);
+        Ok(DeletedFilesPage {
+            files,
+            next_offset: (commit_count == limit).then_some(offset + limit),
+        })
     }
 
-    fn get_root_path(&self) -> String {
-        "/".to_string()
-    }
+    /// Restores the blob as it was just before `commit` deleted it and
+    /// commits it at `destination`.
+    pub fn recover_deleted_file(
+        &self,
+        path: &str,
+        commit: &str,
+        destination: &str,
+    ) -> Result<(), StorageError> {
+        let repo_path = shell_quote(&self.config.local_path);
+        // The file is gone at `commit` itself, so it is read from the parent.
+        let source = self.revision_path(path, &format!("{}^", commit))?;
+        let destination = self.repo_path(destination);
+        let blob_ref = format!("{}^:{}", commit, source);
+        let show_cmd = format!(
+            "cd {repo} && if git lfs version >/dev/null 2>&1; then git show {blob} | git lfs smudge -- {path}; else git show {blob}; fi",
+            repo = repo_path,
+            blob = shell_quote(&blob_ref),
+            path = shell_quote(&source)
+        );
+        let content = self.execute_remote_command_bytes_checked(&show_cmd)?;
 
-    fn storage_type(&self) -> StorageType {
-        StorageType::GitHub
+        if let Some(parent) = destination.parent() {
+            let parent = self.path_translator().to_absolute(&parent);
+            self.execute_remote_command_checked(&format!("mkdir -p {}", shell_quote(&parent)))?;
+        }
+        self.write_to_clone(destination.as_str(), &content)?;
+        self.stage_paths(&[destination.as_str()])?;
+        self.commit_and_push(
+            &[destination.as_str()],
+            &format!("Recover {} from {}", source, commit),
+        )
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Lists the commits that changed `path`, newest first, following it
+    /// across renames. A `limit` of 0 lists them all.
+    pub fn get_file_history(
+        &self,
+        path: &str,
+        limit: usize,
+    ) -> Result<Vec<FileRevision>, StorageError> {
+        self.ensure_full_history()?;
+        let path = self.repo_path(path);
+        let max_count = match limit {
+            0 => String::new(),
+            n => format!(" --max-count={}", n),
+        };
+        let log_cmd = format!(
+            "cd {} && export GIT_LITERAL_PATHSPECS=1 && git log --follow --name-only -z --format=%x1e%H%x1f%an%x1f%at%x1f%s{} -- {}",
+            shell_quote(&self.config.local_path),
+            max_count,
+            shell_quote(path.relative())
+        );
+        let output = self.execute_remote_command_bytes_checked(&log_cmd)?;
+        Ok(parse_file_history(&output))
+    }
+
+    /// Reads `path` as it was at `commit`, smudging LFS pointers.
+    pub fn read_file_at_revision(&self, path: &str, commit: &str) -> Result<Vec<u8>, StorageError> {
+        let source = self.revision_path(path, commit)?;
+        self.execute_remote_command_bytes_checked(&format!(
+            "cd {} && {}",
+            shell_quote(&self.config.local_path),
+            show_revision_command(commit, &source)
+        ))
+    }
+
+    /// Puts `path` back as it was at `commit` and commits that.
+    pub fn restore_file_version(&self, path: &str, commit: &str) -> Result<(), StorageError> {
+        let source = self.revision_path(path, commit)?;
+        let target = self.repo_path(path);
+        let restore_cmd = format!(
+            "cd {} && {{ {}; }} > {}",
+            shell_quote(&self.config.local_path),
+            show_revision_command(commit, &source),
+            shell_quote(target.relative())
+        );
+        self.execute_remote_command_checked(&restore_cmd)?;
+        self.stage_paths(&[target.as_str()])?;
+        self.commit_and_push(
+            &[target.as_str()],
+            &format!("Restore {} from {}", target.relative(), commit),
+        )
+    }
+
+    /// The repo-relative path `path` had at `commit`, looked up through its
+    /// history when it has been renamed since.
+    fn revision_path(&self, path: &str, commit: &str) -> Result<String, StorageError> {
+        if commit.is_empty() || commit.starts_with('-') || commit.contains(':') {
+            return Err(StorageError::InvalidInput(format!(
+                "Invalid commit: {:?}",
+                commit
+            )));
+        }
+        let current = self.repo_path(path);
+        let exists_cmd = format!(
+            "cd {} && git cat-file -e {} 2>/dev/null && echo 'exists' || echo 'missing'",
+            shell_quote(&self.config.local_path),
+            shell_quote(&format!("{}:{}", commit, current.relative()))
+        );
+        if self.execute_remote_command(&exists_cmd)?.trim() == "exists" {
+            return Ok(current.relative().to_string());
+        }
+        self.get_file_history(path, 0)?
+            .into_iter()
+            .find(|revision| revision.commit.starts_with(commit))
+            .map(|revision| RemotePath::new(&revision.path).relative().to_string())
+            .ok_or_else(|| StorageError::NotFound(format!("{} at {}", path, commit)))
+    }
+
+    /// Lists the branches on the remote, sorted by name.
+    pub fn list_branches(&self) -> Result<Vec<Branch>, StorageError> {
+        let ls_cmd = format!(
+            "cd {} && git ls-remote --heads origin",
+            shell_quote(&self.config.local_path)
+        );
+        let output = self.execute_remote_command_checked(&ls_cmd)?;
+        let mut branches = parse_ls_remote_heads(&output, &self.branch());
+        branches.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(branches)
+    }
+
+    /// Checks out `name` in the existing clone, tracking the remote branch,
+    /// and makes it the branch later commits are pushed to.
+    pub fn switch_branch(&self, name: &str) -> Result<BranchSwitch, StorageError> {
+        if name.is_empty() || name.starts_with('-') {
+            return Err(StorageError::InvalidInput(format!(
+                "Invalid branch name: {:?}",
+                name
+            )));
+        }
+        let mut branch = self.branch.lock()?;
+        let repo_path = shell_quote(&self.config.local_path);
+
+        let status_cmd = format!(
+            "cd {} && git status --porcelain --untracked-files=no",
+            repo_path
+        );
+        let dirty = dirty_paths(&self.execute_remote_command_checked(&status_cmd)?);
+        if !dirty.is_empty() {
+            return Err(StorageError::InvalidInput(format!(
+                "Cannot switch to {}: the clone has uncommitted changes to {}",
+                name,
+                dirty.join(", ")
+            )));
+        }
+
+        let exists_cmd = format!(
+            "cd {} && git ls-remote --exit-code --heads origin {} >/dev/null && echo 'exists' || echo 'missing'",
+            repo_path,
+            shell_quote(&format!("refs/heads/{}", name))
+        );
+        if self.execute_remote_command(&exists_cmd)?.trim() != "exists" {
+            return Err(StorageError::NotFound(format!(
+                "Branch {} in {}",
+                name, self.config.repo_url
+            )));
+        }
+        self.execute_remote_command_checked(&format!(
+            "cd {} && git fetch{} origin {}",
+            repo_path,
+            self.depth_arg(),
+            shell_quote(&fetch_refspec(name))
+        ))?;
+
+        let checkout_cmd = format!(
+            "cd {repo} && export GIT_LFS_SKIP_SMUDGE=1 && \
+             if git rev-parse --verify -q {local} >/dev/null; then git checkout -q {name}; \
+             else git checkout -q -b {name} --track {remote}; fi && \
+             git pull -q{depth} origin {name}",
+            repo = repo_path,
+            depth = self.depth_arg(),
+            local = shell_quote(&format!("refs/heads/{}", name)),
+            name = shell_quote(name),
+            remote = shell_quote(&format!("origin/{}", name))
+        );
+        self.invalidate_listing_cache();
+        self.forget_materialized();
+        self.execute_remote_command_checked(&checkout_cmd)?;
+        *branch = name.to_string();
+        self.lfs_tracking_ready.store(false, Ordering::Relaxed);
+
+        let head = self
+            .execute_remote_command_checked(&format!("cd {} && git rev-parse HEAD", repo_path))?;
+        log::info!("Switched {} to {}", self.config.local_path, name);
+        Ok(BranchSwitch {
+            branch: name.to_string(),
+            head: head.trim().to_string(),
+        })
+    }
+
+    /// Tracks [`GitHubConfig::lfs_patterns`] ahead of the first write when
+    /// [`GitHubConfig::manage_lfs_tracking`] is set. The `.gitattributes`
+    /// change is committed on its own, or left staged for manual commits.
+    fn ensure_lfs_tracking(&self) -> Result<(), StorageError> {
+        if !self.config.manage_lfs_tracking || self.lfs_tracking_ready.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let patterns: Vec<Cow<'_, str>> = if self.config.lfs_patterns.is_empty() {
+            DEFAULT_LFS_PATTERNS
+                .iter()
+                .map(|p| shell_quote(p))
+                .collect()
+        } else {
+            self.config
+                .lfs_patterns
+                .iter()
+                .map(|p| shell_quote(p))
+                .collect()
+        };
+        let commit = if self.config.manual_commits {
+            String::new()
+        } else {
+            format!(
+                " && {{ git diff --cached --quiet -- .gitattributes || {} commit -q -m {} -- .gitattributes; }}",
+                self.git_with_author(),
+                shell_quote("Track media files with Git LFS")
+            )
+        };
+        let track_cmd = format!(
+            "cd {} && git lfs track {} >/dev/null && git add .gitattributes{}",
+            shell_quote(&self.config.local_path),
+            patterns.join(" "),
+            commit
+        );
+        self.execute_remote_command_checked(&track_cmd)
+            .inspect_err(|e| log::warn!("Git LFS setup failed: {}", e))?;
+        self.lfs_tracking_ready.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Patterns the repository's `.gitattributes` stores with Git LFS.
+    pub fn get_lfs_tracking(&self) -> Result<Vec<String>, StorageError> {
+        let attributes = self.execute_remote_command(&format!(
+            "cd {} && cat .gitattributes 2>/dev/null",
+            shell_quote(&self.config.local_path)
+        ))?;
+        Ok(parse_lfs_patterns(&attributes))
+    }
+}
+
+/// Exit status of the refresh's merge when the branch cannot be
+/// fast-forwarded.
+const MERGE_DIVERGED: i32 = 3;
+
+/// Exit status of `git ls-remote --exit-code` when no ref matched.
+const LS_REMOTE_NO_MATCH: i32 = 2;
+
+/// Lists the `branch` head of `repo_url`, or its `HEAD` for an empty
+/// `branch`, failing with [`LS_REMOTE_NO_MATCH`] if there is none.
+fn ls_remote_command(repo_url: &str, branch: &str) -> String {
+    if branch.is_empty() {
+        return format!("git ls-remote --exit-code {} HEAD", shell_quote(repo_url));
+    }
+    format!(
+        "git ls-remote --exit-code --heads {} {}",
+        shell_quote(repo_url),
+        shell_quote(&format!("refs/heads/{}", branch))
+    )
+}
+
+/// Whether `git push` stderr says the remote has commits the push lacks.
+fn is_push_rejected(stderr: &str) -> bool {
+    stderr.contains("[rejected]")
+        && (stderr.contains("non-fast-forward") || stderr.contains("fetch first"))
+}
+
+/// Parses `git status --porcelain -z --branch` output.
+fn parse_git_status(output: &[u8]) -> GitStatus {
+    let mut status = GitStatus::default();
+    let mut records = output.split(|b| *b == 0).filter(|r| !r.is_empty());
+
+    while let Some(record) = records.next() {
+        if let Some(header) = record.strip_prefix(b"## ") {
+            parse_status_header(&String::from_utf8_lossy(header), &mut status);
+            continue;
+        }
+        let [x, y, b' ', path @ ..] = record else {
+            continue;
+        };
+        // Renames and copies are followed by the path they came from.
+        let original_path = if matches!(x, b'R' | b'C') {
+            records
+                .next()
+                .map(|p| format!("/{}", String::from_utf8_lossy(p)))
+        } else {
+            None
+        };
+        let unmerged = *x == b'U' || *y == b'U' || (x == y && matches!(x, b'A' | b'D'));
+        let (staged, unstaged) = if unmerged {
+            (Some(GitChange::Unmerged), Some(GitChange::Unmerged))
+        } else if *x == b'?' {
+            (None, Some(GitChange::Untracked))
+        } else {
+            (GitChange::from_code(*x), GitChange::from_code(*y))
+        };
+        status.entries.push(GitStatusEntry {
+            path: format!("/{}", String::from_utf8_lossy(path)),
+            original_path,
+            staged,
+            unstaged,
+        });
+    }
+
+    status
+}
+
+/// Parses the branch line, such as `main...origin/main [ahead 1, behind 2]`.
+fn parse_status_header(header: &str, status: &mut GitStatus) {
+    let (refs, tracking) = match header.split_once(" [") {
+        Some((refs, tracking)) => (refs, tracking.trim_end_matches(']')),
+        None => (header, ""),
+    };
+    let refs = refs.strip_prefix("No commits yet on ").unwrap_or(refs);
+    if refs.starts_with("HEAD (no branch)") {
+        return;
+    }
+    let (branch, upstream) = match refs.split_once("...") {
+        Some((branch, upstream)) => (branch, Some(upstream.to_string())),
+        None => (refs, None),
+    };
+    status.branch = Some(branch.to_string());
+    status.upstream = upstream;
+    for part in tracking.split(", ") {
+        if let Some(n) = part.strip_prefix("ahead ") {
+            status.ahead = n.parse().unwrap_or(0);
+        } else if let Some(n) = part.strip_prefix("behind ") {
+            status.behind = n.parse().unwrap_or(0);
+        }
+    }
+}
+
+/// Builds the command printing `path` at `commit`, run from the clone.
+fn show_revision_command(commit: &str, path: &str) -> String {
+    let blob = shell_quote(&format!("{}:{}", commit, path)).into_owned();
+    format!(
+        "if git lfs version >/dev/null 2>&1; then git show {blob} | git lfs smudge -- {path}; else git show {blob}; fi",
+        blob = blob,
+        path = shell_quote(path)
+    )
+}
+
+/// Parses `git log --follow --name-only -z` output printed with the format
+/// `%x1e%H%x1f%an%x1f%at%x1f%s`.
+fn parse_file_history(output: &[u8]) -> Vec<FileRevision> {
+    output
+        .split(|b| *b == 0x1e)
+        .filter(|r| !r.is_empty())
+        .filter_map(|record| {
+            let mut fields = record.split(|b| *b == 0);
+            let header = String::from_utf8_lossy(fields.next().unwrap_or_default());
+            let [commit, author, date, message] = header.splitn(4, '\x1f').collect::<Vec<_>>()[..]
+            else {
+                return None;
+            };
+            let path = fields
+                .map(|name| name.strip_prefix(b"\n").unwrap_or(name))
+                .find(|name| !name.is_empty())?;
+            Some(FileRevision {
+                commit: commit.to_string(),
+                author: author.to_string(),
+                date: date.trim().parse().unwrap_or(0),
+                message: message.trim_end().to_string(),
+                path: format!("/{}", String::from_utf8_lossy(path)),
+            })
+        })
+        .collect()
+}
+
+/// Normalizes sparse checkout paths, empty if any of them is the root.
+fn sparse_cone(paths: &[String]) -> Vec<RemotePath> {
+    let mut cone: Vec<RemotePath> = Vec::new();
+    for path in paths.iter().map(|p| RemotePath::new(p)) {
+        if path.is_root() {
+            return Vec::new();
+        }
+        if !cone.contains(&path) {
+            cone.push(path);
+        }
+    }
+    cone
+}
+
+/// Picks the branch to check out from `git ls-remote --symref` output: the
+/// `requested` one if the remote has it, or the default for an empty one.
+fn pick_branch(ls_remote: &str, requested: &str) -> Result<String, StorageError> {
+    if requested.is_empty() {
+        return ls_remote
+            .lines()
+            .filter_map(|line| line.strip_prefix("ref: ")?.strip_suffix("\tHEAD"))
+            .find_map(|target| target.strip_prefix("refs/heads/"))
+            .map(str::to_string)
+            .ok_or_else(|| {
+                StorageError::NotFound("default branch; the repository may be empty".to_string())
+            });
+    }
+    let branches = parse_ls_remote_heads(ls_remote, requested);
+    if branches.iter().any(|b| b.current) {
+        return Ok(requested.to_string());
+    }
+    let names: Vec<&str> = branches.iter().map(|b| b.name.as_str()).collect();
+    Err(StorageError::NotFound(format!(
+        "branch {}; available: [{}]",
+        requested,
+        names.join(", ")
+    )))
+}
+
+/// Whether git's stderr says the remote refused access to the repository,
+/// rather than failing to reach it.
+fn is_access_denied(stderr: &str) -> bool {
+    [
+        "Permission denied",
+        "Repository not found",
+        "Could not read from remote repository",
+        "Authentication failed",
+    ]
+    .iter()
+    .any(|message| stderr.contains(message))
+}
+
+/// Classifies a failed `stat` of `path` by its stderr. Only a missing path
+/// is [`StorageError::NotFound`]; anything else keeps its message.
+fn stat_error(path: &RemotePath, error: ssh_util::ExecError) -> StorageError {
+    if error.stderr.contains("No such file or directory") {
+        StorageError::NotFound(path.to_string())
+    } else if error.stderr.contains("Permission denied") {
+        StorageError::PermissionDenied(path.to_string())
+    } else {
+        error.into()
+    }
+}
+
+/// Refspec fetching `branch` into its remote-tracking ref.
+fn fetch_refspec(branch: &str) -> String {
+    format!("+refs/heads/{0}:refs/remotes/origin/{0}", branch)
+}
+
+/// Parses `git ls-remote --heads` output into branches, marking `current`.
+fn parse_ls_remote_heads(output: &str, current: &str) -> Vec<Branch> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (commit, reference) = line.split_once('\t')?;
+            let name = reference.trim().strip_prefix("refs/heads/")?;
+            Some(Branch {
+                name: name.to_string(),
+                commit: commit.trim().to_string(),
+                current: name == current,
+            })
+        })
+        .collect()
+}
+
+/// Paths `git status --porcelain` reports as changed.
+fn dirty_paths(status: &str) -> Vec<String> {
+    status
+        .lines()
+        .filter_map(|line| line.get(3..))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Patterns with `filter=lfs` in a `.gitattributes` file, in file order.
+fn parse_lfs_patterns(attributes: &str) -> Vec<String> {
+    attributes
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (pattern, attributes) = match line.strip_prefix('"') {
+                Some(quoted) => quoted.split_once('"')?,
+                None => line.split_once(char::is_whitespace)?,
+            };
+            attributes
+                .split_whitespace()
+                .any(|attribute| attribute == "filter=lfs")
+                .then(|| pattern.to_string())
+        })
+        .collect()
+}
+
+fn quote_repo_paths(paths: &[&str]) -> String {
+    paths
+        .iter()
+        .map(|p| shell_quote(RemotePath::new(p).relative()).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn git_rm_command(repo_path: &str, path: &str) -> String {
+    format!(
+        "cd {} && export GIT_LITERAL_PATHSPECS=1 && git rm -q -- {}",
+        shell_quote(repo_path),
+        quote_repo_paths(&[path])
+    )
+}
+
+/// Counts the files and directories that go away with `dir`, given the
+/// repo-relative paths of the files tracked below it.
+fn count_removed(tracked: &[String], dir: &RemotePath) -> DeleteDirectoryResult {
+    let mut dirs = HashSet::from([dir.clone()]);
+    for file in tracked {
+        let mut parent = RemotePath::new(file).parent();
+        while let Some(p) = parent {
+            if p == *dir || !dirs.insert(p.clone()) {
+                break;
+            }
+            parent = p.parent();
+        }
+    }
+    DeleteDirectoryResult {
+        files_removed: tracked.len() as u64,
+        dirs_removed: dirs.len() as u64,
+    }
+}
+
+fn git_mv_command(repo_path: &str, from: &str, to: &str) -> String {
+    format!(
+        "cd {} && export GIT_LITERAL_PATHSPECS=1 && git mv -f -- {}",
+        shell_quote(repo_path),
+        quote_repo_paths(&[from, to])
+    )
+}
+
+/// Paths materialized per command, keeping command lines short.
+const LFS_MATERIALIZE_BATCH: usize = 200;
+
+/// Builds a command replacing each LFS pointer among `rel_paths` with its
+/// content. Other files are left alone; failures are reported on stderr
+/// after trying every path. A progress line counting from `done` towards
+/// `total` is printed after each path.
+fn materialize_command(repo_path: &str, rel_paths: &[&str], done: usize, total: usize) -> String {
+    let quoted: Vec<Cow<'_, str>> = rel_paths.iter().map(|p| shell_quote(p)).collect();
+    format!(
+        "cd {} && failed=0; n={}; for f in {}; do \
+         if [ -f \"$f\" ] && head -c 64 \"$f\" | grep -q '^version https://git-lfs'; then \
+         if git lfs smudge -- \"$f\" < \"$f\" > \"$f.lfs-tmp\" && chmod --reference=\"$f\" \"$f.lfs-tmp\" \
+         && mv -f \"$f.lfs-tmp\" \"$f\"; then :; \
+         else rm -f \"$f.lfs-tmp\"; echo \"Could not fetch $f from LFS\" >&2; failed=1; fi; \
+         fi; n=$((n+1)); echo \"Fetching LFS objects: $((n*100/{total}))% ($n/{total})\"; \
+         done; exit $failed",
+        shell_quote(repo_path),
+        done,
+        quoted.join(" "),
+        total = total.max(1)
+    )
+}
+
+/// Parses a git `--progress` line such as
+/// `remote: Counting objects:  42% (420/1000)`. Lines without counts,
+/// like `Resolving deltas: done.`, are skipped.
+fn parse_progress_line(line: &str) -> Option<GitProgress> {
+    let line = line.trim();
+    let line = line.strip_prefix("remote:").map_or(line, str::trim_start);
+    let (phase, rest) = line.split_once(':')?;
+    let (percent, rest) = rest.split_once('%')?;
+    let (counts, _) = rest.trim_start().strip_prefix('(')?.split_once(')')?;
+    let (received, total) = counts.split_once('/')?;
+    Some(GitProgress {
+        phase: phase.trim().to_string(),
+        percent: percent.trim().parse::<u8>().ok()?.min(100),
+        received: received.trim().parse().ok()?,
+        total: total.trim().parse().ok()?,
+    })
+}
+
+/// Builds one command printing every file base64-encoded on its own line,
+/// or `!` for paths that are not regular files. LFS pointers are smudged.
+fn batch_read_command(repo_path: &str, rel_paths: &[&str]) -> String {
+    let quoted: Vec<Cow<'_, str>> = rel_paths.iter().map(|p| shell_quote(p)).collect();
+    format!(
+        "cd {} && for f in {}; do \
+         if [ ! -f \"$f\" ]; then echo '!'; \
+         elif head -c 64 \"$f\" | grep -q '^version https://git-lfs'; then \
+         git lfs smudge < \"$f\" | base64 -w0; echo; \
+         else base64 -w0 < \"$f\"; echo; fi; done",
+        shell_quote(repo_path),
+        quoted.join(" ")
+    )
+}
+
+fn parse_batch_read(output: &str, count: usize) -> Vec<FileReadOutcome> {
+    let mut lines = output.lines();
+    (0..count)
+        .map(|_| match lines.next() {
+            Some("!") => Err("No such file".to_string()),
+            Some(line) => utils::base64_decode(line).map_err(|e| e.to_string()),
+            None => Err("No output for file".to_string()),
+        })
+        .collect()
+}
+
+/// Entries of the clone hidden even from listings that show dotfiles.
+const REPO_METADATA: &[&str] = &[".git", ".gitattributes"];
+
+/// `find -printf` format of directory listings: the entry's type, its
+/// target's type, size, mtime, octal mode, owner and name, then the symlink
+/// target (empty for other entries) as a record of its own. Records are
+/// NUL-terminated since names may hold tabs and newlines.
+const LISTING_FORMAT: &str = "'%y\\t%Y\\t%s\\t%T@\\t%m\\t%u\\t%f\\0%l\\0'";
+
+/// Largest file checked for being an LFS pointer, which is about 130 bytes.
+const MAX_LFS_POINTER_SIZE: u64 = 1024;
+
+/// awk program printing a listing record of kind `P` with the real size of
+/// each LFS pointer file it is given, so listings show that size instead of
+/// the pointer's.
+const POINTER_SIZE_SCRIPT: &str = r#"'FNR == 1 && !/^version https:\/\/git-lfs/ { nextfile } /^size [0-9]+$/ { n = split(FILENAME, p, "/"); printf "P\tf\t%s\t\t\t\t%s%c%c", $2, p[n], 0, 0; nextfile }'"#;
+
+/// Length of the leading part of streamed `output` holding only whole
+/// entries, which take two records each.
+fn complete_listing_len(output: &[u8]) -> usize {
+    output
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| **b == 0)
+        .skip(1)
+        .step_by(2)
+        .last()
+        .map_or(0, |(i, _)| i + 1)
+}
+
+/// Parses directory listing records printed with [`LISTING_FORMAT`],
+/// preceded by those of [`POINTER_SIZE_SCRIPT`]. Pointer sizes are kept in
+/// `lfs_sizes` as the two may arrive in different chunks.
+fn parse_listing(
+    output: &[u8],
+    dir: &RemotePath,
+    filter: MediaFilter,
+    show_hidden: bool,
+    lfs_sizes: &mut HashMap<String, u64>,
+) -> Vec<FileInfo> {
+    let mut files = Vec::new();
+
+    let mut records = output.split(|b| *b == 0);
+    while let Some(record) = records.next() {
+        let target = records.next().unwrap_or_default();
+        let record = String::from_utf8_lossy(record);
+        let fields: Vec<&str> = record.splitn(7, '\t').collect();
+        let [kind, target_kind, size, mtime, mode, owner, name] = fields[..] else {
+            continue;
+        };
+        if kind == "P" {
+            if let Ok(size) = size.parse() {
+                lfs_sizes.insert(name.to_string(), size);
+            }
+            continue;
+        }
+        if name.is_empty() || !storage::is_listed(name, show_hidden, REPO_METADATA) {
+            continue;
+        }
+
+        let is_dir = target_kind == "d";
+        if !filter.matches_entry(name, is_dir) {
+            continue;
+        }
+
+        files.push(FileInfo {
+            name: name.to_string(),
+            path: dir.join(name).to_string(),
+            size: match lfs_sizes.get(name) {
+                Some(size) if kind == "f" => *size,
+                _ => size.parse().unwrap_or(0),
+            },
+            is_dir,
+            is_symlink: kind == "l",
+            link_target: (kind == "l").then(|| String::from_utf8_lossy(target).into_owned()),
+            modified: mtime.parse::<f64>().ok().map(|t| t as u64),
+            mime_type: if is_dir { None } else { detect_mime_type(name) },
+            thumbnail: None,
+            permissions: u32::from_str_radix(mode, 8).ok(),
+            owner: Some(owner.to_string()),
+            extra: BTreeMap::new(),
+        });
+    }
+
+    files
+}
+
+/// Parses `git ls-tree -r -t -l -z` output for the subtree at `dir`.
+fn parse_ls_tree(output: &[u8], dir: &RemotePath, max_depth: usize) -> Vec<FileInfo> {
+    let base_depth = dir.relative().split('/').filter(|s| !s.is_empty()).count();
+    let mut files = Vec::new();
+
+    for entry in output.split(|b| *b == 0).filter(|e| !e.is_empty()) {
+        let entry = String::from_utf8_lossy(entry);
+        let Some((meta, rel_path)) = entry.split_once('\t') else {
+            continue;
+        };
+        let meta: Vec<&str> = meta.split_whitespace().collect();
+        if meta.len() < 4 || rel_path == dir.relative() {
+            continue;
+        }
+        let is_dir = match meta[1] {
+            "tree" => true,
+            "blob" => false,
+            _ => continue,
+        };
+
+        let path = RemotePath::new(rel_path);
+        let depth = rel_path.split('/').count() - base_depth;
+        if max_depth > 0 && depth > max_depth {
+            continue;
+        }
+        // One over the cap, so the caller can tell the listing is truncated.
+        if files.len() > MAX_RECURSIVE_ENTRIES {
+            break;
+        }
+
+        let name = path.file_name().unwrap_or_default().to_string();
+        let mime_type = if is_dir {
+            None
+        } else {
+            detect_mime_type(&name)
+        };
+        files.push(FileInfo {
+            name,
+            path: path.to_string(),
+            size: meta[3].parse().unwrap_or(0),
+            is_dir,
+            is_symlink: false,
+            link_target: None,
+            modified: None,
+            mime_type,
+            thumbnail: None,
+            permissions: None,
+            owner: None,
+            extra: BTreeMap::new(),
+        });
+    }
+
+    files
+}
+
+/// Picks the tracked paths from `git ls-files -z` output whose file name
+/// contains `pattern`, keeping at most `max_results`.
+fn filter_ls_files(
+    output: &[u8],
+    pattern: &str,
+    case_sensitive: bool,
+    max_results: usize,
+) -> Vec<String> {
+    output
+        .split(|b| *b == 0)
+        .map(String::from_utf8_lossy)
+        .filter(|path| {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            !name.is_empty() && name_matches(name, pattern, case_sensitive)
+        })
+        .take(max_results)
+        .map(|path| path.into_owned())
+        .collect()
+}
+
+/// Parses `git log -z --name-only --format=%x1e%H%x1f%an%x1f%at` output,
+/// returning the deleted entries and the number of commits seen.
+fn parse_deleted_files_log(output: &[u8], existing: &HashSet<String>) -> (Vec<DeletedFile>, usize) {
+    let mut files = Vec::new();
+    let mut commits = 0;
+
+    for record in output.split(|b| *b == 0x1e).filter(|r| !r.is_empty()) {
+        let mut fields = record.split(|b| *b == 0);
+        let header = String::from_utf8_lossy(fields.next().unwrap_or_default());
+        let mut header_parts = header.split('\x1f');
+        let (Some(commit), Some(author), Some(timestamp)) = (
+            header_parts.next(),
+            header_parts.next(),
+            header_parts.next(),
+        ) else {
+            continue;
+        };
+        commits += 1;
+
+        for name in fields {
+            let name = name.strip_prefix(b"\n").unwrap_or(name);
+            if name.is_empty() {
+                continue;
+            }
+            let name = String::from_utf8_lossy(name).into_owned();
+            files.push(DeletedFile {
+                readded: existing.contains(&name),
+                path: format!("/{}", name),
+                commit: commit.to_string(),
+                author: author.to_string(),
+                deleted_at: timestamp.trim().parse().unwrap_or(0),
+            });
+        }
+    }
+
+    (files, commits)
+}
+
+impl Storage for GitHubStorage {
+    fn connect(&mut self) -> Result<(), StorageError> {
+        let session = self
+            .open_session(Duration::from_secs(CONNECTION_TIMEOUT_SECS))
+            .map_err(|e| e.error)?;
+
+        // A fresh flag, so a late report about the old session is ignored.
+        self.lost = Arc::new(AtomicBool::new(false));
+        self.keepalive = Keepalive::start(
+            &session,
+            self.config
+                .keepalive_secs
+                .unwrap_or(utils::DEFAULT_KEEPALIVE_SECS),
+            Some(utils::flag_lost(
+                &self.lost,
+                self.on_connection_lost.clone(),
+            )),
+        );
+        self.remote_thumbnailer = if self.config.remote_thumbnails {
+            thumbnails::probe_remote_thumbnailer(&session)
+        } else {
+            None
+        };
+        self.session = Some(session);
+        self.resolve_branch()?;
+        log::info!(
+            "Preparing {} in {}",
+            self.config.repo_url,
+            self.config.local_path
+        );
+        self.ensure_repo_exists()
+            .inspect_err(|e| log::warn!("Cloning {} failed: {}", self.config.repo_url, e))?;
+
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.keepalive = None;
+        if self.config.remove_clone_on_disconnect && self.is_connected() {
+            if let Err(e) = self.cleanup_remote_clone() {
+                log::warn!("Removing clone {} failed: {}", self.config.local_path, e);
+            }
+        }
+        if let Some(session) = self.session.take() {
+            let _ = session.disconnect(None, "Closing connection", None);
+        }
+        self.repo_cloned = false;
+        self.remote_thumbnailer = None;
+        self.invalidate_listing_cache();
+        self.forget_materialized();
+    }
+
+    // `session` is only stored once authenticated. Asking the session itself
+    // would wait behind any transfer holding its lock.
+    fn is_connected(&self) -> bool {
+        self.session.is_some() && !self.lost.load(Ordering::Relaxed)
+    }
+
+    fn set_connection_lost_handler(&mut self, handler: ConnectionLostHandler) {
+        self.on_connection_lost = Some(handler);
+    }
+
+    fn connection_lost(&self, error: &StorageError) {
+        if !self.lost.swap(true, Ordering::Relaxed) {
+            log::warn!("Connection to {} lost: {}", self.config.repo_url, error);
+        }
+        if let Some(on_lost) = &self.on_connection_lost {
+            on_lost(error);
+        }
+    }
+
+    fn auto_reconnect(&self) -> bool {
+        self.config.auto_reconnect
+    }
+
+    fn ping(&self) -> Result<(), StorageError> {
+        self.execute_remote_command_bytes("true").map(|_| ())
+    }
+
+    fn metrics(&self) -> &StorageMetrics {
+        &self.metrics
+    }
+
+    fn list_directory_page(
+        &self,
+        path: &str,
+        options: &ListOptions,
+    ) -> Result<(Vec<FileInfo>, usize), StorageError> {
+        let _ = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let dir = self.repo_path(path);
+        self.ensure_checked_out(&dir)?;
+
+        let mut cache = self.listing_cache.lock().map_err(|e| e.to_string())?;
+        let mut listing = match cache.take() {
+            Some(cached) if cached.is_fresh(&dir, options) => cached,
+            _ => CachedListing {
+                files: self.fetch_directory(&dir, options.filter, options.show_hidden)?,
+                dir,
+                filter: options.filter,
+                show_hidden: options.show_hidden,
+                fetched_at: Instant::now(),
+            },
+        };
+        storage::sort_files(&mut listing.files, options.sort_by, options.sort_order);
+        let page = storage::paginate(&listing.files, options.offset, options.limit);
+        let total = listing.files.len();
+        *cache = Some(listing);
+        Ok((page, total))
+    }
+
+    fn list_directory_streamed(
+        &self,
+        path: &str,
+        options: &ListOptions,
+        batch_size: usize,
+        on_batch: &mut BatchCallback<'_>,
+    ) -> Result<usize, StorageError> {
+        let dir = self.repo_path(path);
+        self.ensure_checked_out(&dir)?;
+        let mut batcher = ListingBatcher::new(batch_size, on_batch);
+        let mut pending = Vec::new();
+        let mut lfs_sizes = HashMap::new();
+        self.stream_remote_command(&self.listing_command(&dir), None, &mut |chunk, _| {
+            pending.extend_from_slice(chunk);
+            let complete = complete_listing_len(&pending);
+            let files = parse_listing(
+                &pending[..complete],
+                &dir,
+                options.filter,
+                options.show_hidden,
+                &mut lfs_sizes,
+            );
+            pending.drain(..complete);
+            files.into_iter().all(|file| batcher.push(file))
+        })?;
+        Ok(batcher.finish())
+    }
+
+    fn list_directory_recursive(
+        &self,
+        path: &str,
+        max_depth: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<RecursiveListing, StorageError> {
+        let _ = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let dir = self.repo_path(path);
+        let pathspec = if dir.is_root() {
+            ".".to_string()
+        } else {
+            format!("{}/", dir.relative())
+        };
+        let ls_cmd = format!(
+            "cd {} && export GIT_LITERAL_PATHSPECS=1 && git ls-tree -r -t -l -z HEAD -- {}",
+            shell_quote(&self.config.local_path),
+            shell_quote(&pathspec)
+        );
+        let output = self.execute_remote_command_cancellable(&ls_cmd, cancelled)?;
+        Ok(RecursiveListing::capped(parse_ls_tree(
+            &output, &dir, max_depth,
+        )))
+    }
+
+    fn search(
+        &self,
+        root: &str,
+        pattern: &str,
+        case_sensitive: bool,
+        limit: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<SearchResult, StorageError> {
+        let dir = self.repo_path(root);
+        let pathspec = if dir.is_root() { "." } else { dir.relative() };
+        let ls_cmd = format!(
+            "cd {} && export GIT_LITERAL_PATHSPECS=1 && git ls-files -z -- {}",
+            shell_quote(&self.config.local_path),
+            shell_quote(pathspec)
+        );
+        let output = self.execute_remote_command_cancellable(&ls_cmd, cancelled)?;
+        let matches = filter_ls_files(&output, pattern, case_sensitive, limit.saturating_add(1));
+        if matches.is_empty() {
+            return Ok(SearchResult::capped(Vec::new(), limit));
+        }
+
+        let paths: Vec<&str> = matches.iter().map(String::as_str).collect();
+        let stat_cmd = format!(
+            "cd {} && stat --printf 'f\\t%s\\t%Y\\t%n\\0' -- {}",
+            shell_quote(&self.config.local_path),
+            paths
+                .iter()
+                .map(|p| shell_quote(p))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        let output = self.execute_remote_command_cancellable(&stat_cmd, cancelled)?;
+        let files = utils::parse_stat_records(&output)
+            .iter()
+            .map(|(rel_path, stat)| utils::sftp_file_info(RemotePath::new(rel_path).as_str(), stat))
+            .collect();
+        Ok(SearchResult::capped(files, limit))
+    }
+
+    fn search_contents(
+        &self,
+        root: &str,
+        query: &str,
+        regex: bool,
+        include: Option<&str>,
+        limit: usize,
+    ) -> Result<ContentSearchResult, StorageError> {
+        let translator = self.path_translator();
+        let grep_cmd = utils::grep_command(
+            &translator.resolve(root),
+            query,
+            regex,
+            include,
+            Some(".git"),
+            limit.saturating_add(1),
+        );
+        let mut matches = utils::parse_grep_output(&self.execute_remote_command_bytes(&grep_cmd)?);
+        matches.retain_mut(|m| match translator.from_absolute(&m.path) {
+            Some(path) => {
+                m.path = path.to_string();
+                true
+            }
+            None => false,
+        });
+        let truncated = matches.len() > limit;
+        matches.truncate(limit);
+        Ok(ContentSearchResult { matches, truncated })
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, StorageError> {
+        metrics::metered_read(&self.metrics, || {
+            self.get_lfs_file_content(self.repo_path(path).relative())
+        })
+    }
+
+    fn read_file_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, bool), StorageError> {
+        let content_cmd = self.file_content_command(self.repo_path(path).relative())?;
+        // One extra byte tells whether anything follows the range.
+        let range_cmd = format!(
+            "{} | tail -c +{} | head -c {}",
+            content_cmd,
+            offset.saturating_add(1),
+            length.saturating_add(1)
+        );
+        let data = metrics::metered_read(&self.metrics, || {
+            self.execute_remote_command_bytes(&range_cmd)
+        })?;
+        Ok(storage::finish_range_read(data, length))
+    }
+
+    fn read_files(&self, paths: &[String]) -> Result<Vec<FileReadOutcome>, StorageError> {
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        let rel_paths: Vec<RemotePath> = paths.iter().map(|p| self.repo_path(p)).collect();
+        let rel_paths: Vec<&str> = rel_paths.iter().map(|p| p.relative()).collect();
+        let start = Instant::now();
+        let output =
+            self.execute_remote_command(&batch_read_command(&self.config.local_path, &rel_paths))?;
+        let outcomes = parse_batch_read(&output, paths.len());
+        // One command read them all, so its time is split between them.
+        let each = start.elapsed() / outcomes.len().max(1) as u32;
+        for content in outcomes.iter().flatten() {
+            self.metrics.record_read(content.len() as u64, each);
+        }
+        Ok(outcomes)
+    }
+
+    fn read_file_streamed(
+        &self,
+        path: &str,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let cat_cmd = self.file_content_command(self.repo_path(path).relative())?;
+        let total = session
+            .sftp()?
+            .stat(Path::new(&self.repo_file_path(path)))?
+            .size;
+        let start = Instant::now();
+        let mut read = 0u64;
+        let completed = self.stream_remote_command(&cat_cmd, total, &mut |chunk, total| {
+            read += chunk.len() as u64;
+            on_chunk(chunk, total)
+        })?;
+        self.metrics.record_read(read, start.elapsed());
+        Ok(completed)
+    }
+
+    /// Writes into the remote clone and commits; `git add` runs the LFS clean
+    /// filter for any pattern tracked in `.gitattributes`.
+    fn write_file(&self, path: &str, data: &[u8]) -> Result<(), StorageError> {
+        let path = self.repo_path(path);
+        metrics::metered_write(&self.metrics, data.len(), || {
+            self.write_to_clone(path.as_str(), data)
+        })?;
+        self.stage_paths(&[path.as_str()])?;
+        self.commit_and_push(&[path.as_str()], &format!("Update {}", path.relative()))
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let sftp = session.sftp()?;
+        Ok(utils::sftp_exists(
+            &sftp,
+            Path::new(&self.repo_file_path(path)),
+        )?)
+    }
+
+    fn stat(&self, path: &str) -> Result<FileInfo, StorageError> {
+        let path = self.repo_path(path);
+        let stat_cmd = format!(
+            "stat --printf '%F\\t%s\\t%Y\\t%n\\0' -- {}",
+            shell_quote(&self.path_translator().to_absolute(&path))
+        );
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        self.metrics.record_command();
+        let output = ssh_util::ssh_exec(session, &stat_cmd)?
+            .checked(&stat_cmd)
+            .map_err(|e| stat_error(&path, e))?;
+        match utils::parse_stat_records(&output).first() {
+            Some((_, stat)) => Ok(utils::sftp_file_info(path.as_str(), stat)),
+            None => Err(StorageError::Protocol(format!(
+                "Unexpected stat output for {}",
+                path
+            ))),
+        }
+    }
+
+    fn set_permissions(&self, path: &str, mode: u32) -> Result<FileInfo, StorageError> {
+        let info = self.stat(path)?;
+        let path = self.repo_path(path);
+        let chmod_cmd = format!(
+            "chmod {:o} -- {} 2>&1",
+            mode,
+            shell_quote(&self.path_translator().to_absolute(&path))
+        );
+        let output = self.execute_remote_command(&chmod_cmd)?;
+        if !output.is_empty() {
+            return Err(output.trim().to_string().into());
+        }
+        // Only the executable bit is tracked, so this commit may be empty.
+        self.stage_paths(&[path.as_str()])?;
+        self.commit_and_push(
+            &[path.as_str()],
+            &format!("Change mode of {} to {:o}", path.relative(), mode),
+        )?;
+        Ok(FileInfo {
+            permissions: Some(mode),
+            ..info
+        })
+    }
+
+    fn set_modified(&self, path: &str, mtime: u64) -> Result<FileInfo, StorageError> {
+        let info = self.stat(path)?;
+        // git does not track mtimes, so there is nothing to commit.
+        let touch_cmd = format!(
+            "touch -c -m -d @{} -- {} 2>&1",
+            mtime,
+            shell_quote(&self.repo_file_path(path))
+        );
+        let output = self.execute_remote_command(&touch_cmd)?;
+        if !output.is_empty() {
+            return Err(output.trim().to_string().into());
+        }
+        self.invalidate_listing_cache();
+        Ok(FileInfo {
+            modified: Some(mtime),
+            ..info
+        })
+    }
+
+    fn directory_size(&self, path: &str) -> Result<DirectoryUsage, StorageError> {
+        let usage_cmd = utils::disk_usage_command(&self.repo_file_path(path), Some(".git"));
+        let output = self.execute_remote_command(&usage_cmd)?;
+        utils::parse_disk_usage(&output)
+            .ok_or_else(|| format!("Could not measure {}", self.repo_path(path)).into())
+    }
+
+    fn archive_directory(
+        &self,
+        path: &str,
+        format: ArchiveFormat,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        let output =
+            self.execute_remote_command(&archive::tool_check_command(format.remote_tool()))?;
+        if !output.contains("yes") {
+            return Err(StorageError::ToolMissing(format.remote_tool()));
+        }
+        let archive_cmd =
+            archive::archive_command(&self.repo_file_path(path), format, Some(".git"));
+        self.stream_remote_command(&archive_cmd, None, on_chunk)
+    }
+
+    fn extract_archive(&self, path: &str, destination: &str) -> Result<Vec<String>, StorageError> {
+        let format = ExtractFormat::from_path(path)
+            .ok_or_else(|| format!("Unsupported archive type: {}", path))?;
+        let output =
+            self.execute_remote_command(&archive::tool_check_command(format.remote_tool()))?;
+        if !output.contains("yes") {
+            return Err(StorageError::ToolMissing(format.remote_tool()));
+        }
+
+        let destination = self.repo_path(destination);
+        let extract_cmd = archive::extract_command(
+            &self.repo_file_path(path),
+            &self.path_translator().to_absolute(&destination),
+            format,
+        );
+        let entries = archive::parse_extract_output(&self.execute_remote_command(&extract_cmd)?)?;
+
+        let extracted: Vec<String> = entries
+            .iter()
+            .map(|entry| destination.join(entry).as_str().to_string())
+            .collect();
+        if !extracted.is_empty() {
+            let paths: Vec<&str> = extracted.iter().map(String::as_str).collect();
+            self.stage_paths(&paths)?;
+            self.commit_and_push(
+                &paths,
+                &format!("Extract {}", self.repo_path(path).relative()),
+            )?;
+        }
+        Ok(entries)
+    }
+
+    fn checksum(
+        &self,
+        path: &str,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<FileChecksum, StorageError> {
+        let info = self.stat(path)?;
+        if info.is_dir {
+            return Err(StorageError::InvalidInput("is a directory".to_string()));
+        }
+        let hash_cmd = format!(
+            "{} -- {} 2>/dev/null",
+            algorithm.remote_tool(),
+            shell_quote(&self.repo_file_path(path))
+        );
+        let output = self.execute_remote_command_bytes(&hash_cmd)?;
+        let digest =
+            match checksum::parse_checksum_output(&String::from_utf8_lossy(&output), algorithm) {
+                Some(digest) => digest,
+                None => checksum::hash_streamed(self, path, algorithm)?,
+            };
+        Ok(FileChecksum {
+            algorithm,
+            digest,
+            size: info.size,
+        })
+    }
+
+    fn checksums(
+        &self,
+        paths: &[String],
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<Vec<Option<String>>, StorageError> {
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        let absolute: Vec<String> = paths.iter().map(|p| self.repo_file_path(p)).collect();
+        let output = self.execute_remote_command_bytes(&checksum::batch_checksum_command(
+            &absolute, algorithm,
+        ))?;
+        let digests = checksum::parse_checksum_lines(&String::from_utf8_lossy(&output), algorithm);
+        if digests.is_empty() {
+            // Most likely the tool is missing; hash each file locally instead.
+            return Ok(paths
+                .iter()
+                .map(|p| checksum::hash_streamed(self, p, algorithm).ok())
+                .collect());
+        }
+        Ok(absolute.iter().map(|p| digests.get(p).cloned()).collect())
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let path = self.repo_path(path);
+        let stat = session
+            .sftp()?
+            .stat(Path::new(&self.path_translator().to_absolute(&path)))?;
+        if stat.is_dir() {
+            return Err(StorageError::InvalidInput("is a directory".to_string()));
+        }
+
+        self.execute_remote_command_checked(&git_rm_command(
+            &self.config.local_path,
+            path.as_str(),
+        ))?;
+        self.commit_and_push(&[path.as_str()], &format!("Delete {}", path.relative()))
+    }
+
+    fn delete_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+    ) -> Result<DeleteDirectoryResult, StorageError> {
+        let dir = paths::guard_directory_delete(&self.path_translator(), path)?;
+        let ls_cmd = format!(
+            "cd {} && export GIT_LITERAL_PATHSPECS=1 && git ls-files -z -- {}",
+            shell_quote(&self.config.local_path),
+            shell_quote(&format!("{}/", dir.relative()))
+        );
+        let output = self.execute_remote_command_bytes(&ls_cmd)?;
+        let tracked: Vec<String> = output
+            .split(|b| *b == 0)
+            .filter(|f| !f.is_empty())
+            .map(|f| String::from_utf8_lossy(f).into_owned())
+            .collect();
+
+        if tracked.is_empty() {
+            return Err(StorageError::NotFound(dir.to_string()));
+        }
+        let gitkeep = dir.join(GITKEEP_FILE);
+        if !recursive && tracked.iter().any(|f| f != gitkeep.relative()) {
+            return Err(StorageError::InvalidInput(
+                "Directory not empty".to_string(),
+            ));
+        }
+
+        let rm_cmd = format!(
+            "cd {} && export GIT_LITERAL_PATHSPECS=1 && git rm -r -q -- {}",
+            shell_quote(&self.config.local_path),
+            shell_quote(dir.relative())
+        );
+        self.execute_remote_command_checked(&rm_cmd)?;
+        self.commit_and_push(
+            &[dir.as_str()],
+            &format!("Delete directory {}", dir.relative()),
+        )?;
+        Ok(count_removed(&tracked, &dir))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<FileInfo, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let sftp = session.sftp()?;
+        let source = self.repo_path(from);
+        let destination = self.repo_path(to);
+        let destination_abs = self.path_translator().to_absolute(&destination);
+
+        if let Some(parent) = utils::parent_path(&destination_abs) {
+            if !utils::sftp_exists(&sftp, Path::new(parent))? {
+                return Err(StorageError::NotFound(parent.to_string()));
+            }
+        }
+
+        self.execute_remote_command_checked(&git_mv_command(
+            &self.config.local_path,
+            source.as_str(),
+            destination.as_str(),
+        ))?;
+        self.commit_and_push(
+            &[source.as_str(), destination.as_str()],
+            &format!("Rename {} to {}", source.relative(), destination.relative()),
+        )?;
+
+        let stat = sftp.stat(Path::new(&destination_abs))?;
+        Ok(utils::sftp_file_info(destination.as_str(), &stat))
+    }
+
+    fn rename_with_record(
+        &self,
+        from: &str,
+        to: &str,
+        record: &str,
+        content: Option<&[u8]>,
+    ) -> Result<FileInfo, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let sftp = session.sftp()?;
+        let source = self.repo_path(from);
+        let destination = self.repo_path(to);
+        let record = self.repo_path(record);
+        let destination_abs = self.path_translator().to_absolute(&destination);
+
+        if let Some(parent) = utils::parent_path(&destination_abs) {
+            if !utils::sftp_exists(&sftp, Path::new(parent))? {
+                return Err(StorageError::NotFound(parent.to_string()));
+            }
+        }
+
+        match content {
+            Some(content) => {
+                metrics::metered_write(&self.metrics, content.len(), || {
+                    self.write_to_clone(record.as_str(), content)
+                })?;
+                self.stage_paths(&[record.as_str()])?;
+            }
+            None => {
+                self.execute_remote_command_checked(&git_rm_command(
+                    &self.config.local_path,
+                    record.as_str(),
+                ))?;
+            }
+        }
+        self.execute_remote_command_checked(&git_mv_command(
+            &self.config.local_path,
+            source.as_str(),
+            destination.as_str(),
+        ))?;
+        self.commit_and_push(
+            &[source.as_str(), destination.as_str(), record.as_str()],
+            &format!("Move {} to {}", source.relative(), destination.relative()),
+        )?;
+
+        let stat = sftp.stat(Path::new(&destination_abs))?;
+        Ok(utils::sftp_file_info(destination.as_str(), &stat))
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> Result<FileInfo, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let sftp = session.sftp()?;
+        let translator = self.path_translator();
+        let source = self.repo_path(from);
+        let destination = self.repo_path(to);
+        let destination_abs = translator.to_absolute(&destination);
+
+        if sftp
+            .stat(Path::new(&translator.to_absolute(&source)))?
+            .is_dir()
+        {
+            return Err(StorageError::InvalidInput(
+                "is a directory; use copy_directory".to_string(),
+            ));
+        }
+        if let Some(parent) = utils::parent_path(&destination_abs) {
+            if !utils::sftp_exists(&sftp, Path::new(parent))? {
+                return Err(StorageError::NotFound(parent.to_string()));
+            }
+        }
+
+        let cp_cmd = format!(
+            "cd {} && cp -p -- {} {} 2>&1",
+            shell_quote(&self.config.local_path),
+            shell_quote(source.relative()),
+            shell_quote(destination.relative())
+        );
+        let output = self.execute_remote_command(&cp_cmd)?;
+        if !output.is_empty() {
+            return Err(output.trim().to_string().into());
+        }
+        self.stage_paths(&[destination.as_str()])?;
+        self.commit_and_push(
+            &[destination.as_str()],
+            &format!("Copy {} to {}", source.relative(), destination.relative()),
+        )?;
+
+        let stat = sftp.stat(Path::new(&destination_abs))?;
+        Ok(utils::sftp_file_info(destination.as_str(), &stat))
+    }
+
+    fn create_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+    ) -> Result<CreateDirectoryResult, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        let sftp = session.sftp()?;
+        let directory = self.repo_path(path);
+        let absolute = self.path_translator().to_absolute(&directory);
+
+        let existed = match sftp.stat(Path::new(&absolute)) {
+            Ok(stat) if stat.is_dir() => true,
+            Ok(_) => {
+                return Err(StorageError::InvalidInput(format!(
+                    "Path exists and is not a directory: {}",
+                    directory
+                )))
+            }
+            Err(_) => false,
+        };
+
+        if !existed {
+            if !recursive {
+                if let Some(parent) = utils::parent_path(&absolute) {
+                    if !utils::sftp_exists(&sftp, Path::new(parent))? {
+                        return Err(StorageError::NotFound(parent.to_string()));
+                    }
+                }
+            }
+            let gitkeep = directory.join(GITKEEP_FILE);
+            let mkdir_cmd = format!(
+                "mkdir -p {} && touch {}",
+                shell_quote(&absolute),
+                shell_quote(&self.path_translator().to_absolute(&gitkeep))
+            );
+            self.execute_remote_command_checked(&mkdir_cmd)?;
+            self.stage_paths(&[gitkeep.as_str()])?;
+            self.commit_and_push(
+                &[gitkeep.as_str()],
+                &format!("Create directory {}", directory.relative()),
+            )?;
+        }
+
+        let stat = sftp.stat(Path::new(&absolute))?;
+        Ok(CreateDirectoryResult {
+            directory: utils::sftp_file_info(directory.as_str(), &stat),
+            existed,
+        })
+    }
+
+    fn get_file_thumbnail(
+        &self,
+        path: &str,
+        max_size: u32,
+        options: ThumbnailOptions,
+    ) -> Result<Thumbnail, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        thumbnails::remote_file_thumbnail(
+            self,
+            session,
+            self.remote_thumbnailer,
+            &self.repo_file_path(path),
+            path,
+            max_size,
+            options,
+        )
+        .map_err(StorageError::from)
+    }
+
+    fn remote_thumbnailer(&self) -> Option<RemoteThumbnailer> {
+        self.remote_thumbnailer
+    }
+
+    fn probe_media(&self, path: &str) -> Result<MediaProbe, StorageError> {
+        let session = self.session.as_ref().ok_or(StorageError::NotConnected)?;
+        media_probe::probe_remote(self, session, &self.repo_file_path(path), path)
+            .map_err(StorageError::from)
+    }
+
+    /// The first sparse path, so browsing starts inside the checkout.
+    fn get_root_path(&self) -> String {
+        self.sparse_paths()
+            .first()
+            .map_or_else(|| "/".to_string(), RemotePath::to_string)
+    }
+
+    fn path_translator(&self) -> PathTranslator {
+        PathTranslator::new(&self.config.local_path)
+    }
+
+    fn storage_type(&self) -> StorageType {
+        StorageType::GitHub
+    }
+
+    fn connection_id(&self) -> String {
+        format!("{}#{}", self.config.repo_url, self.branch())
+    }
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
     use base64::Engine;
 
@@ -363,6 +2802,24 @@ mod tests {
             ssh_key_content: base64::engine::general_purpose::STANDARD.encode(b"test key"),
             branch: "main".to_string(),
             local_path: "/tmp/testrepo".to_string(),
+            key_passphrase: None,
+            known_hosts_file: None,
+            keepalive_secs: None,
+            auto_reconnect: false,
+            remote_thumbnails: false,
+            author_name: None,
+            author_email: None,
+            clone_depth: None,
+            partial_clone: false,
+            sparse_paths: Vec::new(),
+            api_token: None,
+            min_free_space_mb: None,
+            skip_disk_check: false,
+            manual_commits: false,
+            manage_lfs_tracking: false,
+            lfs_patterns: Vec::new(),
+            replace_existing: false,
+            remove_clone_on_disconnect: false,
         }
     }
 
@@ -382,16 +2839,357 @@ mod tests {
     }
 
     #[test]
-    fn test_get_github_host() {
+    fn test_repo_paths_are_relative_to_clone() {
+        let storage = GitHubStorage::new(create_test_config());
+        assert_eq!(storage.repo_file_path("/"), "/tmp/testrepo");
+        assert_eq!(storage.repo_file_path(""), "/tmp/testrepo");
+        assert_eq!(storage.repo_file_path("/photos/"), "/tmp/testrepo/photos");
+        assert_eq!(
+            storage.repo_file_path("/tmp/testrepo/photos/a.jpg"),
+            "/tmp/testrepo/photos/a.jpg"
+        );
+        // Listing paths round-trip into the relative form read_file uses.
+        let listed = storage.repo_path("/photos").join("a b.jpg");
+        assert_eq!(listed.as_str(), "/photos/a b.jpg");
+        assert_eq!(
+            storage.repo_path(listed.as_str()).relative(),
+            "photos/a b.jpg"
+        );
+    }
+
+    #[test]
+    fn test_ssh_target() {
         let config = GitHubConfig {
             repo_url: "git@github.com:testuser/testrepo.git".to_string(),
             username: "git".to_string(),
             ssh_key_content: "dGVzdA==".to_string(),
             branch: "main".to_string(),
             local_path: "/tmp/testrepo".to_string(),
+            key_passphrase: None,
+            known_hosts_file: None,
+            keepalive_secs: None,
+            auto_reconnect: false,
+            remote_thumbnails: false,
+            author_name: None,
+            author_email: None,
+            clone_depth: None,
+            partial_clone: false,
+            sparse_paths: Vec::new(),
+            api_token: None,
+            min_free_space_mb: None,
+            skip_disk_check: false,
+            manual_commits: false,
+            manage_lfs_tracking: false,
+            lfs_patterns: Vec::new(),
+            replace_existing: false,
+            remove_clone_on_disconnect: false,
         };
-        let storage = GitHubStorage::new(config);
-        assert_eq!(storage.get_github_host(), "github.com");
+        let mut storage = GitHubStorage::new(config);
+        assert_eq!(
+            storage.ssh_target().unwrap(),
+            ("github.com".to_string(), 22)
+        );
+
+        storage.config.repo_url = "ssh://git@gitlab.example.com:2222/group/repo.git".to_string();
+        assert_eq!(
+            storage.ssh_target().unwrap(),
+            ("gitlab.example.com".to_string(), 2222)
+        );
+        storage.config.repo_url = "/srv/git/repo.git".to_string();
+        assert!(matches!(
+            storage.ssh_target(),
+            Err(StorageError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_ls_remote_command() {
+        assert_eq!(
+            ls_remote_command("git@github.com:a/b.git", "feature/x y"),
+            "git ls-remote --exit-code --heads 'git@github.com:a/b.git' 'refs/heads/feature/x y'"
+        );
+        assert_eq!(
+            ls_remote_command("git@github.com:a/b.git", ""),
+            "git ls-remote --exit-code 'git@github.com:a/b.git' HEAD"
+        );
+    }
+
+    #[test]
+    fn test_parse_file_history_follows_renames() {
+        let output = b"\x1eaaa111\x1fAlice\x1f1700000000\x1fCrop: tighter\0\nphotos/b.jpg\0\x1ebbb222\x1fBob Smith\x1f1690000000\x1fAdd a\x1fb\0\nold/a b.jpg\0";
+        let history = parse_file_history(output);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].commit, "aaa111");
+        assert_eq!(history[0].message, "Crop: tighter");
+        assert_eq!(history[0].path, "/photos/b.jpg");
+        assert_eq!(history[1].author, "Bob Smith");
+        assert_eq!(history[1].date, 1690000000);
+        assert_eq!(history[1].message, "Add a\x1fb");
+        assert_eq!(history[1].path, "/old/a b.jpg");
+    }
+
+    #[test]
+    fn test_show_revision_command_quotes_path() {
+        assert_eq!(
+            show_revision_command("abc123", "it's.jpg"),
+            "if git lfs version >/dev/null 2>&1; then git show 'abc123:it'\\''s.jpg' | git lfs smudge -- 'it'\\''s.jpg'; else git show 'abc123:it'\\''s.jpg'; fi"
+        );
+    }
+
+    #[test]
+    fn test_sparse_paths() {
+        let storage = GitHubStorage::new(GitHubConfig {
+            sparse_paths: vec!["assets/photos/".to_string(), "/docs".to_string()],
+            ..create_test_config()
+        });
+        assert_eq!(storage.get_root_path(), "/assets/photos");
+        assert_eq!(
+            storage.config().sparse_paths,
+            vec!["/assets/photos", "/docs"]
+        );
+        for dir in [
+            "/",
+            "/assets",
+            "/assets/photos",
+            "/assets/photos/2023",
+            "/docs",
+        ] {
+            assert!(
+                storage.ensure_checked_out(&RemotePath::new(dir)).is_ok(),
+                "{}",
+                dir
+            );
+        }
+        assert!(matches!(
+            storage.ensure_checked_out(&RemotePath::new("/assets/video")),
+            Err(StorageError::NotCheckedOut(_))
+        ));
+
+        assert!(sparse_cone(&["/a".to_string(), "/".to_string()]).is_empty());
+        let storage = GitHubStorage::new(create_test_config());
+        assert!(storage
+            .ensure_checked_out(&RemotePath::new("/anything"))
+            .is_ok());
+    }
+
+    const LS_REMOTE_SYMREF: &str = "ref: refs/heads/master\tHEAD\n\
+        aaa111\tHEAD\n\
+        aaa111\trefs/heads/master\n\
+        bbb222\trefs/heads/release/2.0\n";
+
+    #[test]
+    fn test_pick_branch_detects_default() {
+        assert_eq!(pick_branch(LS_REMOTE_SYMREF, "").unwrap(), "master");
+        assert!(matches!(
+            pick_branch("", ""),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_pick_branch_checks_requested() {
+        assert_eq!(
+            pick_branch(LS_REMOTE_SYMREF, "release/2.0").unwrap(),
+            "release/2.0"
+        );
+        let error = pick_branch(LS_REMOTE_SYMREF, "main").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Not found: branch main; available: [master, release/2.0]"
+        );
+    }
+
+    #[test]
+    fn test_is_access_denied() {
+        assert!(is_access_denied(
+            "git@github.com: Permission denied (publickey).\nfatal: Could not read from remote repository."
+        ));
+        assert!(is_access_denied("ERROR: Repository not found."));
+        assert!(!is_access_denied(
+            "ssh: Could not resolve hostname github.com: Temporary failure in name resolution"
+        ));
+    }
+
+    #[test]
+    fn test_stat_error() {
+        let path = RemotePath::new("/photos/a.jpg");
+        let failed = |stderr: &str| ssh_util::ExecError {
+            command: "stat".to_string(),
+            exit_status: 1,
+            stderr: stderr.to_string(),
+        };
+        assert!(matches!(
+            stat_error(&path, failed("stat: cannot statx '/repo/photos/a.jpg': No such file or directory")),
+            StorageError::NotFound(p) if p == "/photos/a.jpg"
+        ));
+        assert!(matches!(
+            stat_error(
+                &path,
+                failed("stat: cannot statx '/repo/photos/a.jpg': Permission denied")
+            ),
+            StorageError::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            stat_error(&path, failed("bash: stat: command not found")),
+            StorageError::Protocol(_)
+        ));
+    }
+
+    #[test]
+    fn test_depth_arg() {
+        let storage = GitHubStorage::new(create_test_config());
+        assert_eq!(storage.depth_arg(), "");
+        let storage = GitHubStorage::new(GitHubConfig {
+            clone_depth: Some(1),
+            ..create_test_config()
+        });
+        assert_eq!(storage.depth_arg(), " --depth 1");
+        let storage = GitHubStorage::new(GitHubConfig {
+            clone_depth: Some(0),
+            ..create_test_config()
+        });
+        assert_eq!(storage.depth_arg(), "");
+        assert_eq!(
+            fetch_refspec("feature/x"),
+            "+refs/heads/feature/x:refs/remotes/origin/feature/x"
+        );
+    }
+
+    #[test]
+    fn test_git_with_author() {
+        let storage = GitHubStorage::new(create_test_config());
+        assert_eq!(storage.git_with_author(), "git");
+
+        let storage = GitHubStorage::new(GitHubConfig {
+            author_name: Some("Ann O'Neil".to_string()),
+            author_email: Some("ann@example.com".to_string()),
+            ..create_test_config()
+        });
+        assert_eq!(
+            storage.git_with_author(),
+            "git -c 'user.name=Ann O'\\''Neil' -c 'user.email=ann@example.com'"
+        );
+    }
+
+    #[test]
+    fn test_parse_git_status() {
+        let output = b"## main...origin/main [ahead 2, behind 1]\0M  photos/a.jpg\0 M b c.png\0R  new.jpg\0old.jpg\0?? notes.txt\0UU merge.txt\0";
+        let status = parse_git_status(output);
+        assert_eq!(status.branch.as_deref(), Some("main"));
+        assert_eq!(status.upstream.as_deref(), Some("origin/main"));
+        assert_eq!((status.ahead, status.behind), (2, 1));
+        assert_eq!(status.entries.len(), 5);
+        assert_eq!(status.entries[0].path, "/photos/a.jpg");
+        assert_eq!(status.entries[0].staged, Some(GitChange::Modified));
+        assert_eq!(status.entries[0].unstaged, None);
+        assert_eq!(status.entries[1].path, "/b c.png");
+        assert_eq!(status.entries[1].staged, None);
+        assert_eq!(status.entries[1].unstaged, Some(GitChange::Modified));
+        assert_eq!(status.entries[2].path, "/new.jpg");
+        assert_eq!(status.entries[2].original_path.as_deref(), Some("/old.jpg"));
+        assert_eq!(status.entries[2].staged, Some(GitChange::Renamed));
+        assert_eq!(status.entries[3].unstaged, Some(GitChange::Untracked));
+        assert_eq!(status.entries[4].staged, Some(GitChange::Unmerged));
+    }
+
+    #[test]
+    fn test_parse_git_status_headers() {
+        let status = parse_git_status(b"## No commits yet on main\0");
+        assert_eq!(status.branch.as_deref(), Some("main"));
+        assert_eq!(status.upstream, None);
+
+        let status = parse_git_status(b"## HEAD (no branch)\0");
+        assert_eq!(status.branch, None);
+    }
+
+    #[test]
+    fn test_is_push_rejected() {
+        assert!(is_push_rejected(
+            " ! [rejected]        main -> main (fetch first)\nerror: failed to push some refs"
+        ));
+        assert!(is_push_rejected(
+            " ! [rejected]        main -> main (non-fast-forward)"
+        ));
+        assert!(!is_push_rejected(
+            " ! [remote rejected] main -> main (protected branch hook declined)"
+        ));
+    }
+
+    #[test]
+    fn test_parse_ls_remote_heads() {
+        let output =
+            "aaa111\trefs/heads/main\nbbb222\trefs/heads/feature/x\nccc333\trefs/tags/v1\n";
+        let branches = parse_ls_remote_heads(output, "main");
+        assert_eq!(
+            branches,
+            vec![
+                Branch {
+                    name: "main".to_string(),
+                    commit: "aaa111".to_string(),
+                    current: true,
+                },
+                Branch {
+                    name: "feature/x".to_string(),
+                    commit: "bbb222".to_string(),
+                    current: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_refresh_summary_within() {
+        let summary = RefreshSummary {
+            old_head: "aaa".to_string(),
+            new_head: "bbb".to_string(),
+            changed_count: 3,
+            changed_paths: vec![
+                "/photos/a.jpg".to_string(),
+                "/photos/2024/b.jpg".to_string(),
+                "/videos/c.mp4".to_string(),
+            ],
+        };
+        let within = summary.within(&RemotePath::new("/photos"));
+        assert_eq!(
+            within.changed_paths,
+            vec!["/photos/a.jpg", "/photos/2024/b.jpg"]
+        );
+        assert_eq!(within.changed_count, 3);
+    }
+
+    #[test]
+    fn test_dirty_paths() {
+        assert!(dirty_paths("").is_empty());
+        assert_eq!(
+            dirty_paths("M  .gitattributes\n M photos/a b.jpg\nD  old.png\n"),
+            vec![".gitattributes", "photos/a b.jpg", "old.png"]
+        );
+    }
+
+    #[test]
+    fn test_parse_lfs_patterns() {
+        let attributes = "# media\n\
+            *.jpg filter=lfs diff=lfs merge=lfs -text\n\
+            *.txt text eol=lf\n\
+            \n\
+            \"raw files/*.dng\" filter=lfs diff=lfs merge=lfs -text\n\
+            *.psd -filter=lfs\n";
+        assert_eq!(
+            parse_lfs_patterns(attributes),
+            vec!["*.jpg", "raw files/*.dng"]
+        );
+        assert!(parse_lfs_patterns("").is_empty());
+    }
+
+    #[test]
+    fn test_config_follows_switched_branch() {
+        let storage = GitHubStorage::new(create_test_config());
+        *storage.branch.lock().unwrap() = "develop".to_string();
+        assert_eq!(storage.config().branch, "develop");
+        assert_eq!(
+            storage.config().repo_url,
+            "git@github.com:testuser/testrepo.git"
+        );
     }
 
     #[test]
@@ -411,6 +3209,344 @@ mod tests {
         assert_eq!(config.branch, deserialized.branch);
     }
 
+    #[test]
+    fn test_parse_listing() {
+        let output = b"d\td\t4096\t1700000100.5\t755\tubuntu\t.git\0\0\
+            d\td\t4096\t1700000200.0\t755\tubuntu\t2023\0\0\
+            f\tf\t1234\t1700000250.1234567890\t644\tubuntu\tbeach day.jpg\0\0";
+        let files = parse_listing(
+            output,
+            &RemotePath::new("/photos"),
+            MediaFilter::All,
+            true,
+            &mut HashMap::new(),
+        );
+        assert_eq!(files.len(), 2);
+        assert!(files[0].is_dir);
+        assert_eq!(files[0].modified, Some(1700000200));
+        assert_eq!(files[1].name, "beach day.jpg");
+        assert_eq!(files[1].path, "/photos/beach day.jpg");
+        assert_eq!(files[1].size, 1234);
+        assert_eq!(files[1].modified, Some(1700000250));
+        assert_eq!(files[1].permissions, Some(0o644));
+        assert_eq!(files[1].owner.as_deref(), Some("ubuntu"));
+        assert_eq!(files[1].mime_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(files[1].link_target, None);
+    }
+
+    #[test]
+    fn test_parse_listing_keeps_unusual_names() {
+        let names = [
+            "two  spaces.jpg",
+            " padded ",
+            "tab\there.png",
+            "new\nline.jpg",
+        ];
+        let output: Vec<u8> = names
+            .iter()
+            .flat_map(|name| format!("f\tf\t1\t1700000000\t644\tme\t{}\0\0", name).into_bytes())
+            .collect();
+        let files = parse_listing(
+            &output,
+            &RemotePath::new("/"),
+            MediaFilter::All,
+            false,
+            &mut HashMap::new(),
+        );
+        let parsed: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(parsed, names);
+        assert_eq!(files[3].path, "/new\nline.jpg");
+    }
+
+    #[test]
+    fn test_parse_listing_reports_lfs_sizes() {
+        let mut lfs_sizes = HashMap::new();
+        let pointers = b"P\tf\t52428800\t\t\t\tbig.jpg\0\0";
+        assert!(parse_listing(
+            pointers,
+            &RemotePath::new("/"),
+            MediaFilter::All,
+            false,
+            &mut lfs_sizes
+        )
+        .is_empty());
+
+        let entries = b"f\tf\t131\t1700000000.0\t644\tubuntu\tbig.jpg\0\0\
+            f\tf\t90\t1700000000.0\t644\tubuntu\tsmall.jpg\0\0";
+        let files = parse_listing(
+            entries,
+            &RemotePath::new("/"),
+            MediaFilter::All,
+            false,
+            &mut lfs_sizes,
+        );
+        assert_eq!(files[0].size, 52428800);
+        assert_eq!(files[1].size, 90);
+    }
+
+    #[test]
+    fn test_materialize_command_quotes_paths() {
+        let command = materialize_command("/tmp/repo", &["a b.jpg", "it's.png"], 200, 202);
+        assert!(command
+            .starts_with("cd /tmp/repo && failed=0; n=200; for f in 'a b.jpg' 'it'\\''s.png'; do"));
+        assert!(command.contains("echo \"Fetching LFS objects: $((n*100/202))% ($n/202)\""));
+        assert!(command.ends_with("exit $failed"));
+    }
+
+    #[test]
+    fn test_parse_progress_line() {
+        assert_eq!(
+            parse_progress_line("Receiving objects:  42% (420/1000), 1.20 MiB | 2.00 MiB/s"),
+            Some(GitProgress {
+                phase: "Receiving objects".to_string(),
+                percent: 42,
+                received: 420,
+                total: 1000,
+            })
+        );
+        let remote = parse_progress_line("remote: Counting objects: 100% (7/7), done.").unwrap();
+        assert_eq!(remote.phase, "Counting objects");
+        assert_eq!((remote.percent, remote.received, remote.total), (100, 7, 7));
+        assert_eq!(
+            parse_progress_line("Fetching LFS objects: 50% (1/2)").map(|p| p.received),
+            Some(1)
+        );
+        assert_eq!(parse_progress_line("Cloning into '/tmp/repo'..."), None);
+        assert_eq!(
+            parse_progress_line("remote: Enumerating objects: 7, done."),
+            None
+        );
+        assert_eq!(parse_progress_line(""), None);
+    }
+
+    #[test]
+    fn test_complete_listing_len() {
+        let output = b"f\tf\t1\t1\t644\tme\ta.jpg\0\0l\tf\t1\t1\t777\tme\tb.jpg\0a.j";
+        let complete = complete_listing_len(output);
+        assert_eq!(&output[complete..], b"l\tf\t1\t1\t777\tme\tb.jpg\0a.j");
+        assert_eq!(complete_listing_len(&output[..complete]), complete);
+        assert_eq!(complete_listing_len(b"f\tf\t1"), 0);
+    }
+
+    #[test]
+    fn test_parse_listing_hides_dotfiles() {
+        let output = b"d\td\t4096\t1700000000\t755\tme\t.git\0\0\
+            f\tf\t10\t1700000000\t644\tme\t.gitattributes\0\0\
+            d\td\t4096\t1700000000\t755\tme\t.imagetrash\0\0\
+            f\tf\t10\t1700000000\t644\tme\t.DS_Store\0\0\
+            f\tf\t10\t1700000000\t644\tme\ta.jpg\0\0";
+        let names = |show_hidden| -> Vec<String> {
+            parse_listing(
+                output,
+                &RemotePath::new("/"),
+                MediaFilter::All,
+                show_hidden,
+                &mut HashMap::new(),
+            )
+            .into_iter()
+            .map(|f| f.name)
+            .collect()
+        };
+        assert_eq!(names(false), vec!["a.jpg"]);
+        assert_eq!(names(true), vec![".imagetrash", ".DS_Store", "a.jpg"]);
+    }
+
+    #[test]
+    fn test_parse_listing_flags_symlinks() {
+        let output = b"l\td\t7\t1700000000\t777\tme\talbum\0../2023/album\0\
+            l\tf\t9\t1700000000\t777\tme\tcover.jpg\0a b/c.jpg\0\
+            l\tN\t6\t1700000000\t777\tme\tbroken\0gone\0\
+            f\tf\t3\t1700000000\t644\tme\tnote.txt\0\0";
+        let files = parse_listing(
+            output,
+            &RemotePath::new("/"),
+            MediaFilter::Images,
+            false,
+            &mut HashMap::new(),
+        );
+        let flags: Vec<(&str, bool, bool)> = files
+            .iter()
+            .map(|f| (f.name.as_str(), f.is_dir, f.is_symlink))
+            .collect();
+        assert_eq!(
+            flags,
+            vec![("album", true, true), ("cover.jpg", false, true)]
+        );
+        assert_eq!(files[0].link_target.as_deref(), Some("../2023/album"));
+        assert_eq!(files[1].link_target.as_deref(), Some("a b/c.jpg"));
+    }
+
+    #[test]
+    fn test_batch_read_command_quotes_paths() {
+        let cmd = batch_read_command("/tmp/image-repo", &["a b.jpg", "it's.png"]);
+        assert!(cmd.starts_with("cd /tmp/image-repo && for f in 'a b.jpg' 'it'\\''s.png'; do"));
+    }
+
+    #[test]
+    fn test_parse_batch_read_keeps_per_file_results() {
+        let output = format!("{}\n!\n\n", utils::base64_encode(&[0, 159, 146, 150]));
+        let results = parse_batch_read(&output, 4);
+        assert_eq!(results[0], Ok(vec![0, 159, 146, 150]));
+        assert_eq!(results[1], Err("No such file".to_string()));
+        assert_eq!(results[2], Ok(Vec::new()));
+        assert!(results[3].is_err());
+    }
+
+    #[test]
+    fn test_parse_deleted_files_log() {
+        let output = b"\x1eaaa111\x1fAlice\x1f1700000000\0\nphotos/c.jpg\0\x1ebbb222\x1fBob Smith\x1f1690000000\0\nphotos/a b.jpg\0photos/new\nline.jpg\0";
+        let existing: HashSet<String> = ["photos/c.jpg".to_string()].into_iter().collect();
+
+        let (files, commits) = parse_deleted_files_log(output, &existing);
+
+        assert_eq!(commits, 2);
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].path, "/photos/c.jpg");
+        assert_eq!(files[0].commit, "aaa111");
+        assert!(files[0].readded);
+        assert_eq!(files[1].path, "/photos/a b.jpg");
+        assert_eq!(files[1].author, "Bob Smith");
+        assert_eq!(files[1].deleted_at, 1690000000);
+        assert!(!files[1].readded);
+        assert_eq!(files[2].path, "/photos/new\nline.jpg");
+    }
+
+    #[test]
+    fn test_parse_deleted_files_log_empty() {
+        let (files, commits) = parse_deleted_files_log(b"", &HashSet::new());
+        assert!(files.is_empty());
+        assert_eq!(commits, 0);
+    }
+
+    const LS_TREE_OUTPUT: &[u8] = b"040000 tree 98b2e8c0       -\tphotos\x00\
+        040000 tree bfff8f83       -\tphotos/2023\x00\
+        040000 tree 2b04c570       -\tphotos/2023/deep\x00\
+        100644 blob 78981922       2\tphotos/2023/deep/x.jpg\x00\
+        100644 blob e0b3f1b0  1048576\tphotos/2023/y z.png\x00";
+
+    #[test]
+    fn test_parse_ls_tree_unlimited_depth() {
+        let files = parse_ls_tree(LS_TREE_OUTPUT, &RemotePath::new("/photos"), 0);
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/photos/2023",
+                "/photos/2023/deep",
+                "/photos/2023/deep/x.jpg",
+                "/photos/2023/y z.png"
+            ]
+        );
+        assert!(files[0].is_dir);
+        assert_eq!(files[3].name, "y z.png");
+        assert_eq!(files[3].size, 1048576);
+        assert_eq!(files[3].mime_type, Some("image/png".to_string()));
+    }
+
+    #[test]
+    fn test_parse_ls_tree_depth_limit() {
+        let files = parse_ls_tree(LS_TREE_OUTPUT, &RemotePath::new("/photos"), 2);
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["/photos/2023", "/photos/2023/deep", "/photos/2023/y z.png"]
+        );
+
+        let files = parse_ls_tree(LS_TREE_OUTPUT, &RemotePath::root(), 1);
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["/photos"]);
+    }
+
+    #[test]
+    fn test_filter_ls_files_matches_file_names_only() {
+        let output = b"IMG_2023/a.jpg\0photos/img_2023_01.JPG\0photos/IMG_2023 b.png\0notes.txt\0";
+        assert_eq!(
+            filter_ls_files(output, "IMG_2023", false, 10),
+            vec!["photos/img_2023_01.JPG", "photos/IMG_2023 b.png"]
+        );
+        assert_eq!(
+            filter_ls_files(output, "IMG_2023", true, 10),
+            vec!["photos/IMG_2023 b.png"]
+        );
+        assert_eq!(filter_ls_files(output, "img", false, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_count_removed() {
+        let tracked = vec![
+            "photos/2023/a.jpg".to_string(),
+            "photos/2023/b.jpg".to_string(),
+            "photos/2023/trip/c.jpg".to_string(),
+            "photos/d.jpg".to_string(),
+        ];
+        let result = count_removed(&tracked, &RemotePath::new("/photos"));
+        assert_eq!(result.files_removed, 4);
+        assert_eq!(result.dirs_removed, 3);
+    }
+
+    #[test]
+    fn test_git_rm_command_quotes_spaces() {
+        let cmd = git_rm_command("/tmp/repo", "/photos/my holiday.jpg");
+        assert_eq!(cmd, "cd /tmp/repo && export GIT_LITERAL_PATHSPECS=1 && git rm -q -- 'photos/my holiday.jpg'");
+    }
+
+    #[test]
+    fn test_git_rm_command_quotes_single_quotes() {
+        let cmd = git_rm_command("/tmp/my repo", "/it's \"fine\".png");
+        assert_eq!(
+            cmd,
+            "cd '/tmp/my repo' && export GIT_LITERAL_PATHSPECS=1 && git rm -q -- 'it'\\''s \"fine\".png'"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_git_rm_command_takes_glob_characters_literally() {
+        let repo = tempfile::tempdir().unwrap();
+        let repo_path = repo.path().to_str().unwrap();
+        let run = |cmd: &str| {
+            std::process::Command::new("sh")
+                .arg("-c")
+                .arg(cmd)
+                .output()
+                .unwrap()
+        };
+        for name in ["a?.jpg", "a1.jpg"] {
+            std::fs::write(repo.path().join(name), b"x").unwrap();
+        }
+        let init = run(&format!(
+            "cd {} && git init -q && git add -A && \
+             git -c user.name=test -c user.email=test@example.com commit -qm init",
+            shell_quote(repo_path)
+        ));
+        if !init.status.success() {
+            // No git to run against.
+            return;
+        }
+
+        assert!(run(&git_rm_command(repo_path, "/a?.jpg")).status.success());
+        let tracked = run(&format!("cd {} && git ls-files", shell_quote(repo_path)));
+        assert_eq!(String::from_utf8_lossy(&tracked.stdout), "a1.jpg\n");
+    }
+
+    #[test]
+    fn test_git_mv_command_across_directories() {
+        let cmd = git_mv_command("/tmp/repo", "/inbox/shot.png", "/archive/2023/shot.png");
+        assert_eq!(
+            cmd,
+            "cd /tmp/repo && export GIT_LITERAL_PATHSPECS=1 && git mv -f -- inbox/shot.png archive/2023/shot.png"
+        );
+    }
+
+    #[test]
+    fn test_git_mv_command_quotes_apostrophes() {
+        let cmd = git_mv_command("/tmp/repo", "/Bob's shot.png", "/Alice's shot.png");
+        assert_eq!(
+            cmd,
+            "cd /tmp/repo && export GIT_LITERAL_PATHSPECS=1 && git mv -f -- 'Bob'\\''s shot.png' 'Alice'\\''s shot.png'"
+        );
+    }
+
     #[test]
     fn test_shell_quote_simple_path() {
         let result = shell_quote("/tmp/test");
@@ -0,0 +1,1011 @@
+//! A read-only backend over the GitHub REST API, for browsing a repository
+//! with just a personal access token. Unlike [`crate::github`] it needs no
+//! SSH host holding a clone. Reads are pinned to the commit the branch
+//! pointed to when connecting, so listings are cached for the session.
+
+use crate::archive::ArchiveFormat;
+use crate::checksum::{self, ChecksumAlgorithm, FileChecksum};
+use crate::error::StorageError;
+use crate::http::{self, HttpAuth};
+use crate::metrics::{self, StorageMetrics};
+use crate::paths::{PathTranslator, RemotePath, ABSOLUTE_PATH_KEY};
+use crate::storage::{
+    self, ChunkCallback, ContentSearchResult, CreateDirectoryResult, DeleteDirectoryResult,
+    DirectoryUsage, FileInfo, FileReadOutcome, ListOptions, RecursiveListing, SearchResult,
+    Storage, StorageType, MAX_RECURSIVE_ENTRIES,
+};
+use crate::thumbnails::{self, Thumbnail, ThumbnailOptions};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const API_URL: &str = "https://api.github.com";
+const API_VERSION: &str = "2022-11-28";
+/// Asks the contents and blobs APIs for the file itself rather than JSON.
+const RAW_MEDIA_TYPE: &str = "application/vnd.github.raw+json";
+const LFS_MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+/// Largest file the contents API serves; bigger ones come from the blobs
+/// API.
+const CONTENTS_MAX_SIZE: u64 = 1024 * 1024;
+/// Most entries the contents API lists; fuller directories are read from
+/// the trees API.
+const CONTENTS_LIST_LIMIT: usize = 1000;
+/// LFS pointer files are far smaller; bigger files are never pointers.
+const LFS_POINTER_MAX_SIZE: u64 = 1024;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GitHubApiConfig {
+    pub owner: String,
+    pub repo: String,
+    /// The repository's default branch when unset.
+    #[serde(default)]
+    pub branch: Option<String>,
+    /// Personal access token; public repositories can be read without one,
+    /// at a far lower rate limit.
+    #[serde(default)]
+    pub token: String,
+}
+
+// Written by hand so the token never ends up in logs.
+impl fmt::Debug for GitHubApiConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GitHubApiConfig")
+            .field("owner", &self.owner)
+            .field("repo", &self.repo)
+            .field("branch", &self.branch)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GitHubApiConfig {
+    /// The API URL of `rest` below the repository.
+    fn api_url(&self, rest: &str) -> String {
+        format!(
+            "{}/repos/{}/{}/{}",
+            API_URL,
+            http::encode_path(&self.owner),
+            http::encode_path(&self.repo),
+            rest
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    Submodule,
+}
+
+/// A directory entry from either the contents or the trees API.
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    name: String,
+    kind: EntryKind,
+    sha: String,
+    size: u64,
+}
+
+#[derive(Deserialize)]
+struct ContentItem {
+    name: String,
+    sha: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+impl From<ContentItem> for Entry {
+    fn from(item: ContentItem) -> Self {
+        let kind = match item.kind.as_str() {
+            "dir" => EntryKind::Dir,
+            "symlink" => EntryKind::Symlink,
+            "submodule" => EntryKind::Submodule,
+            _ => EntryKind::File,
+        };
+        Entry {
+            name: item.name,
+            kind,
+            sha: item.sha,
+            size: item.size,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Tree {
+    tree: Vec<TreeItem>,
+    #[serde(default)]
+    truncated: bool,
+}
+
+/// An entry of a tree, whose `path` is relative to the listed tree.
+#[derive(Deserialize)]
+struct TreeItem {
+    path: String,
+    mode: String,
+    #[serde(rename = "type")]
+    kind: String,
+    sha: String,
+    #[serde(default)]
+    size: u64,
+}
+
+impl TreeItem {
+    fn entry(&self) -> Entry {
+        let kind = match (self.kind.as_str(), self.mode.as_str()) {
+            ("tree", _) => EntryKind::Dir,
+            ("commit", _) => EntryKind::Submodule,
+            (_, "120000") => EntryKind::Symlink,
+            _ => EntryKind::File,
+        };
+        Entry {
+            name: self
+                .path
+                .rsplit('/')
+                .next()
+                .unwrap_or(&self.path)
+                .to_string(),
+            kind,
+            sha: self.sha.clone(),
+            size: self.size,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RepoInfo {
+    default_branch: String,
+    /// Size of the repository's git data in KiB, without LFS objects.
+    #[serde(default)]
+    size: u64,
+}
+
+/// A client for the REST API, authenticated with `token` unless it is empty.
+fn api_client(token: &str) -> Result<Client, StorageError> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static("application/vnd.github+json"),
+    );
+    headers.insert(
+        "x-github-api-version",
+        HeaderValue::from_static(API_VERSION),
+    );
+    let auth = if token.is_empty() {
+        HttpAuth::None
+    } else {
+        HttpAuth::Bearer {
+            token: token.to_string(),
+        }
+    };
+    http::build_client(http::client_builder(&auth, headers)?)
+}
+
+/// Size in bytes of the git data of `owner/repo`, as GitHub reports it.
+pub fn repository_size(owner: &str, repo: &str, token: &str) -> Result<u64, StorageError> {
+    let config = GitHubApiConfig {
+        owner: owner.to_string(),
+        repo: repo.to_string(),
+        branch: None,
+        token: token.to_string(),
+    };
+    let info: RepoInfo = parse_json(http::send_retrying(
+        api_client(token)?.get(config.api_url("")),
+        &format!("{}/{}", owner, repo),
+    )?)?;
+    Ok(info.size * 1024)
+}
+
+#[derive(Deserialize)]
+struct BranchInfo {
+    commit: BranchCommit,
+}
+
+#[derive(Deserialize)]
+struct BranchCommit {
+    sha: String,
+    commit: CommitDetail,
+}
+
+#[derive(Deserialize)]
+struct CommitDetail {
+    tree: ShaRef,
+}
+
+#[derive(Deserialize)]
+struct ShaRef {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct LfsBatchResponse {
+    objects: Vec<LfsObject>,
+}
+
+#[derive(Deserialize)]
+struct LfsObject {
+    #[serde(default)]
+    actions: Option<LfsActions>,
+    #[serde(default)]
+    error: Option<LfsError>,
+}
+
+#[derive(Deserialize)]
+struct LfsActions {
+    download: LfsAction,
+}
+
+#[derive(Deserialize)]
+struct LfsAction {
+    href: String,
+    #[serde(default)]
+    header: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct LfsError {
+    code: u16,
+    message: String,
+}
+
+/// The object an LFS pointer file stands for.
+#[derive(Debug, PartialEq)]
+struct LfsPointer {
+    oid: String,
+    size: u64,
+}
+
+fn parse_lfs_pointer(data: &[u8]) -> Option<LfsPointer> {
+    let text = std::str::from_utf8(data).ok()?;
+    if !text.starts_with("version https://git-lfs.github.com/spec/") {
+        return None;
+    }
+    let mut oid = None;
+    let mut size = None;
+    for line in text.lines() {
+        if let Some(value) = line.strip_prefix("oid sha256:") {
+            oid = Some(value.trim().to_string());
+        } else if let Some(value) = line.strip_prefix("size ") {
+            size = value.trim().parse().ok();
+        }
+    }
+    Some(LfsPointer {
+        oid: oid?,
+        size: size?,
+    })
+}
+
+fn parse_json<T: DeserializeOwned>(response: Response) -> Result<T, StorageError> {
+    serde_json::from_str(&response.text()?)
+        .map_err(|e| StorageError::Protocol(format!("Unexpected GitHub response: {}", e)))
+}
+
+fn read_only() -> StorageError {
+    StorageError::Unsupported(
+        "The GitHub API connection is read-only; connect over SSH to make changes".to_string(),
+    )
+}
+
+/// A file's bytes, fetched already when they were needed to tell an LFS
+/// pointer apart, or still to be requested.
+enum Content {
+    Fetched(Vec<u8>),
+    Request(Box<RequestBuilder>),
+}
+
+/// What connecting found out.
+struct Session {
+    client: Client,
+    /// Follows the links LFS hands out, which must not get the token.
+    downloads: Client,
+    branch: String,
+    head: String,
+    root_tree: String,
+}
+
+pub struct GitHubApiStorage {
+    config: GitHubApiConfig,
+    /// Set by `connect`; `None` while disconnected.
+    session: Option<Session>,
+    /// Listings by directory. Reads are pinned to one commit, so they never
+    /// go stale.
+    listings: Mutex<HashMap<RemotePath, Arc<Vec<Entry>>>>,
+    metrics: StorageMetrics,
+}
+
+impl GitHubApiStorage {
+    pub fn new(config: GitHubApiConfig) -> Self {
+        GitHubApiStorage {
+            config,
+            session: None,
+            listings: Mutex::new(HashMap::new()),
+            metrics: StorageMetrics::default(),
+        }
+    }
+
+    pub fn config(&self) -> &GitHubApiConfig {
+        &self.config
+    }
+
+    fn session(&self) -> Result<&Session, StorageError> {
+        self.session.as_ref().ok_or(StorageError::NotConnected)
+    }
+
+    fn api_get(&self, rest: &str, what: &str) -> Result<Response, StorageError> {
+        let session = self.session()?;
+        http::send_retrying(session.client.get(self.config.api_url(rest)), what)
+    }
+
+    /// The entries of the directory `dir`, from the contents API or, when
+    /// it holds more than that lists, the trees API.
+    fn listing(&self, dir: &RemotePath) -> Result<Arc<Vec<Entry>>, StorageError> {
+        if let Some(listing) = self.listings.lock()?.get(dir) {
+            return Ok(listing.clone());
+        }
+        let head = &self.session()?.head;
+        let rest = format!("contents{}?ref={}", http::encode_path(dir.as_str()), head);
+        let value: serde_json::Value = parse_json(self.api_get(&rest, dir.as_str())?)?;
+        if !value.is_array() {
+            return Err(StorageError::InvalidInput(format!(
+                "Not a directory: {}",
+                dir
+            )));
+        }
+        let items: Vec<ContentItem> = serde_json::from_value(value)
+            .map_err(|e| StorageError::Protocol(format!("Unexpected GitHub response: {}", e)))?;
+        let mut entries: Vec<Entry> = items.into_iter().map(Entry::from).collect();
+        if entries.len() >= CONTENTS_LIST_LIMIT {
+            let rest = format!("git/trees/{}", self.tree_sha(dir)?);
+            let tree: Tree = parse_json(self.api_get(&rest, dir.as_str())?)?;
+            entries = tree.tree.iter().map(TreeItem::entry).collect();
+        }
+        let listing = Arc::new(entries);
+        self.listings.lock()?.insert(dir.clone(), listing.clone());
+        Ok(listing)
+    }
+
+    /// The entry for `path`, looked up in its parent's listing.
+    fn entry(&self, path: &RemotePath) -> Result<Entry, StorageError> {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(Entry {
+                name: String::new(),
+                kind: EntryKind::Dir,
+                sha: self.session()?.root_tree.clone(),
+                size: 0,
+            });
+        };
+        self.listing(&parent)?
+            .iter()
+            .find(|entry| entry.name == name)
+            .cloned()
+            .ok_or_else(|| StorageError::NotFound(path.to_string()))
+    }
+
+    fn tree_sha(&self, dir: &RemotePath) -> Result<String, StorageError> {
+        let entry = self.entry(dir)?;
+        if entry.kind != EntryKind::Dir {
+            return Err(StorageError::InvalidInput(format!(
+                "Not a directory: {}",
+                dir
+            )));
+        }
+        Ok(entry.sha)
+    }
+
+    /// Everything below `dir` with paths relative to it, from a single
+    /// recursive trees request. `None` when GitHub truncated the tree.
+    fn subtree(&self, dir: &RemotePath) -> Result<Option<Vec<(String, Entry)>>, StorageError> {
+        let rest = format!("git/trees/{}?recursive=1", self.tree_sha(dir)?);
+        let tree: Tree = parse_json(self.api_get(&rest, dir.as_str())?)?;
+        if tree.truncated {
+            return Ok(None);
+        }
+        Ok(Some(
+            tree.tree
+                .iter()
+                .map(|item| (item.path.clone(), item.entry()))
+                .collect(),
+        ))
+    }
+
+    fn file_info(&self, path: &RemotePath, entry: &Entry) -> FileInfo {
+        let is_dir = entry.kind == EntryKind::Dir;
+        FileInfo {
+            name: entry.name.clone(),
+            path: path.to_string(),
+            size: if is_dir { 0 } else { entry.size },
+            is_dir,
+            is_symlink: entry.kind == EntryKind::Symlink,
+            link_target: None,
+            modified: None,
+            mime_type: if entry.kind == EntryKind::File {
+                storage::detect_mime_type(&entry.name)
+            } else {
+                None
+            },
+            thumbnail: None,
+            permissions: None,
+            owner: None,
+            extra: BTreeMap::from([(ABSOLUTE_PATH_KEY.to_string(), path.to_string())]),
+        }
+    }
+
+    /// The bytes of the file at `path`. Files small enough to be LFS
+    /// pointers are fetched to check, and pointers resolved to the object
+    /// they stand for.
+    fn content(&self, path: &RemotePath) -> Result<Content, StorageError> {
+        let session = self.session()?;
+        let entry = self.entry(path)?;
+        match entry.kind {
+            EntryKind::Dir => return Err(StorageError::InvalidInput("is a directory".to_string())),
+            EntryKind::Submodule => {
+                return Err(StorageError::Unsupported(format!(
+                    "{} is a submodule",
+                    path
+                )))
+            }
+            EntryKind::File | EntryKind::Symlink => {}
+        }
+        // The contents API stops at 1 MB; blobs go up to 100 MB.
+        let rest = if entry.size > CONTENTS_MAX_SIZE {
+            format!("git/blobs/{}", entry.sha)
+        } else {
+            format!(
+                "contents{}?ref={}",
+                http::encode_path(path.as_str()),
+                session.head
+            )
+        };
+        let request = session
+            .client
+            .get(self.config.api_url(&rest))
+            .header(ACCEPT, RAW_MEDIA_TYPE);
+        if entry.size > LFS_POINTER_MAX_SIZE || entry.kind == EntryKind::Symlink {
+            return Ok(Content::Request(Box::new(request)));
+        }
+        let data = http::send_retrying(request, path.as_str())?
+            .bytes()?
+            .to_vec();
+        match parse_lfs_pointer(&data) {
+            Some(pointer) => Ok(Content::Request(Box::new(
+                self.lfs_download(&pointer, path)?,
+            ))),
+            None => Ok(Content::Fetched(data)),
+        }
+    }
+
+    /// Asks the LFS batch API where the object of `pointer` is.
+    fn lfs_download(
+        &self,
+        pointer: &LfsPointer,
+        path: &RemotePath,
+    ) -> Result<RequestBuilder, StorageError> {
+        let session = self.session()?;
+        let url = format!(
+            "https://github.com/{}/{}.git/info/lfs/objects/batch",
+            http::encode_path(&self.config.owner),
+            http::encode_path(&self.config.repo)
+        );
+        let body = serde_json::json!({
+            "operation": "download",
+            "transfers": ["basic"],
+            "objects": [{"oid": pointer.oid, "size": pointer.size}],
+            "ref": {"name": format!("refs/heads/{}", session.branch)},
+        });
+        let mut request = session
+            .client
+            .post(url)
+            .header(ACCEPT, LFS_MEDIA_TYPE)
+            .header(CONTENT_TYPE, LFS_MEDIA_TYPE)
+            .body(body.to_string());
+        if !self.config.token.is_empty() {
+            request = request.basic_auth("x-access-token", Some(&self.config.token));
+        }
+        let batch: LfsBatchResponse = parse_json(http::send_retrying(request, path.as_str())?)?;
+        let object = batch
+            .objects
+            .into_iter()
+            .next()
+            .ok_or_else(|| StorageError::Protocol("Empty LFS batch response".to_string()))?;
+        if let Some(error) = object.error {
+            return Err(match error.code {
+                404 | 410 => StorageError::NotFound(format!("LFS object of {}", path)),
+                401 | 403 => StorageError::PermissionDenied(error.message),
+                _ => StorageError::Protocol(format!("LFS: {}", error.message)),
+            });
+        }
+        let action = object
+            .actions
+            .map(|actions| actions.download)
+            .ok_or_else(|| StorageError::Protocol("LFS offered no download".to_string()))?;
+        let mut download = session.downloads.get(action.href);
+        for (name, value) in action.header {
+            download = download.header(name, value);
+        }
+        Ok(download)
+    }
+}
+
+impl Storage for GitHubApiStorage {
+    fn connect(&mut self) -> Result<(), StorageError> {
+        if self.config.owner.is_empty() || self.config.repo.is_empty() {
+            return Err(StorageError::InvalidInput(
+                "An owner and repository are required".to_string(),
+            ));
+        }
+        let client = api_client(&self.config.token)?;
+        let repo = format!("{}/{}", self.config.owner, self.config.repo);
+        let branch = match self.config.branch.as_deref() {
+            Some(branch) if !branch.is_empty() => branch.to_string(),
+            _ => {
+                let info: RepoInfo = parse_json(http::send_retrying(
+                    client.get(self.config.api_url("")),
+                    &repo,
+                )?)?;
+                info.default_branch
+            }
+        };
+        let url = self
+            .config
+            .api_url(&format!("branches/{}", http::encode_path(&branch)));
+        let info: BranchInfo = parse_json(http::send_retrying(
+            client.get(url),
+            &format!("branch {} of {}", branch, repo),
+        )?)?;
+        log::info!(
+            "Connected to GitHub repository {} at {} ({})",
+            repo,
+            branch,
+            info.commit.sha
+        );
+        self.listings.lock()?.clear();
+        self.session = Some(Session {
+            client,
+            downloads: http::client(&HttpAuth::None, false)?,
+            branch,
+            head: info.commit.sha,
+            root_tree: info.commit.commit.tree.sha,
+        });
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.session = None;
+    }
+
+    fn is_connected(&self) -> bool {
+        self.session.is_some()
+    }
+
+    fn ping(&self) -> Result<(), StorageError> {
+        // Checking the rate limit does not count against it.
+        let session = self.session()?;
+        http::send_retrying(
+            session.client.get(format!("{}/rate_limit", API_URL)),
+            "GitHub",
+        )?;
+        Ok(())
+    }
+
+    fn metrics(&self) -> &StorageMetrics {
+        &self.metrics
+    }
+
+    fn list_directory_page(
+        &self,
+        path: &str,
+        options: &ListOptions,
+    ) -> Result<(Vec<FileInfo>, usize), StorageError> {
+        let dir = self.path_translator().to_remote(path);
+        let mut files: Vec<FileInfo> = self
+            .listing(&dir)?
+            .iter()
+            .filter(|entry| {
+                storage::is_listed(&entry.name, options.show_hidden, &[])
+                    && options
+                        .filter
+                        .matches_entry(&entry.name, entry.kind == EntryKind::Dir)
+            })
+            .map(|entry| self.file_info(&dir.join(&entry.name), entry))
+            .collect();
+        storage::sort_files(&mut files, options.sort_by, options.sort_order);
+        let total = files.len();
+        Ok((
+            storage::paginate(&files, options.offset, options.limit),
+            total,
+        ))
+    }
+
+    fn list_directory_recursive(
+        &self,
+        path: &str,
+        max_depth: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<RecursiveListing, StorageError> {
+        let dir = self.path_translator().to_remote(path);
+        let Some(entries) = self.subtree(&dir)? else {
+            return storage::list_by_walking(self, path, max_depth, cancelled);
+        };
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(StorageError::Cancelled);
+        }
+        let files: Vec<FileInfo> = entries
+            .iter()
+            .filter(|(relative, _)| max_depth == 0 || relative.split('/').count() <= max_depth)
+            .take(MAX_RECURSIVE_ENTRIES + 1)
+            .map(|(relative, entry)| self.file_info(&dir.join(relative), entry))
+            .collect();
+        Ok(RecursiveListing::capped(files))
+    }
+
+    fn search(
+        &self,
+        root: &str,
+        pattern: &str,
+        case_sensitive: bool,
+        limit: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<SearchResult, StorageError> {
+        let dir = self.path_translator().to_remote(root);
+        let Some(entries) = self.subtree(&dir)? else {
+            return storage::search_by_walking(
+                self,
+                root,
+                pattern,
+                case_sensitive,
+                limit,
+                cancelled,
+            );
+        };
+        let files = entries
+            .iter()
+            .filter(|(_, entry)| storage::name_matches(&entry.name, pattern, case_sensitive))
+            .take(limit.saturating_add(1))
+            .map(|(relative, entry)| self.file_info(&dir.join(relative), entry))
+            .collect();
+        Ok(SearchResult::capped(files, limit))
+    }
+
+    fn search_contents(
+        &self,
+        _root: &str,
+        _query: &str,
+        _regex: bool,
+        _include: Option<&str>,
+        _limit: usize,
+    ) -> Result<ContentSearchResult, StorageError> {
+        Err(StorageError::Unsupported(
+            "Content search is not supported over the GitHub API".to_string(),
+        ))
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, StorageError> {
+        let path = self.path_translator().to_remote(path);
+        metrics::metered_read(&self.metrics, || match self.content(&path)? {
+            Content::Fetched(data) => Ok(data),
+            Content::Request(request) => Ok(http::send_retrying(*request, path.as_str())?
+                .bytes()?
+                .to_vec()),
+        })
+    }
+
+    fn read_file_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, bool), StorageError> {
+        let start = Instant::now();
+        let path = self.path_translator().to_remote(path);
+        let (data, at_end) = match self.content(&path)? {
+            Content::Fetched(data) => {
+                let start = (offset as usize).min(data.len());
+                let end = start.saturating_add(length as usize).saturating_add(1);
+                let range = data[start..end.min(data.len())].to_vec();
+                storage::finish_range_read(range, length)
+            }
+            Content::Request(request) => http::read_range(*request, path.as_str(), offset, length)?,
+        };
+        self.metrics.record_read(data.len() as u64, start.elapsed());
+        Ok((data, at_end))
+    }
+
+    fn read_files(&self, paths: &[String]) -> Result<Vec<FileReadOutcome>, StorageError> {
+        let mut outcomes = Vec::with_capacity(paths.len());
+        for path in paths {
+            outcomes.push(match self.read_file(path) {
+                Err(e) if e.is_transport() || matches!(e, StorageError::NotConnected) => {
+                    return Err(e)
+                }
+                result => result.map_err(|e| e.to_string()),
+            });
+        }
+        Ok(outcomes)
+    }
+
+    fn read_file_streamed(
+        &self,
+        path: &str,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        let start = Instant::now();
+        let path = self.path_translator().to_remote(path);
+        let (read, completed) = match self.content(&path)? {
+            Content::Fetched(data) => {
+                let total = Some(data.len() as u64);
+                (data.len() as u64, data.is_empty() || on_chunk(&data, total))
+            }
+            Content::Request(request) => {
+                http::stream_body(http::send_retrying(*request, path.as_str())?, on_chunk)?
+            }
+        };
+        self.metrics.record_read(read, start.elapsed());
+        Ok(completed)
+    }
+
+    fn write_file(&self, _path: &str, _data: &[u8]) -> Result<(), StorageError> {
+        Err(read_only())
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        match self.entry(&self.path_translator().to_remote(path)) {
+            Ok(_) => Ok(true),
+            Err(StorageError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn stat(&self, path: &str) -> Result<FileInfo, StorageError> {
+        let path = self.path_translator().to_remote(path);
+        Ok(self.file_info(&path, &self.entry(&path)?))
+    }
+
+    fn set_permissions(&self, _path: &str, _mode: u32) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn set_modified(&self, _path: &str, _mtime: u64) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn directory_size(&self, path: &str) -> Result<DirectoryUsage, StorageError> {
+        let Some(entries) = self.subtree(&self.path_translator().to_remote(path))? else {
+            return storage::size_by_walking(self, path);
+        };
+        let mut usage = DirectoryUsage::default();
+        for (_, entry) in &entries {
+            if entry.kind == EntryKind::Dir {
+                usage.dir_count += 1;
+            } else {
+                usage.file_count += 1;
+                usage.total_bytes += entry.size;
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Nothing can be run on GitHub's side, so the caller builds zips
+    /// itself.
+    fn archive_directory(
+        &self,
+        _path: &str,
+        format: ArchiveFormat,
+        _on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        Err(StorageError::ToolMissing(format.remote_tool()))
+    }
+
+    fn extract_archive(
+        &self,
+        _path: &str,
+        _destination: &str,
+    ) -> Result<Vec<String>, StorageError> {
+        Err(read_only())
+    }
+
+    fn checksum(
+        &self,
+        path: &str,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<FileChecksum, StorageError> {
+        let info = self.stat(path)?;
+        if info.is_dir {
+            return Err(StorageError::InvalidInput("is a directory".to_string()));
+        }
+        Ok(FileChecksum {
+            algorithm,
+            digest: checksum::hash_streamed(self, path, algorithm)?,
+            size: info.size,
+        })
+    }
+
+    fn checksums(
+        &self,
+        paths: &[String],
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<Vec<Option<String>>, StorageError> {
+        Ok(paths
+            .iter()
+            .map(|p| checksum::hash_streamed(self, p, algorithm).ok())
+            .collect())
+    }
+
+    fn delete_file(&self, _path: &str) -> Result<(), StorageError> {
+        Err(read_only())
+    }
+
+    fn delete_directory(
+        &self,
+        _path: &str,
+        _recursive: bool,
+    ) -> Result<DeleteDirectoryResult, StorageError> {
+        Err(read_only())
+    }
+
+    fn rename(&self, _from: &str, _to: &str) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn copy_file(&self, _from: &str, _to: &str) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn create_directory(
+        &self,
+        _path: &str,
+        _recursive: bool,
+    ) -> Result<CreateDirectoryResult, StorageError> {
+        Err(read_only())
+    }
+
+    fn get_file_thumbnail(
+        &self,
+        path: &str,
+        max_size: u32,
+        options: ThumbnailOptions,
+    ) -> Result<Thumbnail, StorageError> {
+        thumbnails::fetched_file_thumbnail(self, path, max_size, options)
+            .map_err(StorageError::from)
+    }
+
+    fn get_root_path(&self) -> String {
+        "/".to_string()
+    }
+
+    fn path_translator(&self) -> PathTranslator {
+        PathTranslator::new("/")
+    }
+
+    fn storage_type(&self) -> StorageType {
+        StorageType::GitHubApi
+    }
+
+    fn connection_id(&self) -> String {
+        let branch = match &self.session {
+            Some(session) => session.branch.as_str(),
+            None => self.config.branch.as_deref().unwrap_or_default(),
+        };
+        format!(
+            "github-api:{}/{}#{}",
+            self.config.owner, self.config.repo, branch
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GET /repos/octo/photos/contents/albums, trimmed to the fields used.
+    const CONTENTS_LISTING: &str = r#"[
+      {"name": "2023", "path": "albums/2023", "sha": "4b825dc642cb6eb9a060e54bf8d69288fbee4904",
+       "size": 0, "type": "dir", "download_url": null},
+      {"name": "cover.jpg", "path": "albums/cover.jpg", "sha": "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391",
+       "size": 184320, "type": "file",
+       "download_url": "https://raw.githubusercontent.com/octo/photos/main/albums/cover.jpg"},
+      {"name": "latest", "path": "albums/latest", "sha": "9daeafb9864cf43055ae93beb0afd6c7d144bfa4",
+       "size": 4, "type": "symlink", "download_url": null}
+    ]"#;
+
+    /// GET /repos/octo/photos/git/trees/{sha}?recursive=1.
+    const RECURSIVE_TREE: &str = r#"{
+      "sha": "1f4a3c6", "url": "https://api.github.com/repos/octo/photos/git/trees/1f4a3c6",
+      "tree": [
+        {"path": "2023", "mode": "040000", "type": "tree", "sha": "a1"},
+        {"path": "2023/beach.raw", "mode": "100644", "type": "blob", "sha": "b2", "size": 25165824},
+        {"path": "latest", "mode": "120000", "type": "blob", "sha": "c3", "size": 4},
+        {"path": "vendor", "mode": "160000", "type": "commit", "sha": "d4"}
+      ],
+      "truncated": false
+    }"#;
+
+    fn storage() -> GitHubApiStorage {
+        GitHubApiStorage::new(GitHubApiConfig {
+            owner: "octo".to_string(),
+            repo: "photos".to_string(),
+            branch: None,
+            token: "ghp_secret".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_contents_listing_entries() {
+        let items: Vec<ContentItem> = serde_json::from_str(CONTENTS_LISTING).unwrap();
+        let entries: Vec<Entry> = items.into_iter().map(Entry::from).collect();
+        let kinds: Vec<EntryKind> = entries.iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, [EntryKind::Dir, EntryKind::File, EntryKind::Symlink]);
+
+        let storage = storage();
+        let dir = RemotePath::new("/albums");
+        let cover = storage.file_info(&dir.join(&entries[1].name), &entries[1]);
+        assert_eq!(cover.path, "/albums/cover.jpg");
+        assert_eq!(cover.size, 184320);
+        assert_eq!(cover.mime_type.as_deref(), Some("image/jpeg"));
+        let album = storage.file_info(&dir.join(&entries[0].name), &entries[0]);
+        assert!(album.is_dir && album.mime_type.is_none());
+        assert!(
+            storage
+                .file_info(&dir.join("latest"), &entries[2])
+                .is_symlink
+        );
+    }
+
+    #[test]
+    fn test_tree_entries() {
+        let tree: Tree = serde_json::from_str(RECURSIVE_TREE).unwrap();
+        assert!(!tree.truncated);
+        let entries: Vec<Entry> = tree.tree.iter().map(TreeItem::entry).collect();
+        assert_eq!(entries[0].kind, EntryKind::Dir);
+        assert_eq!(entries[1].name, "beach.raw");
+        assert_eq!(entries[1].size, 25165824);
+        assert_eq!(entries[2].kind, EntryKind::Symlink);
+        assert_eq!(entries[3].kind, EntryKind::Submodule);
+    }
+
+    #[test]
+    fn test_parse_lfs_pointer() {
+        let pointer = b"version https://git-lfs.github.com/spec/v1\n\
+            oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+            size 12345\n";
+        assert_eq!(
+            parse_lfs_pointer(pointer),
+            Some(LfsPointer {
+                oid: "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393".to_string(),
+                size: 12345,
+            })
+        );
+        assert_eq!(parse_lfs_pointer(b"\xff\xd8\xff\xe0 not a pointer"), None);
+        assert_eq!(
+            parse_lfs_pointer(b"version https://git-lfs.github.com/spec/v1\nsize 1\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_api_urls_and_debug() {
+        let storage = storage();
+        assert_eq!(
+            storage.config().api_url(&format!(
+                "contents{}?ref=abc",
+                http::encode_path("/my album/é.jpg")
+            )),
+            "https://api.github.com/repos/octo/photos/contents/my%20album/%C3%A9.jpg?ref=abc"
+        );
+        assert!(!format!("{:?}", storage.config()).contains("ghp_secret"));
+        assert_eq!(storage.connection_id(), "github-api:octo/photos#");
+        assert_eq!(
+            storage.write_file("/a.jpg", b"").unwrap_err().code(),
+            "UNSUPPORTED"
+        );
+        assert_eq!(storage.stat("/a.jpg").unwrap_err().code(), "NOT_CONNECTED");
+    }
+}
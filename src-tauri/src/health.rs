@@ -0,0 +1,96 @@
+//! Connection health checks for the connectivity indicator: a timed round
+//! trip to the server, on demand or on a timer.
+
+use crate::commands::BackendSlot;
+use crate::connection_events::EventSink;
+use crate::error::StorageError;
+use serde::Serialize;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+pub const HEALTH_EVENT: &str = "storage://health";
+/// A ping taking longer than this counts as a dead connection.
+pub const PING_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_HEALTH_INTERVAL: Duration = Duration::from_secs(30);
+pub const MIN_HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Clone)]
+pub struct HealthStatus {
+    pub ok: bool,
+    /// Round trip time, or how long it took to fail.
+    pub latency_ms: u64,
+    pub error: Option<StorageError>,
+}
+
+/// Payload of `storage://health`.
+#[derive(Debug, Serialize, Clone)]
+pub struct HealthEvent {
+    pub slot: BackendSlot,
+    #[serde(flatten)]
+    pub status: HealthStatus,
+}
+
+/// Times `ping`, giving up after `timeout` with [`StorageError::Timeout`].
+/// A ping stuck on a dead socket is left to finish on its own thread.
+pub fn timed_ping(
+    ping: impl FnOnce() -> Result<(), StorageError> + Send + 'static,
+    timeout: Duration,
+) -> HealthStatus {
+    let start = Instant::now();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(ping());
+    });
+    let result = rx
+        .recv_timeout(timeout)
+        .unwrap_or(Err(StorageError::Timeout));
+    HealthStatus {
+        ok: result.is_ok(),
+        latency_ms: start.elapsed().as_millis() as u64,
+        error: result.err(),
+    }
+}
+
+pub fn emit_health(sink: &dyn EventSink, slot: BackendSlot, status: HealthStatus) {
+    if let Ok(payload) = serde_json::to_value(HealthEvent { slot, status }) {
+        sink.send(HEALTH_EVENT, payload);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection_events::tests::Recorder;
+
+    #[test]
+    fn test_timed_ping() {
+        let status = timed_ping(|| Ok(()), PING_TIMEOUT);
+        assert!(status.ok && status.error.is_none());
+
+        let status = timed_ping(
+            || {
+                std::thread::sleep(Duration::from_millis(500));
+                Ok(())
+            },
+            Duration::from_millis(50),
+        );
+        assert!(!status.ok);
+        assert_eq!(status.error.unwrap().code(), "TIMEOUT");
+        assert!(status.latency_ms < 500);
+    }
+
+    #[test]
+    fn test_health_event_payload() {
+        let recorder = Recorder::default();
+        let status = timed_ping(|| Err(StorageError::NotConnected), PING_TIMEOUT);
+        emit_health(&recorder, BackendSlot::Primary, status);
+
+        let events = recorder.0.lock().unwrap();
+        let (event, payload) = &events[0];
+        assert_eq!(event, HEALTH_EVENT);
+        assert_eq!(payload["slot"], "primary");
+        assert_eq!(payload["ok"], false);
+        assert!(payload["latency_ms"].is_u64());
+        assert_eq!(payload["error"]["code"], "NOT_CONNECTED");
+    }
+}
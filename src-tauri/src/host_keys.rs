@@ -0,0 +1,239 @@
+//! Server host key verification against OpenSSH `known_hosts` files.
+
+use crate::utils;
+use base64::Engine;
+use ssh2::{CheckResult, HashType, HostKeyType, KnownHostFileKind, KnownHosts, Session};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Error codes reported to the frontend by the connect commands.
+pub const HOST_KEY_UNKNOWN: &str = "HOST_KEY_UNKNOWN";
+pub const HOST_KEY_MISMATCH: &str = "HOST_KEY_MISMATCH";
+
+/// File in the app data directory holding keys accepted in the app.
+pub const APP_KNOWN_HOSTS_FILE: &str = "known_hosts";
+
+/// The host key a server presented during the handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfferedHostKey {
+    pub host: String,
+    pub port: u16,
+    pub key_type: &'static str,
+    pub key: Vec<u8>,
+    /// `SHA256:<base64>`, as printed by `ssh-keygen -l`.
+    pub fingerprint: String,
+}
+
+impl OfferedHostKey {
+    /// The entry `ssh` itself would write for this key.
+    pub fn known_hosts_line(&self) -> String {
+        let pattern = if self.port == 22 {
+            self.host.clone()
+        } else {
+            format!("[{}]:{}", self.host, self.port)
+        };
+        format!(
+            "{} {} {}\n",
+            pattern,
+            self.key_type,
+            utils::base64_encode(&self.key)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum HostKeyError {
+    /// No known_hosts entry exists for the host yet.
+    Unknown(OfferedHostKey),
+    /// The host is known under a different key.
+    Mismatch(OfferedHostKey),
+}
+
+impl HostKeyError {
+    pub fn offered(&self) -> &OfferedHostKey {
+        match self {
+            HostKeyError::Unknown(offered) | HostKeyError::Mismatch(offered) => offered,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            HostKeyError::Unknown(_) => HOST_KEY_UNKNOWN,
+            HostKeyError::Mismatch(_) => HOST_KEY_MISMATCH,
+        }
+    }
+}
+
+impl fmt::Display for HostKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostKeyError::Unknown(offered) => write!(
+                f,
+                "The authenticity of host {} can't be established ({} key {})",
+                offered.host, offered.key_type, offered.fingerprint
+            ),
+            HostKeyError::Mismatch(offered) => write!(
+                f,
+                "Host key for {} has changed and is now {} {}; refusing to connect",
+                offered.host, offered.key_type, offered.fingerprint
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HostKeyError {}
+
+fn key_type_name(key_type: HostKeyType) -> &'static str {
+    match key_type {
+        HostKeyType::Rsa => "ssh-rsa",
+        HostKeyType::Dss => "ssh-dss",
+        HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+        HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+        HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+        HostKeyType::Ed25519 => "ssh-ed25519",
+        HostKeyType::Unknown => "unknown",
+    }
+}
+
+fn format_fingerprint(hash: &[u8]) -> String {
+    format!(
+        "SHA256:{}",
+        base64::engine::general_purpose::STANDARD_NO_PAD.encode(hash)
+    )
+}
+
+/// The user's own `~/.ssh/known_hosts`.
+fn user_known_hosts() -> Option<PathBuf> {
+    utils::home_dir().map(|home| home.join(".ssh").join("known_hosts"))
+}
+
+/// Checks the key offered in `session`'s handshake against the user's
+/// known_hosts and `app_known_hosts`. Call right after `handshake()`.
+pub fn verify_host_key(
+    session: &Session,
+    host: &str,
+    port: u16,
+    app_known_hosts: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (key, key_type) = session
+        .host_key()
+        .ok_or("The server did not offer a host key")?;
+    let hash = session
+        .host_key_hash(HashType::Sha256)
+        .ok_or("Could not hash the server's host key")?;
+    let offered = OfferedHostKey {
+        host: host.to_string(),
+        port,
+        key_type: key_type_name(key_type),
+        key: key.to_vec(),
+        fingerprint: format_fingerprint(hash),
+    };
+
+    let mut known_hosts = session.known_hosts()?;
+    for file in user_known_hosts()
+        .iter()
+        .map(PathBuf::as_path)
+        .chain(app_known_hosts)
+    {
+        if let Ok(content) = std::fs::read_to_string(file) {
+            read_known_hosts(&mut known_hosts, &content);
+        }
+    }
+
+    match known_hosts.check_port(host, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound => Err(HostKeyError::Unknown(offered).into()),
+        CheckResult::Mismatch => Err(HostKeyError::Mismatch(offered).into()),
+        CheckResult::Failure => Err("Host key verification failed".into()),
+    }
+}
+
+/// Adds the entries of a known_hosts file one line at a time. libssh2's own
+/// reader gives up at the first line it cannot parse, such as `@revoked`
+/// markers or security key types, so those are skipped here like OpenSSH
+/// does.
+fn read_known_hosts(known_hosts: &mut KnownHosts, content: &str) {
+    for line in content.lines() {
+        let _ = known_hosts.read_str(line, KnownHostFileKind::OpenSSH);
+    }
+}
+
+/// Appends `offered` to the app-managed known_hosts file.
+pub fn remember_host_key(path: &Path, offered: &OfferedHostKey) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(offered.known_hosts_line().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offered(port: u16) -> OfferedHostKey {
+        OfferedHostKey {
+            host: "ec2-3-15-99-1.us-east-2.compute.amazonaws.com".to_string(),
+            port,
+            key_type: "ssh-ed25519",
+            key: b"key".to_vec(),
+            fingerprint: format_fingerprint(&[0xab; 32]),
+        }
+    }
+
+    #[test]
+    fn test_format_fingerprint_has_no_padding() {
+        let fingerprint = format_fingerprint(&[0; 32]);
+        assert_eq!(
+            fingerprint,
+            "SHA256:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"
+        );
+    }
+
+    #[test]
+    fn test_known_hosts_line() {
+        assert_eq!(
+            offered(22).known_hosts_line(),
+            "ec2-3-15-99-1.us-east-2.compute.amazonaws.com ssh-ed25519 a2V5\n"
+        );
+        assert_eq!(
+            offered(2222).known_hosts_line(),
+            "[ec2-3-15-99-1.us-east-2.compute.amazonaws.com]:2222 ssh-ed25519 a2V5\n"
+        );
+    }
+
+    #[test]
+    fn test_unparsable_lines_do_not_hide_later_entries() {
+        let mut key = b"\0\0\0\x0bssh-ed25519\0\0\0\x20".to_vec();
+        key.extend([7; 32]);
+        let content = format!(
+            "@cert-authority *.example.com ssh-ed25519 {key}\n\
+             @revoked bad.example.com ssh-ed25519 {key}\n\
+             sk.example.com sk-ssh-ed25519@openssh.com AAAAGnNrLXNzaC1lZDI1NTE5QG9wZW5zc2guY29t\n\
+             not a known_hosts line\n\
+             host.example.com ssh-ed25519 {key}\n",
+            key = utils::base64_encode(&key)
+        );
+        let session = Session::new().unwrap();
+        let mut known_hosts = session.known_hosts().unwrap();
+        read_known_hosts(&mut known_hosts, &content);
+        assert!(matches!(
+            known_hosts.check_port("host.example.com", 22, &key),
+            CheckResult::Match
+        ));
+    }
+
+    #[test]
+    fn test_remember_host_key_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(APP_KNOWN_HOSTS_FILE);
+        remember_host_key(&path, &offered(22)).unwrap();
+        remember_host_key(&path, &offered(2222)).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 2);
+    }
+}
@@ -0,0 +1,451 @@
+//! Plumbing shared by the backends speaking HTTP: the blocking client,
+//! escaping paths into URLs, and turning failed requests into
+//! [`StorageError`]s.
+
+use crate::error::StorageError;
+use crate::storage::{self, ChunkCallback, STREAM_CHUNK_SIZE};
+use crate::utils;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, RANGE, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::{self, Read};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Limit for establishing a connection.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Limit for each read or write of a request, not for the whole transfer.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Attempts at a rate-limited request before giving up.
+pub const RATE_LIMIT_ATTEMPTS: u32 = 4;
+/// Longest wait for a rate limit to lift; longer ones are reported instead.
+pub const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+/// Escaped in a path segment: everything but RFC 3986's unreserved
+/// characters.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// How requests authenticate.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HttpAuth {
+    #[default]
+    None,
+    Basic {
+        username: String,
+        password: String,
+    },
+    Bearer {
+        token: String,
+    },
+}
+
+impl HttpAuth {
+    /// The `Authorization` header value, marked sensitive so it stays out of
+    /// logs.
+    fn header(&self) -> Result<Option<HeaderValue>, StorageError> {
+        let value = match self {
+            HttpAuth::None => return Ok(None),
+            HttpAuth::Basic { username, password } => format!(
+                "Basic {}",
+                utils::base64_encode(format!("{}:{}", username, password).as_bytes())
+            ),
+            HttpAuth::Bearer { token } => format!("Bearer {}", token),
+        };
+        let mut value = HeaderValue::from_str(&value).map_err(|_| {
+            StorageError::InvalidInput("Credentials contain invalid characters".to_string())
+        })?;
+        value.set_sensitive(true);
+        Ok(Some(value))
+    }
+}
+
+impl std::fmt::Debug for HttpAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpAuth::None => f.write_str("None"),
+            HttpAuth::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            HttpAuth::Bearer { .. } => f.debug_struct("Bearer").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// A client with the app's timeouts that sends `auth` with every request.
+/// `accept_invalid_certs` lets self-signed servers through.
+pub fn client(auth: &HttpAuth, accept_invalid_certs: bool) -> Result<Client, StorageError> {
+    build_client(
+        client_builder(auth, HeaderMap::new())?.danger_accept_invalid_certs(accept_invalid_certs),
+    )
+}
+
+/// Like [`client`], also sending `headers` with every request, for
+/// backends with more to configure.
+pub fn client_builder(
+    auth: &HttpAuth,
+    mut headers: HeaderMap,
+) -> Result<ClientBuilder, StorageError> {
+    if let Some(value) = auth.header()? {
+        headers.insert(AUTHORIZATION, value);
+    }
+    Ok(Client::builder()
+        .user_agent(concat!("iMAGE/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .default_headers(headers))
+}
+
+/// Finishes a builder from [`client_builder`].
+pub fn build_client(builder: ClientBuilder) -> Result<Client, StorageError> {
+    builder
+        .build()
+        .map_err(|e| StorageError::Internal(format!("Failed to create HTTP client: {}", e)))
+}
+
+/// Escapes the absolute `path` for a URL, segment by segment, so spaces,
+/// `#`, `?` and non-ASCII names survive.
+pub fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| utf8_percent_encode(segment, SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Undoes the escaping of a URL path.
+pub fn decode_path(path: &str) -> String {
+    percent_decode_str(path).decode_utf8_lossy().into_owned()
+}
+
+/// The still escaped path of `href`, which servers give either as a full
+/// URL or as just its path, without query or fragment.
+pub fn href_path(href: &str) -> &str {
+    let path = match href.find("://") {
+        Some(i) => {
+            let rest = &href[i + 3..];
+            rest.find('/').map_or("/", |j| &rest[j..])
+        }
+        None => href,
+    };
+    path.split(['?', '#']).next().unwrap_or(path)
+}
+
+/// Sends `request`, turning transport failures and error statuses into
+/// [`StorageError`]s. `what` names the resource in not-found errors.
+pub fn send(request: RequestBuilder, what: &str) -> Result<Response, StorageError> {
+    check_status(request.send()?, what)
+}
+
+/// Like [`send`], but waits and retries while the server says the client is
+/// rate-limited, backing off exponentially when it does not say for how
+/// long. Gives up with [`StorageError::RateLimited`] after
+/// [`RATE_LIMIT_ATTEMPTS`], or right away when the limit lifts only after
+/// [`MAX_RATE_LIMIT_WAIT`].
+pub fn send_retrying(request: RequestBuilder, what: &str) -> Result<Response, StorageError> {
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let retry = request.try_clone().ok_or_else(|| {
+            StorageError::Internal("Streamed requests cannot be retried".to_string())
+        })?;
+        let response = retry.send()?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let Some(wait) = rate_limit_wait(response.status(), response.headers(), now, backoff)
+        else {
+            return check_status(response, what);
+        };
+        let host = response
+            .url()
+            .host_str()
+            .unwrap_or("the server")
+            .to_string();
+        if attempt >= RATE_LIMIT_ATTEMPTS || wait > MAX_RATE_LIMIT_WAIT {
+            return Err(StorageError::RateLimited(format!(
+                "Rate limited by {}; try again in {}s",
+                host,
+                wait.as_secs().max(1)
+            )));
+        }
+        log::warn!("Rate limited by {}, retrying in {:?}", host, wait);
+        std::thread::sleep(wait);
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// How long to wait before retrying a response with `status` and `headers`
+/// at unix time `now`, or `None` when it is not a rate limit. Servers name
+/// the wait in Retry-After or, like GitHub, the end of the window in
+/// X-RateLimit-Reset; `backoff` is used when they do neither.
+pub fn rate_limit_wait(
+    status: StatusCode,
+    headers: &HeaderMap,
+    now: u64,
+    backoff: Duration,
+) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let exhausted = header("x-ratelimit-remaining") == Some("0");
+    let retry_after = headers
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs);
+    let limited = status == StatusCode::TOO_MANY_REQUESTS
+        || (status == StatusCode::FORBIDDEN && (exhausted || retry_after.is_some()));
+    if !limited {
+        return None;
+    }
+    let until_reset = header("x-ratelimit-reset")
+        .filter(|_| exhausted)
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(|reset| Duration::from_secs(reset.saturating_sub(now)));
+    Some(retry_after.or(until_reset).unwrap_or(backoff))
+}
+
+/// Passes successful responses through and classifies the rest.
+pub fn check_status(response: Response, what: &str) -> Result<Response, StorageError> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else {
+        Err(status_error(status, what))
+    }
+}
+
+/// The error for an unsuccessful `status` of a request about `what`.
+pub fn status_error(status: StatusCode, what: &str) -> StorageError {
+    match status {
+        StatusCode::UNAUTHORIZED => StorageError::AuthFailed {
+            reason: "the server rejected the credentials (HTTP 401)".to_string(),
+        },
+        StatusCode::FORBIDDEN => StorageError::PermissionDenied(what.to_string()),
+        StatusCode::NOT_FOUND | StatusCode::GONE => StorageError::NotFound(what.to_string()),
+        StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => StorageError::Timeout,
+        StatusCode::TOO_MANY_REQUESTS => {
+            StorageError::RateLimited("Rate limited by the server".to_string())
+        }
+        StatusCode::PAYLOAD_TOO_LARGE => {
+            StorageError::TooLarge(format!("{} is too large for the server", what))
+        }
+        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => {
+            StorageError::Unsupported(format!(
+                "The server does not support this operation (HTTP {})",
+                status
+            ))
+        }
+        _ => StorageError::Protocol(format!("HTTP {}", status)),
+    }
+}
+
+/// Classifies a request that got no response. Rejected certificates get
+/// [`StorageError::Tls`] so the user can be told to trust the server.
+pub fn transport_error(e: &reqwest::Error) -> StorageError {
+    let message = describe(e);
+    if is_certificate_error(e) {
+        StorageError::Tls(message)
+    } else if e.is_timeout() {
+        StorageError::Timeout
+    } else if e.is_builder() {
+        StorageError::InvalidInput(message)
+    } else if e.is_connect() || e.is_request() || e.is_body() {
+        StorageError::ConnectionLost(message)
+    } else {
+        StorageError::Protocol(message)
+    }
+}
+
+/// `e` and its causes, which reqwest leaves out of its own message.
+fn describe(e: &(dyn Error + 'static)) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// Whether the TLS layer refused the server's certificate. The TLS
+/// backends' error types differ by platform, so this goes by the message.
+fn is_certificate_error(e: &(dyn Error + 'static)) -> bool {
+    let mut current = Some(e);
+    while let Some(e) = current {
+        if e.to_string().to_lowercase().contains("certificate") {
+            return true;
+        }
+        current = e.source();
+    }
+    false
+}
+
+/// Hands the body of `response` to `on_chunk` in chunks of at most
+/// [`STREAM_CHUNK_SIZE`]. Returns the bytes read and whether the body was
+/// read to the end.
+pub fn stream_body(
+    mut response: Response,
+    on_chunk: &mut ChunkCallback<'_>,
+) -> Result<(u64, bool), StorageError> {
+    let total = response.content_length();
+    let mut buf = vec![0u8; STREAM_CHUNK_SIZE];
+    let mut read = 0u64;
+    loop {
+        let n = response.read(&mut buf)?;
+        if n == 0 {
+            return Ok((read, true));
+        }
+        read += n as u64;
+        if !on_chunk(&buf[..n], total) {
+            return Ok((read, false));
+        }
+    }
+}
+
+/// Reads up to `length` bytes at `offset` of what the GET `request`
+/// fetches, as [`crate::storage::Storage::read_file_range`] does. Servers
+/// ignoring the Range header have the skipped part read and dropped.
+pub fn read_range(
+    request: RequestBuilder,
+    what: &str,
+    offset: u64,
+    length: u64,
+) -> Result<(Vec<u8>, bool), StorageError> {
+    // One extra byte tells whether anything follows the range.
+    let last = offset.saturating_add(length);
+    let response = request
+        .header(RANGE, format!("bytes={}-{}", offset, last))
+        .send()?;
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Ok((Vec::new(), true));
+    }
+    let response = check_status(response, what)?;
+    let skip = if response.status() == StatusCode::PARTIAL_CONTENT {
+        0
+    } else {
+        offset
+    };
+    let mut body = response.take(skip.saturating_add(length).saturating_add(1));
+    io::copy(&mut (&mut body).take(skip), &mut io::sink())?;
+    let mut data = Vec::new();
+    body.read_to_end(&mut data)?;
+    Ok(storage::finish_range_read(data, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_escaping_round_trips() {
+        let cases = [
+            ("/", "/"),
+            ("/my photos/a b.jpg", "/my%20photos/a%20b.jpg"),
+            (
+                "/Fotos/Überblick 🌅.jpg",
+                "/Fotos/%C3%9Cberblick%20%F0%9F%8C%85.jpg",
+            ),
+            ("/50%/#1?.png", "/50%25/%231%3F.png"),
+            ("/a-b_c.d~e", "/a-b_c.d~e"),
+        ];
+        for (path, encoded) in cases {
+            assert_eq!(encode_path(path), encoded);
+            assert_eq!(decode_path(&encode_path(path)), path);
+        }
+        // Servers may escape less than we do.
+        assert_eq!(decode_path("/a b/%c3%a9t%C3%A9.jpg"), "/a b/été.jpg");
+    }
+
+    #[test]
+    fn test_href_path() {
+        assert_eq!(
+            href_path("https://dav.example.com:8443/files/a%20b.jpg"),
+            "/files/a%20b.jpg"
+        );
+        assert_eq!(href_path("https://dav.example.com"), "/");
+        assert_eq!(href_path("/files/dir/?x=1"), "/files/dir/");
+        assert_eq!(href_path("/files/a.jpg#frag"), "/files/a.jpg");
+    }
+
+    #[test]
+    fn test_status_errors() {
+        let code = |status| status_error(status, "/a.jpg").code();
+        assert_eq!(code(StatusCode::UNAUTHORIZED), "AUTH_FAILED");
+        assert_eq!(code(StatusCode::FORBIDDEN), "PERMISSION_DENIED");
+        assert_eq!(code(StatusCode::NOT_FOUND), "NOT_FOUND");
+        assert_eq!(code(StatusCode::GATEWAY_TIMEOUT), "TIMEOUT");
+        assert_eq!(code(StatusCode::NOT_IMPLEMENTED), "UNSUPPORTED");
+        assert_eq!(code(StatusCode::TOO_MANY_REQUESTS), "RATE_LIMITED");
+        assert_eq!(
+            status_error(StatusCode::BAD_GATEWAY, "/a.jpg").to_string(),
+            "HTTP 502 Bad Gateway"
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_wait() {
+        let backoff = Duration::from_secs(1);
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut map = HeaderMap::new();
+            for (name, value) in pairs {
+                map.insert(*name, HeaderValue::from_static(value));
+            }
+            map
+        };
+        // GitHub's primary limit: wait until the window resets.
+        let exhausted = headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1700000042"),
+        ]);
+        assert_eq!(
+            rate_limit_wait(StatusCode::FORBIDDEN, &exhausted, 1_700_000_000, backoff),
+            Some(Duration::from_secs(42))
+        );
+        // Secondary limits name the wait.
+        let retry = headers(&[("retry-after", "7")]);
+        assert_eq!(
+            rate_limit_wait(StatusCode::FORBIDDEN, &retry, 0, backoff),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            rate_limit_wait(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), 0, backoff),
+            Some(backoff)
+        );
+        // A plain 403 is a permission problem.
+        let remaining = headers(&[("x-ratelimit-remaining", "4999")]);
+        assert_eq!(
+            rate_limit_wait(StatusCode::FORBIDDEN, &remaining, 0, backoff),
+            None
+        );
+        assert_eq!(
+            rate_limit_wait(StatusCode::OK, &exhausted, 0, backoff),
+            None
+        );
+    }
+
+    #[test]
+    fn test_auth_header() {
+        let basic = HttpAuth::Basic {
+            username: "alice".to_string(),
+            password: "s3cret".to_string(),
+        };
+        let header = basic.header().unwrap().unwrap();
+        assert_eq!(header.to_str().unwrap(), "Basic YWxpY2U6czNjcmV0");
+        assert!(header.is_sensitive());
+        assert!(!format!("{:?}", basic).contains("s3cret"));
+        assert!(HttpAuth::None.header().unwrap().is_none());
+        let bearer = HttpAuth::Bearer {
+            token: "bad\ntoken".to_string(),
+        };
+        assert_eq!(bearer.header().unwrap_err().code(), "INVALID_INPUT");
+    }
+}
@@ -0,0 +1,829 @@
+//! A read-only backend over a plain web server's directory listings, such
+//! as nginx's or Apache's autoindex pages, for public image archives.
+//! Listings are parsed from the HTML page, or from JSON when the server
+//! offers it, and files are fetched with GET.
+
+use crate::archive::ArchiveFormat;
+use crate::checksum::{self, ChecksumAlgorithm, FileChecksum};
+use crate::error::StorageError;
+use crate::http::{self, HttpAuth};
+use crate::metrics::{self, StorageMetrics};
+use crate::paths::{PathTranslator, RemotePath, ABSOLUTE_PATH_KEY};
+use crate::storage::{
+    self, ChunkCallback, ContentSearchResult, CreateDirectoryResult, DeleteDirectoryResult,
+    DirectoryUsage, FileInfo, FileReadOutcome, ListOptions, RecursiveListing, SearchResult,
+    Storage, StorageType,
+};
+use crate::thumbnails::{self, Thumbnail, ThumbnailOptions};
+use crate::utils;
+use reqwest::blocking::Client;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, UNIX_EPOCH};
+
+/// Servers that can list in JSON, such as Caddy, are asked to.
+const INDEX_ACCEPT: &str = "application/json, text/html;q=0.9";
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HttpIndexConfig {
+    /// The directory to browse, such as `https://data.example.org/images/`.
+    pub base_url: String,
+    #[serde(default)]
+    pub auth: HttpAuth,
+}
+
+/// A link of a listing. Sizes shown rounded, like Apache's "2.4M", are only
+/// as exact as the page.
+#[derive(Debug, Clone, PartialEq)]
+struct IndexEntry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<u64>,
+}
+
+/// The name of the direct child of `dir_url` that `href` links to, and
+/// whether it is a directory. Sort links, parents, and links elsewhere are
+/// not children.
+fn child_name(dir_url: &Url, href: &str) -> Option<(String, bool)> {
+    if href.starts_with(['?', '#']) {
+        return None;
+    }
+    let url = dir_url.join(href).ok()?;
+    if url.origin() != dir_url.origin() {
+        return None;
+    }
+    let rest = url.path().strip_prefix(dir_url.path())?;
+    let (segment, is_dir) = match rest.strip_suffix('/') {
+        Some(segment) => (segment, true),
+        None => (rest, false),
+    };
+    let name = http::decode_path(segment);
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return None;
+    }
+    Some((name, is_dir))
+}
+
+/// Undoes the HTML escaping autoindex pages apply to names and links.
+fn unescape_html(s: &str) -> String {
+    s.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    unescape_html(&text)
+}
+
+/// The value of the attribute `name` of `tag`, which runs from the `<` to
+/// before the `>`. `lower` is `tag` in lowercase.
+fn attribute<'a>(tag: &'a str, lower: &str, name: &str) -> Option<&'a str> {
+    let mut from = 0;
+    loop {
+        let i = from + lower[from..].find(name)?;
+        from = i + name.len();
+        let rest = lower[from..].trim_start();
+        if !lower[..i].ends_with(|c: char| c.is_ascii_whitespace()) || !rest.starts_with('=') {
+            continue;
+        }
+        let value = tag[tag.len() - rest.len() + 1..].trim_start();
+        return match value.chars().next()? {
+            quote @ ('"' | '\'') => {
+                let value = &value[1..];
+                value.find(quote).map(|end| &value[..end])
+            }
+            _ => value.split(|c: char| c.is_ascii_whitespace()).next(),
+        };
+    }
+}
+
+/// A date as autoindex pages print them: "2023-03-12" (Apache),
+/// "12-Mar-2023" (nginx) or "2023-Mar-12" (lighttpd).
+fn parse_date(token: &str) -> Option<(i64, i64, i64)> {
+    let mut parts = token.splitn(3, '-');
+    let (first, month, last) = (parts.next()?, parts.next()?, parts.next()?);
+    let month = month
+        .parse::<i64>()
+        .ok()
+        .filter(|m| (1..=12).contains(m))
+        .or_else(|| {
+            let i = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))?;
+            Some(i as i64 + 1)
+        })?;
+    let (year, day) = match (first.len(), last.len()) {
+        (4, _) => (first, last),
+        (_, 4) => (last, first),
+        _ => return None,
+    };
+    let day = day.parse::<i64>().ok().filter(|d| (1..=31).contains(d))?;
+    Some((year.parse().ok()?, month, day))
+}
+
+/// A time of day such as "10:15" or "10:15:34".
+fn parse_clock(token: &str) -> Option<(i64, i64, i64)> {
+    let mut parts = token.split(':').map(|p| p.parse::<i64>().ok());
+    let (hour, minute) = (parts.next()??, parts.next()??);
+    let second = parts.next().unwrap_or(Some(0))?;
+    parts.next().is_none().then_some((hour, minute, second))
+}
+
+/// Bytes in a size column, exact like "2483921" or rounded like "2.4M",
+/// "512K" or "1.2 MiB", whose unit may be the `next` token.
+fn parse_size(token: &str, next: Option<&str>) -> Option<u64> {
+    let split = token
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(token.len());
+    let (number, unit) = token.split_at(split);
+    let value: f64 = number.parse().ok()?;
+    let scale = |unit: &str| -> Option<f64> {
+        let unit = unit.trim_end_matches(['B', 'b']).trim_end_matches('i');
+        Some(match unit.to_ascii_uppercase().as_str() {
+            "" => 1.0,
+            "K" => 1024.0,
+            "M" => 1024.0 * 1024.0,
+            "G" => 1024.0 * 1024.0 * 1024.0,
+            "T" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+            _ => return None,
+        })
+    };
+    let scale = match (unit, next) {
+        ("", Some(next)) if next.chars().all(|c| c.is_ascii_alphabetic()) => {
+            scale(next).unwrap_or(1.0)
+        }
+        (unit, _) => scale(unit)?,
+    };
+    Some((value * scale) as u64)
+}
+
+/// The date and size in the columns after a link, when there are any.
+fn parse_columns(text: &str) -> (Option<u64>, Option<u64>) {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let mut modified = None;
+    let mut rest = &tokens[..];
+    if let Some((i, (year, month, day))) = tokens
+        .iter()
+        .enumerate()
+        .find_map(|(i, t)| Some((i, parse_date(t)?)))
+    {
+        let clock = tokens.get(i + 1).and_then(|t| parse_clock(t));
+        let (hour, minute, second) = clock.unwrap_or((0, 0, 0));
+        modified = utils::unix_time(year, month, day, hour, minute, second);
+        rest = &tokens[i + 1 + usize::from(clock.is_some())..];
+    }
+    let size = rest
+        .iter()
+        .enumerate()
+        .find_map(|(i, t)| parse_size(t, rest.get(i + 1).copied()));
+    (modified, size)
+}
+
+/// The entries of the HTML listing of `dir_url`: its links to direct
+/// children, with the date and size nginx, Apache and lighttpd print in
+/// the columns after each link.
+fn parse_html_index(html: &str, dir_url: &Url) -> Vec<IndexEntry> {
+    // ASCII lowercasing keeps byte offsets, so `lower` indexes `html`.
+    let lower = html.to_ascii_lowercase();
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pos = 0;
+    while let Some(i) = lower[pos..].find("<a") {
+        let start = pos + i;
+        pos = start + 2;
+        if !lower[pos..].starts_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let Some(tag_end) = lower[start..].find('>').map(|i| start + i) else {
+            break;
+        };
+        pos = tag_end + 1;
+        let Some(href) = attribute(&html[start..tag_end], &lower[start..tag_end], "href") else {
+            continue;
+        };
+        let Some((name, is_dir)) = child_name(dir_url, &unescape_html(href)) else {
+            continue;
+        };
+        let after = lower[pos..].find("</a>").map_or(pos, |i| pos + i + 4);
+        let end = [lower[after..].find('\n'), lower[after..].find("<a")]
+            .into_iter()
+            .flatten()
+            .min()
+            .map_or(lower.len(), |i| after + i);
+        let (modified, size) = parse_columns(&strip_tags(&html[after..end]));
+        if seen.insert(name.clone()) {
+            entries.push(IndexEntry {
+                name,
+                is_dir,
+                size: if is_dir { 0 } else { size.unwrap_or(0) },
+                modified,
+            });
+        }
+        pos = after;
+    }
+    entries
+}
+
+/// An entry of nginx's `autoindex_format json` or Caddy's JSON listing.
+#[derive(Deserialize)]
+struct JsonEntry {
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+    #[serde(default)]
+    is_dir: Option<bool>,
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    mtime: Option<String>,
+    #[serde(default)]
+    mod_time: Option<String>,
+}
+
+fn parse_json_index(body: &str) -> Result<Vec<IndexEntry>, StorageError> {
+    let items: Vec<JsonEntry> = serde_json::from_str(body)
+        .map_err(|e| StorageError::Protocol(format!("Unexpected JSON listing: {}", e)))?;
+    Ok(items
+        .into_iter()
+        .filter_map(|item| {
+            let name = item.name.trim_end_matches('/');
+            if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+                return None;
+            }
+            let is_dir = item
+                .is_dir
+                .unwrap_or(item.kind.as_deref() == Some("directory"))
+                || item.name.ends_with('/');
+            let modified = match (&item.mtime, &item.mod_time) {
+                (Some(mtime), _) => httpdate::parse_http_date(mtime)
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs()),
+                (None, Some(mod_time)) => utils::parse_rfc3339(mod_time),
+                (None, None) => None,
+            };
+            Some(IndexEntry {
+                name: name.to_string(),
+                is_dir,
+                size: if is_dir { 0 } else { item.size.unwrap_or(0) },
+                modified,
+            })
+        })
+        .collect())
+}
+
+fn read_only() -> StorageError {
+    StorageError::Unsupported("Directory listings over HTTP are read-only".to_string())
+}
+
+pub struct HttpIndexStorage {
+    config: HttpIndexConfig,
+    /// Set by `connect`; `None` while disconnected.
+    client: Option<Client>,
+    /// `config.base_url` with a trailing slash, which paths are below.
+    base: Option<Url>,
+    /// Listings by directory, reused to stat files until a name is missing.
+    listings: Mutex<HashMap<RemotePath, Arc<Vec<IndexEntry>>>>,
+    metrics: StorageMetrics,
+}
+
+impl HttpIndexStorage {
+    pub fn new(config: HttpIndexConfig) -> Self {
+        HttpIndexStorage {
+            config,
+            client: None,
+            base: None,
+            listings: Mutex::new(HashMap::new()),
+            metrics: StorageMetrics::default(),
+        }
+    }
+
+    pub fn config(&self) -> &HttpIndexConfig {
+        &self.config
+    }
+
+    fn client(&self) -> Result<&Client, StorageError> {
+        self.client.as_ref().ok_or(StorageError::NotConnected)
+    }
+
+    /// The URL of `path`, ending in a slash for directories.
+    fn url(&self, path: &RemotePath, is_dir: bool) -> Result<Url, StorageError> {
+        let base = self.base.as_ref().ok_or(StorageError::NotConnected)?;
+        let mut relative = http::encode_path(path.as_str().trim_start_matches('/'));
+        if is_dir && !relative.is_empty() {
+            relative.push('/');
+        }
+        base.join(&relative)
+            .map_err(|e| StorageError::InvalidInput(format!("Invalid path {}: {}", path, e)))
+    }
+
+    /// Fetches and parses the listing of `dir`. Links are resolved against
+    /// the URL the server redirected to, if any, as a browser would.
+    fn fetch_listing(&self, dir: &RemotePath) -> Result<Arc<Vec<IndexEntry>>, StorageError> {
+        let request = self
+            .client()?
+            .get(self.url(dir, true)?)
+            .header(ACCEPT, INDEX_ACCEPT);
+        let response = http::send(request, dir.as_str())?;
+        let mut dir_url = response.url().clone();
+        if !dir_url.path().ends_with('/') {
+            dir_url.set_path(&format!("{}/", dir_url.path()));
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let entries = if content_type.contains("json") {
+            parse_json_index(&response.text()?)?
+        } else if content_type.is_empty() || content_type.starts_with("text/html") {
+            parse_html_index(&response.text()?, &dir_url)
+        } else {
+            return Err(StorageError::InvalidInput(format!(
+                "Not a directory listing: {}",
+                dir
+            )));
+        };
+        let listing = Arc::new(entries);
+        self.listings.lock()?.insert(dir.clone(), listing.clone());
+        Ok(listing)
+    }
+
+    /// The entry at `path` from its directory's listing, fetched again when
+    /// the cached one lacks it.
+    fn entry(&self, path: &RemotePath) -> Result<IndexEntry, StorageError> {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(IndexEntry {
+                name: String::new(),
+                is_dir: true,
+                size: 0,
+                modified: None,
+            });
+        };
+        let find = |listing: &[IndexEntry]| listing.iter().find(|e| e.name == name).cloned();
+        let cached = self.listings.lock()?.get(&parent).cloned();
+        if let Some(entry) = cached.and_then(|listing| find(&listing)) {
+            return Ok(entry);
+        }
+        find(&self.fetch_listing(&parent)?).ok_or_else(|| StorageError::NotFound(path.to_string()))
+    }
+
+    fn file_info(&self, path: &RemotePath, entry: &IndexEntry) -> FileInfo {
+        FileInfo {
+            name: entry.name.clone(),
+            path: path.to_string(),
+            size: entry.size,
+            is_dir: entry.is_dir,
+            is_symlink: false,
+            link_target: None,
+            modified: entry.modified,
+            mime_type: if entry.is_dir {
+                None
+            } else {
+                storage::detect_mime_type(&entry.name)
+            },
+            thumbnail: None,
+            permissions: None,
+            owner: None,
+            extra: BTreeMap::from([(ABSOLUTE_PATH_KEY.to_string(), path.to_string())]),
+        }
+    }
+}
+
+impl Storage for HttpIndexStorage {
+    fn connect(&mut self) -> Result<(), StorageError> {
+        let mut base = Url::parse(&self.config.base_url).map_err(|e| {
+            StorageError::InvalidInput(format!("Invalid URL {}: {}", self.config.base_url, e))
+        })?;
+        if !matches!(base.scheme(), "http" | "https") {
+            return Err(StorageError::InvalidInput(format!(
+                "URLs must be http or https: {}",
+                self.config.base_url
+            )));
+        }
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        base.set_query(None);
+        base.set_fragment(None);
+        self.base = Some(base);
+        self.client = Some(http::client(&self.config.auth, false)?);
+        self.listings.lock()?.clear();
+        if let Err(e) = self.fetch_listing(&RemotePath::root()) {
+            self.client = None;
+            return Err(e);
+        }
+        log::info!("Connected to directory listing {}", self.config.base_url);
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.client = None;
+    }
+
+    fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    fn ping(&self) -> Result<(), StorageError> {
+        let request = self.client()?.head(self.url(&RemotePath::root(), true)?);
+        http::send(request, "/")?;
+        Ok(())
+    }
+
+    fn metrics(&self) -> &StorageMetrics {
+        &self.metrics
+    }
+
+    fn list_directory_page(
+        &self,
+        path: &str,
+        options: &ListOptions,
+    ) -> Result<(Vec<FileInfo>, usize), StorageError> {
+        let dir = self.path_translator().to_remote(path);
+        let mut files: Vec<FileInfo> = self
+            .fetch_listing(&dir)?
+            .iter()
+            .filter(|entry| {
+                storage::is_listed(&entry.name, options.show_hidden, &[])
+                    && options.filter.matches_entry(&entry.name, entry.is_dir)
+            })
+            .map(|entry| self.file_info(&dir.join(&entry.name), entry))
+            .collect();
+        storage::sort_files(&mut files, options.sort_by, options.sort_order);
+        let total = files.len();
+        Ok((
+            storage::paginate(&files, options.offset, options.limit),
+            total,
+        ))
+    }
+
+    fn list_directory_recursive(
+        &self,
+        path: &str,
+        max_depth: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<RecursiveListing, StorageError> {
+        storage::list_by_walking(self, path, max_depth, cancelled)
+    }
+
+    fn search(
+        &self,
+        root: &str,
+        pattern: &str,
+        case_sensitive: bool,
+        limit: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<SearchResult, StorageError> {
+        storage::search_by_walking(self, root, pattern, case_sensitive, limit, cancelled)
+    }
+
+    fn search_contents(
+        &self,
+        _root: &str,
+        _query: &str,
+        _regex: bool,
+        _include: Option<&str>,
+        _limit: usize,
+    ) -> Result<ContentSearchResult, StorageError> {
+        Err(StorageError::Unsupported(
+            "Content search is not supported on directory listings".to_string(),
+        ))
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, StorageError> {
+        let path = self.path_translator().to_remote(path);
+        let request = self.client()?.get(self.url(&path, false)?);
+        metrics::metered_read(&self.metrics, || {
+            Ok(http::send(request, path.as_str())?.bytes()?.to_vec())
+        })
+    }
+
+    fn read_file_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, bool), StorageError> {
+        let start = Instant::now();
+        let path = self.path_translator().to_remote(path);
+        let request = self.client()?.get(self.url(&path, false)?);
+        let (data, at_end) = http::read_range(request, path.as_str(), offset, length)?;
+        self.metrics.record_read(data.len() as u64, start.elapsed());
+        Ok((data, at_end))
+    }
+
+    fn read_files(&self, paths: &[String]) -> Result<Vec<FileReadOutcome>, StorageError> {
+        let mut outcomes = Vec::with_capacity(paths.len());
+        for path in paths {
+            outcomes.push(match self.read_file(path) {
+                Err(e) if e.is_transport() || matches!(e, StorageError::NotConnected) => {
+                    return Err(e)
+                }
+                result => result.map_err(|e| e.to_string()),
+            });
+        }
+        Ok(outcomes)
+    }
+
+    fn read_file_streamed(
+        &self,
+        path: &str,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        let start = Instant::now();
+        let path = self.path_translator().to_remote(path);
+        let request = self.client()?.get(self.url(&path, false)?);
+        let (read, completed) = http::stream_body(http::send(request, path.as_str())?, on_chunk)?;
+        self.metrics.record_read(read, start.elapsed());
+        Ok(completed)
+    }
+
+    fn write_file(&self, _path: &str, _data: &[u8]) -> Result<(), StorageError> {
+        Err(read_only())
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        match self.entry(&self.path_translator().to_remote(path)) {
+            Ok(_) => Ok(true),
+            Err(StorageError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn stat(&self, path: &str) -> Result<FileInfo, StorageError> {
+        let path = self.path_translator().to_remote(path);
+        Ok(self.file_info(&path, &self.entry(&path)?))
+    }
+
+    fn set_permissions(&self, _path: &str, _mode: u32) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn set_modified(&self, _path: &str, _mtime: u64) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn directory_size(&self, path: &str) -> Result<DirectoryUsage, StorageError> {
+        storage::size_by_walking(self, path)
+    }
+
+    /// Nothing can be run on the server, so the caller builds zips itself.
+    fn archive_directory(
+        &self,
+        _path: &str,
+        format: ArchiveFormat,
+        _on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        Err(StorageError::ToolMissing(format.remote_tool()))
+    }
+
+    fn extract_archive(
+        &self,
+        _path: &str,
+        _destination: &str,
+    ) -> Result<Vec<String>, StorageError> {
+        Err(read_only())
+    }
+
+    fn checksum(
+        &self,
+        path: &str,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<FileChecksum, StorageError> {
+        let info = self.stat(path)?;
+        if info.is_dir {
+            return Err(StorageError::InvalidInput("is a directory".to_string()));
+        }
+        Ok(FileChecksum {
+            algorithm,
+            digest: checksum::hash_streamed(self, path, algorithm)?,
+            size: info.size,
+        })
+    }
+
+    fn checksums(
+        &self,
+        paths: &[String],
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<Vec<Option<String>>, StorageError> {
+        Ok(paths
+            .iter()
+            .map(|p| checksum::hash_streamed(self, p, algorithm).ok())
+            .collect())
+    }
+
+    fn delete_file(&self, _path: &str) -> Result<(), StorageError> {
+        Err(read_only())
+    }
+
+    fn delete_directory(
+        &self,
+        _path: &str,
+        _recursive: bool,
+    ) -> Result<DeleteDirectoryResult, StorageError> {
+        Err(read_only())
+    }
+
+    fn rename(&self, _from: &str, _to: &str) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn copy_file(&self, _from: &str, _to: &str) -> Result<FileInfo, StorageError> {
+        Err(read_only())
+    }
+
+    fn create_directory(
+        &self,
+        _path: &str,
+        _recursive: bool,
+    ) -> Result<CreateDirectoryResult, StorageError> {
+        Err(read_only())
+    }
+
+    fn get_file_thumbnail(
+        &self,
+        path: &str,
+        max_size: u32,
+        options: ThumbnailOptions,
+    ) -> Result<Thumbnail, StorageError> {
+        thumbnails::fetched_file_thumbnail(self, path, max_size, options)
+            .map_err(StorageError::from)
+    }
+
+    fn get_root_path(&self) -> String {
+        "/".to_string()
+    }
+
+    fn path_translator(&self) -> PathTranslator {
+        PathTranslator::new("/")
+    }
+
+    fn storage_type(&self) -> StorageType {
+        StorageType::HttpIndex
+    }
+
+    fn connection_id(&self) -> String {
+        format!("http:{}", self.config.base_url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// nginx 1.24 with `autoindex on`; long names are cut short in the text
+    /// but not in the links.
+    const NGINX_INDEX: &str = r#"<html>
+<head><title>Index of /datasets/pets/</title></head>
+<body>
+<h1>Index of /datasets/pets/</h1><hr><pre><a href="../">../</a>
+<a href="cats/">cats/</a>                                              12-Mar-2023 10:15                   -
+<a href="IMG%200001.jpg">IMG 0001.jpg</a>                                       12-Mar-2023 10:16             2483921
+<a href="a-rather-long-file-name-for-a-dog-photo-taken.png">a-rather-long-file-name-for-a-dog-photo-ta..&gt;</a> 01-Jan-2024 00:00              1024
+<a href="Tom%20%26%20Jerry.gif">Tom &amp; Jerry.gif</a>                                  05-Jun-2022 18:30:12            512
+</pre><hr></body>
+</html>
+"#;
+
+    /// Apache 2.4 with FancyIndexing, whose sizes are rounded.
+    const APACHE_INDEX: &str = r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 3.2 Final//EN">
+<html>
+ <head>
+  <title>Index of /datasets/pets</title>
+ </head>
+ <body>
+<h1>Index of /datasets/pets</h1>
+  <table>
+   <tr><th valign="top"><img src="/icons/blank.gif" alt="[ICO]"></th><th><a href="?C=N;O=D">Name</a></th><th><a href="?C=M;O=A">Last modified</a></th><th><a href="?C=S;O=A">Size</a></th><th><a href="?C=D;O=A">Description</a></th></tr>
+   <tr><th colspan="5"><hr></th></tr>
+<tr><td valign="top"><img src="/icons/back.gif" alt="[PARENTDIR]"></td><td><a href="/datasets/">Parent Directory</a></td><td>&nbsp;</td><td align="right">  - </td><td>&nbsp;</td></tr>
+<tr><td valign="top"><img src="/icons/folder.gif" alt="[DIR]"></td><td><a href="cats/">cats/</a></td><td align="right">2023-03-12 10:15  </td><td align="right">  - </td><td>&nbsp;</td></tr>
+<tr><td valign="top"><img src="/icons/image2.gif" alt="[IMG]"></td><td><a href="IMG%200001.jpg">IMG 0001.jpg</a></td><td align="right">2023-03-12 10:16  </td><td align="right">2.4M</td><td>&nbsp;</td></tr>
+<tr><td valign="top"><img src="/icons/image2.gif" alt="[IMG]"></td><td><a href="/datasets/pets/dog.png">dog.png</a></td><td align="right">2024-01-01 00:00  </td><td align="right">512K</td><td>Rex, 3 years</td></tr>
+<tr><td valign="top"><img src="/icons/unknown.gif" alt="[   ]"></td><td><a href="https://mirror.example.org/pets.tar">pets.tar</a></td><td align="right">2024-01-01 00:00  </td><td align="right">1.1G</td><td>&nbsp;</td></tr>
+   <tr><th colspan="5"><hr></th></tr>
+</table>
+<address>Apache/2.4.57 (Debian) Server at data.example.org Port 443</address>
+</body></html>
+"#;
+
+    fn dir_url() -> Url {
+        Url::parse("https://data.example.org/datasets/pets/").unwrap()
+    }
+
+    #[test]
+    fn test_parse_nginx_index() {
+        let entries = parse_html_index(NGINX_INDEX, &dir_url());
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "cats",
+                "IMG 0001.jpg",
+                "a-rather-long-file-name-for-a-dog-photo-taken.png",
+                "Tom & Jerry.gif"
+            ]
+        );
+        assert!(entries[0].is_dir);
+        assert_eq!(
+            entries[0].modified,
+            utils::unix_time(2023, 3, 12, 10, 15, 0)
+        );
+        assert_eq!(entries[1].size, 2483921);
+        assert_eq!(entries[2].size, 1024);
+        assert_eq!(
+            entries[3].modified,
+            utils::unix_time(2022, 6, 5, 18, 30, 12)
+        );
+    }
+
+    #[test]
+    fn test_parse_apache_index() {
+        let entries = parse_html_index(APACHE_INDEX, &dir_url());
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["cats", "IMG 0001.jpg", "dog.png"]);
+        assert!(entries[0].is_dir && entries[0].size == 0);
+        assert_eq!(entries[1].size, 2516582);
+        assert_eq!(
+            entries[1].modified,
+            utils::unix_time(2023, 3, 12, 10, 16, 0)
+        );
+        assert_eq!(entries[2].size, 512 * 1024);
+    }
+
+    #[test]
+    fn test_parse_json_index() {
+        let nginx = r#"[
+          {"name": "cats", "type": "directory", "mtime": "Sun, 12 Mar 2023 10:15:00 GMT"},
+          {"name": "IMG 0001.jpg", "type": "file", "mtime": "Sun, 12 Mar 2023 10:16:00 GMT", "size": 2483921}
+        ]"#;
+        let entries = parse_json_index(nginx).unwrap();
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[1].size, 2483921);
+        assert_eq!(
+            entries[1].modified,
+            utils::unix_time(2023, 3, 12, 10, 16, 0)
+        );
+
+        let caddy = r#"[
+          {"name": "cats/", "size": 4096, "url": "./cats/", "mod_time": "2023-03-12T10:15:00.5Z", "is_dir": true},
+          {"name": "dog.png", "size": 524288, "url": "./dog.png", "mod_time": "2024-01-01T01:00:00+01:00", "is_dir": false}
+        ]"#;
+        let entries = parse_json_index(caddy).unwrap();
+        assert_eq!(entries[0].name, "cats");
+        assert!(entries[0].is_dir && entries[0].size == 0);
+        assert_eq!(entries[1].modified, utils::unix_time(2024, 1, 1, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_child_name_and_sizes() {
+        let dir = dir_url();
+        assert_eq!(child_name(&dir, "cats/"), Some(("cats".to_string(), true)));
+        assert_eq!(
+            child_name(&dir, "/datasets/pets/a%20b.jpg"),
+            Some(("a b.jpg".to_string(), false))
+        );
+        assert_eq!(
+            child_name(&dir, "https://data.example.org/datasets/pets/c.jpg"),
+            Some(("c.jpg".to_string(), false))
+        );
+        assert_eq!(child_name(&dir, "../"), None);
+        assert_eq!(child_name(&dir, "?C=M;O=A"), None);
+        assert_eq!(child_name(&dir, "cats/kitten.jpg"), None);
+        assert_eq!(
+            child_name(&dir, "https://elsewhere.org/datasets/pets/c.jpg"),
+            None
+        );
+
+        assert_eq!(parse_size("2483921", None), Some(2483921));
+        assert_eq!(parse_size("1.5", Some("KiB")), Some(1536));
+        assert_eq!(parse_size("2M", None), Some(2 * 1024 * 1024));
+        assert_eq!(parse_size("-", None), None);
+        assert_eq!(parse_size("years", None), None);
+    }
+}
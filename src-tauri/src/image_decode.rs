@@ -0,0 +1,491 @@
+//! Decoding image files into pixels for thumbnails and previews, including
+//! formats the `image` crate cannot read by itself.
+
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageReader, Limits};
+use std::fmt;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Error code reported to the frontend when a file cannot be decoded in
+/// this build, so it can show a placeholder instead.
+pub const UNSUPPORTED_FORMAT: &str = "UNSUPPORTED_FORMAT";
+
+/// The file's format cannot be decoded, either because support was not
+/// compiled in or because the decoder rejected it.
+#[derive(Debug)]
+pub struct UnsupportedFormatError(pub String);
+
+impl fmt::Display for UnsupportedFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: cannot decode {}", UNSUPPORTED_FORMAT, self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedFormatError {}
+
+/// Error code reported to the frontend for images over the pixel limit.
+pub const IMAGE_TOO_LARGE: &str = "IMAGE_TOO_LARGE";
+pub const DEFAULT_MAX_IMAGE_PIXELS: u64 = 100_000_000;
+/// Files bigger than this are not read for thumbnails at all.
+pub const MAX_THUMBNAIL_INPUT_BYTES: u64 = 200 * 1024 * 1024;
+/// Decoder working memory allowed on top of the output pixels.
+const DECODE_ALLOC_SLACK: u64 = 64 * 1024 * 1024;
+
+static MAX_IMAGE_PIXELS: AtomicU64 = AtomicU64::new(DEFAULT_MAX_IMAGE_PIXELS);
+
+/// Largest width times height that will be decoded.
+pub fn max_image_pixels() -> u64 {
+    MAX_IMAGE_PIXELS.load(Ordering::Relaxed)
+}
+
+pub fn set_max_image_pixels(max_pixels: u64) {
+    MAX_IMAGE_PIXELS.store(max_pixels, Ordering::Relaxed);
+}
+
+/// The image header declares more pixels than [`max_image_pixels`].
+#[derive(Debug, PartialEq)]
+pub struct ImageTooLargeError {
+    pub width: u32,
+    pub height: u32,
+    pub max_pixels: u64,
+}
+
+impl ImageTooLargeError {
+    fn check(width: u32, height: u32, max_pixels: u64) -> Result<(), Self> {
+        if u64::from(width) * u64::from(height) > max_pixels {
+            return Err(ImageTooLargeError {
+                width,
+                height,
+                max_pixels,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ImageTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}x{} exceeds the limit of {} pixels",
+            IMAGE_TOO_LARGE, self.width, self.height, self.max_pixels
+        )
+    }
+}
+
+impl std::error::Error for ImageTooLargeError {}
+
+/// The frontend code for thumbnail failures it shows a placeholder for.
+pub fn error_code(e: &(dyn std::error::Error + 'static)) -> Option<&'static str> {
+    if e.is::<UnsupportedFormatError>() {
+        Some(UNSUPPORTED_FORMAT)
+    } else if e.is::<ImageTooLargeError>() {
+        Some(IMAGE_TOO_LARGE)
+    } else {
+        None
+    }
+}
+
+/// Decodes `content`, a file of type `mime` as given by
+/// [`crate::storage::detect_mime_type`].
+pub fn decode_image(
+    content: &[u8],
+    mime: &str,
+) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    match mime {
+        "image/heic" | "image/heif" => decode_heif(content),
+        // Decoding needs the `avif` feature, which pulls in dav1d.
+        "image/avif" => decode_limited(content, Some(ImageFormat::Avif), mime),
+        _ => decode_limited(content, None, mime),
+    }
+}
+
+/// Decodes `content` after checking the dimensions in its header against
+/// [`max_image_pixels`], so a huge or malicious image is refused before
+/// anything is allocated for it. `format` is guessed when not given.
+pub fn decode_limited(
+    content: &[u8],
+    format: Option<ImageFormat>,
+    mime: &str,
+) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    limited_reader(content, format, mime)?
+        .decode()
+        .map_err(|e| unsupported(mime, e))
+}
+
+/// Pixels and raw Exif data of an image.
+pub type ImageWithExif = (DynamicImage, Option<Vec<u8>>);
+
+/// Like [`decode_limited`], but also returns the file's Exif data. The Exif
+/// orientation is applied to the pixels and reset in the returned data, so
+/// the image is upright wherever it is written.
+pub fn decode_with_exif(
+    content: &[u8],
+    mime: &str,
+) -> Result<ImageWithExif, Box<dyn std::error::Error>> {
+    let mut decoder = limited_reader(content, None, mime)?
+        .into_decoder()
+        .map_err(|e| unsupported(mime, e))?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut exif = decoder.exif_metadata().ok().flatten();
+    let mut img = DynamicImage::from_decoder(decoder).map_err(|e| unsupported(mime, e))?;
+    img.apply_orientation(orientation);
+    if let Some(exif) = exif.as_mut() {
+        let _ = Orientation::remove_from_exif_chunk(exif);
+    }
+    Ok((img, exif))
+}
+
+/// A reader for `content` whose limits fit the dimensions in its header,
+/// after checking them against [`max_image_pixels`].
+fn limited_reader<'a>(
+    content: &'a [u8],
+    format: Option<ImageFormat>,
+    mime: &str,
+) -> Result<ImageReader<Cursor<&'a [u8]>>, Box<dyn std::error::Error>> {
+    let reader = || -> Result<_, Box<dyn std::error::Error>> {
+        Ok(match format {
+            Some(format) => ImageReader::with_format(Cursor::new(content), format),
+            None => ImageReader::new(Cursor::new(content)).with_guessed_format()?,
+        })
+    };
+    let max_pixels = max_image_pixels();
+    let (width, height) = reader()?
+        .into_dimensions()
+        .map_err(|e| unsupported(mime, e))?;
+    ImageTooLargeError::check(width, height, max_pixels)?;
+
+    // Decoders that misreport their header still cannot exceed these.
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(width);
+    limits.max_image_height = Some(height);
+    limits.max_alloc = Some(max_pixels.saturating_mul(4) + DECODE_ALLOC_SLACK);
+    let mut reader = reader()?;
+    reader.limits(limits);
+    Ok(reader)
+}
+
+/// Formats the `image` crate does not know become [`UnsupportedFormatError`];
+/// corrupt files of known formats keep their decoding error.
+fn unsupported(mime: &str, e: ImageError) -> Box<dyn std::error::Error> {
+    match e {
+        ImageError::Unsupported(_) => UnsupportedFormatError(mime.to_string()).into(),
+        e => e.into(),
+    }
+}
+
+/// The smallest JPEG decoding scale, as 1/n with n of 8, 4, 2 or 1, that
+/// still leaves the longer side of a `width` x `height` image at least
+/// `max_size`.
+pub fn jpeg_scale_denominator(width: u32, height: u32, max_size: u32) -> u32 {
+    let longest = width.max(height);
+    [8, 4, 2]
+        .into_iter()
+        .find(|n| longest.div_ceil(*n) >= max_size)
+        .unwrap_or(1)
+}
+
+/// Decodes a JPEG at the smallest scale still covering `max_size`, which
+/// skips most of the work for large photos. The pixel limit applies to the
+/// scaled image, as that is all that gets allocated.
+#[cfg(feature = "turbojpeg")]
+pub fn decode_jpeg_scaled(
+    content: &[u8],
+    max_size: u32,
+) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    use turbojpeg::{Decompressor, Image, PixelFormat, ScalingFactor};
+
+    let mut decompressor = Decompressor::new()?;
+    let header = decompressor.read_header(content)?;
+    let denominator = jpeg_scale_denominator(header.width as u32, header.height as u32, max_size);
+    let factor = ScalingFactor::new(1, denominator as usize);
+    decompressor.set_scaling_factor(factor)?;
+    let scaled = header.scaled(factor);
+    ImageTooLargeError::check(
+        scaled.width as u32,
+        scaled.height as u32,
+        max_image_pixels(),
+    )?;
+
+    let mut image = Image {
+        pixels: vec![0u8; 4 * scaled.width * scaled.height],
+        width: scaled.width,
+        pitch: 4 * scaled.width,
+        height: scaled.height,
+        format: PixelFormat::RGBA,
+    };
+    decompressor.decompress(content, image.as_deref_mut())?;
+    let buffer =
+        image::RgbaImage::from_raw(scaled.width as u32, scaled.height as u32, image.pixels)
+            .ok_or("JPEG has inconsistent dimensions")?;
+    Ok(DynamicImage::ImageRgba8(buffer))
+}
+
+#[cfg(feature = "heif")]
+fn decode_heif(content: &[u8]) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let unsupported = |_| UnsupportedFormatError("image/heic".to_string());
+    let context = HeifContext::read_from_bytes(content).map_err(unsupported)?;
+    let handle = context.primary_image_handle().map_err(unsupported)?;
+    ImageTooLargeError::check(handle.width(), handle.height(), max_image_pixels())?;
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(unsupported)?;
+    let plane = decoded
+        .planes()
+        .interleaved
+        .ok_or_else(|| UnsupportedFormatError("image/heic".to_string()))?;
+
+    // Rows may be padded beyond `width * 4` bytes.
+    let row_len = plane.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+    let buffer = image::RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .ok_or("HEIF image has inconsistent dimensions")?;
+    Ok(DynamicImage::ImageRgba8(buffer))
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(_content: &[u8]) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    Err(UnsupportedFormatError("image/heic".to_string()).into())
+}
+
+/// Upper bound on either side of a rendered SVG, whatever its viewBox or
+/// the requested size.
+pub const MAX_SVG_RENDER_PX: u32 = 4096;
+
+/// Width and height fitting an SVG of `width` x `height` into `max_size`,
+/// at least one pixel and at most [`MAX_SVG_RENDER_PX`] on each side.
+pub fn svg_render_size(width: f32, height: f32, max_size: u32) -> (u32, u32) {
+    let max_size = max_size.clamp(1, MAX_SVG_RENDER_PX) as f32;
+    let scale = max_size / width.max(height);
+    let side = |len: f32| ((len * scale).round() as u32).clamp(1, MAX_SVG_RENDER_PX);
+    (side(width), side(height))
+}
+
+/// Rasterizes an SVG to fit `max_size`. Documents referring to external
+/// files are rejected; nothing outside `content` is ever read or fetched.
+#[cfg(feature = "svg")]
+pub fn render_svg(
+    content: &[u8],
+    max_size: u32,
+) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    use resvg::{tiny_skia, usvg};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let external = Arc::new(AtomicBool::new(false));
+    let seen = external.clone();
+    let options = usvg::Options {
+        resources_dir: None,
+        image_href_resolver: usvg::ImageHrefResolver {
+            resolve_string: Box::new(move |_, _| {
+                seen.store(true, Ordering::Relaxed);
+                None
+            }),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let tree =
+        usvg::Tree::from_data(content, &options).map_err(|e| format!("Invalid SVG: {}", e))?;
+    if external.load(Ordering::Relaxed) {
+        return Err("SVG references external resources".into());
+    }
+
+    let size = tree.size();
+    let (width, height) = svg_render_size(size.width(), size.height(), max_size);
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or("Invalid SVG size")?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(
+            width as f32 / size.width(),
+            height as f32 / size.height(),
+        ),
+        &mut pixmap.as_mut(),
+    );
+
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect();
+    let buffer = image::RgbaImage::from_raw(width, height, pixels)
+        .ok_or("SVG render has inconsistent dimensions")?;
+    Ok(DynamicImage::ImageRgba8(buffer))
+}
+
+#[cfg(not(feature = "svg"))]
+pub fn render_svg(
+    _content: &[u8],
+    _max_size: u32,
+) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    Err(UnsupportedFormatError("image/svg+xml".to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_decode_image_known_format() {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(3, 2)
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        let img = decode_image(&png.into_inner(), "image/png").unwrap();
+        assert_eq!((img.width(), img.height()), (3, 2));
+    }
+
+    #[cfg(not(feature = "heif"))]
+    #[test]
+    fn test_heic_without_feature_is_unsupported() {
+        let err = decode_image(b"\0\0\0\x18ftypheic", "image/heic").unwrap_err();
+        assert!(err.is::<UnsupportedFormatError>());
+        assert_eq!(
+            err.to_string(),
+            "UNSUPPORTED_FORMAT: cannot decode image/heic"
+        );
+    }
+
+    #[test]
+    fn test_svg_render_size_is_capped() {
+        assert_eq!(svg_render_size(100.0, 50.0, 256), (256, 128));
+        assert_eq!(svg_render_size(1.0, 1.0, 100_000), (4096, 4096));
+        assert_eq!(svg_render_size(100_000.0, 1.0, 256), (256, 1));
+    }
+
+    #[cfg(feature = "svg")]
+    #[test]
+    fn test_render_svg() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 1000000 500000">
+            <rect width="1000000" height="500000" fill="red"/></svg>"#;
+        let img = render_svg(svg, 200).unwrap();
+        assert_eq!((img.width(), img.height()), (200, 100));
+
+        assert!(render_svg(b"<svg", 200).is_err());
+        let external = br#"<svg xmlns="http://www.w3.org/2000/svg"
+            xmlns:xlink="http://www.w3.org/1999/xlink" width="10" height="10">
+            <image xlink:href="http://example.com/a.png" width="10" height="10"/></svg>"#;
+        assert_eq!(
+            render_svg(external, 200).unwrap_err().to_string(),
+            "SVG references external resources"
+        );
+    }
+
+    #[cfg(not(feature = "svg"))]
+    #[test]
+    fn test_svg_without_feature_is_unsupported() {
+        assert!(render_svg(b"<svg/>", 200)
+            .unwrap_err()
+            .is::<UnsupportedFormatError>());
+    }
+
+    /// A PNG signature and IHDR chunk declaring `width` x `height`, followed
+    /// by an empty IDAT chunk instead of pixel data.
+    fn png_header(width: u32, height: u32) -> Vec<u8> {
+        let mut ihdr = b"IHDR".to_vec();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&13u32.to_be_bytes());
+        png.extend_from_slice(&ihdr);
+        png.extend_from_slice(&crc32(&ihdr).to_be_bytes());
+        png.extend_from_slice(&0u32.to_be_bytes());
+        png.extend_from_slice(b"IDAT");
+        png.extend_from_slice(&crc32(b"IDAT").to_be_bytes());
+        png
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for byte in data {
+            crc ^= u32::from(*byte);
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn test_enormous_declared_dimensions_are_refused() {
+        let err = decode_image(&png_header(40_000, 40_000), "image/png").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ImageTooLargeError>(),
+            Some(&ImageTooLargeError {
+                width: 40_000,
+                height: 40_000,
+                max_pixels: DEFAULT_MAX_IMAGE_PIXELS,
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "IMAGE_TOO_LARGE: 40000x40000 exceeds the limit of 100000000 pixels"
+        );
+
+        // Extreme aspect ratios are judged by area, not by either side.
+        let err = decode_image(&png_header(1_000_000, 200), "image/png").unwrap_err();
+        assert!(err.is::<ImageTooLargeError>());
+    }
+
+    #[test]
+    fn test_header_within_limit_is_decoded() {
+        // Allowed by the pixel check, so it fails only for lack of data.
+        let err = decode_image(&png_header(100, 100), "image/png").unwrap_err();
+        assert!(!err.is::<ImageTooLargeError>());
+        assert_eq!(
+            ImageTooLargeError::check(10_000, 10_000, DEFAULT_MAX_IMAGE_PIXELS),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_jpeg_scale_denominator() {
+        assert_eq!(jpeg_scale_denominator(6000, 4000, 256), 8);
+        assert_eq!(jpeg_scale_denominator(6000, 4000, 1000), 4);
+        assert_eq!(jpeg_scale_denominator(4000, 6000, 2000), 2);
+        assert_eq!(jpeg_scale_denominator(640, 480, 512), 1);
+        // 1/8 of 2047 rounds up to 256.
+        assert_eq!(jpeg_scale_denominator(2047, 10, 256), 8);
+    }
+
+    #[cfg(feature = "turbojpeg")]
+    #[test]
+    fn test_scaled_jpeg_decode() {
+        let jpeg = |width, height| {
+            let mut jpeg = Cursor::new(Vec::new());
+            DynamicImage::new_rgb8(width, height)
+                .write_to(&mut jpeg, ImageFormat::Jpeg)
+                .unwrap();
+            jpeg.into_inner()
+        };
+
+        // Decoded at 1/8 rather than in full and resized afterwards.
+        let scaled = decode_jpeg_scaled(&jpeg(6000, 4000), 256).unwrap();
+        assert_eq!((scaled.width(), scaled.height()), (750, 500));
+        let scaled = decode_jpeg_scaled(&jpeg(4000, 6000), 2000).unwrap();
+        assert_eq!((scaled.width(), scaled.height()), (2000, 3000));
+        let full = decode_jpeg_scaled(&jpeg(640, 480), 512).unwrap();
+        assert_eq!((full.width(), full.height()), (640, 480));
+    }
+
+    #[test]
+    fn test_corrupt_known_format_keeps_its_error() {
+        let err = decode_image(b"\x89PNG\r\n\x1a\nbroken", "image/png").unwrap_err();
+        assert!(!err.is::<UnsupportedFormatError>());
+    }
+}
@@ -0,0 +1,275 @@
+//! Editing images stored on a backend and writing the result back through
+//! [`Storage::write_file`], which commits on GitHub.
+
+use crate::image_decode::{self, UnsupportedFormatError};
+use crate::storage::{self, detect_mime_type, FileInfo, Storage};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageEncoder, ImageFormat};
+use serde::Deserialize;
+use std::io::Cursor;
+
+/// JPEG quality used when rewriting a photo in place, high enough that a
+/// few rotations are not visible.
+pub const TRANSFORM_JPEG_QUALITY: u8 = 95;
+/// Larger files are not loaded for editing.
+pub const MAX_EDIT_INPUT_BYTES: u64 = image_decode::MAX_THUMBNAIL_INPUT_BYTES;
+/// Appended to a file's path to name its backup.
+pub const BACKUP_SUFFIX: &str = ".orig";
+pub const DEFAULT_CONVERT_QUALITY: u8 = 90;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageTransform {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipH,
+    FlipV,
+}
+
+impl ImageTransform {
+    /// Rotations are clockwise.
+    pub fn apply(self, img: &DynamicImage) -> DynamicImage {
+        match self {
+            ImageTransform::Rotate90 => img.rotate90(),
+            ImageTransform::Rotate180 => img.rotate180(),
+            ImageTransform::Rotate270 => img.rotate270(),
+            ImageTransform::FlipH => img.fliph(),
+            ImageTransform::FlipV => img.flipv(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(default)]
+pub struct TransformOptions {
+    /// Copy the file to `<path>.orig` first, unless that already exists.
+    pub backup: bool,
+    /// Restore the file's modification time after writing it.
+    pub keep_modified: bool,
+}
+
+pub fn backup_path(path: &str) -> String {
+    format!("{}{}", path, BACKUP_SUFFIX)
+}
+
+/// Encodes `img` as `format`. `exif` is kept for JPEG, PNG and WebP and
+/// dropped for other formats; `quality` only applies to JPEG.
+pub fn encode_image(
+    img: &DynamicImage,
+    format: ImageFormat,
+    quality: u8,
+    exif: Option<Vec<u8>>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut data = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let mut encoder = JpegEncoder::new_with_quality(&mut data, quality.clamp(1, 100));
+            if let Some(exif) = exif {
+                encoder.set_exif_metadata(exif)?;
+            }
+            img.write_with_encoder(encoder)?;
+        }
+        ImageFormat::Png => {
+            let mut encoder = PngEncoder::new(&mut data);
+            if let Some(exif) = exif {
+                encoder.set_exif_metadata(exif)?;
+            }
+            img.write_with_encoder(encoder)?;
+        }
+        // The image crate only writes lossless WebP.
+        ImageFormat::WebP => {
+            let mut encoder = WebPEncoder::new_lossless(&mut data);
+            if let Some(exif) = exif {
+                encoder.set_exif_metadata(exif)?;
+            }
+            img.write_with_encoder(encoder)?;
+        }
+        format if format.writing_enabled() => {
+            img.write_to(&mut Cursor::new(&mut data), format)?;
+        }
+        format => return Err(format!("Cannot encode {}", format.to_mime_type()).into()),
+    }
+    Ok(data)
+}
+
+/// Applies `transform` to `content`, a file of type `mime`, and encodes the
+/// result in the same format. The Exif orientation is baked into the pixels
+/// so the result looks the same everywhere.
+pub fn transform_image(
+    content: &[u8],
+    mime: &str,
+    transform: ImageTransform,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let format =
+        image::guess_format(content).map_err(|_| UnsupportedFormatError(mime.to_string()))?;
+    let (img, exif) = image_decode::decode_with_exif(content, mime)?;
+    encode_image(&transform.apply(&img), format, TRANSFORM_JPEG_QUALITY, exif)
+}
+
+/// The output format named by `name`, an extension such as "jpg" or a MIME
+/// type.
+pub fn parse_format(name: &str) -> Option<ImageFormat> {
+    ImageFormat::from_extension(name).or_else(|| ImageFormat::from_mime_type(name))
+}
+
+/// Re-encodes `content`, a file of type `mime`, as `format`. Exif data is
+/// carried over only with `keep_exif`, with its orientation applied.
+pub fn convert_image(
+    content: &[u8],
+    mime: &str,
+    format: ImageFormat,
+    quality: u8,
+    keep_exif: bool,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (img, exif) = match mime {
+        "image/heic" | "image/heif" => (image_decode::decode_image(content, mime)?, None),
+        _ => image_decode::decode_with_exif(content, mime)?,
+    };
+    encode_image(&img, format, quality, exif.filter(|_| keep_exif))
+}
+
+/// Writes `src` converted to `format` to `dest` on `storage` and returns
+/// the new entry. An existing `dest` is replaced.
+pub fn convert_file(
+    storage: &dyn Storage,
+    src: &str,
+    dest: &str,
+    format: ImageFormat,
+    quality: u8,
+    keep_exif: bool,
+) -> Result<FileInfo, Box<dyn std::error::Error>> {
+    let mime = detect_mime_type(src).unwrap_or_default();
+    let content = storage::read_file_capped(storage, src, MAX_EDIT_INPUT_BYTES)?;
+    let converted = convert_image(&content, &mime, format, quality, keep_exif)?;
+    storage.write_file(dest, &converted)?;
+    Ok(storage.stat(dest)?)
+}
+
+/// Rewrites `path` on `storage` with `transform` applied and returns its
+/// new entry.
+pub fn transform_file(
+    storage: &dyn Storage,
+    path: &str,
+    transform: ImageTransform,
+    options: TransformOptions,
+) -> Result<FileInfo, Box<dyn std::error::Error>> {
+    let original = storage.stat(path)?;
+    let mime = detect_mime_type(path).unwrap_or_default();
+    let content = storage::read_file_capped(storage, path, MAX_EDIT_INPUT_BYTES)?;
+    let transformed = transform_image(&content, &mime, transform)?;
+
+    if options.backup {
+        let backup = backup_path(path);
+        // An existing backup holds the real original.
+        if !storage.exists(&backup)? {
+            storage.copy_file(path, &backup)?;
+        }
+    }
+    storage.write_file(path, &transformed)?;
+    match original.modified {
+        Some(modified) if options.keep_modified => Ok(storage.set_modified(path, modified)?),
+        _ => Ok(storage.stat(path)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegDecoder;
+    use image::metadata::Orientation;
+    use image::{ImageDecoder, Rgb, RgbImage};
+
+    /// A little-endian Exif chunk holding only an orientation.
+    fn exif_with_orientation(orientation: u16) -> Vec<u8> {
+        let mut exif = b"II*\0\x08\0\0\0\x01\0".to_vec();
+        exif.extend_from_slice(&0x0112u16.to_le_bytes());
+        exif.extend_from_slice(&3u16.to_le_bytes());
+        exif.extend_from_slice(&1u32.to_le_bytes());
+        exif.extend_from_slice(&orientation.to_le_bytes());
+        exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        exif
+    }
+
+    /// 4x2 with a red pixel at the top left.
+    fn marked() -> DynamicImage {
+        let mut img = RgbImage::new(4, 2);
+        img.put_pixel(0, 0, Rgb([255, 0, 0]));
+        DynamicImage::ImageRgb8(img)
+    }
+
+    #[test]
+    fn test_transform_names() {
+        let parsed: Vec<ImageTransform> =
+            serde_json::from_str(r#"["rotate90", "rotate180", "rotate270", "flip_h", "flip_v"]"#)
+                .unwrap();
+        assert_eq!(parsed[0], ImageTransform::Rotate90);
+        assert_eq!(parsed[4], ImageTransform::FlipV);
+    }
+
+    #[test]
+    fn test_transform_png_keeps_format() {
+        let png = encode_image(&marked(), ImageFormat::Png, 100, None).unwrap();
+        let rotated = transform_image(&png, "image/png", ImageTransform::Rotate90).unwrap();
+        assert_eq!(image::guess_format(&rotated).unwrap(), ImageFormat::Png);
+        let img = image::load_from_memory(&rotated).unwrap().to_rgb8();
+        assert_eq!(img.dimensions(), (2, 4));
+        // Clockwise: the top left ends up at the top right.
+        assert_eq!(img.get_pixel(1, 0), &Rgb([255, 0, 0]));
+
+        let flipped = transform_image(&png, "image/png", ImageTransform::FlipV).unwrap();
+        let img = image::load_from_memory(&flipped).unwrap().to_rgb8();
+        assert_eq!(img.get_pixel(0, 1), &Rgb([255, 0, 0]));
+    }
+
+    #[test]
+    fn test_transform_jpeg_bakes_in_orientation() {
+        // Stored sideways, displayed upright by the Exif orientation.
+        let jpeg = encode_image(
+            &marked(),
+            ImageFormat::Jpeg,
+            100,
+            Some(exif_with_orientation(
+                Orientation::Rotate90.to_exif().into(),
+            )),
+        )
+        .unwrap();
+        let flipped = transform_image(&jpeg, "image/jpeg", ImageTransform::FlipH).unwrap();
+
+        let mut decoder = JpegDecoder::new(Cursor::new(&flipped)).unwrap();
+        assert!(decoder.exif_metadata().unwrap().is_some());
+        assert_eq!(decoder.orientation().unwrap(), Orientation::NoTransforms);
+        assert_eq!(decoder.dimensions(), (2, 4));
+    }
+
+    #[test]
+    fn test_convert_image() {
+        let exif = exif_with_orientation(Orientation::NoTransforms.to_exif().into());
+        let png = encode_image(&marked(), ImageFormat::Png, 100, Some(exif)).unwrap();
+        for keep_exif in [true, false] {
+            let jpeg = convert_image(&png, "image/png", ImageFormat::Jpeg, 90, keep_exif).unwrap();
+            let mut decoder = JpegDecoder::new(Cursor::new(&jpeg)).unwrap();
+            assert_eq!(decoder.exif_metadata().unwrap().is_some(), keep_exif);
+            assert_eq!(decoder.dimensions(), (4, 2));
+        }
+        let webp = convert_image(&png, "image/png", ImageFormat::WebP, 90, false).unwrap();
+        assert_eq!(image::guess_format(&webp).unwrap(), ImageFormat::WebP);
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(parse_format("jpg"), Some(ImageFormat::Jpeg));
+        assert_eq!(parse_format("WEBP"), Some(ImageFormat::WebP));
+        assert_eq!(parse_format("image/png"), Some(ImageFormat::Png));
+        assert_eq!(parse_format("doc"), None);
+    }
+
+    #[test]
+    fn test_transform_rejects_non_images() {
+        let err =
+            transform_image(b"%PDF-1.7", "application/pdf", ImageTransform::Rotate90).unwrap_err();
+        assert!(err.is::<UnsupportedFormatError>());
+    }
+}
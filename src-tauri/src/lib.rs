@@ -1,26 +1,194 @@
+pub mod archive;
+pub mod blurhash;
+pub mod bookmarks;
+pub mod checksum;
 pub mod commands;
+pub mod connection_events;
+pub mod display;
+pub mod duplicates;
 pub mod ec2;
+pub mod error;
+pub mod gdrive;
+pub mod git_remote;
 pub mod github;
+pub mod github_api;
+pub mod health;
+pub mod host_keys;
+pub mod http;
+pub mod http_index;
+pub mod image_decode;
+pub mod image_edit;
+pub mod local;
+pub mod local_tree;
+pub mod logging;
+pub mod media_probe;
+pub mod metrics;
+pub mod paths;
+pub mod prefetch;
+pub mod profiles;
+pub mod raw_preview;
+pub mod recents;
+pub mod secrets;
+pub mod similar;
+pub mod ssh_config;
+pub mod ssh_util;
 pub mod storage;
+pub mod thumbnail_cache;
+pub mod thumbnails;
+pub mod trash;
+pub mod tunnel;
 pub mod utils;
+pub mod validation;
+pub mod view_prefs;
+pub mod watch;
+pub mod webdav;
 
 pub use commands::AppState;
+use tauri::{Manager, RunEvent, WindowEvent};
+use thumbnail_cache::ThumbnailCache;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    logging::init();
     tauri::Builder::default()
         .manage(AppState::new())
+        .setup(|app| {
+            if let Ok(dir) = app.path().app_log_dir() {
+                let _ = logging::logger().set_file(
+                    Some(&logging::log_path(&dir)),
+                    logging::DEFAULT_MAX_LOG_FILE_BYTES,
+                );
+            }
+            if let Ok(dir) = app.path().app_data_dir() {
+                let recents = recents::load_recent_files(&recents::recents_path(&dir));
+                if let Ok(mut state) = app.state::<AppState>().recent_files.lock() {
+                    *state = recents;
+                }
+            }
+            if let Ok(dir) = app.path().app_cache_dir() {
+                let _ = app
+                    .state::<AppState>()
+                    .thumbnail_cache
+                    .set(ThumbnailCache::new(
+                        dir.join(thumbnail_cache::THUMBNAIL_CACHE_DIR),
+                        thumbnail_cache::DEFAULT_THUMBNAIL_CACHE_BYTES,
+                    ));
+            }
+            Ok(())
+        })
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
             commands::connect_ec2,
             commands::connect_github,
+            commands::connect_github_api,
+            commands::connect_gdrive,
+            commands::connect_http,
+            commands::gdrive_auth_start,
+            commands::gdrive_auth_poll,
+            commands::connect_local,
+            commands::connect_sftp,
+            commands::connect_webdav,
+            commands::validate_ec2_connection,
+            commands::validate_github_connection,
+            commands::accept_host_key,
+            commands::load_ssh_config_hosts,
             commands::list_files,
+            commands::list_files_recursive,
+            commands::search_files,
+            commands::search_file_contents,
+            commands::stat_file,
+            commands::probe_media,
+            commands::set_file_modified,
+            commands::transform_image,
+            commands::convert_image,
+            commands::set_permissions,
+            commands::find_duplicates,
+            commands::find_similar_images,
+            commands::get_image_hash,
+            commands::get_file_checksum,
+            commands::get_directory_size,
             commands::read_file,
+            commands::get_recent_files,
+            commands::clear_recent_files,
+            commands::read_files,
+            commands::read_file_range,
+            commands::upload_file,
+            commands::upload_directory,
+            commands::download_file,
+            commands::download_directory_as_zip,
+            commands::extract_archive,
+            commands::transfer_file,
+            commands::cancel_download,
+            commands::delete_file,
+            commands::delete_directory,
+            commands::move_to_trash,
+            commands::list_trash,
+            commands::restore_from_trash,
+            commands::empty_trash,
+            commands::rename_file,
+            commands::copy_file,
+            commands::create_directory,
             commands::get_file_thumbnail,
+            commands::get_thumbnails_batch,
+            commands::get_thumbnail_cache_stats,
+            commands::prefetch_files,
+            commands::list_files_streamed,
+            commands::cancel_listing,
+            commands::cancel_operation,
+            commands::get_prefetch_cache_stats,
+            commands::clear_thumbnail_cache,
+            commands::set_thumbnail_cache_limit,
+            commands::set_max_image_pixels,
+            commands::watch_directory,
+            commands::unwatch_directory,
+            commands::set_display_profile,
             commands::disconnect,
             commands::get_storage_type,
             commands::is_connected,
+            commands::ping_storage,
+            commands::start_health_monitor,
+            commands::stop_health_monitor,
+            commands::get_logs,
+            commands::set_log_level,
+            commands::set_log_file,
+            commands::get_storage_metrics,
+            commands::add_bookmark,
+            commands::remove_bookmark,
+            commands::list_bookmarks,
+            commands::save_profile,
+            commands::list_profiles,
+            commands::delete_profile,
+            commands::connect_profile,
+            commands::store_secret,
+            commands::delete_secret,
+            commands::get_view_prefs,
+            commands::find_deleted_files,
+            commands::recover_deleted_file,
+            commands::get_file_history,
+            commands::read_file_at_revision,
+            commands::restore_file_version,
+            commands::prefetch_lfs,
+            commands::get_lfs_tracking,
+            commands::cleanup_remote_clone,
+            commands::refresh_repository,
+            commands::git_status,
+            commands::git_commit,
+            commands::git_push,
+            commands::set_sparse_paths,
+            commands::list_branches,
+            commands::switch_branch,
+            commands::set_view_prefs,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let RunEvent::ExitRequested { .. }
+            | RunEvent::WindowEvent {
+                event: WindowEvent::Destroyed,
+                ..
+            } = event
+            {
+                commands::shutdown(&app.state::<AppState>(), commands::SHUTDOWN_TIMEOUT);
+            }
+        });
 }
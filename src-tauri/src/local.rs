@@ -0,0 +1,947 @@
+//! A backend over a directory on this machine, for browsing photos without
+//! a server. Tools the SSH backends run remotely, such as grep and zip, run
+//! through the local shell instead.
+
+use crate::archive::{self, ArchiveFormat, ExtractFormat};
+use crate::checksum::{self, ChecksumAlgorithm, FileChecksum};
+use crate::error::StorageError;
+use crate::local_tree;
+use crate::metrics::{self, StorageMetrics};
+use crate::paths::{self, PathTranslator, RemotePath, ABSOLUTE_PATH_KEY};
+use crate::ssh_util::ExecOutput;
+use crate::storage::{
+    self, ChunkCallback, ContentSearchResult, CreateDirectoryResult, DeleteDirectoryResult,
+    DirectoryUsage, FileInfo, FileReadOutcome, ListOptions, RecursiveListing, SearchResult,
+    SortKey, Storage, StorageType, MAX_RECURSIVE_ENTRIES, STREAM_CHUNK_SIZE,
+};
+use crate::thumbnails::{self, Thumbnail, ThumbnailOptions};
+use crate::utils;
+use std::collections::BTreeMap;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+pub struct LocalStorage {
+    base_path: PathBuf,
+    /// `base_path` canonicalized by `connect`; `None` while disconnected.
+    root: Option<PathBuf>,
+    metrics: StorageMetrics,
+}
+
+impl LocalStorage {
+    pub fn new(base_path: PathBuf) -> Self {
+        LocalStorage {
+            base_path,
+            root: None,
+            metrics: StorageMetrics::default(),
+        }
+    }
+
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    fn root(&self) -> Result<&Path, StorageError> {
+        self.root.as_deref().ok_or(StorageError::NotConnected)
+    }
+
+    /// The location of `path` below the root, which need not exist yet.
+    /// Fails with [`StorageError::PermissionDenied`] when a symlink along the
+    /// way, the file itself included, leads outside the root.
+    fn resolve(&self, path: &str) -> Result<PathBuf, StorageError> {
+        let (remote, absolute) = self.locate(path)?;
+        self.check_inside(&remote, &absolute)?;
+        Ok(absolute)
+    }
+
+    /// Like [`LocalStorage::resolve`], but a symlink named by `path` itself
+    /// is not followed, so links can be removed or renamed wherever they
+    /// point.
+    fn resolve_entry(&self, path: &str) -> Result<PathBuf, StorageError> {
+        let (remote, absolute) = self.locate(path)?;
+        match remote.parent() {
+            Some(parent) => self.check_inside(&parent, &self.locate(parent.as_str())?.1)?,
+            None => self.check_inside(&remote, &absolute)?,
+        }
+        Ok(absolute)
+    }
+
+    fn locate(&self, path: &str) -> Result<(RemotePath, PathBuf), StorageError> {
+        let remote = self.path_translator().to_remote(path);
+        let absolute = self.root()?.join(remote.relative());
+        Ok((remote, absolute))
+    }
+
+    /// Canonicalizes the deepest existing ancestor of `absolute` and checks
+    /// that it is below the root. A dangling symlink counts as leading out.
+    fn check_inside(&self, remote: &RemotePath, absolute: &Path) -> Result<(), StorageError> {
+        let root = self.root()?;
+        let mut existing = absolute;
+        let canonical = loop {
+            match existing.canonicalize() {
+                Ok(canonical) => break Some(canonical),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    if fs::symlink_metadata(existing).is_ok() {
+                        break None;
+                    }
+                    match existing.parent() {
+                        Some(parent) => existing = parent,
+                        None => break None,
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        if canonical.is_some_and(|c| c.starts_with(root)) {
+            return Ok(());
+        }
+        Err(StorageError::PermissionDenied(format!(
+            "{} leads outside {}",
+            remote,
+            root.display()
+        )))
+    }
+
+    /// The canonical path of `absolute`, an entry below the root.
+    fn remote_path(&self, absolute: &Path) -> Result<RemotePath, StorageError> {
+        Ok(RemotePath::new(&local_tree::relative_path(
+            self.root()?,
+            absolute,
+        )))
+    }
+
+    /// Metadata of `absolute`, following symlinks, with a missing file
+    /// reported by its canonical path.
+    fn metadata(&self, remote: &RemotePath, absolute: &Path) -> Result<Metadata, StorageError> {
+        fs::metadata(absolute).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => StorageError::NotFound(remote.to_string()),
+            _ => e.into(),
+        })
+    }
+
+    fn stat_entry(&self, absolute: &Path) -> Result<FileInfo, StorageError> {
+        let remote = self.remote_path(absolute)?;
+        let metadata = self.metadata(&remote, absolute)?;
+        Ok(entry_info(&remote, absolute, &metadata))
+    }
+
+    /// Fails with [`StorageError::NotFound`] unless the parent of
+    /// `absolute` exists.
+    fn require_parent(&self, absolute: &Path) -> Result<(), StorageError> {
+        match absolute.parent() {
+            Some(parent) if !parent.is_dir() => {
+                Err(StorageError::NotFound(parent.display().to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Walks below `path` without following symlinks, skipping entries that
+    /// cannot be read. Stops with [`StorageError::Cancelled`] once
+    /// `cancelled` is set, or when `visit` returns `false`.
+    fn walk(
+        &self,
+        path: &str,
+        max_depth: usize,
+        cancelled: &AtomicBool,
+        mut visit: impl FnMut(&walkdir::DirEntry) -> Result<bool, StorageError>,
+    ) -> Result<(), StorageError> {
+        let root = self.resolve(path)?;
+        if !root.is_dir() {
+            return Err(StorageError::InvalidInput("not a directory".to_string()));
+        }
+        let mut walker = WalkDir::new(&root).min_depth(1);
+        if max_depth > 0 {
+            walker = walker.max_depth(max_depth);
+        }
+        for entry in walker.into_iter().filter_map(Result::ok) {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(StorageError::Cancelled);
+            }
+            if !visit(&entry)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(cmd);
+    command
+}
+
+fn shell_error(e: io::Error) -> StorageError {
+    match e.kind() {
+        io::ErrorKind::NotFound => StorageError::ToolMissing("sh"),
+        _ => e.into(),
+    }
+}
+
+/// Runs `cmd` with the local shell, as the SSH backends run it remotely.
+fn shell(cmd: &str) -> Result<ExecOutput, StorageError> {
+    let output = shell_command(cmd).output().map_err(shell_error)?;
+    Ok(ExecOutput {
+        stdout: output.stdout,
+        stderr: output.stderr,
+        exit_status: output.status.code().unwrap_or(-1),
+    })
+}
+
+/// Runs `cmd` with the local shell, handing its stdout to `on_chunk`.
+/// Returns `false` if `on_chunk` stopped it.
+fn shell_streamed(cmd: &str, on_chunk: &mut ChunkCallback<'_>) -> Result<bool, StorageError> {
+    let mut child = shell_command(cmd)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(shell_error)?;
+    let mut stdout = child.stdout.take().ok_or("shell stdout unavailable")?;
+    let mut buf = vec![0u8; STREAM_CHUNK_SIZE];
+    let mut completed = true;
+    loop {
+        let n = stdout.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        if !on_chunk(&buf[..n], None) {
+            completed = false;
+            let _ = child.kill();
+            break;
+        }
+    }
+    child.wait()?;
+    Ok(completed)
+}
+
+fn require_tool(tool: &'static str) -> Result<(), StorageError> {
+    let output = shell(&archive::tool_check_command(tool))?;
+    if !String::from_utf8_lossy(&output.stdout).contains("yes") {
+        return Err(StorageError::ToolMissing(tool));
+    }
+    Ok(())
+}
+
+fn unix_seconds(time: io::Result<std::time::SystemTime>) -> Option<u64> {
+    time.ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+/// The entry for `remote`, found at `absolute` with `metadata`. Symlinks
+/// are recognized from `absolute` whether or not `metadata` follows them.
+fn entry_info(remote: &RemotePath, absolute: &Path, metadata: &Metadata) -> FileInfo {
+    let name = remote.file_name().unwrap_or_default().to_string();
+    let link_target = fs::read_link(absolute)
+        .ok()
+        .map(|target| target.to_string_lossy().into_owned());
+    let mime_type = if metadata.is_dir() {
+        None
+    } else {
+        storage::detect_mime_type(&name)
+    };
+    #[cfg(unix)]
+    let (permissions, owner) = {
+        use std::os::unix::fs::MetadataExt;
+        (
+            Some(metadata.mode() & 0o7777),
+            Some(metadata.uid().to_string()),
+        )
+    };
+    #[cfg(not(unix))]
+    let (permissions, owner) = (None, None);
+    FileInfo {
+        name,
+        path: remote.to_string(),
+        size: metadata.len(),
+        is_dir: metadata.is_dir(),
+        is_symlink: link_target.is_some(),
+        link_target,
+        modified: unix_seconds(metadata.modified()),
+        mime_type,
+        thumbnail: None,
+        permissions,
+        owner,
+        extra: BTreeMap::from([(
+            ABSOLUTE_PATH_KEY.to_string(),
+            absolute.to_string_lossy().into_owned(),
+        )]),
+    }
+}
+
+/// Deletes `dir` bottom-up. Symlinks are removed, never followed.
+fn remove_tree(dir: &Path, result: &mut DeleteDirectoryResult) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_tree(&entry.path(), result)?;
+        } else {
+            fs::remove_file(entry.path())?;
+            result.files_removed += 1;
+        }
+    }
+    fs::remove_dir(dir)?;
+    result.dirs_removed += 1;
+    Ok(())
+}
+
+impl Storage for LocalStorage {
+    fn connect(&mut self) -> Result<(), StorageError> {
+        let root = self.base_path.canonicalize().map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => StorageError::NotFound(self.base_path.display().to_string()),
+            _ => e.into(),
+        })?;
+        if !root.is_dir() {
+            return Err(StorageError::InvalidInput(format!(
+                "{} is not a directory",
+                root.display()
+            )));
+        }
+        log::info!("Opened local directory {}", root.display());
+        self.root = Some(root);
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.root = None;
+    }
+
+    fn is_connected(&self) -> bool {
+        self.root.is_some()
+    }
+
+    fn ping(&self) -> Result<(), StorageError> {
+        fs::read_dir(self.root()?)?;
+        Ok(())
+    }
+
+    fn metrics(&self) -> &StorageMetrics {
+        &self.metrics
+    }
+
+    fn list_directory_page(
+        &self,
+        path: &str,
+        options: &ListOptions,
+    ) -> Result<(Vec<FileInfo>, usize), StorageError> {
+        let dir = self.path_translator().to_remote(path);
+        let absolute = self.resolve(path)?;
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&absolute)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !storage::is_listed(&name, options.show_hidden, &[]) {
+                continue;
+            }
+            let mut metadata = entry.metadata()?;
+            if metadata.is_symlink() && options.resolve_symlinks {
+                // Broken and looping links stay as they are.
+                if let Ok(target) = fs::metadata(entry.path()) {
+                    metadata = target;
+                }
+            }
+            if options.filter.matches_entry(&name, metadata.is_dir()) {
+                entries.push((name, metadata));
+            }
+        }
+        entries.sort_by(|(a, a_meta), (b, b_meta)| {
+            storage::compare_entries(
+                &sort_key(a, a_meta),
+                &sort_key(b, b_meta),
+                options.sort_by,
+                options.sort_order,
+            )
+        });
+
+        let files = entries
+            .iter()
+            .skip(options.offset)
+            .take(options.limit)
+            .map(|(name, metadata)| entry_info(&dir.join(name), &absolute.join(name), metadata))
+            .collect();
+        Ok((files, entries.len()))
+    }
+
+    fn list_directory_recursive(
+        &self,
+        path: &str,
+        max_depth: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<RecursiveListing, StorageError> {
+        let mut files = Vec::new();
+        self.walk(path, max_depth, cancelled, |entry| {
+            if files.len() > MAX_RECURSIVE_ENTRIES {
+                return Ok(false);
+            }
+            if let Ok(metadata) = entry.metadata() {
+                files.push(entry_info(
+                    &self.remote_path(entry.path())?,
+                    entry.path(),
+                    &metadata,
+                ));
+            }
+            Ok(true)
+        })?;
+        Ok(RecursiveListing::capped(files))
+    }
+
+    fn search(
+        &self,
+        root: &str,
+        pattern: &str,
+        case_sensitive: bool,
+        limit: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<SearchResult, StorageError> {
+        let mut files = Vec::new();
+        self.walk(root, 0, cancelled, |entry| {
+            let name = entry.file_name().to_string_lossy();
+            if storage::name_matches(&name, pattern, case_sensitive) {
+                if let Ok(metadata) = entry.metadata() {
+                    files.push(entry_info(
+                        &self.remote_path(entry.path())?,
+                        entry.path(),
+                        &metadata,
+                    ));
+                }
+            }
+            Ok(files.len() <= limit)
+        })?;
+        Ok(SearchResult::capped(files, limit))
+    }
+
+    fn search_contents(
+        &self,
+        root: &str,
+        query: &str,
+        regex: bool,
+        include: Option<&str>,
+        limit: usize,
+    ) -> Result<ContentSearchResult, StorageError> {
+        require_tool("grep")?;
+        let absolute = self.resolve(root)?;
+        let grep_cmd = utils::grep_command(
+            &absolute.to_string_lossy(),
+            query,
+            regex,
+            include,
+            None,
+            limit.saturating_add(1),
+        );
+        let mut matches = utils::parse_grep_output(&shell(&grep_cmd)?.stdout);
+        for m in &mut matches {
+            m.path = self.remote_path(Path::new(&m.path))?.to_string();
+        }
+        let truncated = matches.len() > limit;
+        matches.truncate(limit);
+        Ok(ContentSearchResult { matches, truncated })
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, StorageError> {
+        let absolute = self.resolve(path)?;
+        metrics::metered_read(&self.metrics, || Ok(fs::read(&absolute)?))
+    }
+
+    fn read_file_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, bool), StorageError> {
+        let start = Instant::now();
+        let mut file = File::open(self.resolve(path)?)?;
+        file.seek(SeekFrom::Start(offset))?;
+        // One extra byte tells whether anything follows the range.
+        let mut data = Vec::new();
+        file.take(length.saturating_add(1)).read_to_end(&mut data)?;
+        let (data, at_end) = storage::finish_range_read(data, length);
+        self.metrics.record_read(data.len() as u64, start.elapsed());
+        Ok((data, at_end))
+    }
+
+    fn read_files(&self, paths: &[String]) -> Result<Vec<FileReadOutcome>, StorageError> {
+        Ok(paths
+            .iter()
+            .map(|path| {
+                metrics::metered_read(&self.metrics, || {
+                    let absolute = self.resolve(path).map_err(|e| e.to_string())?;
+                    fs::read(absolute).map_err(|e| e.to_string())
+                })
+            })
+            .collect())
+    }
+
+    fn read_file_streamed(
+        &self,
+        path: &str,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        let start = Instant::now();
+        let mut file = File::open(self.resolve(path)?)?;
+        let total = Some(file.metadata()?.len());
+        let mut buf = vec![0u8; STREAM_CHUNK_SIZE];
+        let mut read = 0u64;
+        let completed = loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break true;
+            }
+            read += n as u64;
+            if !on_chunk(&buf[..n], total) {
+                break false;
+            }
+        };
+        self.metrics.record_read(read, start.elapsed());
+        Ok(completed)
+    }
+
+    fn write_file(&self, path: &str, data: &[u8]) -> Result<(), StorageError> {
+        let absolute = self.resolve(path)?;
+        self.require_parent(&absolute)?;
+        metrics::metered_write(&self.metrics, data.len(), || {
+            Ok(fs::write(&absolute, data)?)
+        })
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        Ok(self.resolve(path)?.try_exists()?)
+    }
+
+    fn stat(&self, path: &str) -> Result<FileInfo, StorageError> {
+        self.stat_entry(&self.resolve(path)?)
+    }
+
+    #[cfg(unix)]
+    fn set_permissions(&self, path: &str, mode: u32) -> Result<FileInfo, StorageError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let absolute = self.resolve(path)?;
+        fs::set_permissions(&absolute, fs::Permissions::from_mode(mode))?;
+        self.stat_entry(&absolute)
+    }
+
+    #[cfg(not(unix))]
+    fn set_permissions(&self, _path: &str, _mode: u32) -> Result<FileInfo, StorageError> {
+        Err(StorageError::Unsupported(
+            "Permission bits are not supported on this system".to_string(),
+        ))
+    }
+
+    fn set_modified(&self, path: &str, mtime: u64) -> Result<FileInfo, StorageError> {
+        let absolute = self.resolve(path)?;
+        self.stat_entry(&absolute)?;
+        File::open(&absolute)?.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+        self.stat_entry(&absolute)
+    }
+
+    fn directory_size(&self, path: &str) -> Result<DirectoryUsage, StorageError> {
+        let mut usage = DirectoryUsage::default();
+        let mut visited = 0usize;
+        self.walk(path, 0, &AtomicBool::new(false), |entry| {
+            visited += 1;
+            if visited > MAX_RECURSIVE_ENTRIES {
+                usage.truncated = true;
+                return Ok(false);
+            }
+            // Symlinks are counted as files and never descended into.
+            if entry.file_type().is_dir() {
+                usage.dir_count += 1;
+            } else {
+                usage.file_count += 1;
+                usage.total_bytes += entry.metadata().map_or(0, |m| m.len());
+            }
+            Ok(true)
+        })?;
+        Ok(usage)
+    }
+
+    fn archive_directory(
+        &self,
+        path: &str,
+        format: ArchiveFormat,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        require_tool(format.remote_tool())?;
+        let absolute = self.resolve(path)?;
+        shell_streamed(
+            &archive::archive_command(&absolute.to_string_lossy(), format, None),
+            on_chunk,
+        )
+    }
+
+    fn extract_archive(&self, path: &str, destination: &str) -> Result<Vec<String>, StorageError> {
+        let format = ExtractFormat::from_path(path)
+            .ok_or_else(|| format!("Unsupported archive type: {}", path))?;
+        require_tool(format.remote_tool())?;
+        let extract_cmd = archive::extract_command(
+            &self.resolve(path)?.to_string_lossy(),
+            &self.resolve(destination)?.to_string_lossy(),
+            format,
+        );
+        let output = shell(&extract_cmd)?;
+        Ok(archive::parse_extract_output(&String::from_utf8_lossy(
+            &output.stdout,
+        ))?)
+    }
+
+    fn checksum(
+        &self,
+        path: &str,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<FileChecksum, StorageError> {
+        let info = self.stat(path)?;
+        if info.is_dir {
+            return Err(StorageError::InvalidInput("is a directory".to_string()));
+        }
+        Ok(FileChecksum {
+            algorithm,
+            digest: checksum::hash_streamed(self, path, algorithm)?,
+            size: info.size,
+        })
+    }
+
+    fn checksums(
+        &self,
+        paths: &[String],
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<Vec<Option<String>>, StorageError> {
+        Ok(paths
+            .iter()
+            .map(|p| checksum::hash_streamed(self, p, algorithm).ok())
+            .collect())
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        let absolute = self.resolve_entry(path)?;
+        if fs::symlink_metadata(&absolute)?.is_dir() {
+            return Err(StorageError::InvalidInput("is a directory".to_string()));
+        }
+        fs::remove_file(&absolute)?;
+        Ok(())
+    }
+
+    fn delete_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+    ) -> Result<DeleteDirectoryResult, StorageError> {
+        let dir = paths::guard_directory_delete(&self.path_translator(), path)?;
+        let absolute = self.resolve_entry(dir.as_str())?;
+        if !fs::symlink_metadata(&absolute)?.is_dir() {
+            return Err(StorageError::InvalidInput("not a directory".to_string()));
+        }
+        if !recursive && fs::read_dir(&absolute)?.next().is_some() {
+            return Err(StorageError::InvalidInput(
+                "Directory not empty".to_string(),
+            ));
+        }
+        let mut result = DeleteDirectoryResult::default();
+        remove_tree(&absolute, &mut result)?;
+        Ok(result)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<FileInfo, StorageError> {
+        let source = self.resolve_entry(from)?;
+        let destination = self.resolve_entry(to)?;
+        self.require_parent(&destination)?;
+        fs::rename(&source, &destination)?;
+        let metadata = fs::symlink_metadata(&destination)?;
+        Ok(entry_info(
+            &self.remote_path(&destination)?,
+            &destination,
+            &metadata,
+        ))
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> Result<FileInfo, StorageError> {
+        let source = self.resolve(from)?;
+        let destination = self.resolve(to)?;
+        if fs::metadata(&source)?.is_dir() {
+            return Err(StorageError::InvalidInput(
+                "is a directory; use copy_directory".to_string(),
+            ));
+        }
+        self.require_parent(&destination)?;
+        fs::copy(&source, &destination)?;
+        // Like `cp -p`, keep the modification time.
+        if let Ok(modified) = fs::metadata(&source)?.modified() {
+            File::options()
+                .write(true)
+                .open(&destination)?
+                .set_modified(modified)?;
+        }
+        self.stat_entry(&destination)
+    }
+
+    fn create_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+    ) -> Result<CreateDirectoryResult, StorageError> {
+        let absolute = self.resolve(path)?;
+        let existed = match fs::metadata(&absolute) {
+            Ok(metadata) if metadata.is_dir() => true,
+            Ok(_) => {
+                return Err(StorageError::InvalidInput(format!(
+                    "Path exists and is not a directory: {}",
+                    absolute.display()
+                )))
+            }
+            Err(_) => false,
+        };
+        if !existed {
+            if recursive {
+                fs::create_dir_all(&absolute)?;
+            } else {
+                match absolute.parent() {
+                    Some(parent) if !parent.is_dir() => {
+                        return Err(format!(
+                            "Parent directory does not exist: {}",
+                            parent.display()
+                        )
+                        .into())
+                    }
+                    _ => fs::create_dir(&absolute)?,
+                }
+            }
+        }
+        Ok(CreateDirectoryResult {
+            directory: self.stat_entry(&absolute)?,
+            existed,
+        })
+    }
+
+    fn get_file_thumbnail(
+        &self,
+        path: &str,
+        max_size: u32,
+        options: ThumbnailOptions,
+    ) -> Result<Thumbnail, StorageError> {
+        thumbnails::fetched_file_thumbnail(self, path, max_size, options)
+            .map_err(StorageError::from)
+    }
+
+    fn get_root_path(&self) -> String {
+        self.root
+            .as_deref()
+            .unwrap_or(&self.base_path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn path_translator(&self) -> PathTranslator {
+        PathTranslator::new(&self.get_root_path())
+    }
+
+    fn storage_type(&self) -> StorageType {
+        StorageType::Local
+    }
+
+    fn connection_id(&self) -> String {
+        format!("local:{}", self.get_root_path())
+    }
+}
+
+fn sort_key<'a>(name: &'a str, metadata: &Metadata) -> SortKey<'a> {
+    SortKey {
+        name,
+        is_dir: metadata.is_dir(),
+        size: metadata.len(),
+        modified: unix_seconds(metadata.modified()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MediaFilter;
+
+    fn test_dir() -> tempfile::TempDir {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("photos/2024")).unwrap();
+        fs::write(dir.join("photos/a.jpg"), b"jpeg").unwrap();
+        fs::write(dir.join("photos/2024/b.png"), b"png!").unwrap();
+        fs::write(dir.join("notes.txt"), b"hello world\n").unwrap();
+        temp
+    }
+
+    fn connected(dir: &Path) -> LocalStorage {
+        let mut storage = LocalStorage::new(dir.to_path_buf());
+        storage.connect().unwrap();
+        storage
+    }
+
+    #[test]
+    fn test_connect_requires_directory() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let mut storage = LocalStorage::new(dir.join("missing"));
+        assert!(matches!(storage.connect(), Err(StorageError::NotFound(_))));
+        let mut storage = LocalStorage::new(dir.join("notes.txt"));
+        assert!(matches!(
+            storage.connect(),
+            Err(StorageError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            storage.read_file("/notes.txt"),
+            Err(StorageError::NotConnected)
+        ));
+
+        let mut storage = connected(dir);
+        assert!(storage.is_connected());
+        storage.ping().unwrap();
+        assert_eq!(storage.storage_type(), StorageType::Local);
+        storage.disconnect();
+        assert!(!storage.is_connected());
+    }
+
+    #[test]
+    fn test_list_and_read() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let storage = connected(dir);
+        let options = ListOptions::default();
+        let (files, total) = storage.list_directory_page("/", &options).unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(files[0].path, "/photos");
+        assert!(files[0].is_dir);
+        assert_eq!(files[1].path, "/notes.txt");
+        assert_eq!(files[1].size, 12);
+
+        let images = ListOptions {
+            filter: MediaFilter::Images,
+            ..options
+        };
+        let (files, _) = storage.list_directory_page("/photos", &images).unwrap();
+        let names: Vec<_> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["2024", "a.jpg"]);
+
+        let all = storage
+            .list_directory_recursive("/", 0, &AtomicBool::new(false))
+            .unwrap();
+        assert!(!all.truncated);
+        let paths: Vec<_> = all.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/notes.txt",
+                "/photos",
+                "/photos/2024",
+                "/photos/2024/b.png",
+                "/photos/a.jpg"
+            ]
+        );
+        let found = storage
+            .search("/", "B.PNG", false, 10, &AtomicBool::new(false))
+            .unwrap();
+        assert_eq!(found.files[0].path, "/photos/2024/b.png");
+
+        assert_eq!(storage.read_file("/photos/a.jpg").unwrap(), b"jpeg");
+        assert_eq!(
+            storage.read_file_range("/notes.txt", 6, 5).unwrap(),
+            (b"world".to_vec(), false)
+        );
+        let usage = storage.directory_size("/").unwrap();
+        assert_eq!((usage.file_count, usage.dir_count), (3, 2));
+        assert_eq!(usage.total_bytes, 20);
+        assert_eq!(storage.metrics().snapshot().files_read, 2);
+    }
+
+    #[test]
+    fn test_write_rename_copy_delete() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let storage = connected(dir);
+        storage.write_file("/photos/c.jpg", b"new").unwrap();
+        assert!(matches!(
+            storage.write_file("/missing/c.jpg", b"new"),
+            Err(StorageError::NotFound(_))
+        ));
+        let moved = storage.rename("/photos/c.jpg", "/c.jpg").unwrap();
+        assert_eq!(moved.path, "/c.jpg");
+        storage.set_modified("/c.jpg", 1_000_000).unwrap();
+        let copy = storage.copy_file("/c.jpg", "/photos/d.jpg").unwrap();
+        assert_eq!((copy.size, copy.modified), (3, Some(1_000_000)));
+
+        let created = storage.create_directory("/x/y", true).unwrap();
+        assert!(!created.existed && created.directory.is_dir);
+        assert!(storage.create_directory("/x/y", false).unwrap().existed);
+        assert!(matches!(
+            storage.delete_file("/x"),
+            Err(StorageError::InvalidInput(_))
+        ));
+        assert!(storage.delete_directory("/x", false).is_err());
+        let removed = storage.delete_directory("/x", true).unwrap();
+        assert_eq!(removed.dirs_removed, 2);
+        storage.delete_file("/c.jpg").unwrap();
+        assert!(!storage.exists("/c.jpg").unwrap());
+        assert!(storage.delete_directory("/", true).is_err());
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let temp = test_dir();
+        let dir = temp.path();
+        image::DynamicImage::new_rgb8(64, 32)
+            .save(dir.join("photos/wide.png"))
+            .unwrap();
+        let storage = connected(dir);
+        let thumbnail = storage
+            .get_file_thumbnail("/photos/wide.png", 16, ThumbnailOptions::default())
+            .unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (16, 8));
+        assert!(thumbnail.data_uri.starts_with("data:image/png;base64,"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rejects_paths_leading_outside() {
+        use std::os::unix::fs::symlink;
+
+        let temp = test_dir();
+        let dir = temp.path();
+        let outside = dir.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret.txt"), b"secret").unwrap();
+        let root = dir.join("photos");
+        symlink(&outside, root.join("escape")).unwrap();
+        symlink(outside.join("nowhere"), root.join("dangling")).unwrap();
+        symlink(root.join("a.jpg"), root.join("inside.jpg")).unwrap();
+        let storage = connected(&root);
+
+        assert_eq!(
+            storage.read_file("/../notes.txt").unwrap_err().code(),
+            "NOT_FOUND"
+        );
+        for path in ["/escape/secret.txt", "/dangling"] {
+            assert!(matches!(
+                storage.read_file(path),
+                Err(StorageError::PermissionDenied(_))
+            ));
+        }
+        for path in ["/dangling", "/escape/new.txt"] {
+            assert!(matches!(
+                storage.write_file(path, b"x"),
+                Err(StorageError::PermissionDenied(_))
+            ));
+        }
+        assert!(!outside.join("nowhere").exists());
+        assert_eq!(storage.read_file("/inside.jpg").unwrap(), b"jpeg");
+
+        // Links themselves can still be listed and removed.
+        let (files, _) = storage
+            .list_directory_page("/", &ListOptions::default())
+            .unwrap();
+        let escape = files.iter().find(|f| f.name == "escape").unwrap();
+        assert!(escape.is_symlink && escape.is_dir);
+        storage.delete_file("/escape").unwrap();
+        assert!(outside.join("secret.txt").exists());
+    }
+}
@@ -0,0 +1,115 @@
+use glob::Pattern;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// A local directory tree flattened for upload. Paths are relative to the
+/// walked root and use `/` separators.
+#[derive(Debug, Default, PartialEq)]
+pub struct LocalTree {
+    /// Directories in creation order, parents before children.
+    pub dirs: Vec<String>,
+    pub files: Vec<String>,
+    /// Entries that could not be read while walking, with the reason.
+    pub errors: Vec<(String, String)>,
+}
+
+/// Walks `root`, keeping files whose relative path matches `include` when
+/// given. Symlinks are skipped unless `follow_symlinks` is set, in which
+/// case walkdir's loop detection applies.
+pub fn scan_local_tree(root: &Path, include: Option<&Pattern>, follow_symlinks: bool) -> LocalTree {
+    let mut tree = LocalTree::default();
+    let walker = WalkDir::new(root)
+        .min_depth(1)
+        .follow_links(follow_symlinks)
+        .sort_by_file_name();
+
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = e.path().map(|p| relative_path(root, p)).unwrap_or_default();
+                tree.errors.push((path, e.to_string()));
+                continue;
+            }
+        };
+        if entry.path_is_symlink() && !follow_symlinks {
+            continue;
+        }
+
+        let rel = relative_path(root, entry.path());
+        if entry.file_type().is_dir() {
+            tree.dirs.push(rel);
+        } else if include.is_none_or(|pattern| pattern.matches(&rel)) {
+            tree.files.push(rel);
+        }
+    }
+    tree
+}
+
+/// `path` relative to `root`, with `/` separators.
+pub(crate) fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn fixture() -> tempfile::TempDir {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("2023/trip")).unwrap();
+        fs::write(root.join("a.jpg"), b"a").unwrap();
+        fs::write(root.join("notes.txt"), b"n").unwrap();
+        fs::write(root.join("2023/b.JPG"), b"b").unwrap();
+        fs::write(root.join("2023/trip/c.jpg"), b"c").unwrap();
+        temp
+    }
+
+    #[test]
+    fn test_scan_local_tree_lists_dirs_before_children() {
+        let temp = fixture();
+        let root = temp.path();
+        let tree = scan_local_tree(root, None, false);
+        assert_eq!(tree.dirs, vec!["2023", "2023/trip"]);
+        assert_eq!(
+            tree.files,
+            vec!["2023/b.JPG", "2023/trip/c.jpg", "a.jpg", "notes.txt"]
+        );
+        assert!(tree.errors.is_empty());
+    }
+
+    #[test]
+    fn test_scan_local_tree_include_glob() {
+        let temp = fixture();
+        let root = temp.path();
+        let pattern = Pattern::new("*.jpg").unwrap();
+        let tree = scan_local_tree(root, Some(&pattern), false);
+        assert_eq!(tree.files, vec!["2023/trip/c.jpg", "a.jpg"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_local_tree_skips_symlinks_by_default() {
+        let temp = fixture();
+        let root = temp.path();
+        std::os::unix::fs::symlink(root.join("a.jpg"), root.join("link.jpg")).unwrap();
+        std::os::unix::fs::symlink(root, root.join("2023/loop")).unwrap();
+
+        let tree = scan_local_tree(root, None, false);
+        assert!(!tree.files.iter().any(|f| f == "link.jpg"));
+        assert!(!tree.dirs.iter().any(|d| d.ends_with("loop")));
+
+        // Following links picks up the file link and reports the loop.
+        let tree = scan_local_tree(root, None, true);
+        assert!(tree.files.iter().any(|f| f == "link.jpg"));
+        assert!(!tree.errors.is_empty());
+    }
+}
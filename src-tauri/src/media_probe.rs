@@ -0,0 +1,197 @@
+//! Reading the dimensions and duration of media files from their headers,
+//! without transferring the whole file.
+
+use crate::ssh_util;
+use crate::storage::{detect_mime_type, Storage};
+use image::{ImageFormat, ImageReader};
+use serde::{Deserialize, Serialize};
+use shell_escape::escape;
+use ssh2::Session;
+use std::io::Cursor;
+
+/// Bytes read from the start of an image to find its dimensions.
+pub const PROBE_HEADER_BYTES: u64 = 64 * 1024;
+/// [`crate::storage::FileInfo::extra`] keys set by listings that include
+/// dimensions.
+pub const WIDTH_KEY: &str = "width";
+pub const HEIGHT_KEY: &str = "height";
+
+/// What is known about a media file; fields that could not be determined
+/// are `None`.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct MediaProbe {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub duration_secs: Option<f64>,
+    pub codec: Option<String>,
+}
+
+/// Dimensions of a JPEG, PNG, GIF, WebP or BMP image from the start of
+/// the file.
+pub fn probe_image_header(header: &[u8]) -> MediaProbe {
+    let Ok(reader) = ImageReader::new(Cursor::new(header)).with_guessed_format() else {
+        return MediaProbe::default();
+    };
+    let codec = match reader.format() {
+        Some(ImageFormat::Jpeg) => "jpeg",
+        Some(ImageFormat::Png) => "png",
+        Some(ImageFormat::Gif) => "gif",
+        Some(ImageFormat::WebP) => "webp",
+        Some(ImageFormat::Bmp) => "bmp",
+        _ => return MediaProbe::default(),
+    };
+    match reader.into_dimensions() {
+        Ok((width, height)) => MediaProbe {
+            width: Some(width),
+            height: Some(height),
+            duration_secs: None,
+            codec: Some(codec.to_string()),
+        },
+        Err(_) => MediaProbe::default(),
+    }
+}
+
+/// Builds a command printing ffprobe's JSON for the first video stream of
+/// `absolute_path`. Exits 127 when ffprobe is missing.
+pub fn ffprobe_command(absolute_path: &str) -> String {
+    format!(
+        "command -v ffprobe >/dev/null 2>&1 || exit 127; \
+         ffprobe -v error -select_streams v:0 \
+         -show_entries stream=width,height,codec_name:format=duration \
+         -print_format json {}",
+        escape(absolute_path.into())
+    )
+}
+
+#[derive(Deserialize)]
+struct FfprobeOutput {
+    #[serde(default)]
+    streams: Vec<FfprobeStream>,
+    format: Option<FfprobeFormat>,
+}
+
+#[derive(Deserialize)]
+struct FfprobeStream {
+    width: Option<u32>,
+    height: Option<u32>,
+    codec_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FfprobeFormat {
+    /// ffprobe prints numbers in `format` as strings.
+    duration: Option<String>,
+}
+
+pub fn parse_ffprobe_output(json: &str) -> MediaProbe {
+    let Ok(output) = serde_json::from_str::<FfprobeOutput>(json) else {
+        return MediaProbe::default();
+    };
+    let stream = output.streams.into_iter().next();
+    MediaProbe {
+        width: stream.as_ref().and_then(|s| s.width),
+        height: stream.as_ref().and_then(|s| s.height),
+        duration_secs: output
+            .format
+            .and_then(|f| f.duration)
+            .and_then(|d| d.parse().ok()),
+        codec: stream.and_then(|s| s.codec_name),
+    }
+}
+
+/// Probes `path` on an SSH-backed `storage`, where the file is at
+/// `absolute_path` on the host behind `session`. Videos are probed with a
+/// remote ffprobe and images from their first [`PROBE_HEADER_BYTES`].
+pub fn probe_remote(
+    storage: &dyn Storage,
+    session: &Session,
+    absolute_path: &str,
+    path: &str,
+) -> Result<MediaProbe, Box<dyn std::error::Error>> {
+    if detect_mime_type(path).is_some_and(|mime| mime.starts_with("video/")) {
+        storage.metrics().record_command();
+        let output = ssh_util::ssh_exec(session, &ffprobe_command(absolute_path))?;
+        if !output.success() {
+            return Ok(MediaProbe::default());
+        }
+        return Ok(parse_ffprobe_output(&String::from_utf8_lossy(
+            &output.stdout,
+        )));
+    }
+    let (header, _) = storage.read_file_range(path, 0, PROBE_HEADER_BYTES)?;
+    Ok(probe_image_header(&header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+
+    fn encoded(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut buffer = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut buffer, format)
+            .unwrap();
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn test_probe_image_header() {
+        for (format, codec) in [
+            (ImageFormat::Jpeg, "jpeg"),
+            (ImageFormat::Png, "png"),
+            (ImageFormat::Gif, "gif"),
+            (ImageFormat::WebP, "webp"),
+            (ImageFormat::Bmp, "bmp"),
+        ] {
+            assert_eq!(
+                probe_image_header(&encoded(37, 21, format)),
+                MediaProbe {
+                    width: Some(37),
+                    height: Some(21),
+                    duration_secs: None,
+                    codec: Some(codec.to_string()),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_header_is_empty() {
+        assert_eq!(probe_image_header(b"%PDF-1.7"), MediaProbe::default());
+        assert_eq!(probe_image_header(b""), MediaProbe::default());
+        // A PNG cut off inside its header.
+        let png = encoded(37, 21, ImageFormat::Png);
+        assert_eq!(probe_image_header(&png[..12]), MediaProbe::default());
+    }
+
+    #[test]
+    fn test_parse_ffprobe_output() {
+        let json = r#"{
+            "programs": [],
+            "streams": [{ "codec_name": "h264", "width": 1920, "height": 1080 }],
+            "format": { "duration": "12.480000" }
+        }"#;
+        assert_eq!(
+            parse_ffprobe_output(json),
+            MediaProbe {
+                width: Some(1920),
+                height: Some(1080),
+                duration_secs: Some(12.48),
+                codec: Some("h264".to_string()),
+            }
+        );
+        assert_eq!(
+            parse_ffprobe_output(r#"{"format": {"duration": "N/A"}}"#),
+            MediaProbe::default()
+        );
+        assert_eq!(parse_ffprobe_output("not json"), MediaProbe::default());
+    }
+
+    #[test]
+    fn test_ffprobe_command_quotes_path() {
+        assert!(
+            ffprobe_command("/srv/my clip.mp4").ends_with("-print_format json '/srv/my clip.mp4'")
+        );
+    }
+}
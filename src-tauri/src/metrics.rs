@@ -0,0 +1,403 @@
+//! Transfer counters kept by each backend for the current connection, so
+//! the UI can show how much data was moved and spot repeated downloads.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+pub struct StorageMetrics {
+    bytes_downloaded: AtomicU64,
+    bytes_uploaded: AtomicU64,
+    files_read: AtomicU64,
+    files_written: AtomicU64,
+    thumbnails_generated: AtomicU64,
+    remote_commands: AtomicU64,
+    transfer_ms: AtomicU64,
+}
+
+impl StorageMetrics {
+    pub fn record_read(&self, bytes: u64, elapsed: Duration) {
+        self.files_read.fetch_add(1, Ordering::Relaxed);
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+        self.add_time(elapsed);
+    }
+
+    pub fn record_write(&self, bytes: u64, elapsed: Duration) {
+        self.files_written.fetch_add(1, Ordering::Relaxed);
+        self.bytes_uploaded.fetch_add(bytes, Ordering::Relaxed);
+        self.add_time(elapsed);
+    }
+
+    pub fn record_thumbnail(&self) {
+        self.thumbnails_generated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_command(&self) {
+        self.remote_commands.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn add_time(&self, elapsed: Duration) {
+        self.transfer_ms
+            .fetch_add(elapsed.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            bytes_uploaded: self.bytes_uploaded.load(Ordering::Relaxed),
+            files_read: self.files_read.load(Ordering::Relaxed),
+            files_written: self.files_written.load(Ordering::Relaxed),
+            thumbnails_generated: self.thumbnails_generated.load(Ordering::Relaxed),
+            remote_commands: self.remote_commands.load(Ordering::Relaxed),
+            transfer_ms: self.transfer_ms.load(Ordering::Relaxed),
+            throughput_bytes_per_sec: 0.0,
+        }
+        .with_throughput()
+    }
+}
+
+/// Runs `read`, counting the file and its bytes when it succeeds.
+pub fn metered_read<E>(
+    metrics: &StorageMetrics,
+    read: impl FnOnce() -> Result<Vec<u8>, E>,
+) -> Result<Vec<u8>, E> {
+    let start = Instant::now();
+    let data = read()?;
+    metrics.record_read(data.len() as u64, start.elapsed());
+    Ok(data)
+}
+
+/// Runs `write` of `bytes`, counting them when it succeeds.
+pub fn metered_write<T, E>(
+    metrics: &StorageMetrics,
+    bytes: usize,
+    write: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let start = Instant::now();
+    let written = write()?;
+    metrics.record_write(bytes as u64, start.elapsed());
+    Ok(written)
+}
+
+#[derive(Debug, Serialize, Clone, Copy, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    pub bytes_downloaded: u64,
+    pub bytes_uploaded: u64,
+    pub files_read: u64,
+    pub files_written: u64,
+    pub thumbnails_generated: u64,
+    pub remote_commands: u64,
+    /// Time spent reading and writing files.
+    pub transfer_ms: u64,
+    /// Bytes moved either way per second of transfer time.
+    pub throughput_bytes_per_sec: f64,
+}
+
+impl MetricsSnapshot {
+    fn with_throughput(mut self) -> Self {
+        let bytes = self.bytes_downloaded + self.bytes_uploaded;
+        self.throughput_bytes_per_sec = if self.transfer_ms == 0 {
+            0.0
+        } else {
+            bytes as f64 * 1000.0 / self.transfer_ms as f64
+        };
+        self
+    }
+
+    /// The totals of both.
+    pub fn combined(self, other: &MetricsSnapshot) -> Self {
+        MetricsSnapshot {
+            bytes_downloaded: self.bytes_downloaded + other.bytes_downloaded,
+            bytes_uploaded: self.bytes_uploaded + other.bytes_uploaded,
+            files_read: self.files_read + other.files_read,
+            files_written: self.files_written + other.files_written,
+            thumbnails_generated: self.thumbnails_generated + other.thumbnails_generated,
+            remote_commands: self.remote_commands + other.remote_commands,
+            transfer_ms: self.transfer_ms + other.transfer_ms,
+            throughput_bytes_per_sec: 0.0,
+        }
+        .with_throughput()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::archive::ArchiveFormat;
+    use crate::checksum::{ChecksumAlgorithm, FileChecksum};
+    use crate::error::StorageError;
+    use crate::paths::PathTranslator;
+    use crate::storage::{
+        ChunkCallback, ContentSearchResult, CreateDirectoryResult, DeleteDirectoryResult,
+        DirectoryUsage, FileInfo, FileReadOutcome, ListOptions, RecursiveListing, SearchResult,
+        Storage, StorageType,
+    };
+    use crate::thumbnails::{self, Thumbnail, ThumbnailOptions};
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+
+    /// In-memory files, metered like a real backend. Operations the tests
+    /// do not need fail as unsupported.
+    #[derive(Default)]
+    pub(crate) struct MockStorage {
+        pub files: HashMap<String, Vec<u8>>,
+        pub metrics: StorageMetrics,
+        /// Returned by every read when set.
+        pub read_error: Option<StorageError>,
+        /// Paths and content of every write, in order.
+        pub written: Mutex<Vec<(String, Vec<u8>)>>,
+    }
+
+    fn unsupported() -> StorageError {
+        StorageError::Unsupported("not implemented by MockStorage".to_string())
+    }
+
+    impl Storage for MockStorage {
+        fn connect(&mut self) -> Result<(), StorageError> {
+            Ok(())
+        }
+        fn disconnect(&mut self) {}
+        fn is_connected(&self) -> bool {
+            true
+        }
+        fn ping(&self) -> Result<(), StorageError> {
+            Ok(())
+        }
+        fn metrics(&self) -> &StorageMetrics {
+            &self.metrics
+        }
+        fn list_directory_page(
+            &self,
+            _path: &str,
+            _options: &ListOptions,
+        ) -> Result<(Vec<FileInfo>, usize), StorageError> {
+            Err(unsupported())
+        }
+        fn list_directory_recursive(
+            &self,
+            _path: &str,
+            _max_depth: usize,
+            _cancelled: &AtomicBool,
+        ) -> Result<RecursiveListing, StorageError> {
+            Err(unsupported())
+        }
+        fn search(
+            &self,
+            _root: &str,
+            _pattern: &str,
+            _case_sensitive: bool,
+            _limit: usize,
+            _cancelled: &AtomicBool,
+        ) -> Result<SearchResult, StorageError> {
+            Err(unsupported())
+        }
+        fn search_contents(
+            &self,
+            _root: &str,
+            _query: &str,
+            _regex: bool,
+            _include: Option<&str>,
+            _limit: usize,
+        ) -> Result<ContentSearchResult, StorageError> {
+            Err(unsupported())
+        }
+        fn read_file(&self, path: &str) -> Result<Vec<u8>, StorageError> {
+            metered_read(&self.metrics, || {
+                if let Some(e) = &self.read_error {
+                    return Err(e.clone());
+                }
+                self.files
+                    .get(path)
+                    .cloned()
+                    .ok_or_else(|| StorageError::NotFound(path.to_string()))
+            })
+        }
+        fn read_file_range(
+            &self,
+            _path: &str,
+            _offset: u64,
+            _length: u64,
+        ) -> Result<(Vec<u8>, bool), StorageError> {
+            Err(unsupported())
+        }
+        fn read_files(&self, _paths: &[String]) -> Result<Vec<FileReadOutcome>, StorageError> {
+            Err(unsupported())
+        }
+        fn read_file_streamed(
+            &self,
+            _path: &str,
+            _on_chunk: &mut ChunkCallback<'_>,
+        ) -> Result<bool, StorageError> {
+            Err(unsupported())
+        }
+        fn write_file(&self, path: &str, data: &[u8]) -> Result<(), StorageError> {
+            self.written
+                .lock()
+                .unwrap()
+                .push((path.to_string(), data.to_vec()));
+            Ok(())
+        }
+        fn exists(&self, path: &str) -> Result<bool, StorageError> {
+            Ok(self.files.contains_key(path))
+        }
+        fn stat(&self, _path: &str) -> Result<FileInfo, StorageError> {
+            Err(unsupported())
+        }
+        fn set_permissions(&self, _path: &str, _mode: u32) -> Result<FileInfo, StorageError> {
+            Err(unsupported())
+        }
+        fn set_modified(&self, _path: &str, _mtime: u64) -> Result<FileInfo, StorageError> {
+            Err(unsupported())
+        }
+        fn directory_size(&self, _path: &str) -> Result<DirectoryUsage, StorageError> {
+            Err(unsupported())
+        }
+        fn archive_directory(
+            &self,
+            _path: &str,
+            _format: ArchiveFormat,
+            _on_chunk: &mut ChunkCallback<'_>,
+        ) -> Result<bool, StorageError> {
+            Err(unsupported())
+        }
+        fn extract_archive(
+            &self,
+            _path: &str,
+            _destination: &str,
+        ) -> Result<Vec<String>, StorageError> {
+            Err(unsupported())
+        }
+        fn checksum(
+            &self,
+            _path: &str,
+            _algorithm: ChecksumAlgorithm,
+        ) -> Result<FileChecksum, StorageError> {
+            Err(unsupported())
+        }
+        fn checksums(
+            &self,
+            _paths: &[String],
+            _algorithm: ChecksumAlgorithm,
+        ) -> Result<Vec<Option<String>>, StorageError> {
+            Err(unsupported())
+        }
+        fn delete_file(&self, _path: &str) -> Result<(), StorageError> {
+            Err(unsupported())
+        }
+        fn delete_directory(
+            &self,
+            _path: &str,
+            _recursive: bool,
+        ) -> Result<DeleteDirectoryResult, StorageError> {
+            Err(unsupported())
+        }
+        fn rename(&self, _from: &str, _to: &str) -> Result<FileInfo, StorageError> {
+            Err(unsupported())
+        }
+        fn copy_file(&self, _from: &str, _to: &str) -> Result<FileInfo, StorageError> {
+            Err(unsupported())
+        }
+        fn create_directory(
+            &self,
+            _path: &str,
+            _recursive: bool,
+        ) -> Result<CreateDirectoryResult, StorageError> {
+            Err(unsupported())
+        }
+        fn get_file_thumbnail(
+            &self,
+            path: &str,
+            _max_size: u32,
+            _options: ThumbnailOptions,
+        ) -> Result<Thumbnail, StorageError> {
+            let data = self.read_file(path)?;
+            Ok(Thumbnail {
+                data_uri: format!("data:image/png;base64,{}", data.len()),
+                blurhash: None,
+                width: 1,
+                height: 1,
+            })
+        }
+        fn get_root_path(&self) -> String {
+            "/".to_string()
+        }
+        fn path_translator(&self) -> PathTranslator {
+            PathTranslator::new("/")
+        }
+        fn storage_type(&self) -> StorageType {
+            StorageType::Ec2
+        }
+        fn connection_id(&self) -> String {
+            "mock".to_string()
+        }
+    }
+
+    fn mock() -> MockStorage {
+        let mut storage = MockStorage::default();
+        storage.files.insert("/a.png".to_string(), vec![0; 100]);
+        storage.files.insert("/b.png".to_string(), vec![0; 50]);
+        storage
+    }
+
+    #[test]
+    fn test_reads_are_counted() {
+        let storage = mock();
+        storage.read_file("/a.png").unwrap();
+        storage.read_file("/a.png").unwrap();
+        assert!(storage.read_file("/missing.png").is_err());
+
+        let metrics = storage.metrics().snapshot();
+        assert_eq!(metrics.files_read, 2);
+        assert_eq!(metrics.bytes_downloaded, 200);
+        assert_eq!(metrics.thumbnails_generated, 0);
+    }
+
+    #[test]
+    fn test_thumbnail_batch_is_counted() {
+        let storage = mock();
+        let paths = ["/a.png", "/b.png", "/missing.png"].map(String::from);
+        let batch = thumbnails::generate_batch(
+            &storage,
+            None,
+            None,
+            &paths,
+            256,
+            ThumbnailOptions::default(),
+            2,
+            &|_| {},
+            &|_| {},
+            &AtomicBool::new(false),
+        );
+        assert_eq!(batch.ready, 2);
+
+        let metrics = storage.metrics().snapshot();
+        assert_eq!(metrics.thumbnails_generated, 2);
+        assert_eq!(metrics.files_read, 2);
+        assert_eq!(metrics.bytes_downloaded, 150);
+    }
+
+    #[test]
+    fn test_snapshot_totals_and_throughput() {
+        let metrics = StorageMetrics::default();
+        metrics.record_read(4000, Duration::from_millis(1500));
+        metrics.record_write(2000, Duration::from_millis(500));
+        metrics.record_command();
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.throughput_bytes_per_sec, 3000.0);
+
+        let total = snapshot.combined(&snapshot);
+        assert_eq!(total.files_read, 2);
+        assert_eq!(total.files_written, 2);
+        assert_eq!(total.remote_commands, 2);
+        assert_eq!(total.transfer_ms, 4000);
+        assert_eq!(total.throughput_bytes_per_sec, 3000.0);
+        assert_eq!(
+            MetricsSnapshot::default()
+                .combined(&MetricsSnapshot::default())
+                .throughput_bytes_per_sec,
+            0.0
+        );
+    }
+}
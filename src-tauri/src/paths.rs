@@ -0,0 +1,385 @@
+//! Canonical remote paths shared by every backend.
+//!
+//! Paths exchanged with the frontend are root-relative: they start with `/`,
+//! carry no trailing slash and no empty, `.` or `..` segments, and `/` is the
+//! storage root. Each backend maps them onto its absolute location on the
+//! remote host through a [`PathTranslator`].
+
+use std::fmt;
+
+/// `FileInfo.extra` key holding the backend's absolute path for an entry.
+pub const ABSOLUTE_PATH_KEY: &str = "absolute_path";
+
+/// Fewest components an absolute path needs before it may be deleted as a
+/// directory.
+const MIN_DELETE_COMPONENTS: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RemotePath(String);
+
+impl RemotePath {
+    pub fn root() -> Self {
+        RemotePath("/".to_string())
+    }
+
+    /// Normalizes `path` as root-relative; `..` never climbs above the root.
+    pub fn new(path: &str) -> Self {
+        let mut parts: Vec<&str> = Vec::new();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    parts.pop();
+                }
+                s => parts.push(s),
+            }
+        }
+        RemotePath(format!("/{}", parts.join("/")))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0 == "/"
+    }
+
+    /// The path without its leading slash, empty for the root.
+    pub fn relative(&self) -> &str {
+        &self.0[1..]
+    }
+
+    pub fn join(&self, name: &str) -> Self {
+        RemotePath::new(&format!("{}/{}", self.0, name))
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        if self.is_root() {
+            return None;
+        }
+        let idx = self.0.rfind('/').unwrap_or(0);
+        Some(RemotePath::new(&self.0[..idx]))
+    }
+
+    /// Whether this is `ancestor` or lies below it.
+    pub fn is_within(&self, ancestor: &RemotePath) -> bool {
+        ancestor.is_root()
+            || self == ancestor
+            || self
+                .0
+                .strip_prefix(&ancestor.0)
+                .is_some_and(|rest| rest.starts_with('/'))
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        if self.is_root() {
+            None
+        } else {
+            self.0.rsplit('/').next()
+        }
+    }
+}
+
+impl fmt::Display for RemotePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Maps canonical paths onto a backend's absolute base directory and back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathTranslator {
+    base: String,
+}
+
+impl PathTranslator {
+    pub fn new(base: &str) -> Self {
+        PathTranslator {
+            base: RemotePath::new(base).0,
+        }
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    /// Canonicalizes frontend input: a root-relative path, or an absolute
+    /// path under the base as held by older bookmarks and recent files.
+    pub fn to_remote(&self, input: &str) -> RemotePath {
+        if input.starts_with('/') {
+            if let Some(path) = self.from_absolute(input) {
+                return path;
+            }
+        }
+        RemotePath::new(input)
+    }
+
+    /// Maps an absolute path reported by the backend, such as a listed entry
+    /// or a search hit, back to its canonical form. `None` if it lies outside
+    /// the base.
+    pub fn from_absolute(&self, absolute: &str) -> Option<RemotePath> {
+        let normalized = RemotePath::new(absolute);
+        if self.base == "/" {
+            return Some(normalized);
+        }
+        match normalized.0.strip_prefix(&self.base) {
+            Some("") => Some(RemotePath::root()),
+            Some(rest) if rest.starts_with('/') => Some(RemotePath(rest.to_string())),
+            _ => None,
+        }
+    }
+
+    pub fn to_absolute(&self, path: &RemotePath) -> String {
+        if path.is_root() {
+            self.base.clone()
+        } else if self.base == "/" {
+            path.0.clone()
+        } else {
+            format!("{}{}", self.base, path.0)
+        }
+    }
+
+    /// Shorthand for translating frontend input straight to an absolute path.
+    pub fn resolve(&self, input: &str) -> String {
+        self.to_absolute(&self.to_remote(input))
+    }
+}
+
+/// Resolves a directory about to be deleted, refusing the storage root and
+/// shallow absolute paths so a bad input can never wipe a home directory.
+pub fn guard_directory_delete(
+    translator: &PathTranslator,
+    input: &str,
+) -> Result<RemotePath, String> {
+    let path = translator.to_remote(input);
+    let absolute = translator.to_absolute(&path);
+    if path.is_root() {
+        return Err(format!("Refusing to delete {}", absolute));
+    }
+    guard_absolute_delete(&absolute)?;
+    Ok(path)
+}
+
+/// Refuses to delete `absolute` unless it is an absolute path at least
+/// [`MIN_DELETE_COMPONENTS`] deep, with nothing left for a shell to expand.
+pub fn guard_absolute_delete(absolute: &str) -> Result<(), String> {
+    let components: Vec<&str> = absolute
+        .split('/')
+        .filter(|s| !s.is_empty() && *s != ".")
+        .collect();
+    if !absolute.starts_with('/')
+        || components.len() < MIN_DELETE_COMPONENTS
+        || components.contains(&"..")
+        || absolute.contains(['~', '$'])
+    {
+        return Err(format!("Refusing to delete {}", absolute));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_path_normalization() {
+        let cases = [
+            ("", "/"),
+            ("/", "/"),
+            ("//", "/"),
+            ("photos", "/photos"),
+            ("/photos/", "/photos"),
+            ("photos//2023///", "/photos/2023"),
+            ("/photos/./2023", "/photos/2023"),
+            ("/photos/../docs", "/docs"),
+            ("/../../etc/passwd", "/etc/passwd"),
+            ("/my holiday/a b.jpg", "/my holiday/a b.jpg"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                RemotePath::new(input).as_str(),
+                expected,
+                "input {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_remote_path_join() {
+        let root = RemotePath::root();
+        assert_eq!(root.join("a.jpg").as_str(), "/a.jpg");
+        assert_eq!(root.join("/a.jpg").as_str(), "/a.jpg");
+        let dir = RemotePath::new("/photos/");
+        assert_eq!(dir.join("a.jpg").as_str(), "/photos/a.jpg");
+        assert_eq!(dir.join("2023/").as_str(), "/photos/2023");
+    }
+
+    #[test]
+    fn test_remote_path_parent_and_name() {
+        let path = RemotePath::new("/photos/2023/a.jpg");
+        assert_eq!(path.file_name(), Some("a.jpg"));
+        assert_eq!(path.relative(), "photos/2023/a.jpg");
+        assert_eq!(path.parent().unwrap().as_str(), "/photos/2023");
+        assert_eq!(RemotePath::new("/a.jpg").parent(), Some(RemotePath::root()));
+        assert_eq!(RemotePath::root().parent(), None);
+        assert_eq!(RemotePath::root().file_name(), None);
+        assert_eq!(RemotePath::root().relative(), "");
+    }
+
+    #[test]
+    fn test_remote_path_is_within() {
+        let photos = RemotePath::new("/photos");
+        assert!(RemotePath::new("/photos/a.jpg").is_within(&photos));
+        assert!(photos.is_within(&photos));
+        assert!(photos.is_within(&RemotePath::root()));
+        assert!(!RemotePath::new("/photos2/a.jpg").is_within(&photos));
+        assert!(!RemotePath::root().is_within(&photos));
+    }
+
+    #[test]
+    fn test_translator_maps_absolute_paths_back() {
+        let translator = PathTranslator::new("/home/ubuntu/");
+        assert_eq!(translator.base(), "/home/ubuntu");
+        let remote = |path: &str| translator.from_absolute(path).map(|p| p.to_string());
+        assert_eq!(remote("/home/ubuntu").as_deref(), Some("/"));
+        assert_eq!(remote("/home/ubuntu/").as_deref(), Some("/"));
+        assert_eq!(
+            remote("/home/ubuntu/photos/a.jpg").as_deref(),
+            Some("/photos/a.jpg")
+        );
+        // A sibling that merely shares the prefix is not under the base.
+        assert_eq!(remote("/home/ubuntu2/x"), None);
+        assert_eq!(remote("/etc/passwd"), None);
+
+        let root = PathTranslator::new("/");
+        assert_eq!(
+            root.from_absolute("/srv/a.jpg"),
+            Some(RemotePath::new("/srv/a.jpg"))
+        );
+    }
+
+    #[test]
+    fn test_translator_accepts_relative_input() {
+        let translator = PathTranslator::new("/home/ubuntu");
+        assert_eq!(translator.to_remote("photos").as_str(), "/photos");
+        assert_eq!(
+            translator.to_remote("/photos/a.jpg").as_str(),
+            "/photos/a.jpg"
+        );
+        assert_eq!(
+            translator.resolve("/photos/a.jpg"),
+            "/home/ubuntu/photos/a.jpg"
+        );
+        // Only a leading slash marks a path as absolute.
+        assert_eq!(
+            translator.to_remote("home/ubuntu/x").as_str(),
+            "/home/ubuntu/x"
+        );
+    }
+
+    #[test]
+    fn test_translator_accepts_absolute_input_under_base() {
+        let translator = PathTranslator::new("/home/ubuntu");
+        assert_eq!(translator.to_remote("/home/ubuntu").as_str(), "/");
+        assert_eq!(translator.to_remote("/home/ubuntu/").as_str(), "/");
+        assert_eq!(
+            translator.to_remote("/home/ubuntu/photos/a.jpg").as_str(),
+            "/photos/a.jpg"
+        );
+        assert_eq!(
+            translator.resolve("/home/ubuntu/photos/a.jpg"),
+            "/home/ubuntu/photos/a.jpg"
+        );
+        // Paths outside the base stay below it.
+        assert_eq!(
+            translator.resolve("/home/ubuntu2/x"),
+            "/home/ubuntu/home/ubuntu2/x"
+        );
+    }
+
+    #[test]
+    fn test_translator_to_absolute_never_doubles_slashes() {
+        let translator = PathTranslator::new("/tmp/image-repo");
+        assert_eq!(translator.resolve("/"), "/tmp/image-repo");
+        assert_eq!(translator.resolve(""), "/tmp/image-repo");
+        assert_eq!(translator.resolve("/photos/"), "/tmp/image-repo/photos");
+        assert_eq!(
+            translator.resolve("//photos//a.jpg"),
+            "/tmp/image-repo/photos/a.jpg"
+        );
+
+        let root = PathTranslator::new("/");
+        assert_eq!(root.resolve("/"), "/");
+        assert_eq!(root.resolve("/srv/photos/"), "/srv/photos");
+    }
+
+    #[test]
+    fn test_translator_round_trip() {
+        for base in ["/home/ubuntu", "/root", "/tmp/image-repo", "/"] {
+            let translator = PathTranslator::new(base);
+            for path in ["/", "/a.jpg", "/photos/2023/IMG 1.jpg"] {
+                let remote = RemotePath::new(path);
+                let absolute = translator.to_absolute(&remote);
+                assert_eq!(
+                    translator.from_absolute(&absolute),
+                    Some(remote),
+                    "base {}",
+                    base
+                );
+                assert_eq!(translator.resolve(path), absolute);
+                assert_eq!(translator.resolve(&absolute), absolute);
+            }
+        }
+    }
+
+    #[test]
+    fn test_guard_directory_delete() {
+        let home = PathTranslator::new("/home/ubuntu");
+        for input in ["", "/", "//", ".", "/home/ubuntu", "/photos/.."] {
+            assert!(guard_directory_delete(&home, input).is_err(), "{:?}", input);
+        }
+        assert_eq!(
+            guard_directory_delete(&home, "/photos").unwrap().as_str(),
+            "/photos"
+        );
+
+        let root = PathTranslator::new("/");
+        assert!(guard_directory_delete(&root, "/srv").is_err());
+        assert!(guard_directory_delete(&root, "/srv/photos").is_ok());
+    }
+
+    #[test]
+    fn test_guard_absolute_delete() {
+        for path in [
+            "",
+            "/",
+            "~",
+            "$HOME",
+            "/home",
+            "/home/./",
+            "/home/ubuntu/..",
+            "repos/photos",
+            "~/repos/photos",
+            "$HOME/photos",
+        ] {
+            assert!(guard_absolute_delete(path).is_err(), "{:?}", path);
+        }
+        assert!(guard_absolute_delete("/tmp/image-repo").is_ok());
+        assert!(guard_absolute_delete("/home/ubuntu/repos/photos/").is_ok());
+    }
+
+    #[test]
+    fn test_translator_does_not_escape_base() {
+        let translator = PathTranslator::new("/tmp/image-repo");
+        assert_eq!(
+            translator.resolve("/../../etc/passwd"),
+            "/tmp/image-repo/etc/passwd"
+        );
+        assert_eq!(
+            translator.resolve("/tmp/image-repo/../secret"),
+            "/tmp/image-repo/tmp/secret"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Files read ahead of time, such as the next images of a slideshow, kept in
+//! memory until `read_file` asks for them.
+
+use crate::storage::{FileInfo, Storage};
+use crate::thumbnails;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_PREFETCH_CACHE_BYTES: u64 = 256 * 1024 * 1024;
+/// Files read at once by a prefetch.
+pub const PREFETCH_WORKERS: usize = 4;
+/// Larger files are left to be read on demand.
+pub const MAX_PREFETCH_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct PrefetchCacheStats {
+    pub entries: usize,
+    pub total_bytes: u64,
+    pub max_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct Entry {
+    storage_id: String,
+    /// The version of the file the data was read from.
+    file: FileInfo,
+    data: Arc<Vec<u8>>,
+}
+
+impl Entry {
+    fn is(&self, storage_id: &str, path: &str) -> bool {
+        self.storage_id == storage_id && self.file.path == path
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Oldest first.
+    queue: VecDeque<Entry>,
+    total_bytes: u64,
+}
+
+impl Entries {
+    fn remove(&mut self, storage_id: &str, path: &str) {
+        if let Some(i) = self.queue.iter().position(|e| e.is(storage_id, path)) {
+            if let Some(entry) = self.queue.remove(i) {
+                self.total_bytes -= entry.data.len() as u64;
+            }
+        }
+    }
+}
+
+/// A bounded in-memory cache evicting the oldest insertions first.
+pub struct PrefetchCache {
+    entries: Mutex<Entries>,
+    max_bytes: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// Bumped by every prefetch so older ones stop early.
+    generation: AtomicU64,
+}
+
+impl PrefetchCache {
+    pub fn new(max_bytes: u64) -> Self {
+        PrefetchCache {
+            entries: Mutex::new(Entries::default()),
+            max_bytes,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn insert(&self, storage_id: &str, file: FileInfo, data: Vec<u8>) {
+        let size = data.len() as u64;
+        if size > self.max_bytes {
+            return;
+        }
+        let mut entries = self.lock();
+        entries.remove(storage_id, &file.path);
+        while entries.total_bytes + size > self.max_bytes {
+            match entries.queue.pop_front() {
+                Some(oldest) => entries.total_bytes -= oldest.data.len() as u64,
+                None => break,
+            }
+        }
+        entries.total_bytes += size;
+        entries.queue.push_back(Entry {
+            storage_id: storage_id.to_string(),
+            file,
+            data: Arc::new(data),
+        });
+    }
+
+    pub fn contains(&self, storage_id: &str, path: &str) -> bool {
+        self.lock().queue.iter().any(|e| e.is(storage_id, path))
+    }
+
+    /// The cached content of `path`, provided `current` (only called when
+    /// there is an entry) still reports the modification time and size it
+    /// was read at. Outdated entries are dropped.
+    pub fn get(
+        &self,
+        storage_id: &str,
+        path: &str,
+        current: impl FnOnce() -> Option<FileInfo>,
+    ) -> Option<Arc<Vec<u8>>> {
+        let cached = self
+            .lock()
+            .queue
+            .iter()
+            .find(|e| e.is(storage_id, path))
+            .map(|e| (e.file.modified, e.file.size, e.data.clone()));
+        let fresh = cached.and_then(|(modified, size, data)| {
+            let file = current()?;
+            (file.modified == modified && file.size == size).then_some(data)
+        });
+        match fresh {
+            Some(data) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(data)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                self.lock().remove(storage_id, path);
+                None
+            }
+        }
+    }
+
+    /// Starts a new prefetch, superseding the running one.
+    pub fn begin(&self) -> u64 {
+        self.generation.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn is_current(&self, generation: u64) -> bool {
+        self.generation.load(Ordering::Relaxed) == generation
+    }
+
+    pub fn stats(&self) -> PrefetchCacheStats {
+        let entries = self.lock();
+        PrefetchCacheStats {
+            entries: entries.queue.len(),
+            total_bytes: entries.total_bytes,
+            max_bytes: self.max_bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Reads `paths` into `cache`, in order and [`PREFETCH_WORKERS`] at a time,
+/// until a newer prefetch than `generation` begins. Failures are skipped.
+pub fn prefetch(
+    cache: &PrefetchCache,
+    storage: &dyn Storage,
+    storage_id: &str,
+    paths: &[String],
+    generation: u64,
+) {
+    thumbnails::for_each_parallel(paths, PREFETCH_WORKERS, |path| {
+        if !cache.is_current(generation) || cache.contains(storage_id, path) {
+            return;
+        }
+        let Ok(file) = storage.stat(path) else {
+            return;
+        };
+        if file.is_dir || file.size > MAX_PREFETCH_FILE_BYTES {
+            return;
+        }
+        if let Ok(data) = storage.read_file(path) {
+            cache.insert(storage_id, file, data);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "ec2:me@host:22";
+
+    fn file(path: &str, size: u64, modified: u64) -> FileInfo {
+        FileInfo::for_file(path, size, Some(modified))
+    }
+
+    #[test]
+    fn test_get_checks_version() {
+        let cache = PrefetchCache::new(100);
+        cache.insert(ID, file("/a.jpg", 3, 1), b"abc".to_vec());
+
+        let data = cache.get(ID, "/a.jpg", || Some(file("/a.jpg", 3, 1)));
+        assert_eq!(data.as_deref().map(Vec::as_slice), Some(&b"abc"[..]));
+        assert!(cache
+            .get("github:o/r@main", "/a.jpg", || unreachable!())
+            .is_none());
+
+        // Changed since it was prefetched.
+        assert!(cache
+            .get(ID, "/a.jpg", || Some(file("/a.jpg", 3, 2)))
+            .is_none());
+        assert!(!cache.contains(ID, "/a.jpg"));
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!(stats.total_bytes, 0);
+    }
+
+    #[test]
+    fn test_evicts_oldest_insertions() {
+        let cache = PrefetchCache::new(10);
+        cache.insert(ID, file("/a", 4, 1), vec![0; 4]);
+        cache.insert(ID, file("/b", 4, 1), vec![0; 4]);
+        // Reading does not make an entry younger.
+        assert!(cache.get(ID, "/a", || Some(file("/a", 4, 1))).is_some());
+        cache.insert(ID, file("/c", 4, 1), vec![0; 4]);
+        assert!(!cache.contains(ID, "/a"));
+        assert!(cache.contains(ID, "/b") && cache.contains(ID, "/c"));
+
+        // Never cached: larger than the whole cache.
+        cache.insert(ID, file("/d", 11, 1), vec![0; 11]);
+        assert!(!cache.contains(ID, "/d"));
+        assert_eq!(cache.stats().total_bytes, 8);
+    }
+
+    #[test]
+    fn test_newer_prefetch_supersedes() {
+        let cache = PrefetchCache::new(10);
+        let first = cache.begin();
+        assert!(cache.is_current(first));
+        let second = cache.begin();
+        assert!(!cache.is_current(first));
+        assert!(cache.is_current(second));
+    }
+}
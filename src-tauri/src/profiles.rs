@@ -0,0 +1,362 @@
+//! Saved connections. A profile holds everything needed to connect except
+//! key material and passwords, which are supplied when connecting.
+
+use crate::commands::{Ec2ConnectRequest, GitHubConnectRequest};
+use crate::ec2::AuthMethod;
+use crate::error::StorageError;
+use crate::tunnel::JumpHostConfig;
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// File in the app config directory holding the profiles.
+pub const PROFILES_FILE: &str = "profiles.json";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct JumpHostProfile {
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    pub username: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Ec2Profile {
+    #[serde(default)]
+    pub host: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub auth_method: AuthMethod,
+    #[serde(default)]
+    pub keepalive_secs: Option<u64>,
+    #[serde(default)]
+    pub auto_reconnect: bool,
+    #[serde(default)]
+    pub remote_thumbnails: bool,
+    #[serde(default)]
+    pub jump_host: Option<JumpHostProfile>,
+    #[serde(default)]
+    pub ssh_config_host: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GitHubProfile {
+    pub repo_url: String,
+    pub username: String,
+    #[serde(default)]
+    pub branch: Option<String>,
+    #[serde(default)]
+    pub local_path: Option<String>,
+    #[serde(default)]
+    pub keepalive_secs: Option<u64>,
+    #[serde(default)]
+    pub auto_reconnect: bool,
+    #[serde(default)]
+    pub remote_thumbnails: bool,
+    #[serde(default)]
+    pub author_name: Option<String>,
+    #[serde(default)]
+    pub author_email: Option<String>,
+    #[serde(default)]
+    pub clone_depth: Option<u32>,
+    #[serde(default)]
+    pub partial_clone: bool,
+    #[serde(default)]
+    pub sparse_paths: Vec<String>,
+    #[serde(default)]
+    pub min_free_space_mb: Option<u64>,
+    #[serde(default)]
+    pub manual_commits: bool,
+    #[serde(default)]
+    pub manage_lfs_tracking: bool,
+    #[serde(default)]
+    pub lfs_patterns: Vec<String>,
+    #[serde(default)]
+    pub remove_clone_on_disconnect: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProfileConfig {
+    Ec2(Ec2Profile),
+    GitHub(GitHubProfile),
+}
+
+impl ProfileConfig {
+    /// Parses the config of a `storage_type` ("ec2" or "github") profile.
+    /// Secret fields, such as `pem_content`, are dropped.
+    pub fn parse(storage_type: &str, config: serde_json::Value) -> Result<Self, StorageError> {
+        let invalid = |e: serde_json::Error| {
+            StorageError::InvalidInput(format!("Invalid {} profile: {}", storage_type, e))
+        };
+        match storage_type.to_lowercase().as_str() {
+            "ec2" => Ok(ProfileConfig::Ec2(
+                serde_json::from_value(config).map_err(invalid)?,
+            )),
+            "github" => Ok(ProfileConfig::GitHub(
+                serde_json::from_value(config).map_err(invalid)?,
+            )),
+            _ => Err(StorageError::InvalidInput(format!(
+                "Unknown storage type: {}",
+                storage_type
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    #[serde(flatten)]
+    pub config: ProfileConfig,
+}
+
+/// Credentials supplied when connecting with a profile, or stored for it
+/// in the keyring.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ProfileSecrets {
+    /// Base64-encoded private key: the EC2 PEM or the GitHub SSH key.
+    pub key_content: String,
+    pub password: Option<String>,
+    pub key_passphrase: Option<String>,
+    /// Base64-encoded private key for the jump host.
+    pub jump_key_content: String,
+    pub jump_key_passphrase: Option<String>,
+}
+
+impl std::fmt::Debug for ProfileSecrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProfileSecrets").finish_non_exhaustive()
+    }
+}
+
+impl Ec2Profile {
+    pub fn connect_request(&self, secrets: ProfileSecrets) -> Ec2ConnectRequest {
+        Ec2ConnectRequest {
+            host: self.host.clone(),
+            username: self.username.clone(),
+            pem_content: secrets.key_content,
+            port: self.port,
+            password: secrets.password,
+            auth_method: self.auth_method,
+            key_passphrase: secrets.key_passphrase,
+            keepalive_secs: self.keepalive_secs,
+            auto_reconnect: self.auto_reconnect,
+            remote_thumbnails: self.remote_thumbnails,
+            jump_host: self.jump_host.as_ref().map(|jump| JumpHostConfig {
+                host: jump.host.clone(),
+                port: jump.port,
+                username: jump.username.clone(),
+                pem_content: secrets.jump_key_content,
+                key_passphrase: secrets.jump_key_passphrase,
+            }),
+            ssh_config_host: self.ssh_config_host.clone(),
+            use_stored_secret: false,
+            profile_name: None,
+        }
+    }
+}
+
+impl GitHubProfile {
+    pub fn connect_request(&self, secrets: ProfileSecrets) -> GitHubConnectRequest {
+        GitHubConnectRequest {
+            repo_url: self.repo_url.clone(),
+            username: self.username.clone(),
+            ssh_key_content: secrets.key_content,
+            branch: self.branch.clone(),
+            local_path: self.local_path.clone(),
+            key_passphrase: secrets.key_passphrase,
+            keepalive_secs: self.keepalive_secs,
+            auto_reconnect: self.auto_reconnect,
+            remote_thumbnails: self.remote_thumbnails,
+            author_name: self.author_name.clone(),
+            author_email: self.author_email.clone(),
+            clone_depth: self.clone_depth,
+            partial_clone: self.partial_clone,
+            sparse_paths: self.sparse_paths.clone(),
+            api_token: None,
+            min_free_space_mb: self.min_free_space_mb,
+            force: false,
+            manual_commits: self.manual_commits,
+            manage_lfs_tracking: self.manage_lfs_tracking,
+            lfs_patterns: self.lfs_patterns.clone(),
+            replace_existing: false,
+            remove_clone_on_disconnect: self.remove_clone_on_disconnect,
+            use_stored_secret: false,
+            profile_name: None,
+        }
+    }
+}
+
+/// Profiles by name.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct ProfileStore {
+    profiles: BTreeMap<String, ProfileConfig>,
+}
+
+impl ProfileStore {
+    /// Ordered by name.
+    pub fn list(&self) -> Vec<Profile> {
+        self.profiles
+            .iter()
+            .map(|(name, config)| Profile {
+                name: name.clone(),
+                config: config.clone(),
+            })
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Profile> {
+        self.profiles.get(name).map(|config| Profile {
+            name: name.to_string(),
+            config: config.clone(),
+        })
+    }
+
+    /// Adds a profile, replacing one of the same name only with `overwrite`.
+    pub fn save(
+        &mut self,
+        name: &str,
+        config: ProfileConfig,
+        overwrite: bool,
+    ) -> Result<Profile, StorageError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(StorageError::InvalidInput(
+                "Profile name must not be empty".to_string(),
+            ));
+        }
+        if !overwrite && self.profiles.contains_key(name) {
+            return Err(StorageError::InvalidInput(format!(
+                "A profile named {} already exists",
+                name
+            )));
+        }
+        self.profiles.insert(name.to_string(), config.clone());
+        Ok(Profile {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Returns whether a profile named `name` existed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.profiles.remove(name).is_some()
+    }
+}
+
+pub fn profiles_path(app_config_dir: &Path) -> PathBuf {
+    app_config_dir.join(PROFILES_FILE)
+}
+
+/// Reads the store at `path`, empty if it does not exist. A corrupt file is
+/// moved aside to `<path>.corrupt`, so the next load starts over, and
+/// reported as [`StorageError::CorruptData`].
+pub fn load_profiles(path: &Path) -> Result<ProfileStore, StorageError> {
+    let Ok(content) = std::fs::read(path) else {
+        return Ok(ProfileStore::default());
+    };
+    serde_json::from_slice(&content).map_err(|e| {
+        let mut corrupt = path.as_os_str().to_owned();
+        corrupt.push(".corrupt");
+        let _ = std::fs::rename(path, &corrupt);
+        StorageError::CorruptData(format!(
+            "Saved profiles could not be read ({}) and were moved to {}",
+            e,
+            Path::new(&corrupt).display()
+        ))
+    })
+}
+
+pub fn save_profiles(path: &Path, store: &ProfileStore) -> std::io::Result<()> {
+    utils::save_json_atomic(path, store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_file() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = profiles_path(dir.path());
+        (dir, path)
+    }
+
+    fn ec2_config() -> ProfileConfig {
+        ProfileConfig::parse(
+            "ec2",
+            serde_json::json!({
+                "host": "10.0.0.5",
+                "username": "ubuntu",
+                "pem_content": "c2VjcmV0",
+                "password": "hunter2",
+            }),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_drops_secrets() {
+        let profile = Profile {
+            name: "prod".to_string(),
+            config: ec2_config(),
+        };
+        let json = serde_json::to_string(&profile).unwrap();
+        assert!(json.contains("\"type\":\"ec2\""));
+        assert!(!json.contains("c2VjcmV0") && !json.contains("hunter2"));
+
+        assert!(ProfileConfig::parse("github", serde_json::json!({})).is_err());
+        assert!(ProfileConfig::parse("ftp", serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn test_names_are_unique() {
+        let mut store = ProfileStore::default();
+        store.save(" prod ", ec2_config(), false).unwrap();
+        let err = store.save("prod", ec2_config(), false).unwrap_err();
+        assert_eq!(err.code(), "INVALID_INPUT");
+        store.save("prod", ec2_config(), true).unwrap();
+        assert!(store.save("  ", ec2_config(), false).is_err());
+        assert_eq!(store.list().len(), 1);
+        assert!(store.remove("prod"));
+        assert!(!store.remove("prod"));
+    }
+
+    #[test]
+    fn test_connect_request_merges_secrets() {
+        let ProfileConfig::Ec2(profile) = ec2_config() else {
+            unreachable!()
+        };
+        let request = profile.connect_request(ProfileSecrets {
+            key_content: "a2V5".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(request.host, "10.0.0.5");
+        assert_eq!(request.pem_content, "a2V5");
+        assert!(request.password.is_none());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let (_dir, path) = temp_file();
+        assert_eq!(load_profiles(&path).unwrap(), ProfileStore::default());
+
+        let mut store = ProfileStore::default();
+        store.save("prod", ec2_config(), false).unwrap();
+        save_profiles(&path, &store).unwrap();
+        assert_eq!(load_profiles(&path).unwrap(), store);
+
+        fs::write(&path, b"{\"prod\": [not json").unwrap();
+        let err = load_profiles(&path).unwrap_err();
+        assert_eq!(err.code(), "CORRUPT_DATA");
+        assert!(path.with_extension("json.corrupt").exists());
+        assert_eq!(load_profiles(&path).unwrap(), ProfileStore::default());
+    }
+}
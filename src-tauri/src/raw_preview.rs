@@ -0,0 +1,286 @@
+//! Locating the JPEG preview embedded in TIFF-based camera RAW files (CR2,
+//! NEF, ARW, DNG), so thumbnails need neither demosaicing nor the whole
+//! file.
+
+/// Bytes read from the start of a RAW file to find its IFDs. Previews
+/// outside this range are fetched with a second range read.
+pub const RAW_HEADER_BYTES: u64 = 512 * 1024;
+/// Larger candidates are the raw image data, not a preview.
+pub const MAX_RAW_PREVIEW_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Stop following IFD chains after this many, in case of loops.
+const MAX_IFDS: usize = 32;
+
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_PHOTOMETRIC: u16 = 0x0106;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+/// Only present on the raw data IFD of CR2 files.
+const TAG_CR2_SLICES: u16 = 0xC640;
+
+const COMPRESSION_OLD_JPEG: u32 = 6;
+const COMPRESSION_JPEG: u32 = 7;
+const PHOTOMETRIC_CFA: u32 = 32803;
+const PHOTOMETRIC_LINEAR_RAW: u32 = 34892;
+
+pub fn is_raw_mime(mime: &str) -> bool {
+    matches!(
+        mime,
+        "image/x-canon-cr2" | "image/x-nikon-nef" | "image/x-sony-arw" | "image/x-adobe-dng"
+    )
+}
+
+/// Where an embedded JPEG lies in the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreviewLocation {
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl PreviewLocation {
+    /// The preview, if it lies entirely within `header`.
+    pub fn slice<'a>(&self, header: &'a [u8]) -> Option<&'a [u8]> {
+        let start = usize::try_from(self.offset).ok()?;
+        let end = start.checked_add(usize::try_from(self.length).ok()?)?;
+        header.get(start..end)
+    }
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16_at(&self, at: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32_at(&self, at: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// The first value of the entry at `entry`, for SHORT and LONG types.
+    fn value(&self, entry: usize) -> Option<u32> {
+        match self.u16_at(entry + 2)? {
+            3 => self.u16_at(entry + 8).map(u32::from),
+            4 | 13 => self.u32_at(entry + 8),
+            _ => None,
+        }
+    }
+
+    /// All values of a LONG or IFD entry, which are stored elsewhere when
+    /// there is more than one.
+    fn values(&self, entry: usize) -> Vec<u32> {
+        let count = self.u32_at(entry + 4).unwrap_or(0) as usize;
+        if count <= 1 {
+            return self.value(entry).into_iter().collect();
+        }
+        let Some(at) = self.u32_at(entry + 8) else {
+            return Vec::new();
+        };
+        (0..count.min(MAX_IFDS))
+            .map_while(|i| self.u32_at(at as usize + i * 4))
+            .collect()
+    }
+}
+
+/// The largest JPEG preview referenced by the IFDs found in `header`,
+/// which holds the start of the file.
+pub fn find_jpeg_preview(header: &[u8]) -> Option<PreviewLocation> {
+    let tiff = Tiff {
+        big_endian: match header.get(..4)? {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => return None,
+        },
+        data: header,
+    };
+
+    let mut pending = vec![tiff.u32_at(4)?];
+    let mut visited = Vec::new();
+    let mut best: Option<PreviewLocation> = None;
+    while let Some(ifd) = pending.pop() {
+        if ifd == 0 || visited.contains(&ifd) || visited.len() >= MAX_IFDS {
+            continue;
+        }
+        visited.push(ifd);
+        let ifd = ifd as usize;
+        let Some(count) = tiff.u16_at(ifd) else {
+            continue;
+        };
+
+        let mut tags = Vec::new();
+        for i in 0..count as usize {
+            let entry = ifd + 2 + i * 12;
+            let Some(tag) = tiff.u16_at(entry) else {
+                break;
+            };
+            if tag == TAG_SUB_IFDS {
+                pending.extend(tiff.values(entry));
+            }
+            tags.push((tag, entry));
+        }
+        if let Some(next) = tiff.u32_at(ifd + 2 + count as usize * 12) {
+            pending.push(next);
+        }
+
+        let value = |wanted: u16| {
+            tags.iter()
+                .find(|(tag, _)| *tag == wanted)
+                .and_then(|(_, entry)| tiff.value(*entry))
+        };
+        let mut candidates = Vec::new();
+        if let (Some(offset), Some(length)) = (value(TAG_JPEG_OFFSET), value(TAG_JPEG_LENGTH)) {
+            candidates.push((offset, length));
+        }
+        let is_preview_strip = matches!(
+            value(TAG_COMPRESSION),
+            Some(COMPRESSION_OLD_JPEG | COMPRESSION_JPEG)
+        ) && !matches!(
+            value(TAG_PHOTOMETRIC),
+            Some(PHOTOMETRIC_CFA | PHOTOMETRIC_LINEAR_RAW)
+        ) && value(TAG_CR2_SLICES).is_none();
+        if is_preview_strip {
+            if let (Some(offset), Some(length)) =
+                (value(TAG_STRIP_OFFSETS), value(TAG_STRIP_BYTE_COUNTS))
+            {
+                candidates.push((offset, length));
+            }
+        }
+
+        for (offset, length) in candidates {
+            let location = PreviewLocation {
+                offset: offset.into(),
+                length: length.into(),
+            };
+            let plausible = location.length > 0 && location.length <= MAX_RAW_PREVIEW_BYTES;
+            if plausible && best.is_none_or(|b| location.length > b.length) {
+                best = Some(location);
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One IFD entry; values are stored as LONGs.
+    fn entry(out: &mut Vec<u8>, big_endian: bool, tag: u16, value: u32) {
+        let (tag, kind, count, value) = if big_endian {
+            (
+                tag.to_be_bytes(),
+                4u16.to_be_bytes(),
+                1u32.to_be_bytes(),
+                value.to_be_bytes(),
+            )
+        } else {
+            (
+                tag.to_le_bytes(),
+                4u16.to_le_bytes(),
+                1u32.to_le_bytes(),
+                value.to_le_bytes(),
+            )
+        };
+        out.extend_from_slice(&tag);
+        out.extend_from_slice(&kind);
+        out.extend_from_slice(&count);
+        out.extend_from_slice(&value);
+    }
+
+    /// A TIFF with one IFD at offset 8 holding `tags`.
+    fn tiff(big_endian: bool, tags: &[(u16, u32)], next: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        if big_endian {
+            out.extend_from_slice(b"MM\0*");
+            out.extend_from_slice(&8u32.to_be_bytes());
+            out.extend_from_slice(&(tags.len() as u16).to_be_bytes());
+        } else {
+            out.extend_from_slice(b"II*\0");
+            out.extend_from_slice(&8u32.to_le_bytes());
+            out.extend_from_slice(&(tags.len() as u16).to_le_bytes());
+        }
+        for (tag, value) in tags {
+            entry(&mut out, big_endian, *tag, *value);
+        }
+        out.extend_from_slice(&if big_endian {
+            next.to_be_bytes()
+        } else {
+            next.to_le_bytes()
+        });
+        out
+    }
+
+    #[test]
+    fn test_finds_jpeg_interchange_preview() {
+        let header = tiff(false, &[(TAG_JPEG_OFFSET, 200), (TAG_JPEG_LENGTH, 50)], 0);
+        assert_eq!(
+            find_jpeg_preview(&header),
+            Some(PreviewLocation {
+                offset: 200,
+                length: 50
+            })
+        );
+    }
+
+    #[test]
+    fn test_prefers_largest_and_skips_raw_strips() {
+        let header = tiff(
+            true,
+            &[
+                (TAG_COMPRESSION, COMPRESSION_OLD_JPEG),
+                (TAG_STRIP_OFFSETS, 1000),
+                (TAG_STRIP_BYTE_COUNTS, 4000),
+                (TAG_JPEG_OFFSET, 100),
+                (TAG_JPEG_LENGTH, 300),
+            ],
+            0,
+        );
+        assert_eq!(find_jpeg_preview(&header).unwrap().length, 4000);
+
+        let raw = tiff(
+            false,
+            &[
+                (TAG_COMPRESSION, COMPRESSION_JPEG),
+                (TAG_PHOTOMETRIC, PHOTOMETRIC_CFA),
+                (TAG_STRIP_OFFSETS, 1000),
+                (TAG_STRIP_BYTE_COUNTS, 4000),
+            ],
+            0,
+        );
+        assert_eq!(find_jpeg_preview(&raw), None);
+    }
+
+    #[test]
+    fn test_rejects_non_tiff_and_loops() {
+        assert_eq!(find_jpeg_preview(b"\xff\xd8\xff\xe0"), None);
+        assert_eq!(find_jpeg_preview(b"II"), None);
+        // The IFD links back to itself.
+        assert_eq!(find_jpeg_preview(&tiff(false, &[], 8)), None);
+    }
+
+    #[test]
+    fn test_preview_slice() {
+        let location = PreviewLocation {
+            offset: 2,
+            length: 3,
+        };
+        assert_eq!(location.slice(b"abcdef"), Some(&b"cde"[..]));
+        assert_eq!(location.slice(b"abc"), None);
+    }
+}
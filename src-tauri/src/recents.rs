@@ -0,0 +1,138 @@
+use crate::storage::StorageType;
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+/// File in the app data directory holding the history.
+pub const RECENTS_FILE: &str = "recent_files.json";
+pub const MAX_RECENT_FILES: usize = 50;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RecentFile {
+    pub storage_type: StorageType,
+    /// See [`crate::storage::Storage::connection_id`].
+    pub connection_id: String,
+    pub path: String,
+    pub mime_type: Option<String>,
+    pub opened_at: u64,
+}
+
+impl RecentFile {
+    fn same_file(&self, other: &RecentFile) -> bool {
+        self.storage_type == other.storage_type
+            && self.connection_id == other.connection_id
+            && self.path == other.path
+    }
+}
+
+/// Most recently opened files, newest first.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct RecentFiles {
+    entries: VecDeque<RecentFile>,
+}
+
+impl Default for RecentFiles {
+    fn default() -> Self {
+        RecentFiles {
+            entries: VecDeque::with_capacity(MAX_RECENT_FILES),
+        }
+    }
+}
+
+impl RecentFiles {
+    /// Moves `file` to the front, replacing an earlier entry for the same
+    /// file, and drops the oldest entries beyond [`MAX_RECENT_FILES`].
+    pub fn record(&mut self, file: RecentFile) {
+        self.entries.retain(|e| !e.same_file(&file));
+        self.entries.push_front(file);
+        self.entries.truncate(MAX_RECENT_FILES);
+    }
+
+    pub fn list(&self, limit: usize) -> Vec<RecentFile> {
+        self.entries.iter().take(limit).cloned().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+pub fn recents_path(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join(RECENTS_FILE)
+}
+
+pub fn load_recent_files(path: &Path) -> RecentFiles {
+    let mut recents: RecentFiles = utils::load_json_or_default(path);
+    recents.entries.truncate(MAX_RECENT_FILES);
+    recents
+}
+
+pub fn save_recent_files(path: &Path, recents: &RecentFiles) -> std::io::Result<()> {
+    utils::save_json_atomic(path, recents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn recent(path: &str, opened_at: u64) -> RecentFile {
+        RecentFile {
+            storage_type: StorageType::Ec2,
+            connection_id: "ubuntu@host:22".to_string(),
+            path: path.to_string(),
+            mime_type: crate::storage::detect_mime_type(path),
+            opened_at,
+        }
+    }
+
+    #[test]
+    fn test_record_bumps_instead_of_duplicating() {
+        let mut recents = RecentFiles::default();
+        recents.record(recent("/a.jpg", 1));
+        recents.record(recent("/b.jpg", 2));
+        recents.record(recent("/a.jpg", 3));
+        let paths: Vec<String> = recents.list(10).into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, vec!["/a.jpg", "/b.jpg"]);
+        assert_eq!(recents.list(1)[0].opened_at, 3);
+    }
+
+    #[test]
+    fn test_record_keeps_other_connections_separate() {
+        let mut recents = RecentFiles::default();
+        recents.record(recent("/a.jpg", 1));
+        recents.record(RecentFile {
+            storage_type: StorageType::GitHub,
+            connection_id: "git@github.com:me/pics.git#main".to_string(),
+            ..recent("/a.jpg", 2)
+        });
+        assert_eq!(recents.list(10).len(), 2);
+    }
+
+    #[test]
+    fn test_record_is_bounded() {
+        let mut recents = RecentFiles::default();
+        for i in 0..MAX_RECENT_FILES + 5 {
+            recents.record(recent(&format!("/{}.jpg", i), i as u64));
+        }
+        let all = recents.list(usize::MAX);
+        assert_eq!(all.len(), MAX_RECENT_FILES);
+        assert_eq!(all[0].path, format!("/{}.jpg", MAX_RECENT_FILES + 4));
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = recents_path(dir.path());
+
+        let mut recents = RecentFiles::default();
+        recents.record(recent("/a.jpg", 1));
+        save_recent_files(&path, &recents).unwrap();
+        assert_eq!(load_recent_files(&path), recents);
+
+        fs::write(&path, b"[{").unwrap();
+        assert_eq!(load_recent_files(&path), RecentFiles::default());
+    }
+}
@@ -0,0 +1,131 @@
+//! Connection secrets kept in the OS credential store, so keys for saved
+//! profiles never have to pass through the webview.
+
+use crate::error::StorageError;
+use crate::profiles::ProfileSecrets;
+use crate::utils;
+
+/// Keyring service the secrets are filed under, one entry per profile.
+pub const KEYRING_SERVICE: &str = "com.hautlys.image";
+
+fn entry_name(profile: &str) -> Result<&str, StorageError> {
+    let profile = profile.trim();
+    if profile.is_empty() {
+        return Err(StorageError::InvalidInput(
+            "Profile name must not be empty".to_string(),
+        ));
+    }
+    Ok(profile)
+}
+
+pub fn store_secret(profile: &str, secrets: &ProfileSecrets) -> Result<(), StorageError> {
+    let profile = entry_name(profile)?;
+    let mut json =
+        serde_json::to_string(secrets).map_err(|e| StorageError::Internal(e.to_string()))?;
+    let result = backend::set(profile, &json);
+    utils::wipe_string(&mut json);
+    result
+}
+
+/// The secrets stored for `profile`, `None` if there are none.
+pub fn load_secret(profile: &str) -> Result<Option<ProfileSecrets>, StorageError> {
+    let profile = entry_name(profile)?;
+    let Some(mut json) = backend::get(profile)? else {
+        return Ok(None);
+    };
+    let secrets = serde_json::from_str(&json).map_err(|e| {
+        StorageError::CorruptData(format!(
+            "The stored secret for {} could not be read: {}",
+            profile, e
+        ))
+    });
+    utils::wipe_string(&mut json);
+    secrets.map(Some)
+}
+
+/// Returns whether a secret was stored for `profile`.
+pub fn delete_secret(profile: &str) -> Result<bool, StorageError> {
+    backend::delete(entry_name(profile)?)
+}
+
+#[cfg(feature = "keyring")]
+mod backend {
+    use super::KEYRING_SERVICE;
+    use crate::error::StorageError;
+    use keyring::{Entry, Error};
+
+    fn keyring_error(e: Error) -> StorageError {
+        match e {
+            Error::NoStorageAccess(inner) | Error::PlatformFailure(inner) => {
+                StorageError::KeyringUnavailable(inner.to_string())
+            }
+            Error::TooLong(..) | Error::Invalid(..) => StorageError::InvalidInput(e.to_string()),
+            other => StorageError::Internal(other.to_string()),
+        }
+    }
+
+    fn entry(profile: &str) -> Result<Entry, StorageError> {
+        Entry::new(KEYRING_SERVICE, profile).map_err(keyring_error)
+    }
+
+    pub fn set(profile: &str, secret: &str) -> Result<(), StorageError> {
+        entry(profile)?.set_password(secret).map_err(keyring_error)
+    }
+
+    pub fn get(profile: &str) -> Result<Option<String>, StorageError> {
+        match entry(profile)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(Error::NoEntry) => Ok(None),
+            Err(e) => Err(keyring_error(e)),
+        }
+    }
+
+    pub fn delete(profile: &str) -> Result<bool, StorageError> {
+        match entry(profile)?.delete_credential() {
+            Ok(()) => Ok(true),
+            Err(Error::NoEntry) => Ok(false),
+            Err(e) => Err(keyring_error(e)),
+        }
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+mod backend {
+    use crate::error::StorageError;
+
+    fn unavailable() -> StorageError {
+        StorageError::KeyringUnavailable("this build has no keyring support".to_string())
+    }
+
+    pub fn set(_profile: &str, _secret: &str) -> Result<(), StorageError> {
+        Err(unavailable())
+    }
+
+    pub fn get(_profile: &str) -> Result<Option<String>, StorageError> {
+        Err(unavailable())
+    }
+
+    pub fn delete(_profile: &str) -> Result<bool, StorageError> {
+        Err(unavailable())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_name_required() {
+        let err = store_secret(" ", &ProfileSecrets::default()).unwrap_err();
+        assert_eq!(err.code(), "INVALID_INPUT");
+        assert!(load_secret("").is_err());
+    }
+
+    #[cfg(not(feature = "keyring"))]
+    #[test]
+    fn test_unavailable_without_keyring() {
+        let err = load_secret("prod").unwrap_err();
+        assert_eq!(err.code(), "KEYRING_UNAVAILABLE");
+        assert!(err.to_string().starts_with("No OS keyring is available"));
+    }
+}
@@ -0,0 +1,324 @@
+//! Finding near-duplicate images, such as the same shot at another size or
+//! compression, by comparing perceptual hashes.
+
+use crate::checksum;
+use crate::duplicates;
+use crate::image_decode;
+use crate::storage::{self, detect_mime_type, FileInfo, Storage};
+use crate::thumbnails;
+use crate::utils;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use openssl::hash::{hash, MessageDigest};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Directory under the app cache dir holding one hash cache per connection.
+pub const HASH_CACHE_DIR: &str = "image_hashes";
+/// Hashes at most this many bits apart count as similar by default.
+pub const DEFAULT_SIMILARITY_THRESHOLD: u32 = 10;
+/// Images are decoded at about this size for hashing, which is all a
+/// 9x8 hash needs.
+const HASH_DECODE_SIZE: u32 = 64;
+
+/// A group of images whose hashes are all linked by distances within the
+/// threshold, ordered by path.
+#[derive(Debug, Serialize, Clone)]
+pub struct SimilarGroup {
+    pub files: Vec<FileInfo>,
+    /// Largest distance between two hashes in the group.
+    pub max_distance: u32,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SimilarScan {
+    /// Largest groups first.
+    pub groups: Vec<SimilarGroup>,
+    pub files_scanned: usize,
+    /// Images that could not be decoded.
+    pub failed: usize,
+    /// The tree held more files than were scanned.
+    pub truncated: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SimilarProgress {
+    pub hashed: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct CachedHash {
+    modified: u64,
+    size: u64,
+    hash: u64,
+}
+
+/// Hashes computed earlier on one connection, valid while a file's
+/// modification time and size are unchanged.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct HashCache {
+    entries: HashMap<String, CachedHash>,
+}
+
+impl HashCache {
+    pub fn get(&self, file: &FileInfo) -> Option<u64> {
+        let cached = self.entries.get(&file.path)?;
+        (Some(cached.modified) == file.modified && cached.size == file.size).then_some(cached.hash)
+    }
+
+    /// Files without a modification time are not cached.
+    pub fn insert(&mut self, file: &FileInfo, hash: u64) {
+        if let Some(modified) = file.modified {
+            self.entries.insert(
+                file.path.clone(),
+                CachedHash {
+                    modified,
+                    size: file.size,
+                    hash,
+                },
+            );
+        }
+    }
+}
+
+/// Where the hashes of the connection `storage_id` are kept.
+pub fn hash_cache_path(cache_dir: &Path, storage_id: &str) -> PathBuf {
+    let name = hash(MessageDigest::sha256(), storage_id.as_bytes())
+        .map(|digest| checksum::to_hex(&digest[..16]))
+        .unwrap_or_else(|_| "default".to_string());
+    cache_dir
+        .join(HASH_CACHE_DIR)
+        .join(format!("{}.json", name))
+}
+
+pub fn load_hash_cache(path: &Path) -> HashCache {
+    utils::load_json_or_default(path)
+}
+
+pub fn save_hash_cache(path: &Path, cache: &HashCache) -> std::io::Result<()> {
+    utils::save_json_atomic(path, cache)
+}
+
+/// 64-bit difference hash: each bit tells whether a pixel of a 9x8
+/// grayscale copy is brighter than its right neighbour.
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut bits = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            bits <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                bits |= 1;
+            }
+        }
+    }
+    bits
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Whether files of type `mime` are hashed.
+fn is_hashable(mime: Option<&str>) -> bool {
+    mime.is_some_and(|mime| mime.starts_with("image/") && mime != "image/svg+xml")
+}
+
+/// The perceptual hash of the image at `path`.
+pub fn image_hash(storage: &dyn Storage, path: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let mime = detect_mime_type(path).unwrap_or_default();
+    let content =
+        storage::read_file_capped(storage, path, image_decode::MAX_THUMBNAIL_INPUT_BYTES)?;
+    let img = thumbnails::decode_for_thumbnail(&content, &mime, HASH_DECODE_SIZE)?;
+    Ok(dhash(&img))
+}
+
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Groups `hashed` files whose hashes are within `threshold` of each other,
+/// directly or through other members.
+fn cluster(hashed: Vec<(FileInfo, u64)>, threshold: u32) -> Vec<SimilarGroup> {
+    let mut parents: Vec<usize> = (0..hashed.len()).collect();
+    for i in 0..hashed.len() {
+        for j in i + 1..hashed.len() {
+            if hamming_distance(hashed[i].1, hashed[j].1) <= threshold {
+                let (a, b) = (find_root(&mut parents, i), find_root(&mut parents, j));
+                parents[a] = b;
+            }
+        }
+    }
+
+    let mut clusters: BTreeMap<usize, Vec<(FileInfo, u64)>> = BTreeMap::new();
+    for (i, entry) in hashed.into_iter().enumerate() {
+        clusters
+            .entry(find_root(&mut parents, i))
+            .or_default()
+            .push(entry);
+    }
+    let mut groups: Vec<SimilarGroup> = clusters
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|mut members| {
+            members.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+            let mut max_distance = 0;
+            for (i, (_, a)) in members.iter().enumerate() {
+                for (_, b) in &members[i + 1..] {
+                    max_distance = max_distance.max(hamming_distance(*a, *b));
+                }
+            }
+            SimilarGroup {
+                files: members.into_iter().map(|(file, _)| file).collect(),
+                max_distance,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.files
+            .len()
+            .cmp(&a.files.len())
+            .then_with(|| a.files[0].path.cmp(&b.files[0].path))
+    });
+    groups
+}
+
+/// Finds groups of similar images below `root`, scanning at most
+/// `max_files`. Hashes are taken from `cache` when current, and new ones
+/// are added to it.
+pub fn find_similar(
+    storage: &dyn Storage,
+    root: &str,
+    threshold: u32,
+    max_files: usize,
+    cache: &mut HashCache,
+    on_progress: &(dyn Fn(SimilarProgress) + Sync),
+) -> Result<SimilarScan, Box<dyn std::error::Error>> {
+    let listing = storage.list_directory_recursive(root, 0, &AtomicBool::new(false))?;
+    let mut truncated = listing.truncated;
+    let mut files: Vec<FileInfo> = listing
+        .files
+        .into_iter()
+        .filter(|f| {
+            !f.is_dir && !duplicates::is_skipped(&f.path) && is_hashable(f.mime_type.as_deref())
+        })
+        .collect();
+    if files.len() > max_files {
+        files.truncate(max_files);
+        truncated = true;
+    }
+    let files_scanned = files.len();
+
+    let mut hashed = Vec::new();
+    let mut unknown = Vec::new();
+    for file in files {
+        match cache.get(&file) {
+            Some(hash) => hashed.push((file, hash)),
+            None => unknown.push(file),
+        }
+    }
+
+    let total = files_scanned;
+    let done = AtomicUsize::new(hashed.len());
+    on_progress(SimilarProgress {
+        hashed: hashed.len(),
+        total,
+    });
+    let computed = Mutex::new(Vec::new());
+    thumbnails::for_each_parallel(&unknown, thumbnails::DEFAULT_THUMBNAIL_WORKERS, |file| {
+        let hash = image_hash(storage, &file.path).ok();
+        if let Ok(mut computed) = computed.lock() {
+            computed.push((file.clone(), hash));
+        }
+        on_progress(SimilarProgress {
+            hashed: done.fetch_add(1, Ordering::Relaxed) + 1,
+            total,
+        });
+    });
+
+    let mut failed = 0;
+    for (file, hash) in computed.into_inner().unwrap_or_default() {
+        match hash {
+            Some(hash) => {
+                cache.insert(&file, hash);
+                hashed.push((file, hash));
+            }
+            None => failed += 1,
+        }
+    }
+    Ok(SimilarScan {
+        groups: cluster(hashed, threshold),
+        files_scanned,
+        failed,
+        truncated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn file(path: &str, modified: Option<u64>) -> FileInfo {
+        FileInfo::for_file(path, 10, modified)
+    }
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let v = (x * 255 / width) as u8;
+            Rgb([v, v / 2, (y * 255 / height) as u8])
+        }))
+    }
+
+    #[test]
+    fn test_dhash_survives_resizing() {
+        let large = dhash(&gradient(640, 480));
+        let small = dhash(&gradient(160, 120));
+        assert!(hamming_distance(large, small) <= 2);
+        let flipped = dhash(&gradient(640, 480).fliph());
+        assert!(hamming_distance(large, flipped) > DEFAULT_SIMILARITY_THRESHOLD);
+    }
+
+    #[test]
+    fn test_cluster_links_chains_and_drops_singles() {
+        let hashed = vec![
+            (file("/c.jpg", None), 0b0011),
+            (file("/a.jpg", None), 0b0000),
+            (file("/b.jpg", None), 0b0001),
+            (file("/z.jpg", None), u64::MAX),
+        ];
+        let groups = cluster(hashed, 1);
+        assert_eq!(groups.len(), 1);
+        let paths: Vec<&str> = groups[0].files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["/a.jpg", "/b.jpg", "/c.jpg"]);
+        assert_eq!(groups[0].max_distance, 2);
+    }
+
+    #[test]
+    fn test_hash_cache_checks_version() {
+        let mut cache = HashCache::default();
+        cache.insert(&file("/a.jpg", Some(5)), 42);
+        cache.insert(&file("/b.jpg", None), 7);
+        assert_eq!(cache.get(&file("/a.jpg", Some(5))), Some(42));
+        assert_eq!(cache.get(&file("/a.jpg", Some(6))), None);
+        assert_eq!(cache.get(&FileInfo::for_file("/a.jpg", 11, Some(5))), None);
+        assert_eq!(cache.get(&file("/b.jpg", None)), None);
+    }
+
+    #[test]
+    fn test_hash_cache_path_is_per_connection() {
+        let dir = Path::new("/cache");
+        let a = hash_cache_path(dir, "ec2:me@a:22");
+        assert!(a.starts_with("/cache/image_hashes"));
+        assert_ne!(a, hash_cache_path(dir, "ec2:me@b:22"));
+    }
+}
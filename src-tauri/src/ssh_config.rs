@@ -0,0 +1,346 @@
+//! A small reader for OpenSSH client config files (`~/.ssh/config`).
+//!
+//! Only `Host`, `Include`, `HostName`, `User`, `Port` and `IdentityFile` are
+//! interpreted. `Match` blocks are skipped.
+
+use crate::utils;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Nested `Include`s deeper than this are ignored, as in OpenSSH.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A concrete `Host` alias with the settings ssh would use for it.
+#[derive(Debug, Serialize, Clone, PartialEq, Default)]
+pub struct SshConfigHost {
+    pub alias: String,
+    pub host_name: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    /// Absolute path, with `~` expanded.
+    pub identity_file: Option<String>,
+}
+
+/// Settings that apply to hosts matching `patterns`.
+#[derive(Debug, Default)]
+struct Block {
+    patterns: Vec<String>,
+    settings: Vec<(String, String)>,
+}
+
+impl Block {
+    fn new(patterns: Vec<String>) -> Self {
+        Block {
+            patterns,
+            settings: Vec::new(),
+        }
+    }
+
+    /// A host matches when any pattern does and no negated (`!`) one does.
+    fn matches(&self, host: &str) -> bool {
+        let mut matched = false;
+        for pattern in &self.patterns {
+            match pattern.strip_prefix('!') {
+                Some(negated) if wildcard_match(negated, host) => return false,
+                Some(_) => {}
+                None => matched |= wildcard_match(pattern, host),
+            }
+        }
+        matched
+    }
+}
+
+/// Case-insensitive match supporting `*` and `?`.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn is_wildcard(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+fn expand_tilde(path: &str, home: &Path) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => home.join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Splits a line into its keyword and arguments; `Key=value` is accepted
+/// and double quotes group arguments containing spaces.
+fn split_line(line: &str) -> Option<(String, Vec<String>)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let split_at = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let keyword = line[..split_at].to_lowercase();
+    let rest = line[split_at..].trim_start_matches(|c: char| c.is_whitespace() || c == '=');
+
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in rest.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    Some((keyword, args))
+}
+
+/// Paths an `Include` argument refers to. Relative paths are taken from
+/// `~/.ssh`, and wildcards are expanded in the file name.
+fn include_paths(arg: &str, home: &Path) -> Vec<PathBuf> {
+    let path = expand_tilde(arg, home);
+    let path = if path.is_absolute() {
+        path
+    } else {
+        home.join(".ssh").join(path)
+    };
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if !is_wildcard(&name) {
+        return vec![path];
+    }
+    let Some(dir) = path.parent() else {
+        return Vec::new();
+    };
+    let mut matches: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| wildcard_match(&name, &entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .collect();
+    matches.sort();
+    matches
+}
+
+fn parse_into(text: &str, home: &Path, depth: usize, blocks: &mut Vec<Block>) {
+    for line in text.lines() {
+        let Some((keyword, args)) = split_line(line) else {
+            continue;
+        };
+        match keyword.as_str() {
+            "host" => blocks.push(Block::new(args)),
+            // Match criteria are not evaluated, so its settings never apply.
+            "match" => blocks.push(Block::new(Vec::new())),
+            "include" if depth < MAX_INCLUDE_DEPTH => {
+                let patterns = blocks
+                    .last()
+                    .map(|b| b.patterns.clone())
+                    .unwrap_or_default();
+                for path in args.iter().flat_map(|arg| include_paths(arg, home)) {
+                    if let Ok(included) = std::fs::read_to_string(&path) {
+                        // The included file starts in the including block.
+                        blocks.push(Block::new(patterns.clone()));
+                        parse_into(&included, home, depth + 1, blocks);
+                    }
+                }
+                // Lines after the Include are back in the including block.
+                blocks.push(Block::new(patterns));
+            }
+            _ => {
+                if let (Some(block), Some(value)) = (blocks.last_mut(), args.first()) {
+                    block.settings.push((keyword, value.clone()));
+                }
+            }
+        }
+    }
+}
+
+fn parse(text: &str, home: &Path) -> Vec<Block> {
+    // Settings before the first Host line apply to every host.
+    let mut blocks = vec![Block::new(vec!["*".to_string()])];
+    parse_into(text, home, 0, &mut blocks);
+    blocks
+}
+
+/// Applies every block matching `alias` in order; the first value of each
+/// setting wins, as in ssh.
+fn resolve(blocks: &[Block], alias: &str, home: &Path) -> SshConfigHost {
+    let mut host = SshConfigHost {
+        alias: alias.to_string(),
+        ..Default::default()
+    };
+    let settings = blocks
+        .iter()
+        .filter(|b| b.matches(alias))
+        .flat_map(|b| &b.settings);
+    for (keyword, value) in settings {
+        match keyword.as_str() {
+            "hostname" if host.host_name.is_none() => {
+                host.host_name = Some(value.replace("%h", alias));
+            }
+            "user" if host.user.is_none() => host.user = Some(value.clone()),
+            "port" if host.port.is_none() => host.port = value.parse().ok(),
+            "identityfile" if host.identity_file.is_none() => {
+                host.identity_file = Some(expand_tilde(value, home).to_string_lossy().to_string());
+            }
+            _ => {}
+        }
+    }
+    host
+}
+
+/// Every concrete alias in the config text, with resolved settings.
+fn hosts_from(text: &str, home: &Path) -> Vec<SshConfigHost> {
+    let blocks = parse(text, home);
+    let mut aliases: Vec<&str> = Vec::new();
+    for pattern in blocks.iter().flat_map(|b| &b.patterns) {
+        if !is_wildcard(pattern)
+            && !pattern.starts_with('!')
+            && !aliases.contains(&pattern.as_str())
+        {
+            aliases.push(pattern);
+        }
+    }
+    aliases
+        .into_iter()
+        .map(|alias| resolve(&blocks, alias, home))
+        .collect()
+}
+
+/// Reads `~/.ssh/config`; a missing file yields no hosts.
+pub fn load_ssh_config_hosts() -> Vec<SshConfigHost> {
+    let Some(home) = utils::home_dir() else {
+        return Vec::new();
+    };
+    match std::fs::read_to_string(home.join(".ssh").join("config")) {
+        Ok(text) => hosts_from(&text, &home),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Looks up `alias`, falling back to treating it as a plain host name.
+pub fn find_host(alias: &str) -> SshConfigHost {
+    load_ssh_config_hosts()
+        .into_iter()
+        .find(|h| h.alias == alias)
+        .unwrap_or_else(|| SshConfigHost {
+            alias: alias.to_string(),
+            ..Default::default()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const CONFIG: &str = "\
+# Defaults first
+User ubuntu
+
+Host web prod-*
+    HostName %h.internal.example.com
+    Port 2222
+    IdentityFile ~/.ssh/prod.pem
+
+Host web
+    User ignored-because-first-wins
+
+Host db !db-old
+    HostName=10.0.0.5
+    IdentityFile \"/keys/my key.pem\"
+
+Host *
+    Port 22
+    User fallback
+";
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("prod-*", "PROD-api"));
+        assert!(wildcard_match("web?", "web1"));
+        assert!(wildcard_match("*.example.com", "a.b.example.com"));
+        assert!(!wildcard_match("web?", "web"));
+        assert!(!wildcard_match("prod-*", "staging-api"));
+    }
+
+    #[test]
+    fn test_hosts_resolve_first_value_wins() {
+        let home = Path::new("/home/me");
+        let hosts = hosts_from(CONFIG, home);
+        let aliases: Vec<&str> = hosts.iter().map(|h| h.alias.as_str()).collect();
+        assert_eq!(aliases, vec!["web", "db"]);
+
+        assert_eq!(
+            hosts[0],
+            SshConfigHost {
+                alias: "web".to_string(),
+                host_name: Some("web.internal.example.com".to_string()),
+                user: Some("ubuntu".to_string()),
+                port: Some(2222),
+                identity_file: Some("/home/me/.ssh/prod.pem".to_string()),
+            }
+        );
+        assert_eq!(hosts[1].host_name.as_deref(), Some("10.0.0.5"));
+        assert_eq!(hosts[1].port, Some(22));
+        assert_eq!(hosts[1].identity_file.as_deref(), Some("/keys/my key.pem"));
+    }
+
+    #[test]
+    fn test_negated_pattern() {
+        let blocks = parse("Host db* !db-old\n  User admin\n", Path::new("/"));
+        assert!(blocks[1].matches("db-new"));
+        assert!(!blocks[1].matches("db-old"));
+    }
+
+    #[test]
+    fn test_include_with_glob() {
+        let home = tempfile::tempdir().unwrap();
+        let conf_d = home.path().join(".ssh").join("conf.d");
+        fs::create_dir_all(&conf_d).unwrap();
+        fs::write(
+            conf_d.join("a.conf"),
+            "Host staging\n  HostName 10.1.0.1\n  User deploy\n",
+        )
+        .unwrap();
+        fs::write(conf_d.join("b.txt"), "Host skipped\n").unwrap();
+
+        let hosts = hosts_from(
+            "Include conf.d/*.conf\nHost web\n  Port 2200\n",
+            home.path(),
+        );
+        let aliases: Vec<&str> = hosts.iter().map(|h| h.alias.as_str()).collect();
+        assert_eq!(aliases, vec!["staging", "web"]);
+        assert_eq!(hosts[0].user.as_deref(), Some("deploy"));
+        assert_eq!(hosts[1].port, Some(2200));
+    }
+}
@@ -0,0 +1,285 @@
+//! Running commands over SSH exec channels, shared by both backends.
+
+use crate::error::StorageError;
+use crate::storage::{ChunkCallback, STREAM_CHUNK_SIZE};
+use ssh2::Session;
+use std::fmt;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// Only the tail of stderr is kept in errors; git can be very chatty.
+const MAX_STDERR_CHARS: usize = 500;
+/// Longest part of a command written to the log.
+const MAX_LOGGED_COMMAND_CHARS: usize = 300;
+
+/// `cmd` as logged: cut short, and redacted by the logger.
+fn logged_command(cmd: &str) -> &str {
+    match cmd.char_indices().nth(MAX_LOGGED_COMMAND_CHARS) {
+        Some((end, _)) => &cmd[..end],
+        None => cmd,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_status: i32,
+}
+
+impl ExecOutput {
+    pub fn success(&self) -> bool {
+        self.exit_status == 0
+    }
+
+    /// Returns stdout, or an [`ExecError`] carrying stderr if `command`
+    /// exited with a non-zero status.
+    pub fn checked(self, command: &str) -> Result<Vec<u8>, ExecError> {
+        if self.success() {
+            return Ok(self.stdout);
+        }
+        let stderr = String::from_utf8_lossy(&self.stderr);
+        let stderr = stderr.trim();
+        let tail_start = stderr
+            .char_indices()
+            .rev()
+            .nth(MAX_STDERR_CHARS - 1)
+            .map_or(0, |(i, _)| i);
+        Err(ExecError {
+            command: command.to_string(),
+            exit_status: self.exit_status,
+            stderr: stderr[tail_start..].to_string(),
+        })
+    }
+}
+
+/// A remote command exited with a non-zero status.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecError {
+    pub command: String,
+    pub exit_status: i32,
+    pub stderr: String,
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Remote command exited with status {}", self.exit_status)?;
+        if !self.stderr.is_empty() {
+            write!(f, ": {}", self.stderr)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+/// Runs `cmd` to completion, collecting stdout and stderr separately along
+/// with its exit status.
+pub fn ssh_exec(session: &Session, cmd: &str) -> Result<ExecOutput, StorageError> {
+    let start = Instant::now();
+    log::debug!("exec: {}", logged_command(cmd));
+    let mut channel = session.channel_session()?;
+    channel.exec(cmd)?;
+
+    let mut stdout = Vec::new();
+    channel.read_to_end(&mut stdout)?;
+    let mut stderr = Vec::new();
+    channel.stderr().read_to_end(&mut stderr)?;
+
+    channel.wait_eof()?;
+    channel.close()?;
+    channel.wait_close()?;
+
+    let exit_status = channel.exit_status()?;
+    log::debug!(
+        "exec exited with {} after {} ms",
+        exit_status,
+        start.elapsed().as_millis()
+    );
+    Ok(ExecOutput {
+        stdout,
+        stderr,
+        exit_status,
+    })
+}
+
+/// Runs `cmd` with stderr merged into stdout, handing the output to
+/// `on_output` as it arrives. The merged output is returned as `stderr` so
+/// [`ExecOutput::checked`] still reports its tail.
+pub fn ssh_exec_merged(
+    session: &Session,
+    cmd: &str,
+    on_output: &mut dyn FnMut(&[u8]),
+) -> Result<ExecOutput, StorageError> {
+    let start = Instant::now();
+    log::debug!("exec (merged): {}", logged_command(cmd));
+    let mut channel = session.channel_session()?;
+    channel.handle_extended_data(ssh2::ExtendedData::Merge)?;
+    channel.exec(cmd)?;
+
+    let mut output = Vec::new();
+    let mut buf = vec![0u8; STREAM_CHUNK_SIZE];
+    loop {
+        let n = channel.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        on_output(&buf[..n]);
+        output.extend_from_slice(&buf[..n]);
+    }
+
+    channel.wait_eof()?;
+    channel.close()?;
+    channel.wait_close()?;
+
+    let exit_status = channel.exit_status()?;
+    log::debug!(
+        "exec exited with {} after {} ms",
+        exit_status,
+        start.elapsed().as_millis()
+    );
+    Ok(ExecOutput {
+        stdout: Vec::new(),
+        stderr: output,
+        exit_status,
+    })
+}
+
+/// Runs `cmd` and hands its stdout to `on_chunk` as it arrives, along with
+/// `total` when the caller knows the output size. Returns `false` if
+/// `on_chunk` stopped the transfer, and fails if the command exited with a
+/// non-zero status, since the output it streamed is then incomplete.
+pub fn ssh_exec_streamed(
+    session: &Session,
+    cmd: &str,
+    total: Option<u64>,
+    on_chunk: &mut ChunkCallback<'_>,
+) -> Result<bool, StorageError> {
+    match exec_streamed(session, cmd, total, on_chunk)? {
+        Some(output) => {
+            output.checked(cmd)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Streams stdout to `on_chunk`, returning stderr and the exit status once
+/// the command finished, or `None` if `on_chunk` stopped it.
+fn exec_streamed(
+    session: &Session,
+    cmd: &str,
+    total: Option<u64>,
+    on_chunk: &mut ChunkCallback<'_>,
+) -> Result<Option<ExecOutput>, StorageError> {
+    log::debug!("exec (streamed): {}", logged_command(cmd));
+    let mut channel = session.channel_session()?;
+    channel.exec(cmd)?;
+
+    let mut buf = vec![0u8; STREAM_CHUNK_SIZE];
+    let mut completed = true;
+    loop {
+        let n = channel.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        if !on_chunk(&buf[..n], total) {
+            completed = false;
+            break;
+        }
+    }
+
+    let mut stderr = Vec::new();
+    if completed {
+        channel.stderr().read_to_end(&mut stderr)?;
+        channel.wait_eof()?;
+    }
+    channel.close()?;
+    channel.wait_close()?;
+
+    if !completed {
+        return Ok(None);
+    }
+    Ok(Some(ExecOutput {
+        stdout: Vec::new(),
+        stderr,
+        exit_status: channel.exit_status()?,
+    }))
+}
+
+/// Runs `cmd` collecting its stdout, closing the channel and failing with
+/// [`StorageError::Cancelled`] as soon as `cancelled` is set. Like the
+/// stdout of [`ssh_exec`], the output is returned whatever the exit status.
+pub fn ssh_exec_cancellable(
+    session: &Session,
+    cmd: &str,
+    cancelled: &AtomicBool,
+) -> Result<Vec<u8>, StorageError> {
+    let mut stdout = Vec::new();
+    let finished = exec_streamed(session, cmd, None, &mut |chunk, _| {
+        stdout.extend_from_slice(chunk);
+        !cancelled.load(Ordering::Relaxed)
+    })?;
+    if finished.is_none() || cancelled.load(Ordering::Relaxed) {
+        return Err(StorageError::Cancelled);
+    }
+    Ok(stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(exit_status: i32, stderr: &str) -> ExecOutput {
+        ExecOutput {
+            stdout: b"out".to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+            exit_status,
+        }
+    }
+
+    #[test]
+    fn test_checked_success_returns_stdout() {
+        assert_eq!(
+            output(0, "warning: ignored").checked("true").unwrap(),
+            b"out"
+        );
+    }
+
+    #[test]
+    fn test_checked_keeps_binary_stdout_intact() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let output = ExecOutput {
+            stdout: bytes.clone(),
+            stderr: b"Downloading LFS object".to_vec(),
+            exit_status: 0,
+        };
+        assert_eq!(output.checked("git lfs smudge").unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_checked_failure_carries_stderr() {
+        let err = output(1, "fatal: couldn't find remote ref main\n")
+            .checked("git pull origin main")
+            .unwrap_err();
+        assert_eq!(err.exit_status, 1);
+        assert_eq!(err.command, "git pull origin main");
+        assert_eq!(
+            err.to_string(),
+            "Remote command exited with status 1: fatal: couldn't find remote ref main"
+        );
+        assert_eq!(
+            output(128, "").checked("git").unwrap_err().to_string(),
+            "Remote command exited with status 128"
+        );
+    }
+
+    #[test]
+    fn test_checked_keeps_stderr_tail() {
+        let noisy = format!("{}fatal: the end", "x".repeat(2000));
+        let err = output(2, &noisy).checked("cmd").unwrap_err();
+        assert_eq!(err.stderr.chars().count(), MAX_STDERR_CHARS);
+        assert!(err.stderr.ends_with("fatal: the end"));
+    }
+}
@@ -1,10 +1,108 @@
+use crate::archive::ArchiveFormat;
+use crate::checksum::{ChecksumAlgorithm, FileChecksum};
+use crate::error::StorageError;
+use crate::media_probe::{self, MediaProbe};
+use crate::metrics::StorageMetrics;
+use crate::paths::PathTranslator;
+use crate::thumbnails::{RemoteThumbnailer, Thumbnail, ThumbnailOptions};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
+use std::iter::Peekable;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Chunk size used by streamed reads.
+pub const STREAM_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Hard cap on entries returned by a recursive listing.
+pub const MAX_RECURSIVE_ENTRIES: usize = 10_000;
+
+/// Result cap used by `search_files` when the caller does not pass one.
+pub const DEFAULT_SEARCH_LIMIT: usize = 500;
+
+/// Splits a read of `length + 1` bytes into the requested range and whether
+/// the end of the file was reached.
+pub fn finish_range_read(mut data: Vec<u8>, length: u64) -> (Vec<u8>, bool) {
+    if data.len() as u64 > length {
+        data.truncate(length as usize);
+        (data, false)
+    } else {
+        (data, true)
+    }
+}
+
+/// Contents of one file in a batch read, or why it could not be read.
+pub type FileReadOutcome = Result<Vec<u8>, String>;
+
+/// Receives each chunk of a streamed read together with the total size when
+/// the backend knows it. Returning `false` stops the read.
+pub type ChunkCallback<'a> = dyn FnMut(&[u8], Option<u64>) -> bool + 'a;
+
+/// Receives each batch of a streamed listing. Returning `false` stops it.
+pub type BatchCallback<'a> = dyn FnMut(Vec<FileInfo>) -> bool + 'a;
+
+/// Collects streamed listing entries into batches for a [`BatchCallback`].
+pub struct ListingBatcher<'a, 'b> {
+    batch: Vec<FileInfo>,
+    batch_size: usize,
+    total: usize,
+    stopped: bool,
+    on_batch: &'a mut BatchCallback<'b>,
+}
+
+impl<'a, 'b> ListingBatcher<'a, 'b> {
+    pub fn new(batch_size: usize, on_batch: &'a mut BatchCallback<'b>) -> Self {
+        let batch_size = batch_size.max(1);
+        ListingBatcher {
+            batch: Vec::with_capacity(batch_size),
+            batch_size,
+            total: 0,
+            stopped: false,
+            on_batch,
+        }
+    }
+
+    /// Adds `file`, handing over a full batch. Returns `false` once the
+    /// receiver has asked to stop.
+    pub fn push(&mut self, file: FileInfo) -> bool {
+        if self.stopped {
+            return false;
+        }
+        self.batch.push(file);
+        self.total += 1;
+        if self.batch.len() >= self.batch_size {
+            self.flush();
+        }
+        !self.stopped
+    }
+
+    fn flush(&mut self) {
+        if !self.batch.is_empty() && !self.stopped {
+            let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(self.batch_size));
+            self.stopped = !(self.on_batch)(batch);
+        }
+    }
+
+    /// Hands over the last partial batch and returns how many entries were
+    /// listed.
+    pub fn finish(mut self) -> usize {
+        self.flush();
+        self.total
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub enum StorageType {
     Ec2,
     GitHub,
+    GitHubApi,
+    GoogleDrive,
+    HttpIndex,
+    Local,
+    Sftp,
+    WebDav,
 }
 
 impl fmt::Display for StorageType {
@@ -12,6 +110,12 @@ impl fmt::Display for StorageType {
         match self {
             StorageType::Ec2 => write!(f, "ec2"),
             StorageType::GitHub => write!(f, "github"),
+            StorageType::GitHubApi => write!(f, "github_api"),
+            StorageType::GoogleDrive => write!(f, "gdrive"),
+            StorageType::HttpIndex => write!(f, "http"),
+            StorageType::Local => write!(f, "local"),
+            StorageType::Sftp => write!(f, "sftp"),
+            StorageType::WebDav => write!(f, "webdav"),
         }
     }
 }
@@ -23,37 +127,776 @@ impl std::str::FromStr for StorageType {
         match s.to_lowercase().as_str() {
             "ec2" => Ok(StorageType::Ec2),
             "github" => Ok(StorageType::GitHub),
+            "github_api" => Ok(StorageType::GitHubApi),
+            "gdrive" => Ok(StorageType::GoogleDrive),
+            "http" => Ok(StorageType::HttpIndex),
+            "local" => Ok(StorageType::Local),
+            "sftp" => Ok(StorageType::Sftp),
+            "webdav" => Ok(StorageType::WebDav),
             _ => Err(format!("Unknown storage type: {}", s)),
         }
     }
 }
 
+/// A directory entry. `path` is always canonical (root-relative, see
+/// [`crate::paths`]); backend-specific details such as the absolute path on
+/// EC2 live in `extra`.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileInfo {
     pub name: String,
     pub path: String,
     pub size: u64,
+    /// For symlinks, whether the target is a directory.
     pub is_dir: bool,
+    #[serde(default)]
+    pub is_symlink: bool,
+    /// Where a symlink points, as stored in the link.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,
     pub modified: Option<u64>,
     pub mime_type: Option<String>,
     pub thumbnail: Option<String>,
+    /// Unix permission bits, exchanged as an octal string such as "644".
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "octal_mode")]
+    pub permissions: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+}
+
+/// Parses an octal permission string such as "644" or "0755".
+pub fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(bits) if !mode.is_empty() && bits <= 0o7777 => Ok(bits),
+        _ => Err(format!("Invalid mode: {}", mode)),
+    }
+}
+
+mod octal_mode {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(mode: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error> {
+        match mode {
+            Some(bits) => serializer.serialize_str(&format!("{:o}", bits)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u32>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|mode| super::parse_mode(&mode).map_err(de::Error::custom))
+            .transpose()
+    }
+}
+
+impl FileInfo {
+    /// Builds the entry for a regular file the app has just written.
+    pub fn for_file(path: &str, size: u64, modified: Option<u64>) -> Self {
+        let name = path
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or(path)
+            .to_string();
+        let mime_type = detect_mime_type(&name);
+        FileInfo {
+            name,
+            path: path.to_string(),
+            size,
+            is_dir: false,
+            is_symlink: false,
+            link_target: None,
+            modified,
+            mime_type,
+            thumbnail: None,
+            permissions: None,
+            owner: None,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+/// Largest file `read_file` will buffer in memory unless told otherwise.
+pub const DEFAULT_MAX_IN_MEMORY_READ: u64 = 256 * 1024 * 1024;
+
+/// A file is too big to be read into memory in one piece.
+#[derive(Debug, PartialEq)]
+pub struct FileTooLargeError {
+    pub size: u64,
+    pub limit: u64,
+}
+
+impl FileTooLargeError {
+    /// Fails once either the announced `total` or the bytes read so far
+    /// exceed `limit`.
+    pub fn check(bytes_read: u64, total: Option<u64>, limit: u64) -> Result<(), Self> {
+        let size = total.unwrap_or(0).max(bytes_read);
+        if size > limit {
+            return Err(FileTooLargeError { size, limit });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FileTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "file too large for in-memory read, use download ({} bytes, limit {})",
+            self.size, self.limit
+        )
+    }
 }
 
+impl std::error::Error for FileTooLargeError {}
+
+/// Reads all of `path`, giving up with [`FileTooLargeError`] as soon as it
+/// turns out to be bigger than `limit`.
+pub fn read_file_capped(
+    storage: &dyn Storage,
+    path: &str,
+    limit: u64,
+) -> Result<Vec<u8>, StorageError> {
+    let mut data = Vec::new();
+    let mut too_large = None;
+    storage.read_file_streamed(path, &mut |chunk, total| {
+        let bytes_read = (data.len() + chunk.len()) as u64;
+        if let Err(e) = FileTooLargeError::check(bytes_read, total, limit) {
+            too_large = Some(e);
+            return false;
+        }
+        data.extend_from_slice(chunk);
+        true
+    })?;
+    match too_large {
+        Some(e) => Err(e.into()),
+        None => Ok(data),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateDirectoryResult {
+    pub directory: FileInfo,
+    /// The directory was already there and nothing was created.
+    pub existed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct DirectoryUsage {
+    pub total_bytes: u64,
+    pub file_count: u64,
+    /// Subdirectories below the measured directory, not counting itself.
+    pub dir_count: u64,
+    /// The walk stopped at [`MAX_RECURSIVE_ENTRIES`], so the totals are low.
+    pub truncated: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct DeleteDirectoryResult {
+    pub files_removed: u64,
+    /// Includes the deleted directory itself.
+    pub dirs_removed: u64,
+}
+
+/// One page of a sorted directory listing.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DirectoryPage {
+    pub files: Vec<FileInfo>,
+    /// Entries in the whole filtered listing.
+    pub total: usize,
+    pub offset: usize,
+}
+
+/// Returns up to `limit` entries starting at `offset`.
+pub fn paginate<T: Clone>(items: &[T], offset: usize, limit: usize) -> Vec<T> {
+    items.iter().skip(offset).take(limit).cloned().collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecursiveListing {
+    /// Sorted by path.
+    pub files: Vec<FileInfo>,
+    /// There were more than [`MAX_RECURSIVE_ENTRIES`] entries.
+    pub truncated: bool,
+}
+
+impl RecursiveListing {
+    /// Sorts `files` and keeps the first [`MAX_RECURSIVE_ENTRIES`]. Backends
+    /// collect one entry more so truncation can be detected.
+    pub fn capped(mut files: Vec<FileInfo>) -> Self {
+        let truncated = files.len() > MAX_RECURSIVE_ENTRIES;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        files.truncate(MAX_RECURSIVE_ENTRIES);
+        RecursiveListing { files, truncated }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchResult {
+    pub files: Vec<FileInfo>,
+    /// More entries matched than the requested limit.
+    pub truncated: bool,
+}
+
+impl SearchResult {
+    /// Keeps the first `limit` entries of `files`, which backends fetch with
+    /// one extra entry so truncation can be detected.
+    pub fn capped(mut files: Vec<FileInfo>, limit: usize) -> Self {
+        let truncated = files.len() > limit;
+        files.truncate(limit);
+        SearchResult { files, truncated }
+    }
+}
+
+/// One line of a file that matched a content search.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SearchMatch {
+    pub path: String,
+    /// 1-based line number.
+    pub line: u64,
+    /// Byte offset of the start of the matching line.
+    pub byte_offset: Option<u64>,
+    pub snippet: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContentSearchResult {
+    pub matches: Vec<SearchMatch>,
+    /// More lines matched than the requested limit.
+    pub truncated: bool,
+}
+
+/// Substring match on a file name, as used by filename search.
+pub fn name_matches(name: &str, pattern: &str, case_sensitive: bool) -> bool {
+    if case_sensitive {
+        name.contains(pattern)
+    } else {
+        name.to_lowercase().contains(&pattern.to_lowercase())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// Natural order: see [`natural_cmp`].
+    #[default]
+    Name,
+    Size,
+    Modified,
+    /// Byte order of the names.
+    Lexical,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaFilter {
+    #[default]
+    All,
+    Images,
+    Videos,
+    Media,
+}
+
+impl MediaFilter {
+    /// Directories always match so navigation keeps working under any filter.
+    pub fn matches(&self, file: &FileInfo) -> bool {
+        file.is_dir || self.matches_mime(file.mime_type.as_deref())
+    }
+
+    /// Checks a raw directory entry so listings can skip it before building
+    /// its `FileInfo`.
+    pub fn matches_entry(&self, name: &str, is_dir: bool) -> bool {
+        is_dir || *self == MediaFilter::All || self.matches_mime(detect_mime_type(name).as_deref())
+    }
+
+    fn matches_mime(&self, mime: Option<&str>) -> bool {
+        let mime = mime.unwrap_or("");
+        match self {
+            MediaFilter::All => true,
+            MediaFilter::Images => mime.starts_with("image/"),
+            MediaFilter::Videos => mime.starts_with("video/"),
+            MediaFilter::Media => mime.starts_with("image/") || mime.starts_with("video/"),
+        }
+    }
+}
+
+/// Whether a directory entry called `name` is listed. Dotfiles, the trash
+/// among them, only show with `show_hidden`; names in `always_hidden`, such
+/// as a backend's own metadata, never do.
+pub fn is_listed(name: &str, show_hidden: bool, always_hidden: &[&str]) -> bool {
+    !always_hidden.contains(&name) && (show_hidden || !name.starts_with('.'))
+}
+
+/// How a directory listing is filtered, ordered and paged.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ListOptions {
+    pub filter: MediaFilter,
+    pub sort_by: SortBy,
+    pub sort_order: SortOrder,
+    pub offset: usize,
+    pub limit: usize,
+    /// Probe each image on the page and record its size in `extra` under
+    /// [`media_probe::WIDTH_KEY`] and [`media_probe::HEIGHT_KEY`]. Costs a
+    /// range read per image.
+    pub include_dimensions: bool,
+    /// Include dotfiles and the trash directory.
+    pub show_hidden: bool,
+    /// Report symlinks as what they point to, so linked directories can be
+    /// opened. Broken and looping links stay files.
+    pub resolve_symlinks: bool,
+    /// Identify files on the page whose extension is unknown from their
+    /// first bytes. Costs a range read per such file. Filters still only
+    /// see the extension.
+    pub sniff: bool,
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        ListOptions {
+            filter: MediaFilter::default(),
+            sort_by: SortBy::default(),
+            sort_order: SortOrder::default(),
+            offset: 0,
+            limit: usize::MAX,
+            include_dimensions: false,
+            show_hidden: false,
+            resolve_symlinks: true,
+            sniff: false,
+        }
+    }
+}
+
+/// The fields of an entry that listings are ordered by, so backends can sort
+/// raw directory entries before building `FileInfo`s.
+#[derive(Debug, Clone, Copy)]
+pub struct SortKey<'a> {
+    pub name: &'a str,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<u64>,
+}
+
+impl<'a> From<&'a FileInfo> for SortKey<'a> {
+    fn from(file: &'a FileInfo) -> Self {
+        SortKey {
+            name: &file.name,
+            is_dir: file.is_dir,
+            size: file.size,
+            modified: file.modified,
+        }
+    }
+}
+
+/// Orders directories first, then by the requested key, ties broken by name.
+pub fn compare_entries(
+    a: &SortKey<'_>,
+    b: &SortKey<'_>,
+    sort_by: SortBy,
+    order: SortOrder,
+) -> std::cmp::Ordering {
+    let ordering = match sort_by {
+        SortBy::Name => natural_cmp(a.name, b.name),
+        SortBy::Size => a
+            .size
+            .cmp(&b.size)
+            .then_with(|| natural_cmp(a.name, b.name)),
+        SortBy::Modified => a
+            .modified
+            .cmp(&b.modified)
+            .then_with(|| natural_cmp(a.name, b.name)),
+        SortBy::Lexical => a.name.cmp(b.name),
+    };
+    let ordering = match order {
+        SortOrder::Asc => ordering,
+        SortOrder::Desc => ordering.reverse(),
+    };
+    b.is_dir.cmp(&a.is_dir).then(ordering)
+}
+
+fn digit_run(chars: &mut Peekable<impl Iterator<Item = char>>) -> String {
+    let mut run = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        run.push(c);
+    }
+    run
+}
+
+/// Compares names case-insensitively with runs of digits compared by value,
+/// so "IMG_2.jpg" comes before "IMG_10.jpg". Of numbers with the same value,
+/// the one with fewer leading zeros comes first; names that still tie are
+/// ordered bytewise so the order is total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut x = a.chars().flat_map(char::to_lowercase).peekable();
+    let mut y = b.chars().flat_map(char::to_lowercase).peekable();
+    let mut tie = Ordering::Equal;
+    loop {
+        match (x.peek().copied(), y.peek().copied()) {
+            (None, None) => return tie.then_with(|| a.cmp(b)),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(c), Some(d)) if c.is_ascii_digit() && d.is_ascii_digit() => {
+                let (m, n) = (digit_run(&mut x), digit_run(&mut y));
+                // Compared as strings, so numbers of any length work.
+                let (m_value, n_value) = (m.trim_start_matches('0'), n.trim_start_matches('0'));
+                let ordering = m_value
+                    .len()
+                    .cmp(&n_value.len())
+                    .then_with(|| m_value.cmp(n_value));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                tie = tie.then(m.len().cmp(&n.len()));
+            }
+            (Some(c), Some(d)) => {
+                if c != d {
+                    return c.cmp(&d);
+                }
+                x.next();
+                y.next();
+            }
+        }
+    }
+}
+
+/// Sorts a listing with directories first, then by the requested key.
+pub fn sort_files(files: &mut [FileInfo], sort_by: SortBy, order: SortOrder) {
+    files.sort_by(|a, b| compare_entries(&a.into(), &b.into(), sort_by, order));
+}
+
+/// Visits everything below `root` breadth first, down to `max_depth` levels
+/// (0 means unlimited), listing one directory at a time with
+/// [`Storage::list_directory`]. For backends without a cheaper recursive
+/// listing. Stops when `visit` returns `false`, and with
+/// [`StorageError::Cancelled`] once `cancelled` is set.
+pub fn walk_listings(
+    storage: &dyn Storage,
+    root: &str,
+    max_depth: usize,
+    cancelled: &AtomicBool,
+    visit: &mut dyn FnMut(FileInfo) -> bool,
+) -> Result<(), StorageError> {
+    let root = storage.path_translator().to_remote(root).to_string();
+    let mut pending = VecDeque::from([(root, 1)]);
+    while let Some((dir, depth)) = pending.pop_front() {
+        if cancelled.load(std::sync::atomic::Ordering::Relaxed) {
+            return Err(StorageError::Cancelled);
+        }
+        for file in storage.list_directory(&dir, MediaFilter::All)? {
+            if file.is_dir && !file.is_symlink && (max_depth == 0 || depth < max_depth) {
+                pending.push_back((file.path.clone(), depth + 1));
+            }
+            if !visit(file) {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// [`Storage::list_directory_recursive`] on top of [`walk_listings`].
+pub fn list_by_walking(
+    storage: &dyn Storage,
+    path: &str,
+    max_depth: usize,
+    cancelled: &AtomicBool,
+) -> Result<RecursiveListing, StorageError> {
+    let mut files = Vec::new();
+    walk_listings(storage, path, max_depth, cancelled, &mut |file| {
+        files.push(file);
+        files.len() <= MAX_RECURSIVE_ENTRIES
+    })?;
+    Ok(RecursiveListing::capped(files))
+}
+
+/// [`Storage::search`] on top of [`walk_listings`].
+pub fn search_by_walking(
+    storage: &dyn Storage,
+    root: &str,
+    pattern: &str,
+    case_sensitive: bool,
+    limit: usize,
+    cancelled: &AtomicBool,
+) -> Result<SearchResult, StorageError> {
+    let mut files = Vec::new();
+    walk_listings(storage, root, 0, cancelled, &mut |file| {
+        if name_matches(&file.name, pattern, case_sensitive) {
+            files.push(file);
+        }
+        files.len() <= limit
+    })?;
+    Ok(SearchResult::capped(files, limit))
+}
+
+/// [`Storage::directory_size`] on top of [`walk_listings`].
+pub fn size_by_walking(storage: &dyn Storage, path: &str) -> Result<DirectoryUsage, StorageError> {
+    let mut usage = DirectoryUsage::default();
+    let mut visited = 0usize;
+    walk_listings(storage, path, 0, &AtomicBool::new(false), &mut |file| {
+        visited += 1;
+        if visited > MAX_RECURSIVE_ENTRIES {
+            usage.truncated = true;
+            return false;
+        }
+        if file.is_dir {
+            usage.dir_count += 1;
+        } else {
+            usage.file_count += 1;
+            usage.total_bytes += file.size;
+        }
+        true
+    })?;
+    Ok(usage)
+}
+
+/// Told the error showing that a connection is gone. Called from the
+/// keepalive thread as well as from operations, possibly more than once.
+pub type ConnectionLostHandler = Arc<dyn Fn(&StorageError) + Send + Sync>;
+
 pub trait Storage: Send + Sync {
-    fn connect(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+    fn connect(&mut self) -> Result<(), StorageError>;
     fn disconnect(&mut self);
     fn is_connected(&self) -> bool;
-    fn list_directory(&self, path: &str) -> Result<Vec<FileInfo>, Box<dyn std::error::Error>>;
-    fn read_file(&self, path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+    /// Sets what to call when the keepalive or an operation finds the
+    /// connection dead. Takes effect on the next [`Storage::connect`].
+    fn set_connection_lost_handler(&mut self, _handler: ConnectionLostHandler) {}
+    /// Reports that `error`, from an operation, showed the connection is
+    /// gone. Afterwards [`Storage::is_connected`] is false.
+    fn connection_lost(&self, _error: &StorageError) {}
+    /// The cheapest real round trip to the server, such as running `true`
+    /// over SSH. Errors are returned without calling
+    /// [`Storage::connection_lost`].
+    fn ping(&self) -> Result<(), StorageError>;
+    /// Transfer counters of this connection.
+    fn metrics(&self) -> &StorageMetrics;
+    /// Drops the current session and connects again with the same config.
+    fn reconnect(&mut self) -> Result<(), StorageError> {
+        self.disconnect();
+        self.connect()
+    }
+    /// Whether operations that fail because the connection dropped should
+    /// reconnect and retry once.
+    fn auto_reconnect(&self) -> bool {
+        false
+    }
+    /// Lists the direct children of `path` that pass `filter`, hidden ones
+    /// included.
+    fn list_directory(
+        &self,
+        path: &str,
+        filter: MediaFilter,
+    ) -> Result<Vec<FileInfo>, StorageError> {
+        let options = ListOptions {
+            filter,
+            show_hidden: true,
+            ..Default::default()
+        };
+        Ok(self.list_directory_page(path, &options)?.0)
+    }
+    /// Returns one sorted page of the listing along with the total number of
+    /// entries that passed the filter.
+    fn list_directory_page(
+        &self,
+        path: &str,
+        options: &ListOptions,
+    ) -> Result<(Vec<FileInfo>, usize), StorageError>;
+    /// Lists the direct children of `path` that pass the filter and hidden
+    /// file settings of `options`, handing them to `on_batch` at most
+    /// `batch_size` at a time in the order they are read. Sorting and paging
+    /// options are ignored. Returns the number of entries listed.
+    fn list_directory_streamed(
+        &self,
+        path: &str,
+        options: &ListOptions,
+        batch_size: usize,
+        on_batch: &mut BatchCallback<'_>,
+    ) -> Result<usize, StorageError> {
+        let everything = ListOptions {
+            offset: 0,
+            limit: usize::MAX,
+            ..*options
+        };
+        let mut batcher = ListingBatcher::new(batch_size, on_batch);
+        for file in self.list_directory_page(path, &everything)?.0 {
+            if !batcher.push(file) {
+                break;
+            }
+        }
+        Ok(batcher.finish())
+    }
+    /// Lists everything below `path` down to `max_depth` levels (0 means
+    /// unlimited), capped at [`MAX_RECURSIVE_ENTRIES`]. Stops with
+    /// [`StorageError::Cancelled`] once `cancelled` is set.
+    fn list_directory_recursive(
+        &self,
+        path: &str,
+        max_depth: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<RecursiveListing, StorageError>;
+    /// Finds entries below `root` whose name contains `pattern`, returning
+    /// at most `limit` of them. Stops with [`StorageError::Cancelled`] once
+    /// `cancelled` is set.
+    fn search(
+        &self,
+        root: &str,
+        pattern: &str,
+        case_sensitive: bool,
+        limit: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<SearchResult, StorageError>;
+    /// Searches the contents of text files below `root` case-insensitively,
+    /// as a fixed string or, with `regex`, an extended regular expression.
+    /// `include` limits the search to file names matching a glob.
+    fn search_contents(
+        &self,
+        root: &str,
+        query: &str,
+        regex: bool,
+        include: Option<&str>,
+        limit: usize,
+    ) -> Result<ContentSearchResult, StorageError>;
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, StorageError>;
+    /// Reads up to `length` bytes starting at `offset`. The flag is set when
+    /// the read reached the end of the file; reading past it yields no bytes
+    /// rather than an error.
+    fn read_file_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, bool), StorageError>;
+    /// Reads several files in one go. The outer error is a connection
+    /// failure; each file carries its own result otherwise.
+    fn read_files(&self, paths: &[String]) -> Result<Vec<FileReadOutcome>, StorageError>;
+    /// Reads `path` in chunks of at most [`STREAM_CHUNK_SIZE`] without
+    /// buffering the whole file. Returns `false` if `on_chunk` stopped it.
+    fn read_file_streamed(
+        &self,
+        path: &str,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError>;
+    fn write_file(&self, path: &str, data: &[u8]) -> Result<(), StorageError>;
+    fn exists(&self, path: &str) -> Result<bool, StorageError>;
+    /// Fetches the entry for a single path, failing with [`StorageError::NotFound`]
+    /// when it does not exist.
+    fn stat(&self, path: &str) -> Result<FileInfo, StorageError>;
+    /// Sets the permission bits of `path` and returns the updated entry.
+    fn set_permissions(&self, path: &str, mode: u32) -> Result<FileInfo, StorageError>;
+    /// Sets the modification time of `path` to `mtime` (unix seconds) and
+    /// returns the updated entry. Times in the future are accepted.
+    fn set_modified(&self, path: &str, mtime: u64) -> Result<FileInfo, StorageError>;
+    /// Totals the size and entry counts of everything below `path` without
+    /// following symlinks.
+    fn directory_size(&self, path: &str) -> Result<DirectoryUsage, StorageError>;
+    /// Streams an archive of the directory at `path` built on the remote
+    /// host. Fails with [`StorageError::ToolMissing`] when the host
+    /// cannot build that format. Returns `false` if `on_chunk` stopped it.
+    fn archive_directory(
+        &self,
+        path: &str,
+        format: ArchiveFormat,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError>;
+    /// Unpacks the archive at `path` into the directory `destination` on the
+    /// remote host and returns the top-level entries it contained. Fails with
+    /// [`StorageError::ToolMissing`] when the extractor is missing.
+    fn extract_archive(&self, path: &str, destination: &str) -> Result<Vec<String>, StorageError>;
+    /// Hashes a file on the remote host, falling back to streaming it and
+    /// hashing locally when the host lacks the tool.
+    fn checksum(
+        &self,
+        path: &str,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<FileChecksum, StorageError>;
+    /// Hashes several files with a single remote call, in the order given.
+    /// Files that could not be hashed yield `None`.
+    fn checksums(
+        &self,
+        paths: &[String],
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<Vec<Option<String>>, StorageError>;
+    /// Removes a regular file; directories are rejected with "is a directory".
+    fn delete_file(&self, path: &str) -> Result<(), StorageError>;
+    /// Removes a directory, refusing the storage root and shallow paths. A
+    /// non-recursive delete fails unless the directory is empty.
+    fn delete_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+    ) -> Result<DeleteDirectoryResult, StorageError>;
+    /// Moves `from` to `to`, replacing an existing destination, and returns
+    /// the entry at its new location.
+    fn rename(&self, from: &str, to: &str) -> Result<FileInfo, StorageError>;
+    /// Moves `from` to `to` together with a record describing the move:
+    /// `record` is written with `content`, or deleted when it is `None`.
+    /// Backends that commit their changes make it a single commit.
+    fn rename_with_record(
+        &self,
+        from: &str,
+        to: &str,
+        record: &str,
+        content: Option<&[u8]>,
+    ) -> Result<FileInfo, StorageError> {
+        match content {
+            Some(content) => {
+                self.write_file(record, content)?;
+                self.rename(from, to)
+            }
+            None => {
+                let moved = self.rename(from, to)?;
+                self.delete_file(record)?;
+                Ok(moved)
+            }
+        }
+    }
+    /// Copies a regular file to `to`, replacing an existing destination, and
+    /// returns the new entry. Directories are rejected.
+    fn copy_file(&self, from: &str, to: &str) -> Result<FileInfo, StorageError>;
+    /// Creates `path`, and its missing parents when `recursive` is set.
+    /// Succeeds without changes if the directory already exists.
+    fn create_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+    ) -> Result<CreateDirectoryResult, StorageError>;
     fn get_file_thumbnail(
         &self,
         path: &str,
         max_size: u32,
-    ) -> Result<String, Box<dyn std::error::Error>>;
+        options: ThumbnailOptions,
+    ) -> Result<Thumbnail, StorageError>;
+    /// The remote tool making this backend's image thumbnails, or `None`
+    /// when they are made locally.
+    fn remote_thumbnailer(&self) -> Option<RemoteThumbnailer> {
+        None
+    }
+    /// Dimensions, duration and codec of a media file, read from its
+    /// header. Files that cannot be parsed give an empty probe, not an error.
+    fn probe_media(&self, path: &str) -> Result<MediaProbe, StorageError> {
+        let (header, _) = self.read_file_range(path, 0, media_probe::PROBE_HEADER_BYTES)?;
+        Ok(media_probe::probe_image_header(&header))
+    }
     fn get_root_path(&self) -> String;
+    fn path_translator(&self) -> PathTranslator;
     fn storage_type(&self) -> StorageType;
+    /// Identifies the remote across sessions: user and host for EC2, the
+    /// repository and branch for GitHub.
+    fn connection_id(&self) -> String;
 }
 
+/// The MIME type of `filename` from its extension. Unknown extensions and
+/// names without one give `None`; see [`sniff_file_mime_type`].
 pub fn detect_mime_type(filename: &str) -> Option<String> {
     use std::path::Path;
 
@@ -70,6 +913,14 @@ pub fn detect_mime_type(filename: &str) -> Option<String> {
         "bmp" => Some("image/bmp".to_string()),
         "svg" => Some("image/svg+xml".to_string()),
         "heic" | "heif" => Some("image/heic".to_string()),
+        "avif" => Some("image/avif".to_string()),
+        "tif" | "tiff" => Some("image/tiff".to_string()),
+        "ico" => Some("image/x-icon".to_string()),
+        "psd" => Some("image/vnd.adobe.photoshop".to_string()),
+        "cr2" => Some("image/x-canon-cr2".to_string()),
+        "nef" => Some("image/x-nikon-nef".to_string()),
+        "arw" => Some("image/x-sony-arw".to_string()),
+        "dng" => Some("image/x-adobe-dng".to_string()),
         "mp4" => Some("video/mp4".to_string()),
         "mov" => Some("video/quicktime".to_string()),
         "avi" => Some("video/x-msvideo".to_string()),
@@ -82,6 +933,7 @@ pub fn detect_mime_type(filename: &str) -> Option<String> {
         "aac" => Some("audio/aac".to_string()),
         "ogg" => Some("audio/ogg".to_string()),
         "m4a" => Some("audio/mp4".to_string()),
+        "opus" => Some("audio/opus".to_string()),
         "pdf" => Some("application/pdf".to_string()),
         "doc" => Some("application/msword".to_string()),
         "docx" => Some(
@@ -101,24 +953,128 @@ pub fn detect_mime_type(filename: &str) -> Option<String> {
         "xml" => Some("application/xml".to_string()),
         "csv" => Some("text/csv".to_string()),
         "log" => Some("text/plain".to_string()),
+        "html" | "htm" => Some("text/html".to_string()),
+        "css" => Some("text/css".to_string()),
+        "js" | "mjs" => Some("text/javascript".to_string()),
+        "ts" => Some("text/x-typescript".to_string()),
+        "rs" => Some("text/x-rust".to_string()),
+        "py" => Some("text/x-python".to_string()),
+        "yaml" | "yml" => Some("application/yaml".to_string()),
+        "toml" => Some("application/toml".to_string()),
+        "epub" => Some("application/epub+zip".to_string()),
+        "ttf" => Some("font/ttf".to_string()),
+        "otf" => Some("font/otf".to_string()),
+        "woff" => Some("font/woff".to_string()),
+        "woff2" => Some("font/woff2".to_string()),
         "zip" => Some("application/zip".to_string()),
         "tar" => Some("application/x-tar".to_string()),
-        "gz" => Some("application/gzip".to_string()),
+        "gz" | "tgz" => Some("application/gzip".to_string()),
         "rar" => Some("application/vnd.rar".to_string()),
         "7z" => Some("application/x-7z-compressed".to_string()),
         _ => None,
     }
 }
 
+/// Bytes [`sniff_mime_type`] needs from the start of a file.
+pub const SNIFF_BYTES: u64 = 16;
+
+/// The type of a file from its first [`SNIFF_BYTES`], for names whose
+/// extension [`detect_mime_type`] does not know.
+pub fn sniff_mime_type(header: &[u8]) -> Option<String> {
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+    let mime = if at(0, b"\xFF\xD8\xFF") {
+        "image/jpeg"
+    } else if at(0, b"\x89PNG\r\n\x1A\n") {
+        "image/png"
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        "image/gif"
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        "image/webp"
+    } else if at(0, b"RIFF") && at(8, b"AVI ") {
+        "video/x-msvideo"
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        "audio/wav"
+    } else if at(0, b"BM") {
+        "image/bmp"
+    } else if at(0, b"II*\0") || at(0, b"MM\0*") {
+        "image/tiff"
+    } else if at(4, b"ftyp") {
+        match header.get(8..12).unwrap_or_default() {
+            b"heic" | b"heix" | b"mif1" | b"msf1" => "image/heic",
+            b"avif" | b"avis" => "image/avif",
+            b"qt  " => "video/quicktime",
+            b"M4V " => "video/x-m4v",
+            b"M4A " => "audio/mp4",
+            _ => "video/mp4",
+        }
+    } else if at(0, b"\x1A\x45\xDF\xA3") {
+        "video/x-matroska"
+    } else if at(0, b"ID3") || at(0, b"\xFF\xFB") {
+        "audio/mpeg"
+    } else if at(0, b"fLaC") {
+        "audio/flac"
+    } else if at(0, b"OggS") {
+        "audio/ogg"
+    } else if at(0, b"%PDF") {
+        "application/pdf"
+    } else if at(0, b"PK\x03\x04") {
+        "application/zip"
+    } else if at(0, b"\x1F\x8B") {
+        "application/gzip"
+    } else if at(0, b"7z\xBC\xAF\x27\x1C") {
+        "application/x-7z-compressed"
+    } else if at(0, b"Rar!\x1A\x07") {
+        "application/vnd.rar"
+    } else {
+        return None;
+    };
+    Some(mime.to_string())
+}
+
+/// The type of `path` on `storage`: from its extension when known, and
+/// otherwise from its first bytes, fetched with a range read.
+pub fn sniff_file_mime_type(
+    storage: &dyn Storage,
+    path: &str,
+) -> Result<Option<String>, StorageError> {
+    if let Some(mime) = detect_mime_type(path) {
+        return Ok(Some(mime));
+    }
+    let (header, _) = storage.read_file_range(path, 0, SNIFF_BYTES)?;
+    Ok(sniff_mime_type(&header))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::str::FromStr;
 
+    #[test]
+    fn test_file_too_large_check() {
+        assert!(FileTooLargeError::check(10, Some(100), 100).is_ok());
+        // The announced size is enough to refuse before buffering anything.
+        assert_eq!(
+            FileTooLargeError::check(10, Some(101), 100),
+            Err(FileTooLargeError {
+                size: 101,
+                limit: 100
+            })
+        );
+        // Backends that cannot tell the size are stopped once it is exceeded.
+        assert!(FileTooLargeError::check(101, None, 100).is_err());
+        assert!(FileTooLargeError::check(101, Some(50), 100)
+            .unwrap_err()
+            .to_string()
+            .starts_with("file too large for in-memory read, use download"));
+    }
+
     #[test]
     fn test_storage_type_display() {
         assert_eq!(StorageType::Ec2.to_string(), "ec2");
         assert_eq!(StorageType::GitHub.to_string(), "github");
+        assert_eq!(StorageType::GitHubApi.to_string(), "github_api");
+        assert_eq!(StorageType::Local.to_string(), "local");
+        assert_eq!(StorageType::WebDav.to_string(), "webdav");
     }
 
     #[test]
@@ -147,6 +1103,14 @@ mod tests {
             Some("image/jpeg".to_string())
         );
         assert_eq!(detect_mime_type("image.PNG"), Some("image/png".to_string()));
+        assert_eq!(
+            detect_mime_type("photo.avif"),
+            Some("image/avif".to_string())
+        );
+        assert_eq!(
+            detect_mime_type("DSC01234.ARW"),
+            Some("image/x-sony-arw".to_string())
+        );
         assert_eq!(detect_mime_type("video.mp4"), Some("video/mp4".to_string()));
         assert_eq!(
             detect_mime_type("audio.mp3"),
@@ -161,7 +1125,275 @@ mod tests {
             detect_mime_type("archive.zip"),
             Some("application/zip".to_string())
         );
+        assert_eq!(detect_mime_type("scan.TIF"), Some("image/tiff".to_string()));
+        assert_eq!(detect_mime_type("main.rs"), Some("text/x-rust".to_string()));
+        assert_eq!(
+            detect_mime_type("config.yml"),
+            Some("application/yaml".to_string())
+        );
+        assert_eq!(
+            detect_mime_type("font.woff2"),
+            Some("font/woff2".to_string())
+        );
+        assert_eq!(
+            detect_mime_type("voice.opus"),
+            Some("audio/opus".to_string())
+        );
         assert_eq!(detect_mime_type("unknown.xyz"), None);
         assert_eq!(detect_mime_type("noextension"), None);
     }
+
+    #[test]
+    fn test_sniff_mime_type() {
+        let cases: [(&[u8], Option<&str>); 9] = [
+            (b"\xFF\xD8\xFF\xE1\0\x18Exif\0\0", Some("image/jpeg")),
+            (b"\x89PNG\r\n\x1A\n\0\0\0\rIHDR", Some("image/png")),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", Some("image/webp")),
+            (b"\0\0\0\x18ftypheic\0\0\0\0", Some("image/heic")),
+            (b"\0\0\0\x20ftypisom\0\0\x02\0", Some("video/mp4")),
+            (b"\0\0\0\x14ftypqt  \0\0\0\0", Some("video/quicktime")),
+            (b"PK\x03\x04\x14\0\0\0", Some("application/zip")),
+            (b"plain text here", None),
+            // Too short to hold the brand.
+            (b"\0\0\0\x18ftyp", Some("video/mp4")),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(sniff_mime_type(header).as_deref(), expected, "{:?}", header);
+        }
+        assert_eq!(sniff_mime_type(b""), None);
+    }
+
+    fn file(name: &str, is_dir: bool, size: u64, modified: Option<u64>) -> FileInfo {
+        FileInfo {
+            name: name.to_string(),
+            path: format!("/{}", name),
+            size,
+            is_dir,
+            is_symlink: false,
+            link_target: None,
+            modified,
+            mime_type: if is_dir { None } else { detect_mime_type(name) },
+            thumbnail: None,
+            permissions: None,
+            owner: None,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_file_info_for_file() {
+        let info = FileInfo::for_file("/photos/beach.JPG", 42, Some(7));
+        assert_eq!(info.name, "beach.JPG");
+        assert_eq!(info.path, "/photos/beach.JPG");
+        assert_eq!(info.size, 42);
+        assert!(!info.is_dir);
+        assert_eq!(info.mime_type, Some("image/jpeg".to_string()));
+    }
+
+    #[test]
+    fn test_recursive_listing_capped() {
+        let file = |path: &str| FileInfo::for_file(path, 0, None);
+        let listing = RecursiveListing::capped(vec![file("/b.jpg"), file("/a.jpg")]);
+        let paths: Vec<&str> = listing.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["/a.jpg", "/b.jpg"]);
+        assert!(!listing.truncated);
+
+        let files = (0..=MAX_RECURSIVE_ENTRIES)
+            .map(|i| file(&format!("/{:05}.jpg", i)))
+            .rev()
+            .collect();
+        let listing = RecursiveListing::capped(files);
+        assert!(listing.truncated);
+        assert_eq!(listing.files.len(), MAX_RECURSIVE_ENTRIES);
+        assert_eq!(listing.files[0].path, "/00000.jpg");
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("644"), Ok(0o644));
+        assert_eq!(parse_mode("0755"), Ok(0o755));
+        assert_eq!(parse_mode("4755"), Ok(0o4755));
+        assert!(parse_mode("").is_err());
+        assert!(parse_mode("rwx").is_err());
+        assert!(parse_mode("888").is_err());
+        assert!(parse_mode("17777").is_err());
+    }
+
+    #[test]
+    fn test_file_info_permissions_serde() {
+        let mut info = file("a.jpg", false, 1, None);
+        let json = serde_json::to_value(&info).unwrap();
+        assert!(json.get("permissions").is_none());
+        assert!(json.get("owner").is_none());
+
+        info.permissions = Some(0o640);
+        info.owner = Some("ubuntu".to_string());
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["permissions"], "640");
+        assert_eq!(json["owner"], "ubuntu");
+        let back: FileInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(back.permissions, Some(0o640));
+    }
+
+    #[test]
+    fn test_finish_range_read() {
+        assert_eq!(
+            finish_range_read(vec![1, 2, 3, 4], 3),
+            (vec![1, 2, 3], false)
+        );
+        assert_eq!(finish_range_read(vec![1, 2, 3], 3), (vec![1, 2, 3], true));
+        assert_eq!(finish_range_read(vec![1], 3), (vec![1], true));
+        assert_eq!(finish_range_read(Vec::new(), 3), (Vec::new(), true));
+    }
+
+    #[test]
+    fn test_paginate() {
+        let items: Vec<u32> = (0..10).collect();
+        assert_eq!(paginate(&items, 0, 3), vec![0, 1, 2]);
+        assert_eq!(paginate(&items, 8, 5), vec![8, 9]);
+        assert!(paginate(&items, 12, 5).is_empty());
+        assert_eq!(paginate(&items, 0, usize::MAX).len(), 10);
+    }
+
+    #[test]
+    fn test_sort_files_keeps_directories_first() {
+        let mut files = vec![
+            file("b.jpg", false, 10, Some(3)),
+            file("zdir", true, 0, None),
+            file("a.jpg", false, 30, Some(1)),
+            file("adir", true, 0, None),
+        ];
+
+        sort_files(&mut files, SortBy::Size, SortOrder::Desc);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["zdir", "adir", "a.jpg", "b.jpg"]);
+
+        sort_files(&mut files, SortBy::Modified, SortOrder::Asc);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["adir", "zdir", "a.jpg", "b.jpg"]);
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        let cases = [
+            ("IMG_2.jpg", "IMG_10.jpg", Ordering::Less),
+            ("img_10.jpg", "IMG_9.jpg", Ordering::Greater),
+            ("Beach.jpg", "apple.jpg", Ordering::Greater),
+            ("a1", "a01", Ordering::Less),
+            ("a01b", "a1c", Ordering::Less),
+            ("a007", "a8", Ordering::Less),
+            ("x", "x1", Ordering::Less),
+            ("1.jpg", "a.jpg", Ordering::Less),
+            ("ÉTÉ.jpg", "été.jpg", Ordering::Less),
+            ("été.jpg", "Zoo.jpg", Ordering::Greater),
+            ("A.jpg", "a.jpg", Ordering::Less),
+            ("a.jpg", "a.jpg", Ordering::Equal),
+            (
+                "shot_123456789012345678901234567890.jpg",
+                "shot_123456789012345678901234567891.jpg",
+                Ordering::Less,
+            ),
+            (
+                "v99999999999999999999",
+                "v100000000000000000000",
+                Ordering::Less,
+            ),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(natural_cmp(a, b), expected, "{} vs {}", a, b);
+            assert_eq!(natural_cmp(b, a), expected.reverse(), "{} vs {}", b, a);
+        }
+    }
+
+    #[test]
+    fn test_sort_files_by_name() {
+        let mut files = vec![
+            file("IMG_10.jpg", false, 1, None),
+            file("img_2.jpg", false, 1, None),
+            file("IMG_1.jpg", false, 1, None),
+        ];
+        sort_files(&mut files, SortBy::Name, SortOrder::Asc);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["IMG_1.jpg", "img_2.jpg", "IMG_10.jpg"]);
+
+        sort_files(&mut files, SortBy::Lexical, SortOrder::Asc);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["IMG_1.jpg", "IMG_10.jpg", "img_2.jpg"]);
+    }
+
+    #[test]
+    fn test_listing_batcher() {
+        let mut batches = Vec::new();
+        let mut on_batch = |batch: Vec<FileInfo>| {
+            batches.push(batch.len());
+            batches.len() < 2
+        };
+        let mut batcher = ListingBatcher::new(2, &mut on_batch);
+        assert!(batcher.push(file("a", false, 1, None)));
+        assert!(batcher.push(file("b", false, 1, None)));
+        assert!(batcher.push(file("c", false, 1, None)));
+        // The second batch is full and the receiver stops.
+        assert!(!batcher.push(file("d", false, 1, None)));
+        assert!(!batcher.push(file("e", false, 1, None)));
+        assert_eq!(batcher.finish(), 4);
+        assert_eq!(batches, vec![2, 2]);
+
+        let mut batches = Vec::new();
+        let mut on_batch = |batch: Vec<FileInfo>| {
+            batches.push(batch.len());
+            true
+        };
+        let mut batcher = ListingBatcher::new(0, &mut on_batch);
+        batcher.push(file("a", false, 1, None));
+        assert_eq!(batcher.finish(), 1);
+        assert_eq!(batches, vec![1]);
+    }
+
+    #[test]
+    fn test_media_filter_matches() {
+        let dir = file("album", true, 0, None);
+        let image = file("a.png", false, 1, None);
+        let video = file("b.mov", false, 1, None);
+        let text = file("c.txt", false, 1, None);
+
+        assert!(MediaFilter::Images.matches(&dir));
+        assert!(MediaFilter::Images.matches(&image));
+        assert!(!MediaFilter::Images.matches(&video));
+        assert!(MediaFilter::Videos.matches(&video));
+        assert!(MediaFilter::Media.matches(&image) && MediaFilter::Media.matches(&video));
+        assert!(!MediaFilter::Media.matches(&text));
+        assert!(MediaFilter::All.matches(&text));
+    }
+
+    #[test]
+    fn test_media_filter_matches_entry_buckets() {
+        let names = [
+            "a.JPG",
+            "b.png",
+            "c.webp",
+            "d.mp4",
+            "e.MOV",
+            "f.mkv",
+            "notes.txt",
+            ".bashrc",
+            "app.log",
+            "README",
+        ];
+        let kept = |filter: MediaFilter| -> Vec<&str> {
+            names
+                .iter()
+                .copied()
+                .filter(|name| filter.matches_entry(name, false))
+                .collect()
+        };
+
+        assert_eq!(kept(MediaFilter::Images), vec!["a.JPG", "b.png", "c.webp"]);
+        assert_eq!(kept(MediaFilter::Videos), vec!["d.mp4", "e.MOV", "f.mkv"]);
+        assert_eq!(
+            kept(MediaFilter::Media),
+            vec!["a.JPG", "b.png", "c.webp", "d.mp4", "e.MOV", "f.mkv"]
+        );
+        assert_eq!(kept(MediaFilter::All).len(), names.len());
+        assert!(MediaFilter::Images.matches_entry(".git", true));
+    }
 }
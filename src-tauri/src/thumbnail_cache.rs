@@ -0,0 +1,483 @@
+//! Generated thumbnails kept on disk, so reopening a folder does not fetch
+//! and re-encode every image again.
+//!
+//! Entries are keyed by a hash of the storage and path followed by a hash of
+//! the modification time, size and requested thumbnail size, so a changed
+//! file simply misses and all entries of one file can be found.
+//! Eviction is least-recently-used by file mtime, which is bumped on every
+//! hit (atime is unreliable on `noatime` mounts). A placeholder is stored
+//! with the thumbnail it was computed from.
+
+use crate::checksum;
+use crate::error::StorageError;
+use crate::prefetch::PrefetchCache;
+use crate::storage::{FileInfo, Storage};
+use crate::thumbnails::{self, Thumbnail, ThumbnailOptions};
+use openssl::hash::{hash, MessageDigest};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Directory under the app cache dir holding the entries.
+pub const THUMBNAIL_CACHE_DIR: &str = "thumbnails";
+pub const DEFAULT_THUMBNAIL_CACHE_BYTES: u64 = 256 * 1024 * 1024;
+
+const ENTRY_EXTENSION: &str = "thumb";
+/// Each entry starts with this and the payload length, so a truncated
+/// write is recognised instead of served. The payload is a [`Thumbnail`]
+/// as JSON; entries in an older format are discarded as damaged.
+const ENTRY_MAGIC: &[u8; 8] = b"ITHUMB2\n";
+const HEADER_LEN: usize = ENTRY_MAGIC.len() + 8;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ThumbnailCacheStats {
+    pub entries: usize,
+    pub total_bytes: u64,
+    pub max_bytes: u64,
+}
+
+pub struct ThumbnailCache {
+    dir: PathBuf,
+    max_bytes: AtomicU64,
+    /// Size on disk as of the last scan plus what was written since;
+    /// `None` until first needed.
+    total_bytes: Mutex<Option<u64>>,
+}
+
+/// Identifies `storage` in cache keys.
+pub fn storage_id(storage: &dyn Storage) -> String {
+    format!("{}:{}", storage.storage_type(), storage.connection_id())
+}
+
+/// The first 128 bits of the SHA-256 of `material`, in hex.
+fn short_digest(material: &str) -> Option<String> {
+    let digest = hash(MessageDigest::sha256(), material.as_bytes()).ok()?;
+    Some(checksum::to_hex(&digest[..16]))
+}
+
+fn path_digest(storage_id: &str, path: &str) -> Option<String> {
+    short_digest(&format!("{}\0{}", storage_id, path))
+}
+
+/// Cache key for `file` at `max_size`, or `None` when the backend did not
+/// report a modification time and changes could not be noticed.
+pub fn cache_key(storage_id: &str, file: &FileInfo, max_size: u32) -> Option<String> {
+    let modified = file.modified?;
+    let version = format!("{}\0{}\0{}", modified, file.size, max_size);
+    Some(format!(
+        "{}-{}",
+        path_digest(storage_id, &file.path)?,
+        short_digest(&version)?
+    ))
+}
+
+fn encode_entry(thumbnail: &Thumbnail) -> Vec<u8> {
+    let payload = serde_json::to_vec(thumbnail).unwrap_or_default();
+    let mut entry = Vec::with_capacity(HEADER_LEN + payload.len());
+    entry.extend_from_slice(ENTRY_MAGIC);
+    entry.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    entry.extend_from_slice(&payload);
+    entry
+}
+
+/// The thumbnail stored in `entry`, or `None` if it is damaged.
+fn decode_entry(entry: &[u8]) -> Option<Thumbnail> {
+    let payload = entry.strip_prefix(ENTRY_MAGIC.as_slice())?;
+    let (len, payload) = payload.split_at_checked(8)?;
+    let len = u64::from_le_bytes(len.try_into().ok()?);
+    if payload.len() as u64 != len {
+        return None;
+    }
+    let thumbnail: Thumbnail = serde_json::from_slice(payload).ok()?;
+    thumbnail.data_uri.starts_with("data:").then_some(thumbnail)
+}
+
+fn is_entry(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == ENTRY_EXTENSION)
+}
+
+impl ThumbnailCache {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        ThumbnailCache {
+            dir,
+            max_bytes: AtomicU64::new(max_bytes),
+            total_bytes: Mutex::new(None),
+        }
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, ENTRY_EXTENSION))
+    }
+
+    /// Entries with their size and last use, oldest first.
+    fn entries(&self) -> Vec<(PathBuf, u64, SystemTime)> {
+        let mut entries: Vec<_> = fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| is_entry(&entry.path()))
+            .filter_map(|entry| {
+                let meta = entry.metadata().ok()?;
+                Some((entry.path(), meta.len(), meta.modified().ok()?))
+            })
+            .collect();
+        entries.sort_by_key(|(_, _, used)| *used);
+        entries
+    }
+
+    /// The cached thumbnail for `key`. Damaged entries are removed and
+    /// reported as a miss.
+    pub fn get(&self, key: &str) -> Option<Thumbnail> {
+        let path = self.entry_path(key);
+        let entry = fs::read(&path).ok()?;
+        match decode_entry(&entry) {
+            Some(thumbnail) => {
+                if let Ok(file) = File::options().append(true).open(&path) {
+                    let _ = file.set_modified(SystemTime::now());
+                }
+                Some(thumbnail)
+            }
+            None => {
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    /// Stores `thumbnail` under `key`, evicting old entries if the cache
+    /// grows past its limit.
+    pub fn put(&self, key: &str, thumbnail: &Thumbnail) -> std::io::Result<()> {
+        static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+        fs::create_dir_all(&self.dir)?;
+        let entry = encode_entry(thumbnail);
+        // Written aside and renamed so readers never see a partial entry.
+        let temp = self.dir.join(format!(
+            "{}.{}.{}.tmp",
+            key,
+            std::process::id(),
+            NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = File::create(&temp)
+            .and_then(|mut file| file.write_all(&entry))
+            .and_then(|_| fs::rename(&temp, self.entry_path(key)));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+            return written;
+        }
+
+        let over_limit = {
+            let mut total = self.total_bytes.lock().unwrap_or_else(|e| e.into_inner());
+            let total = total.get_or_insert_with(|| {
+                self.entries()
+                    .iter()
+                    .map(|(_, size, _)| size)
+                    .sum::<u64>()
+                    .saturating_sub(entry.len() as u64)
+            });
+            *total += entry.len() as u64;
+            *total > self.max_bytes()
+        };
+        if over_limit {
+            self.evict();
+        }
+        Ok(())
+    }
+
+    /// Removes the least recently used entries until the cache fits its
+    /// limit.
+    pub fn evict(&self) {
+        let mut total = self.total_bytes.lock().unwrap_or_else(|e| e.into_inner());
+        let entries = self.entries();
+        let mut size: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        for (path, entry_size, _) in entries {
+            if size <= self.max_bytes() {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                size -= entry_size;
+            }
+        }
+        *total = Some(size);
+    }
+
+    /// Removes every entry of `path`, for when it changed in a way its
+    /// modification time and size may not show.
+    pub fn invalidate(&self, storage_id: &str, path: &str) {
+        let Some(prefix) = path_digest(storage_id, path) else {
+            return;
+        };
+        let mut total = self.total_bytes.lock().unwrap_or_else(|e| e.into_inner());
+        for (entry, _, _) in self.entries() {
+            let name = entry
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            if name
+                .strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.starts_with('-'))
+            {
+                let _ = fs::remove_file(&entry);
+            }
+        }
+        // Recounted on the next write.
+        *total = None;
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max_bytes(&self, max_bytes: u64) {
+        self.max_bytes.store(max_bytes, Ordering::Relaxed);
+        self.evict();
+    }
+
+    pub fn stats(&self) -> ThumbnailCacheStats {
+        let entries = self.entries();
+        ThumbnailCacheStats {
+            entries: entries.len(),
+            total_bytes: entries.iter().map(|(_, size, _)| size).sum(),
+            max_bytes: self.max_bytes(),
+        }
+    }
+
+    pub fn clear(&self) -> std::io::Result<()> {
+        let mut total = self.total_bytes.lock().unwrap_or_else(|e| e.into_inner());
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        *total = Some(0);
+        Ok(())
+    }
+}
+
+/// Returns the thumbnail of `path` from `cache` when it is still current,
+/// otherwise generates it, from `prefetched` content when it holds the file,
+/// and stores it. An entry without the placeholder `options` asks for counts
+/// as a miss. Failing to cache never fails the thumbnail.
+pub fn cached_thumbnail(
+    cache: Option<&ThumbnailCache>,
+    prefetched: Option<&PrefetchCache>,
+    storage: &dyn Storage,
+    path: &str,
+    max_size: u32,
+    options: ThumbnailOptions,
+) -> Result<Thumbnail, Box<dyn std::error::Error>> {
+    let storage_id = storage_id(storage);
+    let generate = || -> Result<Thumbnail, StorageError> {
+        let thumbnail = match prefetched.and_then(|prefetched| {
+            prefetched_thumbnail(prefetched, &storage_id, storage, path, max_size, options)
+        }) {
+            Some(thumbnail) => thumbnail,
+            None => storage.get_file_thumbnail(path, max_size, options)?,
+        };
+        storage.metrics().record_thumbnail();
+        Ok(thumbnail)
+    };
+    let Some(cache) = cache else {
+        return Ok(generate()?);
+    };
+    let key = cache_key(&storage_id, &storage.stat(path)?, max_size);
+    if let Some(thumbnail) = key.as_deref().and_then(|key| cache.get(key)) {
+        if !options.placeholder || thumbnail.blurhash.is_some() {
+            return Ok(thumbnail);
+        }
+    }
+    let thumbnail = generate()?;
+    if let Some(key) = key {
+        let _ = cache.put(&key, &thumbnail);
+    }
+    Ok(thumbnail)
+}
+
+/// The thumbnail of `path` made from the content `prefetched` holds, so the
+/// file is not fetched again. `None` if it is not there or the content alone
+/// does not make a thumbnail, like a video's.
+fn prefetched_thumbnail(
+    prefetched: &PrefetchCache,
+    storage_id: &str,
+    storage: &dyn Storage,
+    path: &str,
+    max_size: u32,
+    options: ThumbnailOptions,
+) -> Option<Thumbnail> {
+    if !prefetched.contains(storage_id, path) {
+        return None;
+    }
+    let content = prefetched.get(storage_id, path, || storage.stat(path).ok())?;
+    let thumbnail = thumbnails::generate_thumbnail(&content, path, max_size, options).ok()?;
+    Some(thumbnail.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thumb() -> Thumbnail {
+        Thumbnail {
+            data_uri: "data:image/jpeg;base64,AAAA".to_string(),
+            blurhash: Some("L00000fQfQfQfQfQfQfQfQfQfQfQ".to_string()),
+            width: 3,
+            height: 2,
+        }
+    }
+
+    fn temp_cache(max_bytes: u64) -> (tempfile::TempDir, ThumbnailCache) {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ThumbnailCache::new(dir.path().to_path_buf(), max_bytes);
+        (dir, cache)
+    }
+
+    fn file(modified: Option<u64>, size: u64) -> FileInfo {
+        FileInfo::for_file("/photos/a.jpg", size, modified)
+    }
+
+    #[test]
+    fn test_cache_key_changes_with_file() {
+        let key = cache_key("ec2:me@host:22", &file(Some(10), 5), 256).unwrap();
+        assert_eq!(key.len(), 65);
+        assert_eq!(
+            cache_key("ec2:me@host:22", &file(Some(10), 5), 256).unwrap(),
+            key
+        );
+        assert_ne!(
+            cache_key("ec2:me@host:22", &file(Some(11), 5), 256).unwrap(),
+            key
+        );
+        assert_ne!(
+            cache_key("ec2:me@host:22", &file(Some(10), 6), 256).unwrap(),
+            key
+        );
+        assert_ne!(
+            cache_key("ec2:me@host:22", &file(Some(10), 5), 512).unwrap(),
+            key
+        );
+        assert_ne!(
+            cache_key("github:o/r@main", &file(Some(10), 5), 256).unwrap(),
+            key
+        );
+        assert_eq!(cache_key("ec2:me@host:22", &file(None, 5), 256), None);
+    }
+
+    #[test]
+    fn test_put_get_and_clear() {
+        let (_dir, cache) = temp_cache(DEFAULT_THUMBNAIL_CACHE_BYTES);
+        assert_eq!(cache.get("k"), None);
+        cache.put("k", &thumb()).unwrap();
+        assert_eq!(cache.get("k"), Some(thumb()));
+        assert_eq!(cache.stats().entries, 1);
+        cache.clear().unwrap();
+        assert_eq!(cache.get("k"), None);
+        assert_eq!(cache.stats().total_bytes, 0);
+    }
+
+    #[test]
+    fn test_truncated_entry_is_a_miss() {
+        let (_dir, cache) = temp_cache(DEFAULT_THUMBNAIL_CACHE_BYTES);
+        cache.put("k", &thumb()).unwrap();
+        let path = cache.entry_path("k");
+        let entry = fs::read(&path).unwrap();
+        fs::write(&path, &entry[..entry.len() - 2]).unwrap();
+        assert_eq!(cache.get("k"), None);
+        assert!(!path.exists());
+
+        assert_eq!(decode_entry(b""), None);
+        assert_eq!(decode_entry(&ENTRY_MAGIC[..4]), None);
+        // The earlier format held the bare data URI.
+        assert_eq!(decode_entry(b"ITHUMB1\n\x05\0\0\0\0\0\0\0data:"), None);
+        assert_eq!(decode_entry(&encode_entry(&thumb())), Some(thumb()));
+    }
+
+    #[test]
+    fn test_invalidate_removes_every_size_of_a_file() {
+        let (_dir, cache) = temp_cache(DEFAULT_THUMBNAIL_CACHE_BYTES);
+        let other = FileInfo::for_file("/photos/b.jpg", 5, Some(10));
+        let keys = [
+            cache_key("ec2:me@host:22", &file(Some(10), 5), 256).unwrap(),
+            cache_key("ec2:me@host:22", &file(Some(10), 5), 512).unwrap(),
+            cache_key("ec2:me@host:22", &other, 256).unwrap(),
+        ];
+        for key in &keys {
+            cache.put(key, &thumb()).unwrap();
+        }
+        cache.invalidate("ec2:me@host:22", "/photos/a.jpg");
+        assert_eq!(cache.get(&keys[0]), None);
+        assert_eq!(cache.get(&keys[1]), None);
+        assert!(cache.get(&keys[2]).is_some());
+    }
+
+    #[test]
+    fn test_eviction_drops_least_recently_used() {
+        let entry_len = encode_entry(&thumb()).len() as u64;
+        let (_dir, cache) = temp_cache(entry_len * 2);
+        cache.put("old", &thumb()).unwrap();
+        cache.put("used", &thumb()).unwrap();
+        let past = SystemTime::now() - std::time::Duration::from_secs(60);
+        for key in ["old", "used"] {
+            let file = File::options()
+                .append(true)
+                .open(cache.entry_path(key))
+                .unwrap();
+            file.set_modified(past).unwrap();
+        }
+        // A hit makes "used" the most recent entry.
+        assert!(cache.get("used").is_some());
+        cache.put("new", &thumb()).unwrap();
+
+        assert_eq!(cache.get("old"), None);
+        assert!(cache.get("used").is_some());
+        assert!(cache.get("new").is_some());
+        assert_eq!(cache.stats().total_bytes, entry_len * 2);
+
+        cache.set_max_bytes(entry_len);
+        assert_eq!(cache.stats().entries, 1);
+    }
+
+    #[test]
+    fn test_thumbnail_uses_prefetched_content() {
+        let png = |width, height| {
+            let mut buffer = std::io::Cursor::new(Vec::new());
+            image::DynamicImage::new_rgb8(width, height)
+                .write_to(&mut buffer, image::ImageFormat::Png)
+                .unwrap();
+            buffer.into_inner()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.png"), png(4, 2)).unwrap();
+        let mut storage = crate::local::LocalStorage::new(dir.path().to_path_buf());
+        storage.connect().unwrap();
+
+        // Content that differs from the file shows which one was used.
+        let prefetched = PrefetchCache::new(1024 * 1024);
+        let file = storage.stat("/a.png").unwrap();
+        prefetched.insert(&storage_id(&storage), file, png(8, 8));
+        let thumbnail = cached_thumbnail(
+            None,
+            Some(&prefetched),
+            &storage,
+            "/a.png",
+            128,
+            ThumbnailOptions::default(),
+        )
+        .unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (8, 8));
+        assert_eq!(storage.metrics().snapshot().files_read, 0);
+
+        let thumbnail = cached_thumbnail(
+            None,
+            None,
+            &storage,
+            "/a.png",
+            128,
+            ThumbnailOptions::default(),
+        )
+        .unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (4, 2));
+    }
+}
@@ -0,0 +1,731 @@
+use crate::blurhash;
+use crate::error::StorageError;
+use crate::image_decode::{self, UnsupportedFormatError};
+use crate::prefetch::PrefetchCache;
+use crate::raw_preview;
+use crate::ssh_util::{self, ExecOutput};
+use crate::storage::{self, detect_mime_type, Storage};
+use crate::thumbnail_cache::{self, ThumbnailCache};
+use crate::utils;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+use shell_escape::escape;
+use ssh2::Session;
+use std::io::{Cursor, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
+
+pub const DEFAULT_THUMBNAIL_WORKERS: usize = 4;
+pub const MAX_THUMBNAIL_WORKERS: usize = 16;
+/// Head of a video fetched for local decoding when the remote host has no
+/// ffmpeg. Enough for the first frame unless the index is at the end.
+pub const VIDEO_SAMPLE_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Exit status of [`video_frame_command`] when ffmpeg is missing.
+const FFMPEG_MISSING_STATUS: i32 = 127;
+
+/// A thumbnail as returned to the UI and kept in the thumbnail cache.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Thumbnail {
+    pub data_uri: String,
+    /// Set when a placeholder was requested, see [`blurhash::placeholder`].
+    pub blurhash: Option<String>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Payload of `thumbnail://ready`.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ThumbnailReady {
+    pub path: String,
+    #[serde(flatten)]
+    pub thumbnail: Thumbnail,
+}
+
+/// Payload of `thumbnail://error`.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ThumbnailError {
+    pub path: String,
+    pub error: String,
+    /// See [`image_decode::error_code`].
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct ThumbnailBatch {
+    pub ready: usize,
+    pub failed: usize,
+}
+
+/// Runs `task` for every item on up to `workers` threads, in no particular
+/// order.
+pub fn for_each_parallel<T: Sync>(items: &[T], workers: usize, task: impl Fn(&T) + Sync) {
+    let next = AtomicUsize::new(0);
+    let workers = workers.clamp(1, items.len().max(1));
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some(item) = items.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    task(item);
+                }
+            });
+        }
+    });
+}
+
+/// Generates thumbnails for `paths` on `workers` threads, taking current
+/// ones from `cache` and file content from `prefetched`. Reads share the backend's session while decoding and
+/// resizing run in parallel. Each result is reported as soon as it is done;
+/// one failure does not stop the others. Paths not started by the time
+/// `cancelled` is set are skipped and counted in neither total.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_batch(
+    storage: &dyn Storage,
+    cache: Option<&ThumbnailCache>,
+    prefetched: Option<&PrefetchCache>,
+    paths: &[String],
+    max_size: u32,
+    options: ThumbnailOptions,
+    workers: usize,
+    on_ready: &(dyn Fn(ThumbnailReady) + Sync),
+    on_error: &(dyn Fn(ThumbnailError) + Sync),
+    cancelled: &AtomicBool,
+) -> ThumbnailBatch {
+    let ready = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    for_each_parallel(paths, workers.min(MAX_THUMBNAIL_WORKERS), |path| {
+        if cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        let start = Instant::now();
+        match thumbnail_cache::cached_thumbnail(cache, prefetched, storage, path, max_size, options)
+        {
+            Ok(thumbnail) => {
+                log::debug!(
+                    "Thumbnail of {} ready in {} ms",
+                    path,
+                    start.elapsed().as_millis()
+                );
+                ready.fetch_add(1, Ordering::Relaxed);
+                on_ready(ThumbnailReady {
+                    path: path.clone(),
+                    thumbnail,
+                })
+            }
+            Err(e) => {
+                log::debug!(
+                    "Thumbnail of {} failed after {} ms: {}",
+                    path,
+                    start.elapsed().as_millis(),
+                    e
+                );
+                failed.fetch_add(1, Ordering::Relaxed);
+                on_error(ThumbnailError {
+                    path: path.clone(),
+                    error: e.to_string(),
+                    code: image_decode::error_code(e.as_ref()).map(str::to_string),
+                });
+            }
+        }
+    });
+    ThumbnailBatch {
+        ready: ready.into_inner(),
+        failed: failed.into_inner(),
+    }
+}
+
+/// Builds a command writing one JPEG frame from about a second into the
+/// video at `absolute_path` to stdout, so only the frame is transferred.
+pub fn video_frame_command(absolute_path: &str) -> String {
+    format!(
+        "command -v ffmpeg >/dev/null 2>&1 || exit {}; \
+         ffmpeg -v error -ss 1 -i {} -frames:v 1 -f image2 -c:v mjpeg -",
+        FFMPEG_MISSING_STATUS,
+        escape(absolute_path.into())
+    )
+}
+
+/// A tool on the remote host that makes thumbnails itself, so only the small
+/// result is transferred.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteThumbnailer {
+    Vipsthumbnail,
+    Magick,
+    /// ImageMagick 6.
+    Convert,
+}
+
+impl RemoteThumbnailer {
+    /// In order of preference.
+    const ALL: [RemoteThumbnailer; 3] = [
+        RemoteThumbnailer::Vipsthumbnail,
+        RemoteThumbnailer::Magick,
+        RemoteThumbnailer::Convert,
+    ];
+
+    pub fn tool(self) -> &'static str {
+        match self {
+            RemoteThumbnailer::Vipsthumbnail => "vipsthumbnail",
+            RemoteThumbnailer::Magick => "magick",
+            RemoteThumbnailer::Convert => "convert",
+        }
+    }
+
+    /// Builds a command printing the name of the first tool found.
+    pub fn probe_command() -> String {
+        let tools: Vec<&str> = Self::ALL.iter().map(|t| t.tool()).collect();
+        format!(
+            "for t in {}; do command -v \"$t\" >/dev/null 2>&1 && {{ echo \"$t\"; break; }}; done; true",
+            tools.join(" ")
+        )
+    }
+
+    pub fn from_probe_output(output: &str) -> Option<Self> {
+        let found = output.trim();
+        Self::ALL.into_iter().find(|t| t.tool() == found)
+    }
+
+    /// Builds a command writing a JPEG of `absolute_path` no larger than
+    /// `max_size` to stdout. Smaller images are not enlarged.
+    pub fn command(self, absolute_path: &str, max_size: u32, jpeg_quality: u8) -> String {
+        let size = format!("{0}x{0}>", max_size);
+        match self {
+            // vipsthumbnail only writes to files.
+            RemoteThumbnailer::Vipsthumbnail => format!(
+                "t=$(mktemp -d) || exit 1; vipsthumbnail {} -s {} -o \"$t/thumb.jpg[Q={}]\" \
+                 && cat \"$t/thumb.jpg\"; s=$?; rm -rf \"$t\"; exit $s",
+                escape(absolute_path.into()),
+                escape(size.into()),
+                jpeg_quality
+            ),
+            // `[0]` picks the first frame or page.
+            RemoteThumbnailer::Magick | RemoteThumbnailer::Convert => format!(
+                "{} {} -auto-orient -thumbnail {} -quality {} jpg:-",
+                self.tool(),
+                escape(format!("{}[0]", absolute_path).into()),
+                escape(size.into()),
+                jpeg_quality
+            ),
+        }
+    }
+}
+
+/// The first [`RemoteThumbnailer`] installed on the host behind `session`.
+pub fn probe_remote_thumbnailer(session: &Session) -> Option<RemoteThumbnailer> {
+    let output = ssh_util::ssh_exec(session, &RemoteThumbnailer::probe_command()).ok()?;
+    RemoteThumbnailer::from_probe_output(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Turns the output of [`RemoteThumbnailer::command`] into a thumbnail, or
+/// `None` if the tool failed and the file should be thumbnailed locally.
+pub fn tool_thumbnail(
+    output: ExecOutput,
+    max_size: u32,
+    options: ThumbnailOptions,
+) -> Option<ThumbnailResult> {
+    if !output.success() || !output.stdout.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    generate_thumbnail(&output.stdout, "thumbnail.jpg", max_size, options).ok()
+}
+
+/// Decodes the first frame of a partial video with a local ffmpeg.
+fn local_video_frame(sample: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut child = match Command::new("ffmpeg")
+        .args(["-v", "error", "-i", "pipe:0", "-frames:v", "1"])
+        .args(["-f", "image2", "-c:v", "mjpeg", "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(StorageError::ToolMissing("ffmpeg").into())
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mut stdin = child.stdin.take().ok_or("ffmpeg stdin unavailable")?;
+    // Fed from another thread so a full stdout pipe cannot deadlock us.
+    // ffmpeg may stop reading once it has a frame, so write errors are fine.
+    let feeder = std::thread::spawn(move || {
+        let _ = stdin.write_all(&sample);
+    });
+    let output = child.wait_with_output()?;
+    let _ = feeder.join();
+    if output.stdout.is_empty() {
+        return Err("Could not decode the start of the video".into());
+    }
+    Ok(output.stdout)
+}
+
+/// Turns the output of [`video_frame_command`] into a thumbnail no larger
+/// than `max_size`. When the remote host has no ffmpeg, `sample` supplies
+/// the start of the file for decoding locally instead.
+pub fn video_thumbnail(
+    remote_frame: ExecOutput,
+    sample: impl FnOnce() -> Result<Vec<u8>, Box<dyn std::error::Error>>,
+    max_size: u32,
+    options: ThumbnailOptions,
+) -> Result<ThumbnailResult, Box<dyn std::error::Error>> {
+    let frame = if remote_frame.exit_status == FFMPEG_MISSING_STATUS {
+        local_video_frame(sample()?)?
+    } else {
+        remote_frame.checked("ffmpeg")?
+    };
+    if frame.is_empty() {
+        return Err("No frame found in video".into());
+    }
+    generate_thumbnail(&frame, "frame.jpg", max_size, options)
+}
+
+/// Decodes `content`, of type `mime`, for a thumbnail no larger than
+/// `max_size`. With the `turbojpeg` feature JPEGs are decoded at reduced
+/// scale instead of in full.
+#[cfg_attr(not(feature = "turbojpeg"), allow(unused_variables))]
+pub fn decode_for_thumbnail(
+    content: &[u8],
+    mime: &str,
+    max_size: u32,
+) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    #[cfg(feature = "turbojpeg")]
+    if mime == "image/jpeg" {
+        return image_decode::decode_jpeg_scaled(content, max_size);
+    }
+    image_decode::decode_image(content, mime)
+}
+
+/// Builds a thumbnail from the preview embedded in a camera RAW file of
+/// type `mime`, reading only the header and the preview through
+/// `read_range(offset, length)`.
+pub fn raw_thumbnail(
+    read_range: impl Fn(u64, u64) -> Result<Vec<u8>, Box<dyn std::error::Error>>,
+    mime: &str,
+    max_size: u32,
+    options: ThumbnailOptions,
+) -> Result<ThumbnailResult, Box<dyn std::error::Error>> {
+    let no_preview = || UnsupportedFormatError(format!("{} without an embedded preview", mime));
+    let header = read_range(0, raw_preview::RAW_HEADER_BYTES)?;
+    let location = raw_preview::find_jpeg_preview(&header).ok_or_else(no_preview)?;
+    let preview = match location.slice(&header) {
+        Some(preview) => preview.to_vec(),
+        None => read_range(location.offset, location.length)?,
+    };
+    if !preview.starts_with(&[0xFF, 0xD8]) {
+        return Err(no_preview().into());
+    }
+    generate_thumbnail(&preview, "preview.jpg", max_size, options)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThumbnailOptions {
+    /// 1 to 100.
+    pub jpeg_quality: u8,
+    /// Also compute a BlurHash placeholder.
+    pub placeholder: bool,
+}
+
+impl Default for ThumbnailOptions {
+    fn default() -> Self {
+        ThumbnailOptions {
+            jpeg_quality: 80,
+            placeholder: false,
+        }
+    }
+}
+
+/// An encoded thumbnail; `mime_type` always names the format of `data`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThumbnailResult {
+    pub mime_type: &'static str,
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: Option<String>,
+}
+
+impl From<ThumbnailResult> for Thumbnail {
+    fn from(result: ThumbnailResult) -> Self {
+        Thumbnail {
+            data_uri: result.data_uri(),
+            blurhash: result.blurhash,
+            width: result.width,
+            height: result.height,
+        }
+    }
+}
+
+impl ThumbnailResult {
+    pub fn data_uri(&self) -> String {
+        format!(
+            "data:{};base64,{}",
+            self.mime_type,
+            utils::base64_encode(&self.data)
+        )
+    }
+}
+
+fn encode(
+    img: &DynamicImage,
+    format: ImageFormat,
+    options: ThumbnailOptions,
+) -> Result<ThumbnailResult, Box<dyn std::error::Error>> {
+    let mut data = Vec::new();
+    if format == ImageFormat::Jpeg {
+        // JPEG has no alpha channel.
+        JpegEncoder::new_with_quality(&mut data, options.jpeg_quality.clamp(1, 100))
+            .encode_image(&img.to_rgb8())?;
+    } else {
+        img.write_to(&mut Cursor::new(&mut data), format)?;
+    }
+    Ok(ThumbnailResult {
+        mime_type: format.to_mime_type(),
+        data,
+        width: img.width(),
+        height: img.height(),
+        blurhash: options.placeholder.then(|| blurhash::placeholder(img)),
+    })
+}
+
+/// Makes a thumbnail no larger than `max_size` on either side from the
+/// contents of `filename`. PNG, GIF and WebP sources keep their format;
+/// SVGs become PNGs and everything else becomes JPEG. Smaller images are
+/// not enlarged.
+pub fn generate_thumbnail(
+    content: &[u8],
+    filename: &str,
+    max_size: u32,
+    options: ThumbnailOptions,
+) -> Result<ThumbnailResult, Box<dyn std::error::Error>> {
+    let guessed = image::guess_format(content).ok();
+    let mime = detect_mime_type(filename)
+        .or_else(|| storage::sniff_mime_type(content))
+        .or_else(|| guessed.map(|format| format.to_mime_type().to_string()))
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+
+    if mime == "image/svg+xml" {
+        let rendered = image_decode::render_svg(content, max_size)?;
+        return encode(&rendered, ImageFormat::Png, options);
+    }
+    if !mime.starts_with("image/") && guessed.is_none() {
+        return Err(UnsupportedFormatError(mime).into());
+    }
+
+    let img = decode_for_thumbnail(content, &mime, max_size)?;
+    let img = if img.width() > max_size || img.height() > max_size {
+        img.thumbnail(max_size, max_size)
+    } else {
+        img
+    };
+    let format = match guessed {
+        Some(format @ (ImageFormat::Png | ImageFormat::Gif | ImageFormat::WebP)) => format,
+        _ => ImageFormat::Jpeg,
+    };
+    encode(&img, format, options)
+}
+
+/// The thumbnail of `path` on a `storage` that cannot run tools where the
+/// files are, made like [`remote_file_thumbnail`] from fetched bytes, with a
+/// local ffmpeg for videos.
+pub fn fetched_file_thumbnail(
+    storage: &dyn Storage,
+    path: &str,
+    max_size: u32,
+    options: ThumbnailOptions,
+) -> Result<Thumbnail, Box<dyn std::error::Error>> {
+    let mime = storage::sniff_file_mime_type(storage, path)?.unwrap_or_default();
+    let thumbnail = if mime.starts_with("video/") {
+        let sample = storage.read_file_range(path, 0, VIDEO_SAMPLE_BYTES)?.0;
+        generate_thumbnail(&local_video_frame(sample)?, "frame.jpg", max_size, options)?
+    } else if raw_preview::is_raw_mime(&mime) {
+        raw_thumbnail(
+            |offset, length| Ok(storage.read_file_range(path, offset, length)?.0),
+            &mime,
+            max_size,
+            options,
+        )?
+    } else {
+        let content =
+            storage::read_file_capped(storage, path, image_decode::MAX_THUMBNAIL_INPUT_BYTES)?;
+        generate_thumbnail(&content, path, max_size, options)?
+    };
+    Ok(thumbnail.into())
+}
+
+/// The thumbnail of `path` on an SSH-backed `storage`, where the file is at
+/// `absolute_path` on the host behind `session`. Videos and RAW files are
+/// handled without transferring them whole, and other images by
+/// `thumbnailer` when given, falling back to fetching the file.
+pub fn remote_file_thumbnail(
+    storage: &dyn Storage,
+    session: &Session,
+    thumbnailer: Option<RemoteThumbnailer>,
+    absolute_path: &str,
+    path: &str,
+    max_size: u32,
+    options: ThumbnailOptions,
+) -> Result<Thumbnail, Box<dyn std::error::Error>> {
+    let mime = storage::sniff_file_mime_type(storage, path)?.unwrap_or_default();
+    let from_tool = || {
+        let thumbnailer = thumbnailer.filter(|_| mime != "image/svg+xml")?;
+        let command = thumbnailer.command(absolute_path, max_size, options.jpeg_quality);
+        storage.metrics().record_command();
+        tool_thumbnail(
+            ssh_util::ssh_exec(session, &command).ok()?,
+            max_size,
+            options,
+        )
+    };
+    let thumbnail = if mime.starts_with("video/") {
+        storage.metrics().record_command();
+        video_thumbnail(
+            ssh_util::ssh_exec(session, &video_frame_command(absolute_path))?,
+            || Ok(storage.read_file_range(path, 0, VIDEO_SAMPLE_BYTES)?.0),
+            max_size,
+            options,
+        )?
+    } else if raw_preview::is_raw_mime(&mime) {
+        raw_thumbnail(
+            |offset, length| Ok(storage.read_file_range(path, offset, length)?.0),
+            &mime,
+            max_size,
+            options,
+        )?
+    } else if let Some(thumbnail) = from_tool() {
+        thumbnail
+    } else {
+        let content =
+            storage::read_file_capped(storage, path, image_decode::MAX_THUMBNAIL_INPUT_BYTES)?;
+        generate_thumbnail(&content, path, max_size, options)?
+    };
+    Ok(thumbnail.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn encoded(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut buffer = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut buffer, format)
+            .unwrap();
+        buffer.into_inner()
+    }
+
+    fn jpeg_frame(width: u32, height: u32) -> Vec<u8> {
+        encoded(width, height, ImageFormat::Jpeg)
+    }
+
+    #[test]
+    fn test_generate_thumbnail_reencodes_to_jpeg() {
+        let options = ThumbnailOptions::default();
+        for (name, format) in [
+            ("scan.bmp", ImageFormat::Bmp),
+            ("scan.tiff", ImageFormat::Tiff),
+        ] {
+            let thumbnail =
+                generate_thumbnail(&encoded(400, 200, format), name, 100, options).unwrap();
+            assert_eq!(thumbnail.mime_type, "image/jpeg");
+            assert_eq!((thumbnail.width, thumbnail.height), (100, 50));
+            assert!(thumbnail.data_uri().starts_with("data:image/jpeg;base64,"));
+            assert_eq!(
+                image::guess_format(&thumbnail.data).unwrap(),
+                ImageFormat::Jpeg
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_thumbnail_keeps_png_and_size() {
+        let png = encoded(40, 30, ImageFormat::Png);
+        // The content decides the format, not a misleading extension.
+        let thumbnail =
+            generate_thumbnail(&png, "icon.jpg", 100, ThumbnailOptions::default()).unwrap();
+        assert_eq!(thumbnail.mime_type, "image/png");
+        assert_eq!((thumbnail.width, thumbnail.height), (40, 30));
+        assert_eq!(thumbnail.blurhash, None);
+    }
+
+    #[test]
+    fn test_generate_thumbnail_with_placeholder() {
+        let options = ThumbnailOptions {
+            placeholder: true,
+            ..ThumbnailOptions::default()
+        };
+        let thumbnail =
+            generate_thumbnail(&jpeg_frame(400, 200), "photo.jpg", 100, options).unwrap();
+        assert_eq!(
+            thumbnail.blurhash.as_deref(),
+            Some("L00000fQfQfQfQfQfQfQfQfQfQfQ")
+        );
+        let thumbnail = Thumbnail::from(thumbnail);
+        assert!(thumbnail.data_uri.starts_with("data:image/jpeg;base64,"));
+        assert_eq!((thumbnail.width, thumbnail.height), (100, 50));
+    }
+
+    #[test]
+    fn test_generate_thumbnail_sniffs_unknown_names() {
+        let jpeg = jpeg_frame(40, 30);
+        for name in ["photo", "backup.dat"] {
+            let thumbnail =
+                generate_thumbnail(&jpeg, name, 100, ThumbnailOptions::default()).unwrap();
+            assert_eq!((thumbnail.width, thumbnail.height), (40, 30));
+        }
+        let err =
+            generate_thumbnail(b"%PDF-1.7", "scan", 100, ThumbnailOptions::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            UnsupportedFormatError("application/pdf".into()).to_string()
+        );
+    }
+
+    #[test]
+    fn test_generate_thumbnail_rejects_non_images() {
+        let err = generate_thumbnail(b"%PDF-1.7", "doc.pdf", 100, ThumbnailOptions::default())
+            .unwrap_err();
+        assert!(err.is::<UnsupportedFormatError>());
+    }
+
+    fn frame_output(stdout: Vec<u8>, exit_status: i32, stderr: &str) -> ExecOutput {
+        ExecOutput {
+            stdout,
+            stderr: stderr.as_bytes().to_vec(),
+            exit_status,
+        }
+    }
+
+    #[test]
+    fn test_raw_thumbnail_uses_embedded_preview() {
+        let preview = jpeg_frame(300, 200);
+        // A little-endian TIFF whose only IFD points at the preview at 38.
+        let mut raw = b"II*\0\x08\0\0\0\x02\0".to_vec();
+        for (tag, value) in [(0x0201u16, 38u32), (0x0202, preview.len() as u32)] {
+            raw.extend_from_slice(&tag.to_le_bytes());
+            raw.extend_from_slice(&4u16.to_le_bytes());
+            raw.extend_from_slice(&1u32.to_le_bytes());
+            raw.extend_from_slice(&value.to_le_bytes());
+        }
+        raw.extend_from_slice(&0u32.to_le_bytes());
+        raw.extend_from_slice(&preview);
+
+        let read_range = |offset: u64, length: u64| {
+            let end = (offset + length).min(raw.len() as u64);
+            Ok(raw[offset as usize..end as usize].to_vec())
+        };
+        let options = ThumbnailOptions::default();
+        let thumbnail = raw_thumbnail(read_range, "image/x-sony-arw", 150, options).unwrap();
+        assert_eq!(thumbnail.mime_type, "image/jpeg");
+        assert_eq!((thumbnail.width, thumbnail.height), (150, 100));
+
+        let err = raw_thumbnail(
+            |_, _| Ok(b"II*\0\0\0\0\0".to_vec()),
+            "image/x-sony-arw",
+            150,
+            options,
+        )
+        .unwrap_err();
+        assert!(err.is::<UnsupportedFormatError>());
+    }
+
+    #[test]
+    fn test_remote_thumbnailer_commands() {
+        assert_eq!(
+            RemoteThumbnailer::from_probe_output("magick\n"),
+            Some(RemoteThumbnailer::Magick)
+        );
+        assert_eq!(RemoteThumbnailer::from_probe_output(""), None);
+        assert_eq!(
+            RemoteThumbnailer::Convert.command("/scans/a b.tif", 256, 80),
+            "convert '/scans/a b.tif[0]' -auto-orient -thumbnail '256x256>' -quality 80 jpg:-"
+        );
+        assert!(RemoteThumbnailer::Vipsthumbnail
+            .command("/scans/a.tif", 256, 80)
+            .starts_with("t=$(mktemp -d) || exit 1; vipsthumbnail /scans/a.tif -s '256x256>'"));
+    }
+
+    #[test]
+    fn test_tool_thumbnail_falls_back_on_failure() {
+        let options = ThumbnailOptions::default();
+        let thumbnail =
+            tool_thumbnail(frame_output(jpeg_frame(256, 128), 0, ""), 256, options).unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (256, 128));
+        assert_eq!(
+            tool_thumbnail(frame_output(Vec::new(), 1, "no decoder"), 256, options),
+            None
+        );
+        assert_eq!(
+            tool_thumbnail(
+                frame_output(b"convert: usage".to_vec(), 0, ""),
+                256,
+                options
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_video_frame_command_quotes_path() {
+        assert_eq!(
+            video_frame_command("/home/me/my clip.mov"),
+            "command -v ffmpeg >/dev/null 2>&1 || exit 127; \
+             ffmpeg -v error -ss 1 -i '/home/me/my clip.mov' -frames:v 1 -f image2 -c:v mjpeg -"
+        );
+    }
+
+    #[test]
+    fn test_video_thumbnail_from_remote_frame() {
+        let thumbnail = video_thumbnail(
+            frame_output(jpeg_frame(640, 360), 0, ""),
+            || panic!("the sample is only needed without a remote ffmpeg"),
+            128,
+            ThumbnailOptions::default(),
+        )
+        .unwrap();
+        let data_uri = thumbnail.data_uri();
+        let encoded = data_uri.strip_prefix("data:image/jpeg;base64,").unwrap();
+        let decoded = image::load_from_memory(&utils::base64_decode(encoded).unwrap()).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (128, 72));
+    }
+
+    #[test]
+    fn test_video_thumbnail_reports_ffmpeg_failure() {
+        let options = ThumbnailOptions::default();
+        let err = video_thumbnail(
+            frame_output(Vec::new(), 1, "moov atom not found"),
+            || panic!("ffmpeg exists on the remote"),
+            128,
+            options,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("moov atom not found"));
+
+        let err = video_thumbnail(
+            frame_output(Vec::new(), 0, ""),
+            || unreachable!(),
+            128,
+            options,
+        );
+        assert_eq!(err.unwrap_err().to_string(), "No frame found in video");
+    }
+
+    #[test]
+    fn test_for_each_parallel_visits_every_item_once() {
+        let items: Vec<usize> = (0..100).collect();
+        let seen = Mutex::new(Vec::new());
+        for_each_parallel(&items, 4, |i| seen.lock().unwrap().push(*i));
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort();
+        assert_eq!(seen, items);
+    }
+
+    #[test]
+    fn test_for_each_parallel_handles_empty_input() {
+        let items: Vec<usize> = Vec::new();
+        for_each_parallel(&items, 0, |_| panic!("no items"));
+    }
+}
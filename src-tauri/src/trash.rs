@@ -0,0 +1,272 @@
+//! Soft delete through a `.imagetrash` directory at the storage root.
+//!
+//! Trashed entries live in `files/` under an id made of their name and the
+//! deletion time; a matching record in `info/` remembers where they came
+//! from. Everything goes through the [`Storage`] primitives; an entry and
+//! its record move together through [`Storage::rename_with_record`], so a
+//! GitHub trash or restore is a single commit.
+
+use crate::error::StorageError;
+use crate::paths::RemotePath;
+use crate::storage::{FileInfo, MediaFilter, Storage};
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const TRASH_DIR: &str = "/.imagetrash";
+const FILES_DIR: &str = "files";
+const INFO_DIR: &str = "info";
+const SECONDS_PER_DAY: u64 = 86_400;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TrashEntry {
+    pub id: String,
+    pub original_path: String,
+    pub deleted_at: u64,
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+fn files_dir() -> RemotePath {
+    RemotePath::new(TRASH_DIR).join(FILES_DIR)
+}
+
+fn info_dir() -> RemotePath {
+    RemotePath::new(TRASH_DIR).join(INFO_DIR)
+}
+
+fn trash_id(name: &str, deleted_at: u64, attempt: usize) -> String {
+    if attempt == 0 {
+        format!("{}.{}", name, deleted_at)
+    } else {
+        format!("{}.{}-{}", name, deleted_at, attempt)
+    }
+}
+
+fn format_trash_info(original_path: &str, deleted_at: u64) -> String {
+    format!("Path={}\nDeletedAt={}\n", original_path, deleted_at)
+}
+
+/// Reads an `info/` record back into the original path and deletion time.
+fn parse_trash_info(content: &str) -> Option<(String, u64)> {
+    let mut path = None;
+    let mut deleted_at = None;
+    for line in content.lines() {
+        if let Some(value) = line.strip_prefix("Path=") {
+            path = Some(value.to_string());
+        } else if let Some(value) = line.strip_prefix("DeletedAt=") {
+            deleted_at = value.trim().parse().ok();
+        }
+    }
+    Some((path?, deleted_at?))
+}
+
+fn is_expired(deleted_at: u64, now: u64, older_than_days: u64) -> bool {
+    now.saturating_sub(deleted_at) >= older_than_days * SECONDS_PER_DAY
+}
+
+fn is_in_trash(path: &RemotePath) -> bool {
+    let trash = RemotePath::new(TRASH_DIR);
+    path == &trash || path.as_str().starts_with(&format!("{}/", trash))
+}
+
+/// Moves `path` into the trash and returns its entry.
+pub fn move_to_trash(storage: &dyn Storage, path: &str) -> Result<TrashEntry, StorageError> {
+    let original = storage.path_translator().to_remote(path);
+    if original.is_root() || is_in_trash(&original) {
+        return Err(StorageError::InvalidInput(format!(
+            "Cannot move {} to the trash",
+            original
+        )));
+    }
+    let info = storage.stat(original.as_str())?;
+
+    storage.create_directory(files_dir().as_str(), true)?;
+    storage.create_directory(info_dir().as_str(), true)?;
+
+    let deleted_at = utils::unix_now();
+    let name = original.file_name().unwrap_or_default();
+    let mut attempt = 0;
+    let id = loop {
+        let id = trash_id(name, deleted_at, attempt);
+        if !storage.exists(files_dir().join(&id).as_str())? {
+            break id;
+        }
+        attempt += 1;
+    };
+
+    storage.rename_with_record(
+        original.as_str(),
+        files_dir().join(&id).as_str(),
+        info_dir().join(&id).as_str(),
+        Some(format_trash_info(original.as_str(), deleted_at).as_bytes()),
+    )?;
+
+    Ok(TrashEntry {
+        id,
+        original_path: original.to_string(),
+        deleted_at,
+        size: info.size,
+        is_dir: info.is_dir,
+    })
+}
+
+/// Lists trashed entries, oldest first. Items without a readable record are
+/// left out.
+pub fn list_trash(storage: &dyn Storage) -> Result<Vec<TrashEntry>, StorageError> {
+    if !storage.exists(info_dir().as_str())? || !storage.exists(files_dir().as_str())? {
+        return Ok(Vec::new());
+    }
+    let files: HashMap<String, FileInfo> = storage
+        .list_directory(files_dir().as_str(), MediaFilter::All)?
+        .into_iter()
+        .map(|f| (f.name.clone(), f))
+        .collect();
+    let records: Vec<String> = storage
+        .list_directory(info_dir().as_str(), MediaFilter::All)?
+        .into_iter()
+        .filter(|f| !f.is_dir && files.contains_key(&f.name))
+        .map(|f| f.name)
+        .collect();
+    let paths: Vec<String> = records
+        .iter()
+        .map(|id| info_dir().join(id).to_string())
+        .collect();
+
+    let mut entries: Vec<TrashEntry> = records
+        .into_iter()
+        .zip(storage.read_files(&paths)?)
+        .filter_map(|(id, content)| {
+            let (original_path, deleted_at) =
+                parse_trash_info(&String::from_utf8_lossy(&content.ok()?))?;
+            let file = &files[&id];
+            Some(TrashEntry {
+                original_path,
+                deleted_at,
+                size: file.size,
+                is_dir: file.is_dir,
+                id,
+            })
+        })
+        .collect();
+    entries.sort_by_key(|e| e.deleted_at);
+    Ok(entries)
+}
+
+fn read_entry_info(storage: &dyn Storage, id: &str) -> Result<(String, u64), StorageError> {
+    if id.is_empty() || id.contains('/') || id == "." || id == ".." {
+        return Err(StorageError::InvalidInput(format!(
+            "Invalid trash entry: {}",
+            id
+        )));
+    }
+    let content = storage.read_file(info_dir().join(id).as_str())?;
+    parse_trash_info(&String::from_utf8_lossy(&content))
+        .ok_or_else(|| StorageError::CorruptData(format!("Corrupt trash record: {}", id)))
+}
+
+/// Moves the entry `id` back to where it was deleted from. Fails rather
+/// than overwrite something that now occupies that path.
+pub fn restore_from_trash(storage: &dyn Storage, id: &str) -> Result<FileInfo, StorageError> {
+    let (original_path, _) = read_entry_info(storage, id)?;
+    let original = RemotePath::new(&original_path);
+    if storage.exists(original.as_str())? {
+        return Err(StorageError::InvalidInput(format!(
+            "{} already exists",
+            original
+        )));
+    }
+    if let Some(parent) = original.parent().filter(|p| !p.is_root()) {
+        storage.create_directory(parent.as_str(), true)?;
+    }
+
+    storage.rename_with_record(
+        files_dir().join(id).as_str(),
+        original.as_str(),
+        info_dir().join(id).as_str(),
+        None,
+    )
+}
+
+/// Permanently removes trashed entries, or only those deleted at least
+/// `older_than_days` ago. Returns how many entries were removed.
+pub fn empty_trash(
+    storage: &dyn Storage,
+    older_than_days: Option<u64>,
+) -> Result<usize, StorageError> {
+    let now = utils::unix_now();
+    let mut removed = 0;
+    for entry in list_trash(storage)? {
+        if older_than_days.is_some_and(|days| !is_expired(entry.deleted_at, now, days)) {
+            continue;
+        }
+        let path = files_dir().join(&entry.id);
+        if entry.is_dir {
+            storage.delete_directory(path.as_str(), true)?;
+        } else {
+            storage.delete_file(path.as_str())?;
+        }
+        storage.delete_file(info_dir().join(&entry.id).as_str())?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trash_id_avoids_collisions() {
+        assert_eq!(trash_id("a.jpg", 1700000000, 0), "a.jpg.1700000000");
+        assert_eq!(trash_id("a.jpg", 1700000000, 2), "a.jpg.1700000000-2");
+    }
+
+    #[test]
+    fn test_trash_info_roundtrip() {
+        let info = format_trash_info("/photos/my trip/a.jpg", 1700000000);
+        assert_eq!(
+            parse_trash_info(&info),
+            Some(("/photos/my trip/a.jpg".to_string(), 1700000000))
+        );
+        assert_eq!(parse_trash_info("Path=/a.jpg\n"), None);
+        assert_eq!(parse_trash_info(""), None);
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let now = 1700000000;
+        assert!(is_expired(now - 30 * SECONDS_PER_DAY, now, 30));
+        assert!(!is_expired(now - 29 * SECONDS_PER_DAY, now, 30));
+        assert!(is_expired(now, now, 0));
+        // Clock skew never makes an entry expire early.
+        assert!(!is_expired(now + 10, now, 1));
+    }
+
+    #[test]
+    fn test_move_to_trash_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("photos")).unwrap();
+        std::fs::write(dir.path().join("photos/a.jpg"), b"jpeg").unwrap();
+        let mut storage = crate::local::LocalStorage::new(dir.path().to_path_buf());
+        storage.connect().unwrap();
+
+        let entry = move_to_trash(&storage, "/photos/a.jpg").unwrap();
+        assert!(!storage.exists("/photos/a.jpg").unwrap());
+        assert_eq!(list_trash(&storage).unwrap(), vec![entry.clone()]);
+
+        let restored = restore_from_trash(&storage, &entry.id).unwrap();
+        assert_eq!(restored.path, "/photos/a.jpg");
+        assert_eq!(storage.read_file("/photos/a.jpg").unwrap(), b"jpeg");
+        assert!(!storage.exists(info_dir().join(&entry.id).as_str()).unwrap());
+        assert!(list_trash(&storage).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_is_in_trash() {
+        assert!(is_in_trash(&RemotePath::new("/.imagetrash")));
+        assert!(is_in_trash(&RemotePath::new("/.imagetrash/files/a.jpg.1")));
+        assert!(!is_in_trash(&RemotePath::new("/.imagetrash-old/a.jpg")));
+        assert!(!is_in_trash(&RemotePath::new("/photos/a.jpg")));
+    }
+}
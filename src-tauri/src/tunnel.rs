@@ -0,0 +1,245 @@
+//! Reaching an SSH server through a bastion host.
+//!
+//! libssh2 sessions need a real socket, so the bastion's `direct-tcpip`
+//! channel is exposed on a loopback TCP connection that the target session
+//! then runs over. A background thread copies bytes between the two.
+
+use crate::host_keys;
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use ssh2::{Channel, Session};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How long the forwarding thread sleeps when neither side had data.
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+const BUFFER_SIZE: usize = 32 * 1024;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JumpHostConfig {
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    pub username: String,
+    /// Base64-encoded private key for the bastion.
+    pub pem_content: String,
+    #[serde(default)]
+    pub key_passphrase: Option<String>,
+}
+
+// Written by hand so credentials never end up in logs.
+impl fmt::Debug for JumpHostConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JumpHostConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A forwarded connection to the target through the bastion. Dropping it
+/// stops forwarding and closes the bastion session, so drop it only after
+/// the target session has disconnected.
+pub struct JumpTunnel {
+    stop: Arc<AtomicBool>,
+    forwarder: Option<JoinHandle<()>>,
+}
+
+impl JumpTunnel {
+    /// Connects and authenticates to `jump`, opens a channel to
+    /// `target_host:target_port` and returns the tunnel with the local
+    /// stream to hand to the target session.
+    pub fn open(
+        jump: &JumpHostConfig,
+        target_host: &str,
+        target_port: u16,
+        known_hosts_file: Option<&Path>,
+        timeout: Duration,
+    ) -> Result<(JumpTunnel, TcpStream), Box<dyn std::error::Error>> {
+        let bastion = connect_bastion(jump, known_hosts_file, timeout)?;
+        let channel = bastion
+            .channel_direct_tcpip(target_host, target_port, None)
+            .map_err(|e| {
+                format!(
+                    "Target {}:{} unreachable from bastion: {}",
+                    target_host, target_port, e
+                )
+            })?;
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (forwarded, _) = listener.accept()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        let forwarder = std::thread::spawn(move || {
+            // Errors only mean one side closed; the target session reports them.
+            let _ = forward(&bastion, channel, forwarded, &flag);
+            bastion.set_blocking(true);
+            let _ = bastion.disconnect(None, "Closing connection", None);
+        });
+        Ok((
+            JumpTunnel {
+                stop,
+                forwarder: Some(forwarder),
+            },
+            client,
+        ))
+    }
+}
+
+impl Drop for JumpTunnel {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(forwarder) = self.forwarder.take() {
+            let _ = forwarder.join();
+        }
+    }
+}
+
+fn connect_bastion(
+    jump: &JumpHostConfig,
+    known_hosts_file: Option<&Path>,
+    timeout: Duration,
+) -> Result<Session, Box<dyn std::error::Error>> {
+    let port = jump.port.unwrap_or(22);
+    let tcp = utils::connect_tcp(&jump.host, port, timeout)
+        .map_err(|e| format!("Bastion {} unreachable: {}", jump.host, e))?;
+    let mut session = Session::new()?;
+    session.set_tcp_stream(tcp);
+    session
+        .handshake()
+        .map_err(|e| format!("Bastion handshake failed: {}", e))?;
+    // Host key and passphrase errors pass through untouched so the connect
+    // response can still carry their error codes.
+    host_keys::verify_host_key(&session, &jump.host, port, known_hosts_file)?;
+
+    let passphrase = jump.key_passphrase.as_deref();
+    let key = utils::decode_private_key(&jump.pem_content, passphrase)?;
+    session
+        .userauth_pubkey_memory(&jump.username, None, &key, passphrase)
+        .map_err(|e| format!("Bastion auth failed: {}", e.message()))?;
+    if !session.authenticated() {
+        return Err("Bastion auth failed".into());
+    }
+    Ok(session)
+}
+
+/// Writes as much of `pending` as `to` accepts without blocking.
+fn flush(pending: &mut Vec<u8>, to: &mut impl Write) -> io::Result<bool> {
+    if pending.is_empty() {
+        return Ok(false);
+    }
+    match to.write(pending) {
+        Ok(n) => {
+            pending.drain(..n);
+            Ok(n > 0)
+        }
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads into `pending` if it is empty. `Ok(None)` means end of stream.
+fn fill(pending: &mut Vec<u8>, from: &mut impl Read, buf: &mut [u8]) -> io::Result<Option<bool>> {
+    if !pending.is_empty() {
+        return Ok(Some(false));
+    }
+    match from.read(buf) {
+        Ok(0) => Ok(None),
+        Ok(n) => {
+            pending.extend_from_slice(&buf[..n]);
+            Ok(Some(true))
+        }
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Some(false)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Copies bytes both ways between the local stream and the channel until
+/// either side closes or `stop` is set.
+fn forward(
+    bastion: &Session,
+    mut channel: Channel,
+    mut local: TcpStream,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    local.set_nonblocking(true)?;
+    bastion.set_blocking(false);
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    let mut to_channel = Vec::new();
+    let mut to_local = Vec::new();
+
+    while !stop.load(Ordering::Relaxed) {
+        let Some(read_local) = fill(&mut to_channel, &mut local, &mut buf)? else {
+            break;
+        };
+        let wrote_channel = flush(&mut to_channel, &mut channel)?;
+        let read_channel = match fill(&mut to_local, &mut channel, &mut buf)? {
+            Some(read) => read,
+            None if channel.eof() => break,
+            None => false,
+        };
+        let wrote_local = flush(&mut to_local, &mut local)?;
+
+        if !(read_local || wrote_channel || read_channel || wrote_local) {
+            std::thread::sleep(IDLE_WAIT);
+        }
+    }
+    let _ = channel.close();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_and_flush() {
+        let mut pending = Vec::new();
+        let mut buf = [0u8; 4];
+        let mut source: &[u8] = b"abcdef";
+        assert_eq!(
+            fill(&mut pending, &mut source, &mut buf).unwrap(),
+            Some(true)
+        );
+        assert_eq!(pending, b"abcd");
+        // Nothing more is read until the pending bytes are written out.
+        assert_eq!(
+            fill(&mut pending, &mut source, &mut buf).unwrap(),
+            Some(false)
+        );
+
+        let mut sink = Vec::new();
+        assert!(flush(&mut pending, &mut sink).unwrap());
+        assert!(pending.is_empty());
+        assert_eq!(
+            fill(&mut pending, &mut source, &mut buf).unwrap(),
+            Some(true)
+        );
+        assert_eq!(fill(&mut Vec::new(), &mut source, &mut buf).unwrap(), None);
+        assert_eq!(sink, b"abcd");
+    }
+
+    #[test]
+    fn test_jump_host_debug_hides_key() {
+        let jump = JumpHostConfig {
+            host: "bastion.example.com".to_string(),
+            port: None,
+            username: "ec2-user".to_string(),
+            pem_content: "c2VjcmV0".to_string(),
+            key_passphrase: Some("hunter2".to_string()),
+        };
+        let debug = format!("{:?}", jump);
+        assert!(debug.contains("bastion.example.com"));
+        assert!(!debug.contains("c2VjcmV0"));
+        assert!(!debug.contains("hunter2"));
+    }
+}
@@ -0,0 +1,113 @@
+//! Testing connection settings without keeping the connection: each step of
+//! connecting is named so the settings screen can say which one failed.
+
+use crate::error::StorageError;
+use serde::Serialize;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Per-step limit when validating, shorter than a real connect's.
+pub const VALIDATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionStep {
+    /// The request is missing a key or password.
+    Credentials,
+    JumpHost,
+    Tcp,
+    Handshake,
+    HostKey,
+    Authentication,
+    /// Reading the repository's refs.
+    RepoAccess,
+    /// The repository has no such branch.
+    Branch,
+}
+
+impl fmt::Display for ConnectionStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConnectionStep::Credentials => "credentials",
+            ConnectionStep::JumpHost => "jump host",
+            ConnectionStep::Tcp => "TCP connection",
+            ConnectionStep::Handshake => "SSH handshake",
+            ConnectionStep::HostKey => "host key check",
+            ConnectionStep::Authentication => "authentication",
+            ConnectionStep::RepoAccess => "repository access",
+            ConnectionStep::Branch => "branch check",
+        })
+    }
+}
+
+/// An error from connecting and the step it came from.
+#[derive(Debug)]
+pub struct StepError {
+    pub step: ConnectionStep,
+    pub error: StorageError,
+}
+
+/// Tags the error of a connection step with [`Step::step`].
+pub trait Step<T> {
+    fn step(self, step: ConnectionStep) -> Result<T, StepError>;
+}
+
+impl<T, E: Into<StorageError>> Step<T> for Result<T, E> {
+    fn step(self, step: ConnectionStep) -> Result<T, StepError> {
+        self.map_err(|e| StepError {
+            step,
+            error: e.into(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ValidationResult {
+    pub ok: bool,
+    pub failed_step: Option<ConnectionStep>,
+    pub error: Option<StorageError>,
+    /// Time taken until success or failure.
+    pub elapsed_ms: u64,
+    /// SHA256 fingerprint of the server's host key when it is not trusted
+    /// yet; `accept_host_key` trusts it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_key_fingerprint: Option<String>,
+}
+
+impl ValidationResult {
+    pub fn new(result: Result<(), StepError>, started: Instant) -> Self {
+        let (failed_step, error) = match result {
+            Ok(()) => (None, None),
+            Err(e) => (Some(e.step), Some(e.error)),
+        };
+        ValidationResult {
+            ok: error.is_none(),
+            failed_step,
+            error,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            host_key_fingerprint: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_step_reported() {
+        let failed: Result<(), std::io::Error> = Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "refused",
+        ));
+        let result = ValidationResult::new(failed.step(ConnectionStep::Tcp), Instant::now());
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["ok"], false);
+        assert_eq!(json["failed_step"], "tcp");
+        assert!(json["error"]["code"].is_string());
+        assert!(json.get("host_key_fingerprint").is_none());
+
+        let result = ValidationResult::new(Ok(()), Instant::now());
+        assert!(result.ok && result.failed_step.is_none());
+    }
+}
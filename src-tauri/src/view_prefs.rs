@@ -0,0 +1,151 @@
+use crate::error::StorageError;
+use crate::storage::{FileInfo, MediaFilter, SortBy, SortOrder, Storage};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+pub const SIDECAR_FILE: &str = ".image-meta.json";
+const VIEW_KEY: &str = "view";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct ViewPrefs {
+    pub sort_by: SortBy,
+    pub sort_order: SortOrder,
+    pub filter: MediaFilter,
+    pub thumbnail_size: Option<u32>,
+}
+
+impl ViewPrefs {
+    pub fn apply(&self, files: Vec<FileInfo>) -> Vec<FileInfo> {
+        let mut files: Vec<FileInfo> = files
+            .into_iter()
+            .filter(|f| self.filter.matches(f))
+            .collect();
+        crate::storage::sort_files(&mut files, self.sort_by, self.sort_order);
+        files
+    }
+}
+
+pub fn sidecar_path(dir: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), SIDECAR_FILE)
+}
+
+/// Missing keys and malformed sidecars fall back to the defaults.
+pub fn parse_view_prefs(content: &[u8]) -> ViewPrefs {
+    serde_json::from_slice::<Value>(content)
+        .ok()
+        .and_then(|mut v| v.get_mut(VIEW_KEY).map(Value::take))
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default()
+}
+
+/// Writes `prefs` under the view key while keeping every other key of the
+/// existing sidecar intact.
+pub fn merge_view_prefs(
+    existing: Option<&[u8]>,
+    prefs: &ViewPrefs,
+) -> Result<Vec<u8>, serde_json::Error> {
+    let mut root = match existing.and_then(|c| serde_json::from_slice::<Value>(c).ok()) {
+        Some(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    root.insert(VIEW_KEY.to_string(), serde_json::to_value(prefs)?);
+    serde_json::to_vec_pretty(&Value::Object(root))
+}
+
+pub fn load_view_prefs(storage: &dyn Storage, dir: &str) -> ViewPrefs {
+    storage
+        .read_file(&sidecar_path(dir))
+        .map(|content| parse_view_prefs(&content))
+        .unwrap_or_default()
+}
+
+/// Fails without writing when the existing sidecar cannot be read, so its
+/// other keys are never lost to a transient error.
+pub fn save_view_prefs(
+    storage: &dyn Storage,
+    dir: &str,
+    prefs: &ViewPrefs,
+) -> Result<(), StorageError> {
+    let path = sidecar_path(dir);
+    let existing = match storage.read_file(&path) {
+        Ok(content) => Some(content),
+        Err(StorageError::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+    let content = merge_view_prefs(existing.as_deref(), prefs)
+        .map_err(|e| StorageError::Internal(e.to_string()))?;
+    storage.write_file(&path, &content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::tests::MockStorage;
+
+    #[test]
+    fn test_sidecar_path() {
+        assert_eq!(sidecar_path("/photos"), "/photos/.image-meta.json");
+        assert_eq!(sidecar_path("/photos/"), "/photos/.image-meta.json");
+        assert_eq!(sidecar_path("/"), "/.image-meta.json");
+    }
+
+    #[test]
+    fn test_parse_view_prefs() {
+        let content =
+            br#"{"view": {"sort_by": "modified", "sort_order": "desc", "filter": "images"}}"#;
+        let prefs = parse_view_prefs(content);
+        assert_eq!(prefs.sort_by, SortBy::Modified);
+        assert_eq!(prefs.sort_order, SortOrder::Desc);
+        assert_eq!(prefs.filter, MediaFilter::Images);
+        assert_eq!(prefs.thumbnail_size, None);
+    }
+
+    #[test]
+    fn test_parse_view_prefs_malformed_falls_back() {
+        assert_eq!(parse_view_prefs(b"not json"), ViewPrefs::default());
+        assert_eq!(parse_view_prefs(b"{\"other\": 1}"), ViewPrefs::default());
+        assert_eq!(
+            parse_view_prefs(br#"{"view": {"sort_by": "bogus"}}"#),
+            ViewPrefs::default()
+        );
+    }
+
+    #[test]
+    fn test_merge_view_prefs_preserves_unrelated_keys() {
+        let existing = br#"{"captions": {"a.jpg": "Beach"}, "view": {"sort_by": "size"}}"#;
+        let prefs = ViewPrefs {
+            sort_by: SortBy::Modified,
+            thumbnail_size: Some(512),
+            ..Default::default()
+        };
+        let merged = merge_view_prefs(Some(existing), &prefs).unwrap();
+        let value: Value = serde_json::from_slice(&merged).unwrap();
+        assert_eq!(value["captions"]["a.jpg"], "Beach");
+        assert_eq!(parse_view_prefs(&merged), prefs);
+    }
+
+    #[test]
+    fn test_save_view_prefs() {
+        let mut storage = MockStorage::default();
+        save_view_prefs(&storage, "/photos", &ViewPrefs::default()).unwrap();
+        assert_eq!(
+            storage.written.lock().unwrap()[0].0,
+            "/photos/.image-meta.json"
+        );
+
+        storage.written.lock().unwrap().clear();
+        storage.read_error = Some(StorageError::Timeout);
+        assert!(matches!(
+            save_view_prefs(&storage, "/photos", &ViewPrefs::default()),
+            Err(StorageError::Timeout)
+        ));
+        assert!(storage.written.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_merge_view_prefs_replaces_malformed_sidecar() {
+        let merged = merge_view_prefs(Some(b"[1, 2"), &ViewPrefs::default()).unwrap();
+        assert_eq!(parse_view_prefs(&merged), ViewPrefs::default());
+    }
+}
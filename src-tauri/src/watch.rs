@@ -0,0 +1,101 @@
+use crate::storage::FileInfo;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(5);
+pub const MIN_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Payload of the `fs://created`, `fs://removed` and `fs://modified`
+/// events.
+#[derive(Debug, Serialize, Clone)]
+pub struct FsChangeEvent {
+    /// The watched directory.
+    pub dir: String,
+    pub file: FileInfo,
+}
+
+#[derive(Debug, Default)]
+pub struct DirectoryChanges {
+    pub created: Vec<FileInfo>,
+    pub removed: Vec<FileInfo>,
+    pub modified: Vec<FileInfo>,
+}
+
+impl DirectoryChanges {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// A listing keyed by path, as kept between two polls.
+pub type Snapshot = HashMap<String, FileInfo>;
+
+pub fn snapshot(files: Vec<FileInfo>) -> Snapshot {
+    files.into_iter().map(|f| (f.path.clone(), f)).collect()
+}
+
+/// Compares two listings by path; an entry whose size or mtime changed is
+/// reported as modified.
+pub fn diff_snapshots(previous: &Snapshot, current: &Snapshot) -> DirectoryChanges {
+    let mut changes = DirectoryChanges::default();
+    for (path, file) in current {
+        match previous.get(path) {
+            None => changes.created.push(file.clone()),
+            Some(old) if old.size != file.size || old.modified != file.modified => {
+                changes.modified.push(file.clone())
+            }
+            Some(_) => {}
+        }
+    }
+    changes.removed = previous
+        .iter()
+        .filter(|(path, _)| !current.contains_key(*path))
+        .map(|(_, file)| file.clone())
+        .collect();
+
+    for list in [
+        &mut changes.created,
+        &mut changes.removed,
+        &mut changes.modified,
+    ] {
+        list.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: u64, modified: u64) -> FileInfo {
+        FileInfo::for_file(path, size, Some(modified))
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let previous = snapshot(vec![
+            file("/shots/a.png", 10, 100),
+            file("/shots/b.png", 20, 100),
+            file("/shots/c.png", 30, 100),
+        ]);
+        let current = snapshot(vec![
+            file("/shots/a.png", 10, 100),
+            file("/shots/b.png", 20, 200),
+            file("/shots/d.png", 40, 300),
+        ]);
+        let changes = diff_snapshots(&previous, &current);
+        let paths = |files: &[FileInfo]| files.iter().map(|f| f.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths(&changes.created), vec!["/shots/d.png"]);
+        assert_eq!(paths(&changes.removed), vec!["/shots/c.png"]);
+        assert_eq!(paths(&changes.modified), vec!["/shots/b.png"]);
+    }
+
+    #[test]
+    fn test_diff_snapshots_detects_size_change() {
+        let previous = snapshot(vec![file("/a.log", 10, 100)]);
+        let current = snapshot(vec![file("/a.log", 11, 100)]);
+        assert_eq!(diff_snapshots(&previous, &current).modified.len(), 1);
+        assert!(diff_snapshots(&current, &current).is_empty());
+    }
+}
@@ -0,0 +1,901 @@
+//! A backend over a WebDAV server such as Nextcloud or a NAS. PROPFIND lists
+//! directories and plain HTTP methods do the rest; nothing runs on the
+//! server, so archives and content search are not available.
+
+use crate::archive::ArchiveFormat;
+use crate::checksum::{self, ChecksumAlgorithm, FileChecksum};
+use crate::error::StorageError;
+use crate::http::{self, HttpAuth};
+use crate::metrics::{self, StorageMetrics};
+use crate::paths::{self, PathTranslator, RemotePath, ABSOLUTE_PATH_KEY};
+use crate::storage::{
+    self, ChunkCallback, ContentSearchResult, CreateDirectoryResult, DeleteDirectoryResult,
+    DirectoryUsage, FileInfo, FileReadOutcome, ListOptions, RecursiveListing, SearchResult,
+    Storage, StorageType,
+};
+use crate::thumbnails::{self, Thumbnail, ThumbnailOptions};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Method, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicBool;
+use std::time::{Instant, UNIX_EPOCH};
+
+const DAV_NAMESPACE: &str = "DAV:";
+
+/// The properties listings need, asked for by every PROPFIND.
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:">
+  <d:prop>
+    <d:resourcetype/>
+    <d:getcontentlength/>
+    <d:getlastmodified/>
+    <d:getcontenttype/>
+  </d:prop>
+</d:propfind>"#;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebDavConfig {
+    /// The collection to browse, such as
+    /// `https://cloud.example.com/remote.php/dav/files/alice/`.
+    pub url: String,
+    #[serde(default)]
+    pub auth: HttpAuth,
+    /// Trust self-signed and otherwise invalid certificates.
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+}
+
+pub struct WebDavStorage {
+    config: WebDavConfig,
+    /// Set by `connect`; `None` while disconnected.
+    client: Option<Client>,
+    /// Scheme, host and port of `config.url`.
+    origin: String,
+    /// The decoded path of `config.url`, which canonical paths are below.
+    base_path: String,
+    metrics: StorageMetrics,
+}
+
+/// One `<response>` of a multistatus body.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct DavEntry {
+    href: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<u64>,
+    content_type: Option<String>,
+}
+
+/// Splits a WebDAV URL into its origin and decoded, normalized path.
+fn endpoint(url: &str) -> Result<(String, String), StorageError> {
+    let parsed = Url::parse(url)
+        .map_err(|e| StorageError::InvalidInput(format!("Invalid WebDAV URL {}: {}", url, e)))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(StorageError::InvalidInput(format!(
+            "WebDAV URLs must be http or https: {}",
+            url
+        )));
+    }
+    Ok((
+        parsed.origin().ascii_serialization(),
+        RemotePath::new(&http::decode_path(parsed.path())).to_string(),
+    ))
+}
+
+fn method(name: &str) -> Method {
+    Method::from_bytes(name.as_bytes()).expect("WebDAV method names are valid tokens")
+}
+
+fn is_dav(node: &roxmltree::Node<'_, '_>, name: &str) -> bool {
+    node.is_element()
+        && node.tag_name().name() == name
+        && node.tag_name().namespace() == Some(DAV_NAMESPACE)
+}
+
+fn dav_child<'a, 'i>(node: roxmltree::Node<'a, 'i>, name: &str) -> Option<roxmltree::Node<'a, 'i>> {
+    node.children().find(|n| is_dav(n, name))
+}
+
+fn text<'a>(node: roxmltree::Node<'a, '_>) -> &'a str {
+    node.text().unwrap_or("").trim()
+}
+
+/// Whether a `<status>` line such as `HTTP/1.1 200 OK` is a success. A
+/// missing status counts as one.
+fn status_ok(status: Option<roxmltree::Node<'_, '_>>) -> bool {
+    status.is_none_or(|node| {
+        text(node)
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .is_some_and(|code| (200..300).contains(&code))
+    })
+}
+
+/// Parses the body of a 207 Multi-Status answer to PROPFIND. Properties in
+/// a failed `<propstat>`, such as the length of a collection, are left at
+/// their defaults, and failed responses are dropped.
+fn parse_multistatus(xml: &str) -> Result<Vec<DavEntry>, StorageError> {
+    let doc = roxmltree::Document::parse(xml)
+        .map_err(|e| StorageError::Protocol(format!("Invalid PROPFIND response: {}", e)))?;
+    let root = doc.root_element();
+    if !is_dav(&root, "multistatus") {
+        return Err(StorageError::Protocol(
+            "The server did not answer PROPFIND with a multistatus; is this a WebDAV URL?"
+                .to_string(),
+        ));
+    }
+    let mut entries = Vec::new();
+    for response in root.children().filter(|n| is_dav(n, "response")) {
+        let Some(href) = dav_child(response, "href").map(text) else {
+            continue;
+        };
+        if !status_ok(dav_child(response, "status")) {
+            continue;
+        }
+        let mut entry = DavEntry {
+            href: href.to_string(),
+            ..Default::default()
+        };
+        for propstat in response.children().filter(|n| is_dav(n, "propstat")) {
+            if !status_ok(dav_child(propstat, "status")) {
+                continue;
+            }
+            let Some(prop) = dav_child(propstat, "prop") else {
+                continue;
+            };
+            for property in prop.children().filter(|n| n.is_element()) {
+                if property.tag_name().namespace() != Some(DAV_NAMESPACE) {
+                    continue;
+                }
+                match property.tag_name().name() {
+                    "resourcetype" => entry.is_dir = dav_child(property, "collection").is_some(),
+                    "getcontentlength" => entry.size = text(property).parse().unwrap_or(0),
+                    "getlastmodified" => {
+                        entry.modified = httpdate::parse_http_date(text(property))
+                            .ok()
+                            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                            .map(|d| d.as_secs())
+                    }
+                    "getcontenttype" => {
+                        // Parameters such as the charset are not part of the type.
+                        let mime = text(property).split(';').next().unwrap_or("").trim();
+                        entry.content_type = Some(mime.to_string()).filter(|m| !m.is_empty());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+impl WebDavStorage {
+    pub fn new(config: WebDavConfig) -> Self {
+        WebDavStorage {
+            config,
+            client: None,
+            origin: String::new(),
+            base_path: "/".to_string(),
+            metrics: StorageMetrics::default(),
+        }
+    }
+
+    pub fn config(&self) -> &WebDavConfig {
+        &self.config
+    }
+
+    fn client(&self) -> Result<&Client, StorageError> {
+        self.client.as_ref().ok_or(StorageError::NotConnected)
+    }
+
+    /// The URL of `path`; collections get the trailing slash servers expect.
+    fn url(&self, path: &RemotePath, collection: bool) -> String {
+        let absolute = self.path_translator().to_absolute(path);
+        let mut url = format!("{}{}", self.origin, http::encode_path(&absolute));
+        if collection && !url.ends_with('/') {
+            url.push('/');
+        }
+        url
+    }
+
+    /// The canonical path of the entry at `href`.
+    fn remote_path(&self, href: &str) -> RemotePath {
+        let absolute = http::decode_path(http::href_path(href));
+        self.path_translator()
+            .from_absolute(&absolute)
+            .unwrap_or_else(|| RemotePath::new(&absolute))
+    }
+
+    fn request(
+        &self,
+        method: Method,
+        path: &RemotePath,
+        collection: bool,
+    ) -> Result<RequestBuilder, StorageError> {
+        Ok(self.client()?.request(method, self.url(path, collection)))
+    }
+
+    /// PROPFIND of `path` alone with `depth` 0, or with its children with 1.
+    fn propfind(&self, path: &RemotePath, depth: u8) -> Result<Vec<DavEntry>, StorageError> {
+        let request = self
+            .request(method("PROPFIND"), path, depth > 0)?
+            .header("Depth", depth.to_string())
+            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(PROPFIND_BODY);
+        let body = http::send(request, path.as_str())?.text()?;
+        parse_multistatus(&body)
+    }
+
+    /// The entries of `listing`, a PROPFIND of `dir` with depth 1, that are
+    /// direct children of it.
+    fn children_in(
+        &self,
+        dir: &RemotePath,
+        listing: Vec<DavEntry>,
+    ) -> Result<Vec<(RemotePath, DavEntry)>, StorageError> {
+        let mut children = Vec::new();
+        for entry in listing {
+            let path = self.remote_path(&entry.href);
+            if path == *dir && !entry.is_dir {
+                return Err(StorageError::InvalidInput(format!(
+                    "Not a directory: {}",
+                    dir
+                )));
+            }
+            if path.parent().as_ref() == Some(dir) {
+                children.push((path, entry));
+            }
+        }
+        Ok(children)
+    }
+
+    fn children(&self, dir: &RemotePath) -> Result<Vec<(RemotePath, DavEntry)>, StorageError> {
+        self.children_in(dir, self.propfind(dir, 1)?)
+    }
+
+    fn file_info(&self, path: &RemotePath, entry: &DavEntry) -> FileInfo {
+        let name = path.file_name().unwrap_or_default().to_string();
+        let mime_type = if entry.is_dir {
+            None
+        } else {
+            storage::detect_mime_type(&name).or_else(|| entry.content_type.clone())
+        };
+        FileInfo {
+            name,
+            path: path.to_string(),
+            size: if entry.is_dir { 0 } else { entry.size },
+            is_dir: entry.is_dir,
+            is_symlink: false,
+            link_target: None,
+            modified: entry.modified,
+            mime_type,
+            thumbnail: None,
+            permissions: None,
+            owner: None,
+            extra: BTreeMap::from([(
+                ABSOLUTE_PATH_KEY.to_string(),
+                self.path_translator().to_absolute(path),
+            )]),
+        }
+    }
+
+    fn stat_path(&self, path: &RemotePath) -> Result<FileInfo, StorageError> {
+        let entries = self.propfind(path, 0)?;
+        let entry = entries
+            .first()
+            .ok_or_else(|| StorageError::NotFound(path.to_string()))?;
+        Ok(self.file_info(path, entry))
+    }
+
+    /// Sends a request creating `path`, which servers answer with 409
+    /// Conflict when its parent is missing.
+    fn send_creating(
+        &self,
+        request: RequestBuilder,
+        path: &RemotePath,
+    ) -> Result<(), StorageError> {
+        let response = request.send()?;
+        if response.status() == StatusCode::CONFLICT {
+            let parent = path.parent().unwrap_or_else(RemotePath::root);
+            return Err(StorageError::NotFound(parent.to_string()));
+        }
+        http::check_status(response, path.as_str())?;
+        Ok(())
+    }
+
+    fn mkcol(&self, path: &RemotePath) -> Result<(), StorageError> {
+        self.send_creating(self.request(method("MKCOL"), path, true)?, path)
+    }
+
+    /// MOVE or COPY of `from` onto `to`, replacing it.
+    fn transfer(&self, verb: &str, from: &RemotePath, to: &RemotePath) -> Result<(), StorageError> {
+        let collection = self.stat_path(from)?.is_dir;
+        let request = self
+            .request(method(verb), from, collection)?
+            .header("Destination", self.url(to, collection))
+            .header("Overwrite", "T");
+        self.send_creating(request, to)
+    }
+}
+
+impl Storage for WebDavStorage {
+    fn connect(&mut self) -> Result<(), StorageError> {
+        let (origin, base_path) = endpoint(&self.config.url)?;
+        self.origin = origin;
+        self.base_path = base_path;
+        self.client = Some(http::client(
+            &self.config.auth,
+            self.config.accept_invalid_certs,
+        )?);
+        let root = match self.stat_path(&RemotePath::root()) {
+            Ok(root) => root,
+            Err(e) => {
+                self.client = None;
+                return Err(e);
+            }
+        };
+        if !root.is_dir {
+            self.client = None;
+            return Err(StorageError::InvalidInput(format!(
+                "{} is not a WebDAV collection",
+                self.config.url
+            )));
+        }
+        log::info!("Connected to WebDAV server {}", self.origin);
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.client = None;
+    }
+
+    fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    fn ping(&self) -> Result<(), StorageError> {
+        self.propfind(&RemotePath::root(), 0)?;
+        Ok(())
+    }
+
+    fn metrics(&self) -> &StorageMetrics {
+        &self.metrics
+    }
+
+    fn list_directory_page(
+        &self,
+        path: &str,
+        options: &ListOptions,
+    ) -> Result<(Vec<FileInfo>, usize), StorageError> {
+        let dir = self.path_translator().to_remote(path);
+        let mut files: Vec<FileInfo> = self
+            .children(&dir)?
+            .iter()
+            .filter(|(path, entry)| {
+                let name = path.file_name().unwrap_or_default();
+                storage::is_listed(name, options.show_hidden, &[])
+                    && options.filter.matches_entry(name, entry.is_dir)
+            })
+            .map(|(path, entry)| self.file_info(path, entry))
+            .collect();
+        storage::sort_files(&mut files, options.sort_by, options.sort_order);
+        let total = files.len();
+        Ok((
+            storage::paginate(&files, options.offset, options.limit),
+            total,
+        ))
+    }
+
+    fn list_directory_recursive(
+        &self,
+        path: &str,
+        max_depth: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<RecursiveListing, StorageError> {
+        storage::list_by_walking(self, path, max_depth, cancelled)
+    }
+
+    fn search(
+        &self,
+        root: &str,
+        pattern: &str,
+        case_sensitive: bool,
+        limit: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<SearchResult, StorageError> {
+        storage::search_by_walking(self, root, pattern, case_sensitive, limit, cancelled)
+    }
+
+    fn search_contents(
+        &self,
+        _root: &str,
+        _query: &str,
+        _regex: bool,
+        _include: Option<&str>,
+        _limit: usize,
+    ) -> Result<ContentSearchResult, StorageError> {
+        Err(StorageError::Unsupported(
+            "Content search is not supported over WebDAV".to_string(),
+        ))
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, StorageError> {
+        let path = self.path_translator().to_remote(path);
+        metrics::metered_read(&self.metrics, || {
+            let request = self.request(Method::GET, &path, false)?;
+            Ok(http::send(request, path.as_str())?.bytes()?.to_vec())
+        })
+    }
+
+    fn read_file_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, bool), StorageError> {
+        let start = Instant::now();
+        let path = self.path_translator().to_remote(path);
+        let request = self.request(Method::GET, &path, false)?;
+        let (data, at_end) = http::read_range(request, path.as_str(), offset, length)?;
+        self.metrics.record_read(data.len() as u64, start.elapsed());
+        Ok((data, at_end))
+    }
+
+    fn read_files(&self, paths: &[String]) -> Result<Vec<FileReadOutcome>, StorageError> {
+        let mut outcomes = Vec::with_capacity(paths.len());
+        for path in paths {
+            outcomes.push(match self.read_file(path) {
+                Err(e) if e.is_transport() || matches!(e, StorageError::NotConnected) => {
+                    return Err(e)
+                }
+                result => result.map_err(|e| e.to_string()),
+            });
+        }
+        Ok(outcomes)
+    }
+
+    fn read_file_streamed(
+        &self,
+        path: &str,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        let start = Instant::now();
+        let path = self.path_translator().to_remote(path);
+        let response = http::send(self.request(Method::GET, &path, false)?, path.as_str())?;
+        let (read, completed) = http::stream_body(response, on_chunk)?;
+        self.metrics.record_read(read, start.elapsed());
+        Ok(completed)
+    }
+
+    fn write_file(&self, path: &str, data: &[u8]) -> Result<(), StorageError> {
+        let path = self.path_translator().to_remote(path);
+        metrics::metered_write(&self.metrics, data.len(), || {
+            let request = self.request(Method::PUT, &path, false)?.body(data.to_vec());
+            self.send_creating(request, &path)
+        })
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        match self.stat(path) {
+            Ok(_) => Ok(true),
+            Err(StorageError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn stat(&self, path: &str) -> Result<FileInfo, StorageError> {
+        self.stat_path(&self.path_translator().to_remote(path))
+    }
+
+    fn set_permissions(&self, _path: &str, _mode: u32) -> Result<FileInfo, StorageError> {
+        Err(StorageError::Unsupported(
+            "WebDAV has no permission bits".to_string(),
+        ))
+    }
+
+    fn set_modified(&self, _path: &str, _mtime: u64) -> Result<FileInfo, StorageError> {
+        Err(StorageError::Unsupported(
+            "WebDAV servers do not let clients set modification times".to_string(),
+        ))
+    }
+
+    fn directory_size(&self, path: &str) -> Result<DirectoryUsage, StorageError> {
+        storage::size_by_walking(self, path)
+    }
+
+    /// Nothing can be run on the server, so the caller builds zips itself.
+    fn archive_directory(
+        &self,
+        _path: &str,
+        format: ArchiveFormat,
+        _on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<bool, StorageError> {
+        Err(StorageError::ToolMissing(format.remote_tool()))
+    }
+
+    fn extract_archive(
+        &self,
+        _path: &str,
+        _destination: &str,
+    ) -> Result<Vec<String>, StorageError> {
+        Err(StorageError::Unsupported(
+            "Archives cannot be extracted over WebDAV".to_string(),
+        ))
+    }
+
+    fn checksum(
+        &self,
+        path: &str,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<FileChecksum, StorageError> {
+        let info = self.stat(path)?;
+        if info.is_dir {
+            return Err(StorageError::InvalidInput("is a directory".to_string()));
+        }
+        Ok(FileChecksum {
+            algorithm,
+            digest: checksum::hash_streamed(self, path, algorithm)?,
+            size: info.size,
+        })
+    }
+
+    fn checksums(
+        &self,
+        paths: &[String],
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<Vec<Option<String>>, StorageError> {
+        Ok(paths
+            .iter()
+            .map(|p| checksum::hash_streamed(self, p, algorithm).ok())
+            .collect())
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        let path = self.path_translator().to_remote(path);
+        if self.stat_path(&path)?.is_dir {
+            return Err(StorageError::InvalidInput("is a directory".to_string()));
+        }
+        http::send(self.request(Method::DELETE, &path, false)?, path.as_str())?;
+        Ok(())
+    }
+
+    fn delete_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+    ) -> Result<DeleteDirectoryResult, StorageError> {
+        let dir = paths::guard_directory_delete(&self.path_translator(), path)?;
+        if !self.stat_path(&dir)?.is_dir {
+            return Err(StorageError::InvalidInput("not a directory".to_string()));
+        }
+        let mut result = DeleteDirectoryResult {
+            files_removed: 0,
+            dirs_removed: 1,
+        };
+        if !self.children(&dir)?.is_empty() {
+            if !recursive {
+                return Err(StorageError::InvalidInput(
+                    "Directory not empty".to_string(),
+                ));
+            }
+            // DELETE of a collection removes everything in it at once, so
+            // count first.
+            let usage = self.directory_size(dir.as_str())?;
+            result.files_removed = usage.file_count;
+            result.dirs_removed += usage.dir_count;
+        }
+        http::send(self.request(Method::DELETE, &dir, true)?, dir.as_str())?;
+        Ok(result)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<FileInfo, StorageError> {
+        let translator = self.path_translator();
+        let to = translator.to_remote(to);
+        self.transfer("MOVE", &translator.to_remote(from), &to)?;
+        self.stat_path(&to)
+    }
+
+    fn copy_file(&self, from: &str, to: &str) -> Result<FileInfo, StorageError> {
+        let translator = self.path_translator();
+        let from = translator.to_remote(from);
+        let to = translator.to_remote(to);
+        if self.stat_path(&from)?.is_dir {
+            return Err(StorageError::InvalidInput(
+                "is a directory; use copy_directory".to_string(),
+            ));
+        }
+        self.transfer("COPY", &from, &to)?;
+        self.stat_path(&to)
+    }
+
+    fn create_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+    ) -> Result<CreateDirectoryResult, StorageError> {
+        let dir = self.path_translator().to_remote(path);
+        let existed = match self.stat_path(&dir) {
+            Ok(info) if info.is_dir => true,
+            Ok(_) => {
+                return Err(StorageError::InvalidInput(format!(
+                    "Path exists and is not a directory: {}",
+                    dir
+                )))
+            }
+            Err(StorageError::NotFound(_)) => false,
+            Err(e) => return Err(e),
+        };
+        if !existed {
+            if recursive {
+                let mut missing = vec![dir.clone()];
+                let mut parent = dir.parent();
+                while let Some(p) = parent.filter(|p| !p.is_root()) {
+                    if self.exists(p.as_str())? {
+                        break;
+                    }
+                    parent = p.parent();
+                    missing.push(p);
+                }
+                for p in missing.iter().rev() {
+                    self.mkcol(p)?;
+                }
+            } else {
+                self.mkcol(&dir).map_err(|e| match e {
+                    StorageError::NotFound(parent) => {
+                        format!("Parent directory does not exist: {}", parent).into()
+                    }
+                    e => e,
+                })?;
+            }
+        }
+        Ok(CreateDirectoryResult {
+            directory: self.stat_path(&dir)?,
+            existed,
+        })
+    }
+
+    fn get_file_thumbnail(
+        &self,
+        path: &str,
+        max_size: u32,
+        options: ThumbnailOptions,
+    ) -> Result<Thumbnail, StorageError> {
+        thumbnails::fetched_file_thumbnail(self, path, max_size, options)
+            .map_err(StorageError::from)
+    }
+
+    fn get_root_path(&self) -> String {
+        self.base_path.clone()
+    }
+
+    fn path_translator(&self) -> PathTranslator {
+        PathTranslator::new(&self.base_path)
+    }
+
+    fn storage_type(&self) -> StorageType {
+        StorageType::WebDav
+    }
+
+    fn connection_id(&self) -> String {
+        format!("webdav:{}{}", self.origin, self.base_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PROPFIND of `/Photos/` on Nextcloud, which reports the missing
+    /// properties of collections in a 404 `<propstat>`.
+    const NEXTCLOUD_LISTING: &str = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:s="http://sabredav.org/ns" xmlns:oc="http://owncloud.org/ns" xmlns:nc="http://nextcloud.org/ns">
+ <d:response>
+  <d:href>/remote.php/dav/files/alice/Photos/</d:href>
+  <d:propstat>
+   <d:prop>
+    <d:resourcetype><d:collection/></d:resourcetype>
+    <d:getlastmodified>Tue, 03 Sep 2024 18:22:09 GMT</d:getlastmodified>
+   </d:prop>
+   <d:status>HTTP/1.1 200 OK</d:status>
+  </d:propstat>
+  <d:propstat>
+   <d:prop>
+    <d:getcontentlength/>
+    <d:getcontenttype/>
+   </d:prop>
+   <d:status>HTTP/1.1 404 Not Found</d:status>
+  </d:propstat>
+ </d:response>
+ <d:response>
+  <d:href>/remote.php/dav/files/alice/Photos/Summer%20trip%20%c3%a9t%c3%a9.jpg</d:href>
+  <d:propstat>
+   <d:prop>
+    <d:resourcetype/>
+    <d:getcontentlength>2481152</d:getcontentlength>
+    <d:getlastmodified>Mon, 12 Aug 2024 09:15:00 GMT</d:getlastmodified>
+    <d:getcontenttype>image/jpeg</d:getcontenttype>
+   </d:prop>
+   <d:status>HTTP/1.1 200 OK</d:status>
+  </d:propstat>
+ </d:response>
+ <d:response>
+  <d:href>/remote.php/dav/files/alice/Photos/Raw%20files/</d:href>
+  <d:propstat>
+   <d:prop>
+    <d:resourcetype><d:collection/></d:resourcetype>
+    <d:getlastmodified>Tue, 03 Sep 2024 18:22:09 GMT</d:getlastmodified>
+   </d:prop>
+   <d:status>HTTP/1.1 200 OK</d:status>
+  </d:propstat>
+ </d:response>
+</d:multistatus>"#;
+
+    /// Apache mod_dav, with its own prefixes for DAV properties and full
+    /// URLs as hrefs.
+    const APACHE_LISTING: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:multistatus xmlns:D="DAV:" xmlns:ns0="DAV:">
+<D:response xmlns:lp1="DAV:" xmlns:lp2="http://apache.org/dav/props/">
+<D:href>http://nas.local/dav/</D:href>
+<D:propstat>
+<D:prop>
+<lp1:resourcetype><D:collection/></lp1:resourcetype>
+<lp1:getlastmodified>Wed, 10 Jan 2024 08:00:00 GMT</lp1:getlastmodified>
+<D:getcontenttype>httpd/unix-directory</D:getcontenttype>
+</D:prop>
+<D:status>HTTP/1.1 200 OK</D:status>
+</D:propstat>
+</D:response>
+<D:response xmlns:lp1="DAV:" xmlns:lp2="http://apache.org/dav/props/">
+<D:href>http://nas.local/dav/notes%231.txt</D:href>
+<D:propstat>
+<D:prop>
+<lp1:resourcetype/>
+<lp1:getcontentlength>12</lp1:getcontentlength>
+<lp1:getlastmodified>Wed, 10 Jan 2024 08:00:00 GMT</lp1:getlastmodified>
+<D:getcontenttype>text/plain; charset=UTF-8</D:getcontenttype>
+<lp2:executable>F</lp2:executable>
+</D:prop>
+<D:status>HTTP/1.1 200 OK</D:status>
+</D:propstat>
+</D:response>
+<D:response>
+<D:href>http://nas.local/dav/locked.bin</D:href>
+<D:status>HTTP/1.1 403 Forbidden</D:status>
+</D:response>
+</D:multistatus>"#;
+
+    fn storage(url: &str) -> WebDavStorage {
+        let mut storage = WebDavStorage::new(WebDavConfig {
+            url: url.to_string(),
+            auth: HttpAuth::None,
+            accept_invalid_certs: false,
+        });
+        (storage.origin, storage.base_path) = endpoint(url).unwrap();
+        storage
+    }
+
+    #[test]
+    fn test_parse_nextcloud_listing() {
+        let entries = parse_multistatus(NEXTCLOUD_LISTING).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0],
+            DavEntry {
+                href: "/remote.php/dav/files/alice/Photos/".to_string(),
+                is_dir: true,
+                size: 0,
+                modified: Some(1725387729),
+                content_type: None,
+            }
+        );
+        assert!(!entries[1].is_dir);
+        assert_eq!(entries[1].size, 2481152);
+        assert_eq!(entries[1].modified, Some(1723454100));
+        assert_eq!(entries[1].content_type.as_deref(), Some("image/jpeg"));
+    }
+
+    #[test]
+    fn test_parse_apache_listing() {
+        let entries = parse_multistatus(APACHE_LISTING).unwrap();
+        // The forbidden entry is dropped.
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[0].modified, Some(1704873600));
+        assert_eq!(entries[1].href, "http://nas.local/dav/notes%231.txt");
+        assert_eq!(entries[1].size, 12);
+        assert_eq!(entries[1].content_type.as_deref(), Some("text/plain"));
+    }
+
+    #[test]
+    fn test_parse_rejects_non_dav_answers() {
+        let e = parse_multistatus("<html><body>Welcome</body></html>").unwrap_err();
+        assert_eq!(e.code(), "PROTOCOL");
+        assert!(parse_multistatus("not xml").is_err());
+    }
+
+    #[test]
+    fn test_children_map_hrefs_to_canonical_paths() {
+        let storage = storage("https://cloud.example.com/remote.php/dav/files/alice/");
+        let dir = RemotePath::new("/Photos");
+        let children = storage
+            .children_in(&dir, parse_multistatus(NEXTCLOUD_LISTING).unwrap())
+            .unwrap();
+        let files: Vec<FileInfo> = children
+            .iter()
+            .map(|(path, entry)| storage.file_info(path, entry))
+            .collect();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "/Photos/Summer trip été.jpg");
+        assert_eq!(files[0].name, "Summer trip été.jpg");
+        assert_eq!(files[0].mime_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(
+            files[0].extra[ABSOLUTE_PATH_KEY],
+            "/remote.php/dav/files/alice/Photos/Summer trip été.jpg"
+        );
+        assert!(files[1].is_dir);
+        assert_eq!(files[1].path, "/Photos/Raw files");
+
+        let apache = self::storage("http://nas.local/dav");
+        let children = apache
+            .children_in(
+                &RemotePath::root(),
+                parse_multistatus(APACHE_LISTING).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].0.as_str(), "/notes#1.txt");
+
+        // Listing a file is refused rather than coming back empty.
+        let file = RemotePath::new("/notes#1.txt");
+        let listing = parse_multistatus(APACHE_LISTING).unwrap()[1..].to_vec();
+        assert_eq!(
+            apache.children_in(&file, listing).unwrap_err().code(),
+            "INVALID_INPUT"
+        );
+    }
+
+    #[test]
+    fn test_urls_escape_paths() {
+        let storage =
+            storage("https://cloud.example.com/remote.php/dav/files/alice%40example.com/");
+        assert_eq!(
+            storage.get_root_path(),
+            "/remote.php/dav/files/alice@example.com"
+        );
+        let path = RemotePath::new("/Fotos 2024/Über #1.jpg");
+        let url = storage.url(&path, false);
+        assert_eq!(
+            url,
+            "https://cloud.example.com/remote.php/dav/files/alice%40example.com/Fotos%202024/%C3%9Cber%20%231.jpg"
+        );
+        assert_eq!(storage.remote_path(&url), path);
+        assert_eq!(
+            storage.url(&RemotePath::new("/Fotos 2024"), true),
+            "https://cloud.example.com/remote.php/dav/files/alice%40example.com/Fotos%202024/"
+        );
+        assert_eq!(
+            storage.url(&RemotePath::root(), true),
+            "https://cloud.example.com/remote.php/dav/files/alice%40example.com/"
+        );
+    }
+
+    #[test]
+    fn test_endpoint_validation() {
+        assert_eq!(
+            endpoint("https://nas.local:5006/").unwrap(),
+            ("https://nas.local:5006".to_string(), "/".to_string())
+        );
+        assert_eq!(
+            endpoint("ftp://nas.local/").unwrap_err().code(),
+            "INVALID_INPUT"
+        );
+        assert_eq!(
+            endpoint("nas.local/dav").unwrap_err().code(),
+            "INVALID_INPUT"
+        );
+    }
+}
@@ -325,7 +325,7 @@ describe('connection store', () => {
       const mockFiles: FileInfo[] = [
         { name: 'test.jpg', path: '/test.jpg', size: 100, isDir: false, mimeType: 'image/jpeg' }
       ]
-      mockInvoke.mockResolvedValueOnce(mockFiles)
+      mockInvoke.mockResolvedValueOnce({ files: mockFiles, total: 1, offset: 0 })
 
       await store.loadFiles('/home/user')
       
@@ -342,9 +342,20 @@ describe('connection store', () => {
       expect(store.error).toBe('Error: Failed to list')
     })
 
+    it('should show the message of command errors', async () => {
+      const store = useConnectionStore()
+      mockInvoke.mockRejectedValueOnce({ code: 'NOT_FOUND', message: 'Not found: /home/user' })
+
+      await store.loadFiles('/home/user')
+
+      expect(store.error).toBe('Not found: /home/user')
+    })
+
     it('should set isLoadingFiles correctly', async () => {
       const store = useConnectionStore()
-      mockInvoke.mockImplementation(() => new Promise(resolve => setTimeout(() => resolve([]), 10)))
+      mockInvoke.mockImplementation(() =>
+        new Promise(resolve => setTimeout(() => resolve({ files: [], total: 0, offset: 0 }), 10))
+      )
 
       const promise = store.loadFiles('/home')
       expect(store.isLoadingFiles).toBe(true)
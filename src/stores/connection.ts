@@ -28,9 +28,33 @@ export interface FileInfo {
   path: string
   size: number
   isDir: boolean
+  isSymlink?: boolean
+  linkTarget?: string
   modified?: number
   mimeType?: string
   thumbnail?: string
+  permissions?: string
+  owner?: string
+}
+
+export interface DirectoryPage {
+  files: FileInfo[]
+  total: number
+  offset: number
+}
+
+/** How commands report failures, e.g. `{ code: 'NOT_FOUND', message: 'Not found: /a.jpg' }`. */
+export interface CommandError {
+  code: string
+  message: string
+}
+
+export function isCommandError(e: unknown): e is CommandError {
+  return typeof e === 'object' && e !== null && 'code' in e && 'message' in e
+}
+
+export function errorMessage(e: unknown): string {
+  return isCommandError(e) ? e.message : String(e)
 }
 
 export const useConnectionStore = defineStore('connection', () => {
@@ -96,7 +120,7 @@ export const useConnectionStore = defineStore('connection', () => {
         return false
       }
     } catch (e) {
-      error.value = String(e)
+      error.value = errorMessage(e)
       return false
     } finally {
       isConnecting.value = false
@@ -136,7 +160,7 @@ export const useConnectionStore = defineStore('connection', () => {
         return false
       }
     } catch (e) {
-      error.value = String(e)
+      error.value = errorMessage(e)
       return false
     } finally {
       isConnecting.value = false
@@ -158,10 +182,10 @@ export const useConnectionStore = defineStore('connection', () => {
     currentPath.value = path
     
     try {
-      const result = await invoke<FileInfo[]>('list_files', { path })
-      files.value = result
+      const result = await invoke<DirectoryPage>('list_files', { path })
+      files.value = result.files
     } catch (e) {
-      error.value = String(e)
+      error.value = errorMessage(e)
     } finally {
       isLoadingFiles.value = false
     }